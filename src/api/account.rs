@@ -1,84 +1,250 @@
 use std::collections::HashMap;
 use revolt_rocket_okapi::openapi;
-use rocket::{post, State, serde::json::Json, http::Status, get};
+use rocket::{post, delete, State, serde::json::Json, http::Status, get};
 
-use crate::{domain::{account::{Account, Balance}, ledger::{Fiat, Crypto}, asset::AssetManager}, mongo::{Repository, Crud}, response::{error::ErrorResponse, custom::Pagination}, fairings::auth::AuthorizedUser, security::permissions::{only_admin, can_continue}};
+use crate::{api::transaction::query_transactions, domain::{account::{Account, Balance, OwnerPermission}, audit::AuditEntry, ledger::{Fiat, Crypto}, asset::AssetManager, transaction::Transaction, event::{Aggregate, DomainEvent, EventType}, grant::GrantScope, user::User}, dto::{account::{AccountCreationRequest, AccountOwnerRequest, AccountRenameRequest}, transaction::TransactionFilter}, events::record, mongo::{finish, modify, modify_in, Crud, Db, LedgerDb, LedgerStore, Session, StoreError, Versioned, VersionedDb, VersionedStore}, response::{error::ErrorResponse, custom::{Pagination, CursorPagination, ETagged}}, fairings::{api_key::SignedJson, auth::{AuthorizedUser, Permitted, AccountsFreeze, AccountsRead}, precondition::IfMatch, request_context::RequestContext}, security::{audit, ownership::{owned_accounts, OwnershipResolver}, permissions::{can_access, can_operate}}};
+
+/// Ledgers opened with a new account when the request names none.
+const DEFAULT_ASSETS: [&str; 3] = ["USD", "BTC", "EUR"];
+const DEFAULT_NAME: &str = "Main";
 
 #[openapi(tag = "Accounts")]
-#[post("/accounts", format = "json")]
+#[post("/accounts", format = "json", data = "<request>")]
 pub async fn create_account(
-    account_db: &State<Repository<Account>>,
-    fiat_db: &State<Repository<Fiat>>,
-    crypto_db: &State<Repository<Crypto>>,
+    request: Option<SignedJson<AccountCreationRequest>>,
+    account_db: &State<VersionedDb<Account>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    user_db: &State<Db<User>>,
+    outbox: &State<Db<DomainEvent>>,
     asset_master: &State<AssetManager>,
+    ownership: &State<OwnershipResolver>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Account>, (Status, Json<ErrorResponse>)> {
-    let mut account = match Account::init(asset_master, &vec!["USD".to_string(), "BTC".to_string(), "EUR".to_string()],_auth.user_id) {
+    let request = match request {
+        Some(request) => request.0,
+        None => AccountCreationRequest { name: None, assets: None, owners: None },
+    };
+    let assets: Vec<String> = match request.assets {
+        Some(assets) if !assets.is_empty() => assets.iter().map(|asset| asset.to_uppercase()).collect(),
+        _ => DEFAULT_ASSETS.iter().map(|asset| asset.to_string()).collect(),
+    };
+    let name = request.name.unwrap_or(DEFAULT_NAME.to_string());
+    let mut account = match Account::init(asset_master, &assets, _auth.user_id.clone(), name) {
         Ok(account) => account,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
     };
-    
-    match account_db.create(account.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
-    }
-
-    for key in account.accounts_fiat.keys() {
-        let asset = match asset_master.get_by_symbol(key){
-            Some(symbol) => symbol,
-            None => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "Asset not found".to_string())))),
-        };
-        let fiat_result = Fiat::new(account.account_number.clone(), asset);
-        let fiat = match fiat_result {
-            Ok(fiat) => fiat,
-            Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+    for owner in request.owners.unwrap_or_default() {
+        if owner.user_id == _auth.user_id {
+            continue;
+        }
+        match user_db.get_by_id(&owner.user_id).await {
+            Ok(_) => (),
+            Err(_) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), format!("User {} not found", owner.user_id))))),
         };
-        match fiat_db.create(fiat).await {
+        match account.set_owner(owner.user_id, Some(owner.permission)) {
             Ok(_) => (),
             Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+        };
+    }
+
+    let mut session = match account_db.get_session().await {
+        Ok(session) => session,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+    };
+    let result = open_account(&mut *session, account, account_db.inner().as_ref(), fiat_db.inner().as_ref(), crypto_db.inner().as_ref(), outbox.inner().as_ref(), asset_master).await;
+    match finish(session, result).await {
+        Ok(account) => {
+            ownership.forget(&account);
+            Ok(Json(account))
         }
+        Err(e) => Err(ErrorResponse::from_store("Account", e)),
     }
+}
 
-    for key in account.accounts_crypto.keys() {
-        let asset = match asset_master.get_by_symbol(key){
-            Some(symbol) => symbol,
-            None => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "Asset not found".to_string())))),
+/// Stores `account` with one ledger per asset as part of `session`, active once they all exist.
+async fn open_account(
+    session: &mut dyn Session,
+    mut account: Account,
+    account_db: &dyn VersionedStore<Account>,
+    fiat_db: &dyn LedgerStore<Fiat>,
+    crypto_db: &dyn LedgerStore<Crypto>,
+    outbox: &dyn Crud<DomainEvent>,
+    asset_master: &AssetManager,
+) -> Result<Account, StoreError> {
+    account_db.create_in(session, account.clone()).await.map_err(StoreError::Database)?;
+    for key in account.accounts_fiat.keys() {
+        let asset = match asset_master.get_by_symbol(key) {
+            Some(asset) => asset,
+            None => return Err(StoreError::Rejected("Asset not found".to_string())),
         };
-        let crypto_result = Crypto::new(account.account_number.clone(), asset, "network".to_string(),"address".to_string());
-        let crypto = match crypto_result {
-            Ok(crypto) => crypto,
-            Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+        open_ledger(session, fiat_db, outbox, Fiat::new(account.account_number.clone(), asset)?).await?;
+    }
+    for key in account.accounts_crypto.keys() {
+        let asset = match asset_master.get_by_symbol(key) {
+            Some(asset) => asset,
+            None => return Err(StoreError::Rejected("Asset not found".to_string())),
         };
-        match crypto_db.create(crypto).await {
-            Ok(_) => (),
-            Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+        let crypto = Crypto::new(account.account_number.clone(), asset, "network".to_string(), "address".to_string())?;
+        open_ledger(session, crypto_db, outbox, crypto).await?;
+    }
+    account.active = true;
+    let account = account_db.update_versioned_in(session, &account.account_number.clone(), account).await?;
+    record(session, outbox, EventType::AccountCreated, &account).await.map_err(StoreError::Database)?;
+    Ok(account)
+}
+
+/// Stores a new `ledger` and its event as part of `session`.
+pub async fn open_ledger<T: Aggregate + Versioned + Clone + Sync>(
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    outbox: &dyn Crud<DomainEvent>,
+    ledger: T,
+) -> Result<T, StoreError> {
+    ledger_db.create_in(session, ledger.clone()).await.map_err(StoreError::Database)?;
+    record(session, outbox, EventType::LedgerCreated, &ledger).await.map_err(StoreError::Database)?;
+    Ok(ledger)
+}
+
+/// Accounts the caller owns or co-owns, whatever their permission.
+#[openapi(tag = "Accounts")]
+#[get("/accounts/owned", format = "json")]
+pub async fn get_owned_accounts(
+    account_db: &State<VersionedDb<Account>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<Account>>, (Status, Json<ErrorResponse>)> {
+    match owned_accounts(account_db.inner().as_ref(), &_auth.user_id).await {
+        Ok(accounts) => Ok(Json(accounts)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+    }
+}
+
+#[openapi(tag = "Accounts")]
+#[post("/accounts/<id>/name", format = "json", data = "<request>")]
+pub async fn rename_account(
+    id: String,
+    request: SignedJson<AccountRenameRequest>,
+    account_db: &State<VersionedDb<Account>>,
+    _auth: AuthorizedUser,
+) -> Result<ETagged<Account>, (Status, Json<ErrorResponse>)> {
+    let result = modify(account_db.inner().as_ref(), &id, |account: &mut Account| {
+        if !can_operate(_auth.clone(), account, OwnerPermission::Full) {
+            return Err("Only owners with Full permission can rename the account".to_string());
         }
+        account.name = request.name.clone();
+        Ok(())
+    }).await;
+    match result {
+        Ok(account) => Ok(ETagged::new(account)),
+        Err(e) => Err(ErrorResponse::from_store("Account", e)),
     }
+}
 
-    account.active = true;
-    match account_db.update_by_id(&account.account_number, account.clone()).await {
+/// Adds a co-owner or changes its permission.
+#[openapi(tag = "Accounts")]
+#[post("/accounts/<id>/owners", format = "json", data = "<request>")]
+pub async fn set_account_owner(
+    id: String,
+    request: SignedJson<AccountOwnerRequest>,
+    account_db: &State<VersionedDb<Account>>,
+    user_db: &State<Db<User>>,
+    outbox: &State<Db<DomainEvent>>,
+    ownership: &State<OwnershipResolver>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<ETagged<Account>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "account.owner.set", &id);
+    match user_db.get_by_id(&request.user_id).await {
         Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+        Err(_) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), format!("User {} not found", request.user_id)))))).await,
+    };
+    let request = request.0;
+    change_owners(id, request.user_id, Some(request.permission), account_db, outbox, ownership, audit_db, entry, _auth).await
+}
+
+/// Removes a co-owner, owners can always leave an account themselves.
+#[openapi(tag = "Accounts")]
+#[delete("/accounts/<id>/owners/<user_id>", format = "json")]
+pub async fn remove_account_owner(
+    id: String,
+    user_id: String,
+    account_db: &State<VersionedDb<Account>>,
+    outbox: &State<Db<DomainEvent>>,
+    ownership: &State<OwnershipResolver>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<ETagged<Account>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "account.owner.remove", &id);
+    change_owners(id, user_id, None, account_db, outbox, ownership, audit_db, entry, _auth).await
+}
+
+async fn change_owners(
+    id: String,
+    user_id: String,
+    permission: Option<OwnerPermission>,
+    account_db: &State<VersionedDb<Account>>,
+    outbox: &State<Db<DomainEvent>>,
+    ownership: &State<OwnershipResolver>,
+    audit_db: &State<Db<AuditEntry>>,
+    entry: AuditEntry,
+    _auth: AuthorizedUser,
+) -> Result<ETagged<Account>, (Status, Json<ErrorResponse>)> {
+    let before = match account_db.get_by_id(&id).await {
+        Ok(account) => account,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e))))).await,
+    };
+    let leaving = permission.is_none() && user_id == _auth.user_id;
+    if !leaving && !can_operate(_auth.clone(), &before, OwnerPermission::Full) {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "Only owners with Full permission can manage the owners".to_string()))));
     }
-    Ok(Json(account))
+    let mut session = match account_db.get_session().await {
+        Ok(session) => session,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e))))).await,
+    };
+    let result = modify_in(account_db.inner().as_ref(), &mut *session, &id, |account: &mut Account| {
+        if !account.is_open() {
+            return Err("Account is closed".to_string());
+        }
+        account.set_owner(user_id.clone(), permission.clone())
+    }).await;
+    let result = match result {
+        Ok(account) => match record(&mut *session, outbox.inner().as_ref(), EventType::AccountOwnersChanged, &account).await {
+            Ok(_) => Ok(account),
+            Err(e) => Err(StoreError::Database(e)),
+        },
+        Err(e) => Err(e),
+    };
+    let result = match finish(session, result).await {
+        Ok(account) => {
+            ownership.forget(&before);
+            ownership.forget(&account);
+            Ok(ETagged::new(account))
+        }
+        Err(e) => Err(ErrorResponse::from_store("Account", e)),
+    };
+    audit::changed(audit_db.inner().as_ref(), entry, &before, result).await
 }
 
 #[openapi(tag = "Accounts")]
 #[get("/accounts?<skip>&<limit>", format = "json")]
 pub async fn get_accounts(
-    account_db: &State<Repository<Account>>,
+    account_db: &State<VersionedDb<Account>>,
     skip: Option<usize>,
     limit: Option<usize>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<AccountsRead>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Pagination<Account>>, (Status, Json<ErrorResponse>)> {
-    if !only_admin(_auth){
-        return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "Only admin can get all accounts".to_string()))));
-    };
+    let entry = audit::entry(&context, Some(&_auth), "account.list", "accounts");
     let skip_value = skip.unwrap_or(0);
     let limit_value = limit.unwrap_or(10);
     let accounts = match account_db.get_all(skip_value, limit_value).await {
         Ok(accounts) => accounts,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e))))).await,
     };
     let pagination = Pagination{
         skip: skip_value as u64,
@@ -86,78 +252,108 @@ pub async fn get_accounts(
         count: account_db.count().await,
         result: accounts,
     };
-    Ok(Json(pagination))
+    audit::outcome(audit_db.inner().as_ref(), entry, Ok(Json(pagination))).await
 }
 
 #[openapi(tag = "Accounts")]
 #[get("/accounts/<id>", format = "json")]
 pub async fn get_account(
-    account_db: &State<Repository<Account>>,
+    account_db: &State<VersionedDb<Account>>,
     id: String,
     _auth: AuthorizedUser,
-) -> Result<Json<Account>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+) -> Result<ETagged<Account>, (Status, Json<ErrorResponse>)> {
+    if !can_access(_auth, &id, GrantScope::ViewBalances) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     let account = match account_db.get_by_id(&id).await {
         Ok(account) => account,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
     };
-    Ok(Json(account))
+    Ok(ETagged::new(account))
 }
 
 #[openapi(tag = "Accounts")]
 #[get("/accounts/<id>/disable", format = "json")]
 pub async fn disable_account(
-    account_db: &State<Repository<Account>>,
+    account_db: &State<VersionedDb<Account>>,
     id: String,
+    if_match: IfMatch,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<AccountsFreeze>,
     _auth: AuthorizedUser,
-) -> Result<Json<Account>, (Status, Json<ErrorResponse>)> {
-    if !only_admin(_auth){
-        return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "Only admin can get all accounts".to_string()))));
-    };
+) -> Result<ETagged<Account>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "account.disable", &id);
     let mut account = match account_db.get_by_id(&id).await {
         Ok(account) => account,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e))))).await,
     };
-    account.active = false;
-    match account_db.update_by_id(&id, account.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+    if !if_match.matches(account.version) {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::PreconditionFailed, Json(ErrorResponse::new("Account".to_string(), "Account was modified since it was read".to_string()))))).await;
+    };
+    let before = account.clone();
+    let result = match if_match.version {
+        Some(_) => {
+            account.active = false;
+            account_db.update_versioned(&id, account).await
+        }
+        None => modify(account_db.inner().as_ref(), &id, |account: &mut Account| {
+            account.active = false;
+            Ok(())
+        }).await,
     };
-    Ok(Json(account))
+    let result = match result {
+        Ok(account) => Ok(ETagged::new(account)),
+        Err(e) => Err(ErrorResponse::from_store("Account", e)),
+    };
+    audit::changed(audit_db.inner().as_ref(), entry, &before, result).await
 }
 
 #[openapi(tag = "Accounts")]
 #[get("/accounts/<id>/enable", format = "json")]
 pub async fn enable_account(
-    account_db: &State<Repository<Account>>,
+    account_db: &State<VersionedDb<Account>>,
     id: String,
+    if_match: IfMatch,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<AccountsFreeze>,
     _auth: AuthorizedUser,
-) -> Result<Json<Account>, (Status, Json<ErrorResponse>)> {
-    if !only_admin(_auth){
-        return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "Only admin can get all accounts".to_string()))));
-    };
+) -> Result<ETagged<Account>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "account.enable", &id);
     let mut account = match account_db.get_by_id(&id).await {
         Ok(account) => account,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e))))).await,
     };
-    account.active = true;
-    match account_db.update_by_id(&id, account.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e)))),
+    if !if_match.matches(account.version) {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::PreconditionFailed, Json(ErrorResponse::new("Account".to_string(), "Account was modified since it was read".to_string()))))).await;
+    };
+    let before = account.clone();
+    let result = match if_match.version {
+        Some(_) => {
+            account.active = true;
+            account_db.update_versioned(&id, account).await
+        }
+        None => modify(account_db.inner().as_ref(), &id, |account: &mut Account| {
+            account.active = true;
+            Ok(())
+        }).await,
+    };
+    let result = match result {
+        Ok(account) => Ok(ETagged::new(account)),
+        Err(e) => Err(ErrorResponse::from_store("Account", e)),
     };
-    Ok(Json(account))
+    audit::changed(audit_db.inner().as_ref(), entry, &before, result).await
 }
 
 #[openapi(tag = "Accounts")]
 #[get("/accounts/<id>/fiats", format = "json")]
 pub async fn get_fiats(
     id: String,
-    fiat_db: &State<Repository<Fiat>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Vec<Fiat>>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_access(_auth, &id, GrantScope::ViewBalances) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match fiat_db.get_by_fields(vec!["account_number".to_string()],vec![id]).await {
@@ -170,10 +366,10 @@ pub async fn get_fiats(
 #[get("/accounts/<id>/cryptos", format = "json")]
 pub async fn get_cryptos(
     id: String,
-    crypto_db: &State<Repository<Crypto>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Vec<Crypto>>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_access(_auth, &id, GrantScope::ViewBalances) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match crypto_db.get_by_fields(vec!["account_number".to_string()],vec![id]).await {
@@ -186,12 +382,12 @@ pub async fn get_cryptos(
 #[get("/accounts/<id>/balances", format = "json")]
 pub async fn balances(
     id: String,
-    crypto_db: &State<Repository<Crypto>>,
-    fiat_db: &State<Repository<Fiat>>,
-    account_db: &State<Repository<Account>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    account_db: &State<VersionedDb<Account>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<HashMap<String,Balance>>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_access(_auth, &id, GrantScope::ViewBalances) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match account_db.get_by_id(&id).await {
@@ -210,4 +406,22 @@ pub async fn balances(
     };
 
     Ok(Json(Account::balance(fiats, cryptos)))
-}
\ No newline at end of file
+}
+
+#[openapi(tag = "Accounts")]
+#[get("/accounts/<id>/transactions?<filter..>", format = "json")]
+pub async fn get_account_transactions(
+    id: String,
+    filter: TransactionFilter,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<CursorPagination<Transaction>>, (Status, Json<ErrorResponse>)> {
+    if !can_access(_auth, &id, GrantScope::ViewTransactions) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
+    };
+    let filter = TransactionFilter { account: Some(id), ..filter };
+    match query_transactions(transaction_db.inner().as_ref(), &filter).await {
+        Ok(page) => Ok(Json(page)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Transaction".to_string(), e)))),
+    }
+}
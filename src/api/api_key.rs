@@ -0,0 +1,209 @@
+use mongodb::bson::{doc, Bson};
+use revolt_rocket_okapi::openapi;
+use rocket::{delete, get, http::Status, post, serde::json::Json, State};
+
+use crate::{
+    domain::{
+        api_key::ApiKey,
+        audit::AuditEntry,
+        user::{Permission, User},
+    },
+    dto::api_key::ApiKeyRequest,
+    fairings::{api_key::SignedJson, auth::AuthorizedUser, request_context::RequestContext},
+    mongo::Db,
+    response::error::ErrorResponse,
+    security::{audit, ownership::OwnershipResolver},
+};
+
+/// Creates a key, the only time its secret is shown besides rotations.
+#[openapi(tag = "ApiKeys")]
+#[post("/api-keys", format = "json", data = "<request>")]
+pub async fn create_api_key(
+    request: SignedJson<ApiKeyRequest>,
+    user_db: &State<Db<User>>,
+    key_db: &State<Db<ApiKey>>,
+    ownership: &State<OwnershipResolver>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<ApiKey>, (Status, Json<ErrorResponse>)> {
+    let mut entry = audit::entry(&context, Some(&_auth), "api_key.create", "api-keys");
+    if _auth.api_key_id.is_some() {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
+        return Err(signed_with_key());
+    }
+    let user_id = request.user_id.clone().unwrap_or(_auth.user_id.clone());
+    if user_id != _auth.user_id && !_auth.has(Permission::UsersManage) {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
+        return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), "Only users:manage can create keys for other users".to_string()))));
+    }
+    let result = issue(request.0, user_id, user_db, key_db, ownership, _auth).await;
+    if let Ok(key) = &result {
+        entry.resource = format!("api-keys/{}", key.id);
+    }
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn issue(
+    request: ApiKeyRequest,
+    user_id: String,
+    user_db: &State<Db<User>>,
+    key_db: &State<Db<ApiKey>>,
+    ownership: &State<OwnershipResolver>,
+    _auth: AuthorizedUser,
+) -> Result<Json<ApiKey>, (Status, Json<ErrorResponse>)> {
+    let user = match user_db.get_by_id(&user_id).await {
+        Ok(user) => user,
+        Err(_) => return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), format!("User {} not found", user_id))))),
+    };
+    let scopes = request.scopes.unwrap_or_default();
+    let role_permissions = user.role.permissions();
+    if let Some(scope) = scopes.iter().find(|scope| !role_permissions.contains(scope)) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), format!("The role of the user does not have {}", scope)))));
+    }
+    // nobody hands out a permission they do not hold themselves
+    if let Some(scope) = scopes.iter().find(|scope| !_auth.has(**scope)) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), format!("You do not have {}", scope)))));
+    }
+    let accounts = request.accounts.unwrap_or_default();
+    let owned = ownership.accounts_of(&user.id).await;
+    if let Some(account) = accounts.iter().find(|account| !owned.contains(account)) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), format!("The user does not own account {}", account)))));
+    }
+    let key = match ApiKey::new(
+        request.name,
+        user.id,
+        scopes,
+        accounts,
+        request.ip_allow_list.unwrap_or_default(),
+        request.expires_at,
+        _auth.user_id,
+    ) {
+        Ok(key) => key,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), e)))),
+    };
+    match key_db.create(key.clone()).await {
+        Ok(_) => Ok(Json(key)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), e)))),
+    }
+}
+
+#[openapi(tag = "ApiKeys")]
+#[get("/api-keys?<user_id>", format = "json")]
+pub async fn get_api_keys(
+    user_id: Option<String>,
+    key_db: &State<Db<ApiKey>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<ApiKey>>, (Status, Json<ErrorResponse>)> {
+    let user_id = user_id.unwrap_or(_auth.user_id.clone());
+    if user_id != _auth.user_id && !_auth.has(Permission::UsersManage) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), "You can only get your own keys".to_string()))));
+    }
+    match key_db.find(doc! {"user_id": &user_id}, doc! {"created_at": -1}, 0).await {
+        Ok(keys) => Ok(Json(keys.iter().map(|key| key.redacted()).collect())),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), e)))),
+    }
+}
+
+/// New secret for the key, the old one stops working at once.
+#[openapi(tag = "ApiKeys")]
+#[post("/api-keys/<id>/rotate", format = "json")]
+pub async fn rotate_api_key(
+    id: String,
+    key_db: &State<Db<ApiKey>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<ApiKey>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "api_key.rotate", &format!("api-keys/{}", id));
+    if _auth.api_key_id.is_some() {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
+        return Err(signed_with_key());
+    }
+    let before = match managed_key(key_db, &id, _auth).await {
+        Ok(key) => key,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err(e)).await,
+    };
+    if !before.is_active() {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), "API key is revoked or expired".to_string()))))).await;
+    }
+    let mut key = before.clone();
+    key.rotate_secret();
+    let (entry, result) = match key_db.update_by_id(&id, key).await {
+        Ok(key) => (entry.changes(&before, &key), Ok(Json(key))),
+        Err(e) => (entry, Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), e))))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+#[openapi(tag = "ApiKeys")]
+#[delete("/api-keys/<id>", format = "json")]
+pub async fn revoke_api_key(
+    id: String,
+    key_db: &State<Db<ApiKey>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<ApiKey>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "api_key.revoke", &format!("api-keys/{}", id));
+    if _auth.api_key_id.is_some() {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
+        return Err(signed_with_key());
+    }
+    let before = match managed_key(key_db, &id, _auth).await {
+        Ok(key) => key,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err(e)).await,
+    };
+    let mut key = before.clone();
+    if let Err(e) = key.revoke() {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), e))))).await;
+    }
+    let (entry, result) = match key_db.update_by_id(&id, key).await {
+        Ok(key) => (entry.changes(&before, &key), Ok(Json(key.redacted()))),
+        Err(e) => (entry, Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), e))))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+/// The key when it belongs to the caller, or the caller manages users.
+async fn managed_key(
+    key_db: &State<Db<ApiKey>>,
+    id: &str,
+    auth: AuthorizedUser,
+) -> Result<ApiKey, (Status, Json<ErrorResponse>)> {
+    let key = match key_db.get_by_id(id).await {
+        Ok(key) => key,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), e)))),
+    };
+    if key.user_id != auth.user_id && !auth.has(Permission::UsersManage) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), "You can only manage your own keys".to_string()))));
+    }
+    Ok(key)
+}
+
+/// Revokes every key of the user still active, as when its password changes.
+pub async fn revoke_all_keys(
+    key_db: &State<Db<ApiKey>>,
+    user_id: &str,
+) -> Result<Vec<ApiKey>, (Status, Json<ErrorResponse>)> {
+    let keys = match key_db.find(doc! {"user_id": user_id, "revoked_at": Bson::Null}, doc! {}, 0).await {
+        Ok(keys) => keys,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), e)))),
+    };
+    let mut revoked = Vec::new();
+    for mut key in keys.into_iter().filter(|key| key.is_active()) {
+        if key.revoke().is_err() {
+            continue;
+        }
+        match key_db.update_by_id(&key.id.clone(), key).await {
+            Ok(key) => revoked.push(key.redacted()),
+            Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), e)))),
+        }
+    }
+    Ok(revoked)
+}
+
+/// Keys are managed with a login, a key cannot mint or rotate keys, itself included.
+fn signed_with_key() -> (Status, Json<ErrorResponse>) {
+    (Status::BadRequest, Json(ErrorResponse::new("ApiKey".to_string(), "API keys cannot be managed with an API key".to_string())))
+}
@@ -0,0 +1,85 @@
+use mongodb::bson::doc;
+use revolt_rocket_okapi::openapi;
+use rocket::{get, http::{ContentType, Status}, serde::json::Json, State};
+
+use crate::{
+    domain::audit::AuditEntry,
+    dto::audit::AuditFilter,
+    export::csv::audit_csv,
+    fairings::{auth::{AuthorizedUser, Permitted, ReportsRead}, request_context::RequestContext},
+    mongo::{Crud, Db},
+    response::{custom::Download, error::ErrorResponse},
+    security::audit,
+};
+
+#[openapi(tag = "Audit")]
+#[get("/audit?<filter..>", format = "json")]
+pub async fn search_audit_log(
+    filter: AuditFilter,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<ReportsRead>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<AuditEntry>>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "audit.search", "audit");
+    let result = match query_audit_log(audit_db.inner().as_ref(), &filter, 50).await {
+        Ok(entries) => Ok(Json(entries)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Audit".to_string(), e)))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+#[openapi(tag = "Audit")]
+#[get("/audit/export?<format>&<filter..>")]
+pub async fn export_audit_log(
+    format: Option<String>,
+    filter: AuditFilter,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<ReportsRead>,
+    _auth: AuthorizedUser,
+) -> Result<Download, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "audit.export", "audit");
+    let result = match query_audit_log(audit_db.inner().as_ref(), &filter, 10_000).await {
+        Ok(entries) => render_export(&entries, format.as_deref().unwrap_or("csv")),
+        Err(e) => Err(e),
+    };
+    let result = match result {
+        Ok(download) => Ok(download),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Audit".to_string(), e)))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+/// Newest first.
+async fn query_audit_log(audit_db: &dyn Crud<AuditEntry>, filter: &AuditFilter, default_limit: usize) -> Result<Vec<AuditEntry>, String> {
+    audit_db
+        .find(filter.to_filter()?, doc! {"occurred_at": -1, "id": -1}, filter.page_size(default_limit))
+        .await
+}
+
+fn render_export(entries: &[AuditEntry], format: &str) -> Result<Download, String> {
+    match format {
+        "csv" => Ok(Download {
+            content_type: ContentType::CSV,
+            filename: "audit_log.csv".to_string(),
+            body: audit_csv(entries),
+        }),
+        "jsonl" => {
+            let mut body = String::new();
+            for entry in entries {
+                match serde_json::to_string(entry) {
+                    Ok(line) => body.push_str(&line),
+                    Err(e) => return Err(format!("Error rendering audit log: {}", e)),
+                }
+                body.push('\n');
+            }
+            Ok(Download {
+                content_type: ContentType::new("application", "x-ndjson"),
+                filename: "audit_log.jsonl".to_string(),
+                body,
+            })
+        }
+        other => Err(format!("Invalid export format: {}", other)),
+    }
+}
@@ -1,22 +1,51 @@
+use mongodb::bson::{doc, Bson};
 use revolt_rocket_okapi::openapi;
-use rocket::{http::Status, post, serde::json::Json, State};
+use jsonwebtoken::jwk::JwkSet;
+use rocket::{delete, get, http::Status, post, serde::json::Json, State};
 use uuid::Uuid;
 
 use crate::{
     domain::{
-        account::Account,
+        audit::{AuditEntry, AuditOutcome},
+        session::{SessionPublic, UserSession},
         user::{Role, User, UserPublic},
     },
     dto::user::{LoginRequest, RefreshToken, Token, UserRegisterRequest},
-    mongo::{Crud, Repository},
+    fairings::{auth::AuthorizedUser, request_context::RequestContext},
+    mongo::{modify, Db, StoreError, VersionedDb},
     response::error::ErrorResponse,
-    security::jwt::{decode_jwt_return_id, encode_token, encode_token_by_refresh, hash_text},
+    security::{
+        audit,
+        jwt::{
+            check_password, decode_refresh_token, encode_session_tokens, get_jwt_refresh_expiration,
+            get_session_max_lifetime, hash_text,
+        },
+        keys::KeyRing,
+        password::PasswordPolicy,
+    },
 };
 
 #[openapi(tag = "Auths")]
 #[post("/auths/register", format = "json", data = "<new_user>")]
 pub async fn register(
-    db: &State<Repository<User>>,
+    db: &State<Db<User>>,
+    policy: &State<PasswordPolicy>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    new_user: Json<UserRegisterRequest>,
+) -> Result<Json<UserPublic>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, None, "auth.register", &new_user.email);
+    let result = create_user(db, policy, new_user).await;
+    let entry = match &result {
+        Ok(user) => entry.by(user.id.clone(), user.role.to_string()),
+        Err(_) => entry,
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn create_user(
+    db: &State<Db<User>>,
+    policy: &State<PasswordPolicy>,
     new_user: Json<UserRegisterRequest>,
 ) -> Result<Json<UserPublic>, (Status, Json<ErrorResponse>)> {
     let mut data = User {
@@ -42,7 +71,10 @@ pub async fn register(
         ));
     }
 
-    let hash_password = match hash_text(data.password.clone(), 4) {
+    if let Err(e) = policy.check(&data.password, &data.email) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Invalid password".to_string(), e))));
+    }
+    let hash_password = match hash_text(data.password.clone(), policy.hash_cost) {
         Ok(hash) => hash,
         Err(_) => {
             return Err((
@@ -68,45 +100,72 @@ pub async fn register(
 #[openapi(tag = "Auths")]
 #[post("/auths/login", format = "json", data = "<option_login_request>")]
 pub async fn login(
-    db: &State<Repository<User>>,
-    resource_db: &State<Repository<Account>>,
+    db: &State<Db<User>>,
+    session_db: &State<VersionedDb<UserSession>>,
+    keys: &State<KeyRing>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
     option_login_request: Option<Json<LoginRequest>>,
 ) -> Result<Json<Token>, (Status, Json<ErrorResponse>)> {
     let login_request = match option_login_request {
         Some(login_request) => login_request,
         None => {
-            return Err((
-                Status::BadRequest,
-                Json(ErrorResponse::new(
-                    "Invalid request".to_string(),
-                    "Invalid request".to_string(),
+            let entry = audit::entry(&context, None, "auth.login", "");
+            return audit::outcome(
+                audit_db.inner().as_ref(),
+                entry,
+                Err((
+                    Status::BadRequest,
+                    Json(ErrorResponse::new(
+                        "Invalid request".to_string(),
+                        "Invalid request".to_string(),
+                    )),
                 )),
-            ))
+            )
+            .await;
         }
     };
+    let entry = audit::entry(&context, None, "auth.login", &login_request.email);
     let user = db
         .get_by_fields(vec!["email".to_string()], vec![login_request.email.clone()])
         .await
         .unwrap_or(vec![]);
     if user.len() != 1 {
-        return Err((
-            Status::InternalServerError,
-            Json(ErrorResponse::new(
-                "Error getting user".to_string(),
-                "Error getting user".to_string(),
+        return audit::outcome(
+            audit_db.inner().as_ref(),
+            entry,
+            Err((
+                Status::InternalServerError,
+                Json(ErrorResponse::new(
+                    "Error getting user".to_string(),
+                    "Error getting user".to_string(),
+                )),
             )),
-        ));
+        )
+        .await;
     }
-    let resources: Vec<String> = resource_db
-        .get_by_fields(vec!["user_owner_id".to_string()], vec![user[0].id.clone()])
-        .await
-        .unwrap_or(vec![])
-        .iter()
-        .map(|acc| acc.account_number.clone())
-        .collect();
-    encode_token(&user[0], resources, login_request.password.clone()).await
+    let entry = entry.by(user[0].id.clone(), user[0].role.to_string());
+    if let Err(e) = check_password(&user[0], login_request.password.clone()) {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err(e)).await;
+    }
+    let session = UserSession::new(
+        user[0].id.clone(),
+        context.user_agent.clone(),
+        context.ip.clone(),
+        *get_jwt_refresh_expiration().await,
+    );
+    let result = match session_db.create(session.clone()).await {
+        Ok(_) => encode_session_tokens(&user[0], &session, keys).await,
+        Err(e) => Err((
+            Status::InternalServerError,
+            Json(ErrorResponse::new("Error creating session".to_string(), e)),
+        )),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
 }
 
+/// Trades a refresh token for a new pair. Each refresh token works once: presenting one that was
+/// already rotated revokes its whole session.
 #[openapi(tag = "Auths")]
 #[post(
     "/auths/refresh-token",
@@ -114,57 +173,210 @@ pub async fn login(
     data = "<option_refresh_token>"
 )]
 pub async fn refresh_tokens(
-    database: &State<Repository<User>>,
-    resource_db: &State<Repository<Account>>,
+    database: &State<Db<User>>,
+    session_db: &State<VersionedDb<UserSession>>,
+    keys: &State<KeyRing>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
     option_refresh_token: Option<Json<RefreshToken>>,
 ) -> Result<Json<Token>, (Status, Json<ErrorResponse>)> {
-    match option_refresh_token {
-        Some(refresh_token) => match decode_jwt_return_id(refresh_token).await {
-            Ok(id) => {
-                let user = match database.get_by_id(&id).await {
-                    Ok(user) => user,
-                    Err(_) => {
-                        return Err((
-                            Status::Unauthorized,
-                            Json(ErrorResponse::new(
-                                "Error getting user".to_string(),
-                                "Error getting user".to_string(),
-                            )),
-                        ))
-                    }
-                };
-                let resources: Vec<String> = resource_db
-                    .get_by_fields(vec!["user_owner_id".to_string()], vec![user.id.clone()])
-                    .await
-                    .unwrap_or(vec![])
-                    .iter()
-                    .map(|acc| acc.account_number.clone())
-                    .collect();
-                match encode_token_by_refresh(&user, resources).await {
-                    Ok(token) => Ok(token),
-                    Err(_) => Err((
-                        Status::Unauthorized,
-                        Json(ErrorResponse::new(
-                            "Error encoding token".to_string(),
-                            "Error encoding token".to_string(),
-                        )),
-                    )),
-                }
+    let mut entry = audit::entry(&context, None, "auth.refresh", "");
+    let claims = match option_refresh_token {
+        Some(refresh_token) => match decode_refresh_token(refresh_token).await {
+            Ok(claims) => claims,
+            Err(_) => {
+                return audit::outcome(audit_db.inner().as_ref(), entry, Err(unauthorized("Error decoding token"))).await
             }
-            Err(_) => Err((
-                Status::Unauthorized,
-                Json(ErrorResponse::new(
-                    "Error decoding token".to_string(),
-                    "Error decoding token".to_string(),
-                )),
-            )),
         },
+        None => {
+            return audit::outcome(
+                audit_db.inner().as_ref(),
+                entry,
+                Err((
+                    Status::BadRequest,
+                    Json(ErrorResponse::new(
+                        "Invalid request".to_string(),
+                        "Invalid request".to_string(),
+                    )),
+                )),
+            )
+            .await
+        }
+    };
+    let user = match database.get_by_id(&claims.user_id).await {
+        Ok(user) => user,
+        Err(_) => {
+            return audit::outcome(audit_db.inner().as_ref(), entry, Err(unauthorized("Error getting user"))).await
+        }
+    };
+    entry = entry.by(user.id.clone(), user.role.to_string());
+    entry.resource = format!("sessions/{}", claims.sid);
+    let session = match session_db.get_by_id(&claims.sid).await {
+        Ok(session) if session.user_id == user.id => session,
+        // tokens from before sessions were stored have none, their users log in again
+        _ => return audit::outcome(audit_db.inner().as_ref(), entry, Err(unauthorized("Session not found"))).await,
+    };
+    if !session.is_active() {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err(unauthorized("Session is revoked or expired"))).await;
+    }
+    if session.refresh_token_id != claims.jti {
+        return refresh_token_reused(session_db, audit_db, entry, &session.id).await;
+    }
+    let mut rotated = session.clone();
+    if let Err(e) = rotated.rotate(context.ip.clone(), *get_jwt_refresh_expiration().await, *get_session_max_lifetime().await) {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err(unauthorized(&e))).await;
+    }
+    let result = match session_db.update_versioned(&session.id, rotated).await {
+        Ok(rotated) => encode_session_tokens(&user, &rotated, keys).await,
+        // another refresh rotated the same token first
+        Err(StoreError::Conflict { .. }) => return refresh_token_reused(session_db, audit_db, entry, &session.id).await,
+        Err(e) => Err(ErrorResponse::from_store("Error rotating session", e)),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+/// Revokes the session a replayed refresh token belongs to, whoever holds its latest token.
+async fn refresh_token_reused(
+    session_db: &State<VersionedDb<UserSession>>,
+    audit_db: &State<Db<AuditEntry>>,
+    entry: AuditEntry,
+    session_id: &str,
+) -> Result<Json<Token>, (Status, Json<ErrorResponse>)> {
+    let _ = modify(session_db.inner().as_ref(), session_id, |session: &mut UserSession| {
+        match session.revoked_at {
+            Some(_) => Ok(()),
+            None => session.revoke("refresh token reuse"),
+        }
+    })
+    .await;
+    let entry = entry.outcome(
+        AuditOutcome::Denied,
+        Some("Refresh token was already used, session revoked".to_string()),
+    );
+    audit::log(audit_db.inner().as_ref(), entry).await;
+    Err(unauthorized("Refresh token was already used"))
+}
+
+fn unauthorized(message: &str) -> (Status, Json<ErrorResponse>) {
+    (
+        Status::Unauthorized,
+        Json(ErrorResponse::new(message.to_string(), message.to_string())),
+    )
+}
+
+/// Ends the session of the calling token, its refresh and access tokens stop working.
+#[openapi(tag = "Auths")]
+#[post("/auths/logout", format = "json")]
+pub async fn logout(
+    session_db: &State<VersionedDb<UserSession>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<SessionPublic>, (Status, Json<ErrorResponse>)> {
+    let session_id = _auth.session_id.clone().unwrap_or_default();
+    let entry = audit::entry(&context, Some(&_auth), "auth.logout", &format!("sessions/{}", session_id));
+    let result = match _auth.session_id.clone() {
+        Some(session_id) => end_session(session_db, &session_id, &_auth.user_id, "logout").await,
         None => Err((
             Status::BadRequest,
-            Json(ErrorResponse::new(
-                "Invalid request".to_string(),
-                "Invalid request".to_string(),
-            )),
+            Json(ErrorResponse::new("Session".to_string(), "Token is not bound to a session".to_string())),
         )),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+/// Ends every active session of the caller, including the current one.
+#[openapi(tag = "Auths")]
+#[post("/auths/logout-all", format = "json")]
+pub async fn logout_all(
+    session_db: &State<VersionedDb<UserSession>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<SessionPublic>>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "auth.logout_all", &_auth.user_id);
+    let result = end_all_sessions(session_db, &_auth.user_id, "logout all").await;
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+/// Ends every active session of `user_id`, e.g. once its password changed.
+pub async fn end_all_sessions(
+    session_db: &State<VersionedDb<UserSession>>,
+    user_id: &str,
+    reason: &str,
+) -> Result<Json<Vec<SessionPublic>>, (Status, Json<ErrorResponse>)> {
+    let mut ended = Vec::new();
+    for session in active_sessions(session_db, user_id).await? {
+        ended.push(end_session(session_db, &session.id, user_id, reason).await?.0);
     }
+    Ok(Json(ended))
+}
+
+#[openapi(tag = "Auths")]
+#[get("/auths/sessions", format = "json")]
+pub async fn get_sessions(
+    session_db: &State<VersionedDb<UserSession>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<SessionPublic>>, (Status, Json<ErrorResponse>)> {
+    match active_sessions(session_db, &_auth.user_id).await {
+        Ok(sessions) => Ok(Json(
+            sessions.iter().map(|session| session.to_response(_auth.session_id.as_deref())).collect(),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// Ends one of the caller's sessions, e.g. a lost device.
+#[openapi(tag = "Auths")]
+#[delete("/auths/sessions/<id>", format = "json")]
+pub async fn revoke_session(
+    id: String,
+    session_db: &State<VersionedDb<UserSession>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<SessionPublic>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "auth.session_revoke", &format!("sessions/{}", id));
+    let result = end_session(session_db, &id, &_auth.user_id, "revoked").await;
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn active_sessions(
+    session_db: &State<VersionedDb<UserSession>>,
+    user_id: &str,
+) -> Result<Vec<UserSession>, (Status, Json<ErrorResponse>)> {
+    match session_db
+        .find(doc! {"user_id": user_id, "revoked_at": Bson::Null}, doc! {"last_used_at": -1}, 0)
+        .await
+    {
+        Ok(sessions) => Ok(sessions.into_iter().filter(|session| session.is_active()).collect()),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Session".to_string(), e)))),
+    }
+}
+
+async fn end_session(
+    session_db: &State<VersionedDb<UserSession>>,
+    session_id: &str,
+    user_id: &str,
+    reason: &str,
+) -> Result<Json<SessionPublic>, (Status, Json<ErrorResponse>)> {
+    match session_db.get_by_id(session_id).await {
+        Ok(session) if session.user_id == user_id => (),
+        _ => {
+            return Err((
+                Status::BadRequest,
+                Json(ErrorResponse::new("Session".to_string(), format!("Session {} not found", session_id))),
+            ))
+        }
+    };
+    match modify(session_db.inner().as_ref(), session_id, |session: &mut UserSession| session.revoke(reason)).await {
+        Ok(session) => Ok(Json(session.to_response(None))),
+        Err(e) => Err(ErrorResponse::from_store("Session", e)),
+    }
+}
+
+/// Public keys access tokens are signed with, for services that only verify them.
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(keys: &State<KeyRing>) -> Json<JwkSet> {
+    Json(keys.jwks())
 }
@@ -0,0 +1,367 @@
+use chrono::Utc;
+use mongodb::bson::doc;
+use revolt_rocket_okapi::openapi;
+use rocket::{http::Status, post, serde::{json::Json, DeserializeOwned}, State};
+use serde::Serialize;
+
+use crate::{
+    api::{account::open_ledger as create_ledger, fiat::cancel_deposit, statement::run_statement_job, transaction::{cancel_tx, complete_tx, confirm_tx, process_tx}},
+    domain::{
+        account::{Account, AccountClosure, AccountStatus, OwnerPermission},
+        asset::{Asset, AssetManager, AssetType},
+        audit::AuditEntry,
+        event::{Aggregate, DomainEvent, EventType},
+        ledger::{Accounting, Crypto, Fiat, FungibleTradeable},
+        statement::{StatementFormat, StatementJob},
+        transaction::{Transaction, TransactionStatus, TransactionType},
+    },
+    dto::account::ClosureRequest,
+    events::record,
+    fairings::{api_key::SignedJson, auth::AuthorizedUser, request_context::RequestContext},
+    mongo::{finish, modify_in, Crud, Db, LedgerDb, LedgerOp, LedgerStore, Session, StoreError, Versioned, VersionedDb, VersionedStore},
+    response::{custom::ETagged, error::ErrorResponse},
+    security::{audit, permissions::{can_continue, can_operate}},
+};
+
+/// Start of the final statement, early enough to cover the whole life of any account.
+const FINAL_STATEMENT_FROM: &str = "1970-01-01T00:00:00+00:00";
+
+/// Closes the account as far as it can go now. While transactions are still in flight the
+/// account stays `Closing` with them in `closure.waiting_for`, calling again picks up from there.
+#[openapi(tag = "Accounts")]
+#[post("/accounts/<id>/close", format = "json", data = "<request>")]
+pub async fn close_account(
+    id: String,
+    request: SignedJson<ClosureRequest>,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    job_db: &State<Db<StatementJob>>,
+    outbox: &State<Db<DomainEvent>>,
+    asset_master: &State<AssetManager>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<ETagged<Account>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "account.close", &id);
+    if !can_continue(_auth.clone(), &id) {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only close your own account".to_string()))));
+    }
+    let account = match account_db.get_by_id(&id).await {
+        Ok(account) => account,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), e))))).await,
+    };
+    if !can_operate(_auth.clone(), &account, OwnerPermission::Full) {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "Only owners with Full permission can close the account".to_string()))));
+    }
+    let result = match close(account.clone(), request.0, &_auth.user_id, account_db, transaction_db, fiat_db, crypto_db, job_db, outbox, asset_master).await {
+        Ok(account) => Ok(ETagged::new(account)),
+        Err(e) => Err(ErrorResponse::from_store("Account", e)),
+    };
+    audit::changed(audit_db.inner().as_ref(), entry, &account, result).await
+}
+
+async fn close(
+    account: Account,
+    request: ClosureRequest,
+    requested_by: &str,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    job_db: &State<Db<StatementJob>>,
+    outbox: &State<Db<DomainEvent>>,
+    asset_master: &State<AssetManager>,
+) -> Result<Account, StoreError> {
+    let id = account.account_number.clone();
+    let mut closure = match (&account.status, account.closure.clone()) {
+        (AccountStatus::Closed, _) => return Err(StoreError::Rejected("Account is already closed".to_string())),
+        (AccountStatus::Closing, Some(closure)) => closure,
+        _ => AccountClosure::new(requested_by.to_string(), None),
+    };
+    if request.destination.is_some() {
+        closure.destination = request.destination.clone();
+    }
+    if let Some(destination) = &closure.destination {
+        if destination == &id {
+            return Err(StoreError::Rejected("Balances cannot be swept into the account being closed".to_string()));
+        }
+        match account_db.get_by_id(destination).await {
+            Ok(destination) if !destination.is_open() => {
+                return Err(StoreError::Rejected(format!("Destination account {} is closed", destination.account_number)))
+            }
+            Ok(_) => (),
+            Err(e) => return Err(StoreError::Database(e)),
+        };
+    }
+
+    // blocked before looking at what is in flight, so nothing new can start meanwhile
+    let mut account = account;
+    if account.status == AccountStatus::Open {
+        account.status = AccountStatus::Closing;
+        account.closure = Some(closure.clone());
+        let mut session = account_db.get_session().await.map_err(StoreError::Database)?;
+        let result = mark_account(&mut *session, account_db.inner().as_ref(), outbox.inner().as_ref(), account, EventType::AccountClosing).await;
+        account = finish(session, result).await?;
+    }
+
+    let in_flight = transaction_db
+        .find(
+            doc! {
+                "transaction_status": {"$in": ["Pending", "Confirmed"]},
+                "$or": [{"from_wallet": &id}, {"to_wallet": &id}],
+            },
+            doc! {"timestamp": 1, "tx_id": 1},
+            0,
+        )
+        .await
+        .map_err(StoreError::Database)?;
+    closure.waiting_for = Vec::new();
+    for tx in in_flight {
+        if !request.cancel_pending.unwrap_or(false) || tx.transaction_status != TransactionStatus::Pending {
+            closure.waiting_for.push(tx.tx_id);
+            continue;
+        }
+        let tx_id = tx.tx_id.clone();
+        match cancel_pending(tx, transaction_db, fiat_db, crypto_db, outbox, asset_master).await {
+            Ok(_) => closure.cancelled.push(tx_id),
+            // it moved on while the closure ran, wait for it like the confirmed ones
+            Err(StoreError::Conflict { .. }) => closure.waiting_for.push(tx_id),
+            Err(e) => return Err(e),
+        };
+    }
+    if !closure.waiting_for.is_empty() {
+        account.closure = Some(closure);
+        return account_db.update_versioned(&id, account).await;
+    }
+
+    let destination = closure.destination.clone();
+    let (mut sweeps, mut symbols) = settle_ledgers(
+        fiat_db.inner().as_ref(),
+        &id,
+        destination.as_deref(),
+        |account_number, asset| Fiat::new(account_number, asset),
+        account_db.inner().as_ref(),
+        transaction_db.inner().as_ref(),
+        outbox.inner().as_ref(),
+        asset_master,
+        requested_by,
+    )
+    .await?;
+    let (crypto_sweeps, crypto_symbols) = settle_ledgers(
+        crypto_db.inner().as_ref(),
+        &id,
+        destination.as_deref(),
+        |account_number, asset| Crypto::new(account_number, asset, "network".to_string(), "address".to_string()),
+        account_db.inner().as_ref(),
+        transaction_db.inner().as_ref(),
+        outbox.inner().as_ref(),
+        asset_master,
+        requested_by,
+    )
+    .await?;
+    sweeps.extend(crypto_sweeps);
+    symbols.extend(crypto_symbols);
+    closure.sweeps.extend(sweeps);
+
+    let now = Utc::now().to_rfc3339();
+    for symbol in symbols {
+        let mut job = StatementJob::new(id.clone(), symbol, FINAL_STATEMENT_FROM.to_string(), now.clone(), StatementFormat::Json);
+        job_db.create(job.clone()).await.map_err(StoreError::Database)?;
+        closure.statements.push(job.id.clone());
+        let transaction_db = transaction_db.inner().clone();
+        let job_db = job_db.inner().clone();
+        rocket::tokio::spawn(async move {
+            run_statement_job(transaction_db.as_ref(), job_db.as_ref(), &mut job).await;
+        });
+    }
+
+    closure.closed_at = Some(now);
+    account.status = AccountStatus::Closed;
+    account.active = false;
+    account.closure = Some(closure);
+    let mut session = account_db.get_session().await.map_err(StoreError::Database)?;
+    let result = mark_account(&mut *session, account_db.inner().as_ref(), outbox.inner().as_ref(), account, EventType::AccountClosed).await;
+    finish(session, result).await
+}
+
+/// Stores the new status of `account` and its event as part of `session`.
+async fn mark_account(
+    session: &mut dyn Session,
+    account_db: &dyn VersionedStore<Account>,
+    outbox: &dyn Crud<DomainEvent>,
+    account: Account,
+    event_type: EventType,
+) -> Result<Account, StoreError> {
+    let account = account_db.update_versioned_in(session, &account.account_number.clone(), account).await?;
+    record(session, outbox, event_type, &account).await.map_err(StoreError::Database)?;
+    Ok(account)
+}
+
+async fn cancel_pending(
+    tx: Transaction,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
+    asset_master: &State<AssetManager>,
+) -> Result<Transaction, StoreError> {
+    let asset = match asset_master.get_by_symbol(&tx.asset) {
+        Some(asset) => asset,
+        None => return Err(StoreError::Rejected(format!("Asset {} not found", tx.asset))),
+    };
+    let mut session = transaction_db.get_session().await.map_err(StoreError::Database)?;
+    let result = match asset.asset_type {
+        AssetType::Fiat => cancel_on(&mut *session, fiat_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, tx).await,
+        AssetType::Crypto => cancel_on(&mut *session, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, tx).await,
+    };
+    finish(session, result).await
+}
+
+async fn cancel_on<
+    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable + Versioned,
+>(
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
+    asset: Asset,
+    mut tx: Transaction,
+) -> Result<Transaction, StoreError> {
+    match tx.transaction_type {
+        TransactionType::Transfer => cancel_tx(session, ledger_db, transaction_db, outbox, asset, tx).await,
+        TransactionType::Deposit => cancel_deposit(session, transaction_db, ledger_db, outbox, tx).await,
+        TransactionType::Withdraw => {
+            let id_ledger = match tx.from_wallet.clone() {
+                Some(wallet) => format!("{}_{}", wallet, tx.asset),
+                None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
+            };
+            let total_amount = tx.total_amount;
+            tx.cancel_transaction()?;
+            let tx = transaction_db.update_versioned_in(session, &tx.tx_id.clone(), tx).await?;
+            ledger_db.apply_in(session, &id_ledger, LedgerOp::CancelWithdraw(total_amount)).await?;
+            record(session, outbox, EventType::TransactionCancelled, &tx).await.map_err(StoreError::Database)?;
+            Ok(tx)
+        }
+        TransactionType::Trading => Err(StoreError::Rejected(format!("Trading transaction {} cannot be cancelled", tx.tx_id))),
+    }
+}
+
+/// Sweeps every funded ledger of the account to `destination` and closes them all. Returns
+/// the sweep transactions and the symbols of the closed ledgers.
+async fn settle_ledgers<T, F>(
+    ledger_db: &dyn LedgerStore<T>,
+    account_number: &str,
+    destination: Option<&str>,
+    open_ledger: F,
+    account_db: &dyn VersionedStore<Account>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
+    asset_master: &AssetManager,
+    id_confirmer: &str,
+) -> Result<(Vec<String>, Vec<String>), StoreError>
+where
+    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable + Aggregate + Versioned,
+    F: Fn(String, Asset) -> Result<T, String>,
+{
+    let ledgers = ledger_db
+        .find(doc! {"account_number": account_number}, doc! {"id": 1}, 0)
+        .await
+        .map_err(StoreError::Database)?;
+    let mut sweeps = Vec::new();
+    let mut symbols = Vec::new();
+    for ledger in ledgers {
+        let symbol = ledger.get_symbol();
+        if ledger.get_hold() != 0.0 {
+            return Err(StoreError::Rejected(format!("{} ledger still has {} on hold", symbol, ledger.get_hold())));
+        }
+        if ledger.get_balance() > 0.0 && destination.is_none() {
+            return Err(StoreError::Rejected("A destination account is needed for the remaining balances".to_string()));
+        }
+        // the sweep and the closing of each ledger land together or not at all
+        let mut session = ledger_db.get_session().await.map_err(StoreError::Database)?;
+        let result = settle_ledger(&mut *session, ledger_db, &ledger, destination, &open_ledger, account_db, transaction_db, outbox, asset_master, id_confirmer).await;
+        if let Some(tx) = finish(session, result).await? {
+            sweeps.push(tx.tx_id);
+        }
+        symbols.push(symbol);
+    }
+    Ok((sweeps, symbols))
+}
+
+/// Sweeps what is left on `ledger` and closes it as part of `session`.
+async fn settle_ledger<T, F>(
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    ledger: &T,
+    destination: Option<&str>,
+    open_ledger: &F,
+    account_db: &dyn VersionedStore<Account>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
+    asset_master: &AssetManager,
+    id_confirmer: &str,
+) -> Result<Option<Transaction>, StoreError>
+where
+    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable + Aggregate + Versioned,
+    F: Fn(String, Asset) -> Result<T, String>,
+{
+    let swept = match destination {
+        Some(destination) if ledger.get_balance() > 0.0 => Some(
+            sweep(session, ledger_db, ledger, destination, open_ledger, account_db, transaction_db, outbox, asset_master, id_confirmer).await?,
+        ),
+        _ => None,
+    };
+    let id = format!("{}_{}", ledger.get_account_number(), ledger.get_symbol());
+    modify_in(ledger_db, session, &id, |ledger: &mut T| ledger.close()).await?;
+    Ok(swept)
+}
+
+/// Moves the whole balance of `ledger` to the same asset at `destination` as a transfer taken
+/// through submission, confirmation and completion, opening the destination ledger when it has none.
+async fn sweep<T, F>(
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    ledger: &T,
+    destination: &str,
+    open_ledger: &F,
+    account_db: &dyn VersionedStore<Account>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
+    asset_master: &AssetManager,
+    id_confirmer: &str,
+) -> Result<Transaction, StoreError>
+where
+    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable + Aggregate + Versioned,
+    F: Fn(String, Asset) -> Result<T, String>,
+{
+    let symbol = ledger.get_symbol();
+    let amount = ledger.get_balance();
+    let asset = match asset_master.get_by_symbol(&symbol) {
+        Some(asset) => asset,
+        None => return Err(StoreError::Rejected(format!("Asset {} not found", symbol))),
+    };
+    let id_from = format!("{}_{}", ledger.get_account_number(), symbol);
+    let id_to = format!("{}_{}", destination, symbol);
+    if ledger_db.get_by_id_in(session, &id_to).await.is_err() {
+        let opened = open_ledger(destination.to_string(), asset.clone())?;
+        modify_in(account_db, session, destination, |account: &mut Account| {
+            if !account.is_open() {
+                return Err(format!("Destination account {} is closed", destination));
+            }
+            match asset.asset_type {
+                AssetType::Fiat => account.add_fiat(asset.clone()),
+                AssetType::Crypto => account.add_crypto(asset.clone()),
+            };
+            Ok(())
+        })
+        .await?;
+        create_ledger(session, ledger_db, outbox, opened).await?;
+    }
+    let submitted = process_tx(session, &id_from, &id_to, ledger_db, transaction_db, outbox, asset.clone(), amount, "Account closure sweep").await?;
+    let confirmed = confirm_tx(session, ledger_db, transaction_db, asset.clone(), submitted, id_confirmer.to_string()).await?;
+    complete_tx(session, ledger_db, transaction_db, outbox, asset, confirmed, format!("closure:{}", ledger.get_account_number())).await
+}
@@ -1,43 +1,51 @@
 use revolt_rocket_okapi::openapi;
 use rocket::{State, http::Status, serde::json::Json, post, get};
 
-use crate::{domain::{account::Account, ledger::{Crypto, FungibleTradeable}, asset::AssetManager, transaction::Transaction}, mongo::{Repository, Crud}, response::error::ErrorResponse, dto::deposit::{Deposit, DepositCreation, DepositConfirmation, Withdrawal, WithdrawalCreation, WithdrawalConfirmation}, fairings::auth::AuthorizedUser, security::permissions::can_continue};
+use crate::{api::{account::open_ledger, fiat::{open_deposit, open_withdrawal, release_withdrawal}}, domain::{account::{Account, OwnerPermission}, grant::GrantScope, ledger::Crypto, asset::AssetManager, transaction::Transaction, event::{DomainEvent, EventType}}, events::record, mongo::{finish, modify_in, Crud, Db, LedgerDb, LedgerOp, LedgerStore, Session, StoreError, VersionedDb, VersionedStore}, response::error::ErrorResponse, dto::deposit::{Deposit, DepositCreation, DepositConfirmation, Withdrawal, WithdrawalCreation, WithdrawalConfirmation}, fairings::{api_key::SignedJson, auth::AuthorizedUser}, security::permissions::{can_access, can_continue, can_operate}};
 
 #[openapi(tag = "Fiats")]
 #[post("/cryptos/<id>/ledgers/<symbol>", format = "json")]
 pub async fn create_crypto(
     id: String,
     symbol: String,
-    account_db: &State<Repository<Account>>,
-    crypto_db: &State<Repository<Crypto>>,
+    account_db: &State<VersionedDb<Account>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
     asset_master: &State<AssetManager>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Crypto>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_continue(_auth.clone(), &id) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     let asset = match asset_master.get_by_symbol(&symbol){
         Some(asset) => asset,
         None => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), "Asset not found".to_string())))),
     };
-    let mut account = match account_db.get_by_id(&id).await {
-        Ok(account) => account,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
-    let account = account.add_crypto(asset.clone());
-    let crypto = match Crypto::new(account.clone().account_number, asset, "network".to_string(), "address".to_string()){
+    let crypto = match Crypto::new(id.clone(), asset.clone(), "network".to_string(), "address".to_string()){
         Ok(crypto) => crypto,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
-    match account_db.update_by_id(&account.account_number, account.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
-    match crypto_db.create(crypto.clone()).await {
-        Ok(_) => (),
+    let mut session = match crypto_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
-    Ok(Json(crypto))
+    let result = match modify_in(account_db.inner().as_ref(), &mut *session, &id, |account: &mut Account| {
+        if !account.is_open() {
+            return Err("Account is closed".to_string());
+        }
+        if !can_operate(_auth.clone(), account, OwnerPermission::Transact) {
+            return Err("Your permission on this account does not allow it".to_string());
+        }
+        account.add_crypto(asset.clone());
+        Ok(())
+    }).await {
+        Ok(_) => open_ledger(&mut *session, crypto_db.inner().as_ref(), outbox.inner().as_ref(), crypto).await,
+        Err(e) => Err(e),
+    };
+    match finish(session, result).await {
+        Ok(crypto) => Ok(Json(crypto)),
+        Err(e) => Err(ErrorResponse::from_store("Crypto", e)),
+    }
 }
 
 #[openapi(tag = "Fiats")]
@@ -45,11 +53,11 @@ pub async fn create_crypto(
 pub async fn get_crypto(
     id: String,
     symbol: String,
-    account_db: &State<Repository<Account>>,
-    crypto_db: &State<Repository<Crypto>>,
+    account_db: &State<VersionedDb<Account>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Vec<Crypto>>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_access(_auth, &id, GrantScope::ViewBalances) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     let account = match account_db.get_by_id(&id).await {
@@ -70,40 +78,35 @@ pub async fn get_crypto(
 #[post("/cryptos/<id>/deposit", format = "json", data = "<deposit>")]
 pub async fn crypto_deposit(
     id: String,
-    deposit: Json<Deposit>,
-    account_db: &State<Repository<Account>>,
-    transaction_db: &State<Repository<Transaction>>,
-    crypto_db: &State<Repository<Crypto>>,
+    deposit: SignedJson<Deposit>,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<DepositCreation<Crypto>>,  (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_continue(_auth.clone(), &id) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match account_db.get_by_id(&id).await {
+        Ok(account) if !account.is_open() => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), "Account is closed".to_string())))),
+        Ok(account) if !can_operate(_auth.clone(), &account, OwnerPermission::Transact) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), "Your permission on this account does not allow it".to_string())))),
         Ok(account) => account,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
     let mut id_ledger = id.clone();
     id_ledger.push('_');
     id_ledger.push_str(&deposit.symbol);
-    let mut crypto = match crypto_db.get_by_id(&id_ledger).await {
-        Ok(crypto) => crypto,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
     let tx = Transaction::new_deposit(deposit.symbol.clone(), deposit.amount, id.clone(), 1);
-    match crypto.deposit(deposit.amount){
-        Ok(_) => (),
+    let mut session = match crypto_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
-    match crypto_db.update_by_id(&crypto.id, crypto.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
-    match transaction_db.create(tx.clone()).await {
-        Ok(tx) => tx,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
-    Ok(Json(DepositCreation{account: crypto, tx_id: tx.tx_id}))
+    let result = open_deposit(&mut *session, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), &id_ledger, &tx).await;
+    match finish(session, result).await {
+        Ok(crypto) => Ok(Json(DepositCreation{account: crypto, tx_id: tx.tx_id, instructions: None})),
+        Err(e) => Err(ErrorResponse::from_store("Crypto", e)),
+    }
 }
 
 #[openapi(tag = "Fiats")]
@@ -111,16 +114,18 @@ pub async fn crypto_deposit(
 pub async fn crypto_confirm_deposit(
     id: String,
     tx_id: String,
-    confirmation: Json<DepositConfirmation>,
-    account_db: &State<Repository<Account>>,
-    transaction_db: &State<Repository<Transaction>>,
-    crypto_db: &State<Repository<Crypto>>,
+    confirmation: SignedJson<DepositConfirmation>,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Crypto>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_continue(_auth.clone(), &id) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match account_db.get_by_id(&id).await {
+        Ok(account) if !can_operate(_auth.clone(), &account, OwnerPermission::Transact) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), "Your permission on this account does not allow it".to_string())))),
         Ok(account) => account,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
@@ -136,14 +141,6 @@ pub async fn crypto_confirm_deposit(
     let mut id_ledger = id.clone();
     id_ledger.push('_');
     id_ledger.push_str(&tx.asset);
-    let mut crypto = match crypto_db.get_by_id(&id_ledger).await {
-        Ok(crypto) => crypto,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
-    match crypto.confirm_deposit(tx.amount){
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
     match tx.confirm_transaction(id){
         Ok(_) => (),
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
@@ -152,52 +149,66 @@ pub async fn crypto_confirm_deposit(
         Ok(_) => (),
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
-    match crypto_db.update_by_id(&crypto.id, crypto.clone()).await {
-        Ok(_) => (),
+    let mut session = match crypto_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
-    Ok(Json(crypto))
+    let result = confirm_crypto_deposit(&mut *session, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), &id_ledger, tx).await;
+    match finish(session, result).await {
+        Ok(crypto) => Ok(Json(crypto)),
+        Err(e) => Err(ErrorResponse::from_store("Crypto", e)),
+    }
+}
+
+/// Stores the completed deposit `tx` and credits what it held as part of `session`.
+async fn confirm_crypto_deposit(
+    session: &mut dyn Session,
+    crypto_db: &dyn LedgerStore<Crypto>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
+    id_ledger: &str,
+    tx: Transaction,
+) -> Result<Crypto, StoreError> {
+    let amount = tx.amount;
+    let tx = transaction_db.update_versioned_in(session, &tx.tx_id.clone(), tx).await?;
+    let crypto = crypto_db.apply_in(session, id_ledger, LedgerOp::ConfirmDeposit(amount)).await?;
+    record(session, outbox, EventType::DepositConfirmed, &tx).await.map_err(StoreError::Database)?;
+    Ok(crypto)
 }
 
 #[openapi(tag = "Fiats")]
 #[post("/cryptos/<id>/withdrawal", format = "json", data = "<withdrawal>")]
 pub async fn crypto_withdrawal(
     id: String,
-    withdrawal: Json<Withdrawal>,
-    transaction_db: &State<Repository<Transaction>>,
-    account_db: &State<Repository<Account>>,
-    crypto_db: &State<Repository<Crypto>>,
+    withdrawal: SignedJson<Withdrawal>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    account_db: &State<VersionedDb<Account>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<WithdrawalCreation<Crypto>>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_continue(_auth.clone(), &id) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match account_db.get_by_id(&id).await {
+        Ok(account) if !account.is_open() => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), "Account is closed".to_string())))),
+        Ok(account) if !can_operate(_auth.clone(), &account, OwnerPermission::Transact) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), "Your permission on this account does not allow it".to_string())))),
         Ok(account) => account,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
     let mut id_ledger = id.clone();
     id_ledger.push('_');
     id_ledger.push_str(&withdrawal.symbol);
-    let mut crypto = match crypto_db.get_by_id(&id_ledger).await {
-        Ok(crypto) => crypto,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
     let mut tx = Transaction::new_withdraw(withdrawal.symbol.clone(), withdrawal.amount, id.clone(), 1);
     tx.add_fee("Withdrawal".to_string(), tx.amount * 0.01);
-    match crypto.withdraw(tx.total_amount){
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
-    match crypto_db.update_by_id(&crypto.id, crypto.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
-    match transaction_db.create(tx.clone()).await {
-        Ok(tx) => tx,
+    let mut session = match crypto_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
-    Ok(Json(WithdrawalCreation{account: crypto, tx_id: tx.tx_id}))
+    let result = open_withdrawal(&mut *session, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), &id_ledger, &tx).await;
+    match finish(session, result).await {
+        Ok(crypto) => Ok(Json(WithdrawalCreation{account: crypto, tx_id: tx.tx_id, instructions: None})),
+        Err(e) => Err(ErrorResponse::from_store("Crypto", e)),
+    }
 }
 
 #[openapi(tag = "Fiats")]
@@ -205,16 +216,18 @@ pub async fn crypto_withdrawal(
 pub async fn crypto_release_withdrawal(
     id: String,
     tx_id: String,
-    confirmation: Json<WithdrawalConfirmation>,
-    account_db: &State<Repository<Account>>,
-    transaction_db: &State<Repository<Transaction>>,
-    crypto_db: &State<Repository<Crypto>>,
+    confirmation: SignedJson<WithdrawalConfirmation>,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Crypto>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_continue(_auth.clone(), &id) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match account_db.get_by_id(&id).await {
+        Ok(account) if !can_operate(_auth.clone(), &account, OwnerPermission::Transact) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), "Your permission on this account does not allow it".to_string())))),
         Ok(account) => account,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
@@ -230,14 +243,6 @@ pub async fn crypto_release_withdrawal(
     let mut id_ledger = id.clone();
     id_ledger.push('_');
     id_ledger.push_str(&tx.asset);
-    let mut crypto = match crypto_db.get_by_id(&id_ledger).await {
-        Ok(crypto) => crypto,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
-    match crypto.confirm_withdraw(tx.total_amount){
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
     match tx.confirm_transaction(id){
         Ok(tx) => tx,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
@@ -246,13 +251,13 @@ pub async fn crypto_release_withdrawal(
         Ok(tx) => tx,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
-    match crypto_db.update_by_id(&crypto.id, crypto.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
-    };
-    match transaction_db.update_by_id(&tx.tx_id.clone(), tx).await {
-        Ok(_) => (),
+    let mut session = match crypto_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Crypto".to_string(), e)))),
     };
-    Ok(Json(crypto))
+    let result = release_withdrawal(&mut *session, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), &id_ledger, tx).await;
+    match finish(session, result).await {
+        Ok(crypto) => Ok(Json(crypto)),
+        Err(e) => Err(ErrorResponse::from_store("Crypto", e)),
+    }
 }
\ No newline at end of file
@@ -1,43 +1,53 @@
+use chrono::{Duration, Utc};
+use mongodb::bson::doc;
 use revolt_rocket_okapi::openapi;
 use rocket::{State, http::Status, serde::json::Json, post, get};
 
-use crate::{domain::{account::Account, ledger::{Fiat, FungibleTradeable}, asset::AssetManager, transaction::Transaction}, mongo::{Repository, Crud}, response::error::ErrorResponse, dto::deposit::{Deposit, DepositCreation, DepositConfirmation, Withdrawal, WithdrawalCreation, WithdrawalConfirmation}, fairings::auth::AuthorizedUser, security::permissions::can_continue};
+use crate::{api::account::open_ledger, domain::{account::{Account, OwnerPermission}, audit::{AuditEntry, AuditOutcome}, grant::GrantScope, ledger::Fiat, asset::AssetManager, payment_reference, transaction::{Transaction, TransactionType}, event::{DomainEvent, EventType}}, events::record, mongo::{finish, modify_in, Crud, Db, LedgerDb, LedgerOp, LedgerStore, Session, StoreError, Versioned, VersionedDb, VersionedStore}, response::error::ErrorResponse, dto::deposit::{Deposit, DepositCreation, DepositConfirmation, DepositInstructions, Withdrawal, WithdrawalCreation, WithdrawalConfirmation, DEPOSIT_EXPIRES_IN}, fairings::{api_key::SignedJson, auth::{AuthorizedUser, Permitted, TransactionsApprove}, request_context::RequestContext}, security::{audit, permissions::{can_access, can_continue, can_operate}}};
 
 #[openapi(tag = "Cryptos")]
 #[post("/fiats/<id>/ledgers/<symbol>", format = "json")]
 pub async fn create_fiat(
     id: String,
     symbol: String,
-    account_db: &State<Repository<Account>>,
-    fiat_db: &State<Repository<Fiat>>,
+    account_db: &State<VersionedDb<Account>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    outbox: &State<Db<DomainEvent>>,
     asset_master: &State<AssetManager>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Fiat>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_continue(_auth.clone(), &id) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     let asset = match asset_master.get_by_symbol(&symbol){
         Some(asset) => asset,
         None => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), "Asset not found".to_string())))),
     };
-    let mut account = match account_db.get_by_id(&id).await {
-        Ok(account) => account,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    let account = account.add_fiat(asset.clone());
-    let fiat = match Fiat::new(account.clone().account_number, asset){
+    let fiat = match Fiat::new(id.clone(), asset.clone()){
         Ok(fiat) => fiat,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
-    match account_db.update_by_id(&account.account_number, account.clone()).await {
-        Ok(_) => (),
+    let mut session = match fiat_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
-    match fiat_db.create(fiat.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
+    let result = match modify_in(account_db.inner().as_ref(), &mut *session, &id, |account: &mut Account| {
+        if !account.is_open() {
+            return Err("Account is closed".to_string());
+        }
+        if !can_operate(_auth.clone(), account, OwnerPermission::Transact) {
+            return Err("Your permission on this account does not allow it".to_string());
+        }
+        account.add_fiat(asset.clone());
+        Ok(())
+    }).await {
+        Ok(_) => open_ledger(&mut *session, fiat_db.inner().as_ref(), outbox.inner().as_ref(), fiat).await,
+        Err(e) => Err(e),
     };
-    Ok(Json(fiat))
+    match finish(session, result).await {
+        Ok(fiat) => Ok(Json(fiat)),
+        Err(e) => Err(ErrorResponse::from_store("Fiat", e)),
+    }
 }
 
 #[openapi(tag = "Cryptos")]
@@ -45,11 +55,11 @@ pub async fn create_fiat(
 pub async fn get_fiat(
     id: String,
     symbol: String,
-    account_db: &State<Repository<Account>>,
-    fiat_db: &State<Repository<Fiat>>,
+    account_db: &State<VersionedDb<Account>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Vec<Fiat>>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_access(_auth, &id, GrantScope::ViewBalances) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     let account = match account_db.get_by_id(&id).await {
@@ -70,61 +80,96 @@ pub async fn get_fiat(
 #[post("/fiats/<id>/deposit", format = "json", data = "<deposit>")]
 pub async fn fiat_deposit(
     id: String,
-    deposit: Json<Deposit>,
-    account_db: &State<Repository<Account>>,
-    transaction_db: &State<Repository<Transaction>>,
-    fiat_db: &State<Repository<Fiat>>,
+    deposit: SignedJson<Deposit>,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    outbox: &State<Db<DomainEvent>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<DepositCreation<Fiat>>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_continue(_auth.clone(), &id) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match account_db.get_by_id(&id).await {
+        Ok(account) if !account.is_open() => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), "Account is closed".to_string())))),
+        Ok(account) if !can_operate(_auth.clone(), &account, OwnerPermission::Transact) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), "Your permission on this account does not allow it".to_string())))),
         Ok(account) => account,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
     let mut id_ledger = id.clone();
     id_ledger.push('_');
     id_ledger.push_str(&deposit.symbol);
-    let mut fiat = match fiat_db.get_by_id(&id_ledger).await {
-        Ok(fiat) => fiat,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    let tx = Transaction::new_deposit(deposit.symbol.clone(), deposit.amount, id.clone(), 1);
-    match fiat.deposit(deposit.amount){
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    match fiat_db.update_by_id(&fiat.id, fiat.clone()).await {
-        Ok(_) => (),
+    let mut tx = Transaction::new_deposit(deposit.symbol.clone(), deposit.amount, id.clone(), 1);
+    let expires_at = (Utc::now() + Duration::seconds(*DEPOSIT_EXPIRES_IN)).to_rfc3339();
+    tx.set_payment_reference(payment_reference::generate(), expires_at.clone());
+    let mut session = match fiat_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
-    match transaction_db.create(tx.clone()).await {
-        Ok(tx) => tx,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
+    let result = open_deposit(&mut *session, fiat_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), &id_ledger, &tx).await;
+    let fiat = match finish(session, result).await {
+        Ok(fiat) => fiat,
+        Err(e) => return Err(ErrorResponse::from_store("Fiat", e)),
     };
-    Ok(Json(DepositCreation{account: fiat, tx_id: tx.tx_id}))
+    let instructions = DepositInstructions::new(
+        tx.payment_reference.clone().unwrap_or_default(),
+        tx.amount,
+        tx.asset.clone(),
+        expires_at,
+    );
+    Ok(Json(DepositCreation{account: fiat, tx_id: tx.tx_id, instructions: Some(instructions)}))
+}
+
+/// Holds the amount of the deposit `tx` on the receiving ledger and stores `tx` as part of `session`.
+pub async fn open_deposit<T: Versioned>(
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
+    id_ledger: &str,
+    tx: &Transaction,
+) -> Result<T, StoreError> {
+    let ledger = ledger_db.apply_in(session, id_ledger, LedgerOp::Deposit(tx.amount)).await?;
+    transaction_db.create_in(session, tx.clone()).await.map_err(StoreError::Database)?;
+    record(session, outbox, EventType::DepositPending, tx).await.map_err(StoreError::Database)?;
+    Ok(ledger)
 }
 
+/// Confirms a deposit the bank reported outside of a statement import, for staff approving
+/// transactions. Owners never confirm their own deposits.
 #[openapi(tag = "Cryptos")]
 #[post("/fiats/<id>/deposit/<tx_id>/confirm", format = "json", data="<confirmation>")]
 pub async fn fiat_confirm_deposit(
     id: String,
     tx_id: String,
-    confirmation: Json<DepositConfirmation>,
-    account_db: &State<Repository<Account>>,
-    transaction_db: &State<Repository<Transaction>>,
-    fiat_db: &State<Repository<Fiat>>,
+    confirmation: SignedJson<DepositConfirmation>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    outbox: &State<Db<DomainEvent>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<TransactionsApprove>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Fiat>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    let entry = audit::entry(&context, Some(&_auth), "deposit.confirm", &tx_id);
+    if !_auth.reaches(&id) {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
-    };
-    match account_db.get_by_id(&id).await {
-        Ok(account) => account,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    let mut tx = match transaction_db.get_by_fields(vec!["to_wallet".to_string(), "tx_id".to_string()], vec![id.clone(),tx_id.clone()]).await{
+    }
+    let result = confirm_deposit(id, tx_id, confirmation.0, transaction_db, fiat_db, outbox, _auth).await;
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn confirm_deposit(
+    id: String,
+    tx_id: String,
+    confirmation: DepositConfirmation,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    outbox: &State<Db<DomainEvent>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Fiat>, (Status, Json<ErrorResponse>)> {
+    let tx = match transaction_db.get_by_fields(vec!["to_wallet".to_string(), "tx_id".to_string()], vec![id.clone(),tx_id.clone()]).await{
         Ok(tx) => {
             if tx.is_empty() {
                 return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), "Transaction not found".to_string()))));
@@ -133,60 +178,160 @@ pub async fn fiat_confirm_deposit(
         },
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
-    let mut id_ledger = id.clone();
-    id_ledger.push('_');
-    id_ledger.push_str(&tx.asset);
-    let mut fiat = match fiat_db.get_by_id(&id_ledger).await {
-        Ok(fiat) => fiat,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    match fiat.confirm_deposit(tx.amount){
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    match tx.confirm_transaction(id){
-        Ok(tx) => tx,
+    // transfers complete through their own flow, confirming one here would skip the sender's side
+    if tx.transaction_type != TransactionType::Deposit {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), "Transaction is not a deposit".to_string()))));
+    }
+    if tx.is_expired() {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), "Deposit has expired".to_string()))));
+    }
+    let mut session = match fiat_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
-    match tx.complete_transaction(confirmation.external_id.clone()){
-        Ok(tx) => tx,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    match transaction_db.update_by_id(&tx.tx_id.clone(), tx).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
+    let result = confirm_fiat_deposit(&mut *session, transaction_db.inner().as_ref(), fiat_db.inner().as_ref(), outbox.inner().as_ref(), tx, _auth.user_id, confirmation.external_id, None).await;
+    match finish(session, result).await {
+        Ok(fiat) => Ok(Json(fiat)),
+        Err(e) => Err(ErrorResponse::from_store("Fiat", e)),
+    }
+}
+
+pub async fn confirm_fiat_deposit(
+    session: &mut dyn Session,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    fiat_db: &dyn LedgerStore<Fiat>,
+    outbox: &dyn Crud<DomainEvent>,
+    mut tx: Transaction,
+    id_confirmer: String,
+    external_id: String,
+    received: Option<f64>,
+) -> Result<Fiat, StoreError> {
+    let mut id_ledger = match tx.to_wallet.clone() {
+        Some(wallet) => wallet,
+        None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
     };
-    match fiat_db.update_by_id(&fiat.id, fiat.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
+    id_ledger.push('_');
+    id_ledger.push_str(&tx.asset);
+    let expected = tx.amount;
+    if let Some(received) = received {
+        if received != tx.amount {
+            tx.adjust_deposit_amount(received)?;
+        }
+    }
+    let amount = tx.amount;
+    tx.confirm_transaction(id_confirmer)?;
+    tx.complete_transaction(external_id)?;
+    // the versioned write lets only one of two concurrent confirmations move the funds
+    let tx = transaction_db.update_versioned_in(session, &tx.tx_id.clone(), tx).await?;
+    if amount != expected {
+        fiat_db.apply_in(session, &id_ledger, LedgerOp::CancelDeposit(expected)).await?;
+        fiat_db.apply_in(session, &id_ledger, LedgerOp::Deposit(amount)).await?;
+    }
+    let fiat = fiat_db.apply_in(session, &id_ledger, LedgerOp::ConfirmDeposit(amount)).await?;
+    record(session, outbox, EventType::DepositConfirmed, &tx).await.map_err(StoreError::Database)?;
+    Ok(fiat)
+}
+
+#[openapi(tag = "Cryptos")]
+#[post("/fiats/deposits/expire", format = "json")]
+pub async fn fiat_expire_deposits(
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    outbox: &State<Db<DomainEvent>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<TransactionsApprove>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<Transaction>>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "deposit.expire", "deposits");
+    let cancelled = match expire_deposits(transaction_db.inner().as_ref(), fiat_db.inner().as_ref(), outbox.inner().as_ref()).await {
+        Ok(cancelled) => cancelled,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err(ErrorResponse::from_store("Fiat", e))).await,
+    };
+    let entry = entry.outcome(
+        AuditOutcome::Success,
+        Some(format!("Cancelled {}", cancelled.iter().map(|tx| tx.tx_id.clone()).collect::<Vec<String>>().join(","))),
+    );
+    audit::outcome(audit_db.inner().as_ref(), entry, Ok(Json(cancelled))).await
+}
+
+/// Cancels every pending deposit past its expiry, releasing what each held, and returns them.
+/// Also run in the background by `DepositExpirer`.
+pub async fn expire_deposits(
+    transaction_db: &dyn VersionedStore<Transaction>,
+    fiat_db: &dyn LedgerStore<Fiat>,
+    outbox: &dyn Crud<DomainEvent>,
+) -> Result<Vec<Transaction>, StoreError> {
+    let expired = transaction_db
+        .find(
+            doc! {
+                "transaction_type": "Deposit",
+                "transaction_status": "Pending",
+                "expires_at": {"$lt": Utc::now().to_rfc3339()},
+            },
+            doc! {"expires_at": 1},
+            0,
+        )
+        .await
+        .map_err(StoreError::Database)?;
+    let mut cancelled = Vec::new();
+    for tx in expired {
+        let mut session = fiat_db.get_session().await.map_err(StoreError::Database)?;
+        let result = cancel_deposit(&mut *session, transaction_db, fiat_db, outbox, tx).await;
+        match finish(session, result).await {
+            Ok(tx) => cancelled.push(tx),
+            // a deposit confirmed while the sweep ran is no longer ours to cancel
+            Err(StoreError::Conflict { .. }) => (),
+            Err(e) => return Err(e),
+        };
+    }
+    Ok(cancelled)
+}
+
+/// Cancels a pending deposit and releases what it held on the receiving ledger as part of `session`.
+pub async fn cancel_deposit<T: Versioned>(
+    session: &mut dyn Session,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    ledger_db: &dyn LedgerStore<T>,
+    outbox: &dyn Crud<DomainEvent>,
+    mut tx: Transaction,
+) -> Result<Transaction, StoreError> {
+    let mut id_ledger = match tx.to_wallet.clone() {
+        Some(wallet) => wallet,
+        None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
     };
-    Ok(Json(fiat))
+    id_ledger.push('_');
+    id_ledger.push_str(&tx.asset);
+    let amount = tx.amount;
+    tx.cancel_transaction()?;
+    let tx = transaction_db.update_versioned_in(session, &tx.tx_id.clone(), tx).await?;
+    ledger_db.apply_in(session, &id_ledger, LedgerOp::CancelDeposit(amount)).await?;
+    record(session, outbox, EventType::TransactionCancelled, &tx).await.map_err(StoreError::Database)?;
+    Ok(tx)
 }
 
 #[openapi(tag = "Cryptos")]
 #[post("/fiats/<id>/withdrawal", format = "json", data = "<withdrawal>")]
 pub async fn fiat_withdrawal(
     id: String,
-    withdrawal: Json<Withdrawal>,
-    transaction_db: &State<Repository<Transaction>>,
-    account_db: &State<Repository<Account>>,
-    fiat_db: &State<Repository<Fiat>>,
+    withdrawal: SignedJson<Withdrawal>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    account_db: &State<VersionedDb<Account>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<WithdrawalCreation<Fiat>>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_continue(_auth.clone(), &id) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match account_db.get_by_id(&id).await {
+        Ok(account) if !account.is_open() => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), "Account is closed".to_string())))),
+        Ok(account) if !can_operate(_auth.clone(), &account, OwnerPermission::Transact) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), "Your permission on this account does not allow it".to_string())))),
         Ok(account) => account,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
     let mut id_ledger = id.clone();
     id_ledger.push('_');
     id_ledger.push_str(&withdrawal.symbol);
-    let mut fiat = match fiat_db.get_by_id(&id_ledger).await {
-        Ok(fiat) => fiat,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
     let mut tx = Transaction::new_withdraw(
         withdrawal.symbol.clone(),
         withdrawal.amount,
@@ -194,19 +339,28 @@ pub async fn fiat_withdrawal(
         1,
     );
     tx.add_fee("Withdrawal".to_string(), tx.amount * 0.02);
-    match fiat.withdraw(tx.total_amount){
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    match fiat_db.update_by_id(&fiat.id, fiat.clone()).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-     match transaction_db.create(tx.clone()).await {
-        Ok(tx) => tx,
+    let mut session = match fiat_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
-    Ok(Json(WithdrawalCreation{account: fiat, tx_id: tx.tx_id}))
+    let result = open_withdrawal(&mut *session, fiat_db.inner().as_ref(), transaction_db.inner().as_ref(), &id_ledger, &tx).await;
+    match finish(session, result).await {
+        Ok(fiat) => Ok(Json(WithdrawalCreation{account: fiat, tx_id: tx.tx_id, instructions: None})),
+        Err(e) => Err(ErrorResponse::from_store("Fiat", e)),
+    }
+}
+
+/// Holds the total of the withdrawal `tx` on the paying ledger and stores `tx` as part of `session`.
+pub async fn open_withdrawal<T: Versioned>(
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    id_ledger: &str,
+    tx: &Transaction,
+) -> Result<T, StoreError> {
+    let ledger = ledger_db.apply_in(session, id_ledger, LedgerOp::Withdraw(tx.total_amount)).await?;
+    transaction_db.create_in(session, tx.clone()).await.map_err(StoreError::Database)?;
+    Ok(ledger)
 }
 
 #[openapi(tag = "Cryptos")]
@@ -214,16 +368,18 @@ pub async fn fiat_withdrawal(
 pub async fn fiat_release_withdrawal(
     id: String,
     tx_id: String,
-    confirmation: Json<WithdrawalConfirmation>,
-    account_db: &State<Repository<Account>>,
-    transaction_db: &State<Repository<Transaction>>,
-    fiat_db: &State<Repository<Fiat>>,
+    confirmation: SignedJson<WithdrawalConfirmation>,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    outbox: &State<Db<DomainEvent>>,
     _auth: AuthorizedUser,
 ) -> Result<Json<Fiat>, (Status, Json<ErrorResponse>)> {
-    if !can_continue(_auth, &id) {
+    if !can_continue(_auth.clone(), &id) {
         return Err((Status::BadRequest, Json(ErrorResponse::new("Account".to_string(), "You can only get your own account".to_string()))));
     };
     match account_db.get_by_id(&id).await {
+        Ok(account) if !can_operate(_auth.clone(), &account, OwnerPermission::Transact) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), "Your permission on this account does not allow it".to_string())))),
         Ok(account) => account,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
@@ -239,14 +395,6 @@ pub async fn fiat_release_withdrawal(
     let mut id_ledger = id.clone();
     id_ledger.push('_');
     id_ledger.push_str(&tx.asset);
-    let mut fiat = match fiat_db.get_by_id(&id_ledger).await {
-        Ok(fiat) => fiat,
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    match fiat.confirm_withdraw(tx.total_amount){
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
     match tx.confirm_transaction(id){
         Ok(tx) => tx,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
@@ -255,13 +403,29 @@ pub async fn fiat_release_withdrawal(
         Ok(tx) => tx,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
-    match fiat_db.update_by_id(&fiat.id, fiat.clone()).await {
-        Ok(_) => (),
+    let mut session = match fiat_db.get_session().await {
+        Ok(session) => session,
         Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
     };
-    match transaction_db.update_by_id(&tx.tx_id.clone(), tx).await {
-        Ok(_) => (),
-        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Fiat".to_string(), e)))),
-    };
-    Ok(Json(fiat))
+    let result = release_withdrawal(&mut *session, fiat_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), &id_ledger, tx).await;
+    match finish(session, result).await {
+        Ok(fiat) => Ok(Json(fiat)),
+        Err(e) => Err(ErrorResponse::from_store("Fiat", e)),
+    }
+}
+
+/// Stores the completed withdrawal `tx` and pays out what it held as part of `session`.
+pub async fn release_withdrawal<T: Versioned>(
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
+    id_ledger: &str,
+    tx: Transaction,
+) -> Result<T, StoreError> {
+    let total_amount = tx.total_amount;
+    let tx = transaction_db.update_versioned_in(session, &tx.tx_id.clone(), tx).await?;
+    let ledger = ledger_db.apply_in(session, id_ledger, LedgerOp::ConfirmWithdraw(total_amount)).await?;
+    record(session, outbox, EventType::WithdrawalReleased, &tx).await.map_err(StoreError::Database)?;
+    Ok(ledger)
 }
\ No newline at end of file
@@ -0,0 +1,134 @@
+use mongodb::bson::doc;
+use revolt_rocket_okapi::openapi;
+use rocket::{get, http::Status, post, serde::json::Json, State};
+
+use crate::{
+    domain::{
+        account::{Account, OwnerPermission},
+        audit::AuditEntry,
+        grant::AccessGrant,
+        user::User,
+    },
+    dto::grant::GrantRequest,
+    fairings::{api_key::SignedJson, auth::AuthorizedUser, request_context::RequestContext},
+    mongo::{Db, VersionedDb},
+    response::error::ErrorResponse,
+    security::{audit, permissions::can_operate},
+};
+
+#[openapi(tag = "Grants")]
+#[post("/accounts/<id>/grants", format = "json", data = "<request>")]
+pub async fn create_grant(
+    id: String,
+    request: SignedJson<GrantRequest>,
+    account_db: &State<VersionedDb<Account>>,
+    user_db: &State<Db<User>>,
+    grant_db: &State<VersionedDb<AccessGrant>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<AccessGrant>, (Status, Json<ErrorResponse>)> {
+    let mut entry = audit::entry(&context, Some(&_auth), "grant.create", &id);
+    let account = match account_db.get_by_id(&id).await {
+        Ok(account) => account,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), e))))).await,
+    };
+    if !can_operate(_auth.clone(), &account, OwnerPermission::Full) {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), "Only owners with Full permission can grant access".to_string()))));
+    }
+    let result = grant(account, request.0, user_db, grant_db, _auth).await;
+    if let Ok(grant) = &result {
+        entry.resource = format!("grants/{}", grant.id);
+    }
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn grant(
+    account: Account,
+    request: GrantRequest,
+    user_db: &State<Db<User>>,
+    grant_db: &State<VersionedDb<AccessGrant>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<AccessGrant>, (Status, Json<ErrorResponse>)> {
+    if !account.is_open() {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), "Account is closed".to_string()))));
+    }
+    match user_db.get_by_id(&request.grantee).await {
+        Ok(_) => (),
+        Err(_) => return Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), format!("User {} not found", request.grantee))))),
+    };
+    let grant = match AccessGrant::new(account.account_number, _auth.user_id, request.grantee, request.scopes, request.transfer_limit, request.total_limit, request.expires_at) {
+        Ok(grant) => grant,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), e)))),
+    };
+    match grant_db.create(grant.clone()).await {
+        Ok(_) => Ok(Json(grant)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), e)))),
+    }
+}
+
+/// Grants given on an account, revoked and expired ones included.
+#[openapi(tag = "Grants")]
+#[get("/accounts/<id>/grants", format = "json")]
+pub async fn get_account_grants(
+    id: String,
+    account_db: &State<VersionedDb<Account>>,
+    grant_db: &State<VersionedDb<AccessGrant>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<AccessGrant>>, (Status, Json<ErrorResponse>)> {
+    let account = match account_db.get_by_id(&id).await {
+        Ok(account) => account,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), e)))),
+    };
+    if !can_operate(_auth, &account, OwnerPermission::View) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), "You can only get the grants of your own account".to_string()))));
+    }
+    match grant_db.find(doc! {"account_number": &id}, doc! {"created_at": -1}, 0).await {
+        Ok(grants) => Ok(Json(grants)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), e)))),
+    }
+}
+
+/// Grants the caller received that are in force now.
+#[openapi(tag = "Grants")]
+#[get("/grants", format = "json")]
+pub async fn get_my_grants(_auth: AuthorizedUser) -> Json<Vec<AccessGrant>> {
+    Json(_auth.grants)
+}
+
+/// Revokes a grant, by a Full owner of the account or by the grantee giving it up.
+#[openapi(tag = "Grants")]
+#[post("/grants/<grant_id>/revoke", format = "json")]
+pub async fn revoke_grant(
+    grant_id: String,
+    account_db: &State<VersionedDb<Account>>,
+    grant_db: &State<VersionedDb<AccessGrant>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<AccessGrant>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "grant.revoke", &format!("grants/{}", grant_id));
+    let mut grant = match grant_db.get_by_id(&grant_id).await {
+        Ok(grant) => grant,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), e))))).await,
+    };
+    let allowed = grant.grantee == _auth.user_id
+        || match account_db.get_by_id(&grant.account_number).await {
+            Ok(account) => can_operate(_auth.clone(), &account, OwnerPermission::Full),
+            Err(_) => false,
+        };
+    if !allowed {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), "You are not allowed to revoke this grant".to_string()))));
+    }
+    let before = grant.clone();
+    if let Err(e) = grant.revoke(_auth.user_id.clone()) {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Grant".to_string(), e))))).await;
+    }
+    let (entry, result) = match grant_db.update_versioned(&grant_id, grant).await {
+        Ok(grant) => (entry.changes(&before, &grant), Ok(Json(grant))),
+        Err(e) => (entry, Err(ErrorResponse::from_store("Grant", e))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
@@ -0,0 +1,115 @@
+use mongodb::bson::doc;
+use revolt_rocket_okapi::openapi;
+use rocket::{get, http::Status, post, serde::json::Json, State};
+
+use crate::{
+    domain::{
+        audit::AuditEntry,
+        grant::GrantScope,
+        integrity::IntegrityReport,
+        statement::JobStatus,
+        transaction::{ChainFinding, Transaction},
+    },
+    fairings::{auth::{AuthorizedUser, Permitted, ReportsRead}, request_context::RequestContext},
+    mongo::{Crud, Db, VersionedDb, VersionedStore},
+    response::error::ErrorResponse,
+    security::{audit, permissions::can_access},
+};
+
+const VERIFY_PAGE_SIZE: usize = 500;
+
+#[openapi(tag = "Integrity")]
+#[get("/transactions/<id>/verify", format = "json")]
+pub async fn verify_transaction(
+    id: String,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<ChainFinding>, (Status, Json<ErrorResponse>)> {
+    let transaction = match transaction_db.get_by_id(&id).await {
+        Ok(transaction) => transaction,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Integrity".to_string(), e)))),
+    };
+    let from = transaction.clone().from_wallet.unwrap_or(" ".to_string());
+    let to = transaction.clone().to_wallet.unwrap_or(" ".to_string());
+    if !(can_access(_auth.clone(), &from, GrantScope::ViewTransactions) || can_access(_auth, &to, GrantScope::ViewTransactions)) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Integrity".to_string(), "You are not allowed to verify this transaction".to_string()))));
+    }
+    Ok(Json(transaction.verify_hash_chain()))
+}
+
+#[openapi(tag = "Integrity")]
+#[post("/integrity/hash-chain", format = "json")]
+pub async fn request_hash_chain_verification(
+    transaction_db: &State<VersionedDb<Transaction>>,
+    report_db: &State<Db<IntegrityReport>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<ReportsRead>,
+    _auth: AuthorizedUser,
+) -> Result<Json<IntegrityReport>, (Status, Json<ErrorResponse>)> {
+    let mut entry = audit::entry(&context, Some(&_auth), "integrity.verify_all", "integrity/hash-chain");
+    let mut report = IntegrityReport::new(_auth.user_id);
+    entry.resource = format!("integrity/hash-chain/{}", report.id);
+    match report_db.create(report.clone()).await {
+        Ok(_) => audit::log(audit_db.inner().as_ref(), entry).await,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Integrity".to_string(), e))))).await,
+    };
+    let response = report.clone();
+    let transaction_db = transaction_db.inner().clone();
+    let report_db = report_db.inner().clone();
+    rocket::tokio::spawn(async move {
+        run_verification(transaction_db.as_ref(), report_db.as_ref(), &mut report).await;
+    });
+    Ok(Json(response))
+}
+
+#[openapi(tag = "Integrity")]
+#[get("/integrity/hash-chain/<id>", format = "json")]
+pub async fn get_hash_chain_verification(
+    id: String,
+    report_db: &State<Db<IntegrityReport>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<ReportsRead>,
+    _auth: AuthorizedUser,
+) -> Result<Json<IntegrityReport>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "integrity.get_report", &format!("integrity/hash-chain/{}", id));
+    let result = match report_db.get_by_id(&id).await {
+        Ok(report) => Ok(Json(report)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Integrity".to_string(), e)))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+/// Pages through every transaction by id, recomputing each chain, and stores the report.
+pub async fn run_verification(
+    transaction_db: &dyn VersionedStore<Transaction>,
+    report_db: &dyn Crud<IntegrityReport>,
+    report: &mut IntegrityReport,
+) {
+    report.status = JobStatus::Running;
+    if let Err(e) = report_db.update_by_id(&report.id.clone(), report.clone()).await {
+        println!("Error updating integrity report {}: {}", report.id, e);
+    }
+    let mut last = String::new();
+    let result = loop {
+        let page = match transaction_db
+            .find(doc! {"tx_id": {"$gt": &last}}, doc! {"tx_id": 1}, VERIFY_PAGE_SIZE)
+            .await
+        {
+            Ok(page) => page,
+            Err(e) => break Err(e),
+        };
+        for transaction in &page {
+            report.add(transaction.verify_hash_chain());
+        }
+        match page.last() {
+            Some(transaction) if page.len() == VERIFY_PAGE_SIZE => last = transaction.tx_id.clone(),
+            _ => break Ok(()),
+        }
+    };
+    report.finish(result);
+    if let Err(e) = report_db.update_by_id(&report.id.clone(), report.clone()).await {
+        println!("Error updating integrity report {}: {}", report.id, e);
+    }
+}
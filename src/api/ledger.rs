@@ -0,0 +1,99 @@
+use mongodb::bson::{doc, Document};
+use revolt_rocket_okapi::openapi;
+use rocket::{get, http::Status, serde::json::Json, State};
+
+use crate::{
+    domain::{
+        grant::GrantScope,
+        merkle::{self, DailyRoot, InclusionProof, LedgerEntry},
+        transaction::Transaction,
+    },
+    fairings::auth::AuthorizedUser,
+    mongo::{Db, VersionedDb},
+    response::error::ErrorResponse,
+    security::permissions::can_access,
+};
+
+#[openapi(tag = "Ledger")]
+#[get("/ledger/roots?<from>&<to>", format = "json")]
+pub async fn get_ledger_roots(
+    from: Option<String>,
+    to: Option<String>,
+    root_db: &State<Db<DailyRoot>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<DailyRoot>>, (Status, Json<ErrorResponse>)> {
+    let mut day = Document::new();
+    if let Some(from) = from {
+        day.insert("$gte", from);
+    }
+    if let Some(to) = to {
+        day.insert("$lte", to);
+    }
+    let filter = if day.is_empty() { doc! {} } else { doc! {"day": day} };
+    match root_db.find(filter, doc! {"day": 1}, 0).await {
+        Ok(roots) => Ok(Json(roots)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), e)))),
+    }
+}
+
+#[openapi(tag = "Ledger")]
+#[get("/ledger/roots/<day>", format = "json")]
+pub async fn get_ledger_root(
+    day: String,
+    root_db: &State<Db<DailyRoot>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<DailyRoot>, (Status, Json<ErrorResponse>)> {
+    match root_db.get_by_id(&day).await {
+        Ok(root) => Ok(Json(root)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), e)))),
+    }
+}
+
+#[openapi(tag = "Ledger")]
+#[get("/ledger/proofs/<tx_id>", format = "json")]
+pub async fn get_inclusion_proof(
+    tx_id: String,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    entry_db: &State<Db<LedgerEntry>>,
+    root_db: &State<Db<DailyRoot>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<InclusionProof>, (Status, Json<ErrorResponse>)> {
+    let transaction = match transaction_db.get_by_id(&tx_id).await {
+        Ok(transaction) => transaction,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), e)))),
+    };
+    let from = transaction.clone().from_wallet.unwrap_or(" ".to_string());
+    let to = transaction.clone().to_wallet.unwrap_or(" ".to_string());
+    if !(can_access(_auth.clone(), &from, GrantScope::ViewTransactions) || can_access(_auth, &to, GrantScope::ViewTransactions)) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), "You are not allowed to get this proof".to_string()))));
+    }
+    let entry = match entry_db.find(doc! {"tx_id": &tx_id}, doc! {}, 1).await {
+        Ok(found) if !found.is_empty() => found[0].clone(),
+        Ok(_) => return Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), "Transaction is not in the ledger yet".to_string())))),
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), e)))),
+    };
+    let root = match root_db.get_by_id(&entry.day).await {
+        Ok(root) => root,
+        Err(_) => return Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), format!("Transaction will be anchored in the root of {}", entry.day))))),
+    };
+    let day_entries = match entry_db.find(doc! {"day": &entry.day}, doc! {"sequence": 1}, 0).await {
+        Ok(day_entries) => day_entries,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), e)))),
+    };
+    let index = match day_entries.iter().position(|day_entry| day_entry.tx_id == tx_id) {
+        Some(index) => index,
+        None => return Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), "Transaction is not in the ledger yet".to_string())))),
+    };
+    let leaves: Vec<String> = day_entries.iter().map(|day_entry| day_entry.leaf.clone()).collect();
+    let path = match merkle::proof(&leaves, index) {
+        Ok(path) => path,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Ledger".to_string(), e)))),
+    };
+    // entries edited after the day was sealed no longer add up to the signed root
+    let leaf = LedgerEntry::leaf_for(entry.sequence, &entry.tx_id, &entry.chain_head);
+    let anchored = transaction.hash.iter().any(|event| event.hash == entry.chain_head);
+    if !anchored || leaf != entry.leaf || !merkle::verify(&leaf, &path, &root.root) {
+        return Err((Status::InternalServerError, Json(ErrorResponse::new("Ledger".to_string(), format!("Ledger entries of {} do not match the published root", entry.day)))));
+    }
+    Ok(Json(InclusionProof { entry, index, path, root }))
+}
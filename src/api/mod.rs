@@ -2,4 +2,15 @@ pub mod account;
 pub mod fiat;
 pub mod crypto;
 pub mod transaction;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod statement;
+pub mod reconciliation;
+pub mod webhook;
+pub mod integrity;
+pub mod ledger;
+pub mod audit;
+pub mod closure;
+pub mod grant;
+pub mod user;
+pub mod api_key;
+pub mod password;
\ No newline at end of file
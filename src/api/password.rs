@@ -0,0 +1,201 @@
+use std::sync::Arc;
+
+use mongodb::bson::{doc, Bson};
+use revolt_rocket_okapi::openapi;
+use rocket::{http::Status, post, serde::json::Json, State};
+
+use crate::{
+    api::{api_key::revoke_all_keys, auth::end_all_sessions},
+    domain::{
+        api_key::ApiKey,
+        audit::{AuditEntry, AuditOutcome},
+        password_reset::PasswordReset,
+        session::UserSession,
+        user::{User, UserPublic},
+    },
+    dto::user::{ChangePasswordRequest, ForgotPasswordRequest, PasswordResetRequested, ResetPasswordRequest},
+    fairings::{api_key::SignedJson, auth::AuthorizedUser, request_context::RequestContext},
+    mongo::{modify, Db, StoreError, VersionedDb},
+    notifier::{Notification, Notifier},
+    response::error::ErrorResponse,
+    security::{
+        audit,
+        jwt::{check_password, hash_text},
+        password::PasswordPolicy,
+    },
+};
+
+/// Needs the current password. Every session of the user ends, including the caller's, and its
+/// API keys and outstanding reset tokens stop working.
+#[openapi(tag = "Auths")]
+#[post("/auths/password", format = "json", data = "<request>")]
+pub async fn change_password(
+    request: SignedJson<ChangePasswordRequest>,
+    user_db: &State<Db<User>>,
+    session_db: &State<VersionedDb<UserSession>>,
+    reset_db: &State<VersionedDb<PasswordReset>>,
+    key_db: &State<Db<ApiKey>>,
+    policy: &State<PasswordPolicy>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<UserPublic>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "auth.password_change", &_auth.user_id);
+    let user = match user_db.get_by_id(&_auth.user_id).await {
+        Ok(user) => user,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Password".to_string(), e))))).await,
+    };
+    if let Err(e) = check_password(&user, request.current_password.clone()) {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err(e)).await;
+    }
+    if request.new_password == request.current_password {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Invalid password".to_string(), "New password must differ from the current one".to_string()))))).await;
+    }
+    let result = set_password(user, &request.new_password, user_db, session_db, reset_db, key_db, policy, "password change").await;
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+/// Sends a single-use reset token to the email when it is registered, retiring the ones sent
+/// before. Answers the same either way so the endpoint does not tell which emails have accounts.
+#[openapi(tag = "Auths")]
+#[post("/auths/password/forgot", format = "json", data = "<request>")]
+pub async fn forgot_password(
+    request: Json<ForgotPasswordRequest>,
+    user_db: &State<Db<User>>,
+    reset_db: &State<VersionedDb<PasswordReset>>,
+    policy: &State<PasswordPolicy>,
+    notifier: &State<Arc<dyn Notifier>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+) -> Result<Json<PasswordResetRequested>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, None, "auth.password_reset_request", &request.email);
+    let requested = Json(PasswordResetRequested {
+        message: "If the email is registered, a reset token was sent to it".to_string(),
+    });
+    let user = match user_db.get_by_fields(vec!["email".to_string()], vec![request.email.clone()]).await {
+        Ok(users) if users.len() == 1 => users[0].clone(),
+        _ => return audit::outcome(audit_db.inner().as_ref(), entry.outcome(AuditOutcome::Denied, Some("Unknown email".to_string())), Ok(requested)).await,
+    };
+    let entry = entry.by(user.id.clone(), user.role.to_string());
+    if let Err(e) = retire_resets(reset_db, &user.id).await {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err(e)).await;
+    }
+    let (reset, token) = PasswordReset::new(user.id.clone(), policy.reset_ttl);
+    if let Err(e) = reset_db.create(reset.clone()).await {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::InternalServerError, Json(ErrorResponse::new("Error creating password reset".to_string(), e))))).await;
+    }
+    let notification = Notification {
+        to: user.email.clone(),
+        subject: "Password reset".to_string(),
+        body: format!(
+            "Use this token to reset your password before {}: {}",
+            reset.expires_at, token
+        ),
+    };
+    // a failed send answers like a sent one, an error here would tell the email is registered
+    if let Err(e) = notifier.send(&notification).await {
+        println!("Error sending password reset: {}", e);
+        let entry = entry.outcome(AuditOutcome::Failure, Some(format!("Error sending password reset: {}", e)));
+        return audit::outcome(audit_db.inner().as_ref(), entry, Ok(requested)).await;
+    }
+    audit::outcome(audit_db.inner().as_ref(), entry, Ok(requested)).await
+}
+
+/// Sets a new password with a token from `forgot_password`. The token works once, every session
+/// of the user ends and its API keys are revoked.
+#[openapi(tag = "Auths")]
+#[post("/auths/password/reset", format = "json", data = "<request>")]
+pub async fn reset_password(
+    request: Json<ResetPasswordRequest>,
+    user_db: &State<Db<User>>,
+    session_db: &State<VersionedDb<UserSession>>,
+    reset_db: &State<VersionedDb<PasswordReset>>,
+    key_db: &State<Db<ApiKey>>,
+    policy: &State<PasswordPolicy>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+) -> Result<Json<UserPublic>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, None, "auth.password_reset", "");
+    let invalid_token = || (Status::BadRequest, Json(ErrorResponse::new("Password reset".to_string(), "Reset token is invalid, used or expired".to_string())));
+    let reset = match reset_db.get_by_id(&PasswordReset::id_of(&request.token)).await {
+        Ok(reset) if reset.is_usable() => reset,
+        _ => return audit::outcome(audit_db.inner().as_ref(), entry, Err(invalid_token())).await,
+    };
+    let user = match user_db.get_by_id(&reset.user_id).await {
+        Ok(user) => user,
+        Err(_) => return audit::outcome(audit_db.inner().as_ref(), entry, Err(invalid_token())).await,
+    };
+    let mut entry = entry.by(user.id.clone(), user.role.to_string());
+    entry.resource = user.id.clone();
+    // checked before the token is spent, so a rejected password can be retried with it
+    if let Err(e) = policy.check(&request.new_password, &user.email) {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Invalid password".to_string(), e))))).await;
+    }
+    let mut consumed = reset.clone();
+    if consumed.consume().is_err() {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err(invalid_token())).await;
+    }
+    match reset_db.update_versioned(&reset.id, consumed).await {
+        Ok(_) => (),
+        // another request spent the token first
+        Err(StoreError::Conflict { .. }) => return audit::outcome(audit_db.inner().as_ref(), entry, Err(invalid_token())).await,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err(ErrorResponse::from_store("Password reset", e))).await,
+    };
+    let result = set_password(user, &request.new_password, user_db, session_db, reset_db, key_db, policy, "password reset").await;
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn set_password(
+    mut user: User,
+    password: &str,
+    user_db: &State<Db<User>>,
+    session_db: &State<VersionedDb<UserSession>>,
+    reset_db: &State<VersionedDb<PasswordReset>>,
+    key_db: &State<Db<ApiKey>>,
+    policy: &State<PasswordPolicy>,
+    reason: &str,
+) -> Result<Json<UserPublic>, (Status, Json<ErrorResponse>)> {
+    if let Err(e) = policy.check(password, &user.email) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Invalid password".to_string(), e))));
+    }
+    user.password = match hash_text(password.to_string(), policy.hash_cost) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return Err((
+                Status::InternalServerError,
+                Json(ErrorResponse::new(
+                    "Error hashing password".to_string(),
+                    "Error hashing password".to_string(),
+                )),
+            ))
+        }
+    };
+    let user = match user_db.update_by_id(&user.id.clone(), user).await {
+        Ok(user) => user,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Password".to_string(), e)))),
+    };
+    end_all_sessions(session_db, &user.id, reason).await?;
+    revoke_all_keys(key_db, &user.id).await?;
+    retire_resets(reset_db, &user.id).await?;
+    Ok(Json(user.to_response()))
+}
+
+/// Spends every reset of the user still usable, so no earlier token outlives a newer one or a
+/// password that was set since.
+async fn retire_resets(
+    reset_db: &State<VersionedDb<PasswordReset>>,
+    user_id: &str,
+) -> Result<(), (Status, Json<ErrorResponse>)> {
+    let resets = match reset_db.find(doc! {"user_id": user_id, "used_at": Bson::Null}, doc! {}, 0).await {
+        Ok(resets) => resets,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Password reset".to_string(), e)))),
+    };
+    for reset in resets.into_iter().filter(|reset| reset.is_usable()) {
+        match modify(reset_db.inner().as_ref(), &reset.id, |reset: &mut PasswordReset| reset.consume()).await {
+            // spent or expired meanwhile, either way it no longer works
+            Ok(_) | Err(StoreError::Rejected(_)) => (),
+            Err(e) => return Err(ErrorResponse::from_store("Password reset", e)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,387 @@
+use mongodb::bson::doc;
+use revolt_rocket_okapi::openapi;
+use rocket::{get, http::Status, post, serde::json::Json, State};
+
+use crate::{
+    api::fiat::confirm_fiat_deposit,
+    domain::{
+        audit::AuditEntry,
+        event::DomainEvent,
+        ledger::Fiat,
+        payment_reference,
+        reconciliation::{BankLine, ImportFormat, MatchedLine, ReviewItem, ReviewStatus, StatementImport},
+        transaction::{Transaction, TransactionStatus, TransactionType},
+    },
+    dto::reconciliation::{ReviewResolution, StatementImportRequest},
+    fairings::{api_key::SignedJson, auth::{AuthorizedUser, Permitted, ReportsRead, TransactionsApprove}, request_context::RequestContext},
+    import::parse_statement,
+    mongo::{finish, Crud, Db, LedgerDb, LedgerStore, Session, StoreError, VersionedDb, VersionedStore},
+    response::error::ErrorResponse,
+    security::audit,
+};
+
+const AMOUNT_TOLERANCE: f64 = 0.005;
+
+enum LineOutcome {
+    Matched(MatchedLine),
+    Duplicate,
+    Review(String),
+}
+
+#[openapi(tag = "Reconciliation")]
+#[post("/reconciliation/imports", format = "json", data = "<request>")]
+pub async fn import_bank_statement(
+    request: SignedJson<StatementImportRequest>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    import_db: &State<Db<StatementImport>>,
+    review_db: &State<VersionedDb<ReviewItem>>,
+    outbox: &State<Db<DomainEvent>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<TransactionsApprove>,
+    _auth: AuthorizedUser,
+) -> Result<Json<StatementImport>, (Status, Json<ErrorResponse>)> {
+    let mut entry = audit::entry(&context, Some(&_auth), "reconciliation.import", "reconciliation/imports");
+    let result = import_statement(request, transaction_db, fiat_db, import_db, review_db, outbox, &_auth).await;
+    if let Ok(import) = &result {
+        entry.resource = format!("reconciliation/imports/{}", import.id);
+    }
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn import_statement(
+    request: SignedJson<StatementImportRequest>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    import_db: &State<Db<StatementImport>>,
+    review_db: &State<VersionedDb<ReviewItem>>,
+    outbox: &State<Db<DomainEvent>>,
+    _auth: &AuthorizedUser,
+) -> Result<Json<StatementImport>, (Status, Json<ErrorResponse>)> {
+    let format = match ImportFormat::from_str(&request.format) {
+        Ok(format) => format,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+    };
+    let lines = match parse_statement(&format, &request.content, request.mapping.as_ref()) {
+        Ok(lines) => lines,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+    };
+    let mut import = StatementImport::new(format, _auth.user_id.clone(), lines.len());
+    let mut reviews = Vec::new();
+    for line in lines {
+        if !line.credit {
+            import.skipped_debits += 1;
+            continue;
+        }
+        match match_line(transaction_db.inner().as_ref(), fiat_db.inner().as_ref(), outbox.inner().as_ref(), &line, &_auth.user_id).await {
+            LineOutcome::Matched(matched) => import.matched.push(matched),
+            LineOutcome::Duplicate => import.duplicates += 1,
+            LineOutcome::Review(reason) => {
+                let review = ReviewItem::new(import.id.clone(), line, reason);
+                import.unmatched.push(review.id.clone());
+                reviews.push(review);
+            }
+        }
+    }
+    if !reviews.is_empty() {
+        match review_db.create_many(reviews).await {
+            Ok(_) => (),
+            Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+        };
+    }
+    match import_db.create(import.clone()).await {
+        Ok(_) => (),
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+    };
+    Ok(Json(import))
+}
+
+#[openapi(tag = "Reconciliation")]
+#[get("/reconciliation/imports/<id>", format = "json")]
+pub async fn get_bank_statement_import(
+    id: String,
+    import_db: &State<Db<StatementImport>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<ReportsRead>,
+    _auth: AuthorizedUser,
+) -> Result<Json<StatementImport>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "reconciliation.get_import", &format!("reconciliation/imports/{}", id));
+    let result = match import_db.get_by_id(&id).await {
+        Ok(import) => Ok(Json(import)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+#[openapi(tag = "Reconciliation")]
+#[get("/reconciliation/reviews?<status>&<limit>", format = "json")]
+pub async fn get_review_queue(
+    status: Option<String>,
+    limit: Option<usize>,
+    review_db: &State<VersionedDb<ReviewItem>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<ReportsRead>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<ReviewItem>>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "reconciliation.list_reviews", "reconciliation/reviews");
+    let status = status.unwrap_or("Open".to_string());
+    let result = match review_db
+        .find(doc! {"status": status}, doc! {"created_at": 1}, limit.unwrap_or(50))
+        .await
+    {
+        Ok(items) => Ok(Json(items)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+#[openapi(tag = "Reconciliation")]
+#[post("/reconciliation/reviews/<id>/resolve", format = "json", data = "<resolution>")]
+pub async fn resolve_review_item(
+    id: String,
+    resolution: SignedJson<ReviewResolution>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    review_db: &State<VersionedDb<ReviewItem>>,
+    outbox: &State<Db<DomainEvent>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<TransactionsApprove>,
+    _auth: AuthorizedUser,
+) -> Result<Json<ReviewItem>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "reconciliation.resolve", &format!("reconciliation/reviews/{}", id));
+    let item = match review_db.get_by_id(&id).await {
+        Ok(item) => item,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e))))).await,
+    };
+    let result = resolve_item(item.clone(), resolution, transaction_db, fiat_db, review_db, outbox, _auth).await;
+    let entry = match &result {
+        Ok(after) => entry.changes(&item, &after.0),
+        Err(_) => entry,
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn resolve_item(
+    mut item: ReviewItem,
+    resolution: SignedJson<ReviewResolution>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    review_db: &State<VersionedDb<ReviewItem>>,
+    outbox: &State<Db<DomainEvent>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<ReviewItem>, (Status, Json<ErrorResponse>)> {
+    if item.status != ReviewStatus::Open {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), "Review item is not open".to_string()))));
+    }
+    let tx = match transaction_db.get_by_id(&resolution.tx_id).await {
+        Ok(tx) => tx,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+    };
+    if tx.transaction_type != TransactionType::Deposit || tx.transaction_status != TransactionStatus::Pending {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), "Transaction is not a pending deposit".to_string()))));
+    }
+    let received = if resolution.accept_received_amount.unwrap_or(false) {
+        Some(item.line.amount)
+    } else {
+        None
+    };
+    match item.close(ReviewStatus::Resolved, _auth.user_id.clone(), Some(resolution.tx_id.clone())) {
+        Ok(_) => (),
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+    };
+    let mut session = match fiat_db.get_session().await {
+        Ok(session) => session,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+    };
+    let result = resolve_in(
+        &mut *session,
+        item,
+        tx,
+        received,
+        transaction_db.inner().as_ref(),
+        fiat_db.inner().as_ref(),
+        review_db.inner().as_ref(),
+        outbox.inner().as_ref(),
+        _auth.user_id,
+    )
+    .await;
+    match finish(session, result).await {
+        Ok(item) => Ok(Json(item)),
+        Err(e) => Err(ErrorResponse::from_store("Reconciliation", e)),
+    }
+}
+
+/// Closes the item before crediting its line, both in `session`: of two resolutions of the same
+/// item only the first closes it, the other conflicts and credits nothing.
+async fn resolve_in(
+    session: &mut dyn Session,
+    item: ReviewItem,
+    tx: Transaction,
+    received: Option<f64>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    fiat_db: &dyn LedgerStore<Fiat>,
+    review_db: &dyn VersionedStore<ReviewItem>,
+    outbox: &dyn Crud<DomainEvent>,
+    id_confirmer: String,
+) -> Result<ReviewItem, StoreError> {
+    let bank_reference = item.line.bank_reference.clone();
+    let item = review_db.update_versioned_in(session, &item.id.clone(), item).await?;
+    confirm_fiat_deposit(session, transaction_db, fiat_db, outbox, tx, id_confirmer, bank_reference, received).await?;
+    Ok(item)
+}
+
+#[openapi(tag = "Reconciliation")]
+#[post("/reconciliation/reviews/<id>/dismiss", format = "json")]
+pub async fn dismiss_review_item(
+    id: String,
+    review_db: &State<VersionedDb<ReviewItem>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<TransactionsApprove>,
+    _auth: AuthorizedUser,
+) -> Result<Json<ReviewItem>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "reconciliation.dismiss", &format!("reconciliation/reviews/{}", id));
+    let item = match review_db.get_by_id(&id).await {
+        Ok(item) => item,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e))))).await,
+    };
+    let mut dismissed = item.clone();
+    let result = match dismissed.close(ReviewStatus::Dismissed, _auth.user_id, None) {
+        Ok(_) => match review_db.update_versioned(&id, dismissed).await {
+            Ok(dismissed) => Ok(Json(dismissed)),
+            Err(e) => Err(ErrorResponse::from_store("Reconciliation", e)),
+        },
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Reconciliation".to_string(), e)))),
+    };
+    let entry = match &result {
+        Ok(after) => entry.changes(&item, &after.0),
+        Err(_) => entry,
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn match_line(
+    transaction_db: &dyn VersionedStore<Transaction>,
+    fiat_db: &dyn LedgerStore<Fiat>,
+    outbox: &dyn Crud<DomainEvent>,
+    line: &BankLine,
+    id_confirmer: &str,
+) -> LineOutcome {
+    // lines without a reference cannot be told apart, they are matched on the remittance alone
+    if let Some(reference) = line.reference() {
+        match transaction_db
+            .find(doc! {"transaction_type": "Deposit", "external_id": reference}, doc! {}, 1)
+            .await
+        {
+            Ok(found) if !found.is_empty() => return LineOutcome::Duplicate,
+            Ok(_) => (),
+            Err(e) => return LineOutcome::Review(e),
+        }
+    }
+    let tokens = line.reference_tokens();
+    let references = payment_reference::find_all(&line.remittance);
+    if tokens.is_empty() && references.is_empty() {
+        return LineOutcome::Review("No deposit reference in remittance information".to_string());
+    }
+    let candidates = match transaction_db
+        .find(
+            doc! {
+                "transaction_type": "Deposit",
+                "$or": [{"payment_reference": {"$in": references}}, {"tx_id": {"$in": tokens}}],
+            },
+            doc! {},
+            2,
+        )
+        .await
+    {
+        Ok(candidates) => candidates,
+        Err(e) => return LineOutcome::Review(e),
+    };
+    let tx = match candidates.len() {
+        0 => return LineOutcome::Review("No deposit matches the reference".to_string()),
+        1 => candidates[0].clone(),
+        _ => return LineOutcome::Review("Reference matches several deposits".to_string()),
+    };
+    if tx.transaction_status != TransactionStatus::Pending {
+        return LineOutcome::Review(format!("Deposit {} is {}", tx.tx_id, tx.transaction_status));
+    }
+    if tx.is_expired() {
+        return LineOutcome::Review(format!("Deposit {} has expired", tx.tx_id));
+    }
+    if let Some(currency) = &line.currency {
+        if currency != &tx.asset {
+            return LineOutcome::Review(format!("Currency mismatch: expected {}", tx.asset));
+        }
+    }
+    if line.amount < tx.amount - AMOUNT_TOLERANCE {
+        return LineOutcome::Review(format!("Underpaid: expected {}, received {}", tx.amount, line.amount));
+    }
+    if line.amount > tx.amount + AMOUNT_TOLERANCE {
+        return LineOutcome::Review(format!("Overpaid: expected {}, received {}", tx.amount, line.amount));
+    }
+    let tx_id = tx.tx_id.clone();
+    let mut session = match fiat_db.get_session().await {
+        Ok(session) => session,
+        Err(e) => return LineOutcome::Review(e),
+    };
+    let result = confirm_fiat_deposit(&mut *session, transaction_db, fiat_db, outbox, tx, id_confirmer.to_string(), line.bank_reference.clone(), None).await;
+    match finish(session, result).await {
+        Ok(_) => LineOutcome::Matched(MatchedLine {
+            tx_id,
+            bank_reference: line.bank_reference.clone(),
+            amount: line.amount,
+        }),
+        Err(e) => LineOutcome::Review(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::{
+        domain::asset::{Asset, AssetType},
+        memory::MemoryRepository,
+        mongo::{LedgerOp, LedgerOperations, StoreError, Transactional},
+    };
+
+    #[rocket::async_test]
+    async fn a_review_item_credits_its_line_once() {
+        env::set_var("HASH_CHAIN_SECRET", "chain-test-secret");
+        let usd = Asset { name: "United State Dollar".to_string(), symbol: "USD".to_string(), asset_type: AssetType::Fiat };
+        let fiats = MemoryRepository::<Fiat>::new("id".to_string());
+        fiats.create(Fiat::new("acc1".to_string(), usd).unwrap()).await.unwrap();
+        fiats.apply("acc1_USD", LedgerOp::Deposit(10.0)).await.unwrap();
+        let transactions = MemoryRepository::<Transaction>::new("tx_id".to_string());
+        let tx = Transaction::new_deposit("USD".to_string(), 10.0, "acc1".to_string(), 1);
+        transactions.create(tx.clone()).await.unwrap();
+        let reviews = MemoryRepository::<ReviewItem>::new("id".to_string());
+        let outbox = MemoryRepository::<DomainEvent>::new("id".to_string());
+        let line = BankLine {
+            booked_at: "2024-01-02".to_string(),
+            amount: 10.0,
+            currency: Some("USD".to_string()),
+            credit: true,
+            bank_reference: "BANK1".to_string(),
+            remittance: "no reference".to_string(),
+        };
+        let mut item = ReviewItem::new("import".to_string(), line, "No reference found".to_string());
+        reviews.create(item.clone()).await.unwrap();
+        item.close(ReviewStatus::Resolved, "operator".to_string(), Some(tx.tx_id.clone())).unwrap();
+
+        // both resolutions read the item while it was open
+        let mut session = fiats.get_session().await.unwrap();
+        let result = resolve_in(&mut *session, item.clone(), tx.clone(), None, &transactions, &fiats, &reviews, &outbox, "operator".to_string()).await;
+        assert!(finish(session, result).await.is_ok());
+        let mut session = fiats.get_session().await.unwrap();
+        let result = resolve_in(&mut *session, item.clone(), tx.clone(), None, &transactions, &fiats, &reviews, &outbox, "operator".to_string()).await;
+        assert!(matches!(finish(session, result).await, Err(StoreError::Conflict { .. })));
+        assert_eq!(fiats.get_by_id("acc1_USD").await.unwrap().balance, 10.0);
+        assert_eq!(reviews.get_by_id(&item.id).await.unwrap().status, ReviewStatus::Resolved);
+    }
+}
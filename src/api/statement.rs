@@ -0,0 +1,213 @@
+use chrono::DateTime;
+use mongodb::bson::doc;
+use revolt_rocket_okapi::openapi;
+use rocket::{get, http::{ContentType, Status}, post, serde::json::Json, State};
+
+use crate::{
+    domain::{
+        account::Account,
+        asset::{AssetManager, AssetType},
+        grant::GrantScope,
+        statement::{JobStatus, Statement, StatementFormat, StatementJob, StatementJobPublic},
+        transaction::Transaction,
+    },
+    dto::{statement::StatementRequest, transaction::normalize_date},
+    export::render_statement,
+    fairings::{api_key::SignedJson, auth::AuthorizedUser},
+    mongo::{Crud, Db, VersionedDb, VersionedStore},
+    response::{custom::Download, error::ErrorResponse},
+    security::permissions::can_access,
+};
+
+const SYNC_STATEMENT_MAX_DAYS: i64 = 31;
+
+#[openapi(tag = "Statements")]
+#[get("/accounts/<id>/statements/<symbol>?<from>&<to>", format = "json")]
+pub async fn get_statement(
+    id: String,
+    symbol: String,
+    from: String,
+    to: String,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Statement>, (Status, Json<ErrorResponse>)> {
+    if !can_access(_auth, &id, GrantScope::ViewTransactions) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), "You can only get your own account".to_string()))));
+    };
+    match account_db.get_by_id(&id).await {
+        Ok(account) => account,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), e)))),
+    };
+    let (from, to) = match statement_period(&from, &to) {
+        Ok(period) => period,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), e)))),
+    };
+    match build_statement(transaction_db.inner().as_ref(), &id, &symbol.to_uppercase(), &from, &to).await {
+        Ok(statement) => Ok(Json(statement)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), e)))),
+    }
+}
+
+#[openapi(tag = "Statements")]
+#[post("/accounts/<id>/statements", format = "json", data = "<request>")]
+pub async fn request_statement(
+    id: String,
+    request: SignedJson<StatementRequest>,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    job_db: &State<Db<StatementJob>>,
+    asset_master: &State<AssetManager>,
+    _auth: AuthorizedUser,
+) -> Result<Json<StatementJobPublic>, (Status, Json<ErrorResponse>)> {
+    if !can_access(_auth, &id, GrantScope::ViewTransactions) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), "You can only get your own account".to_string()))));
+    };
+    match account_db.get_by_id(&id).await {
+        Ok(account) => account,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), e)))),
+    };
+    let format = match StatementFormat::from_str(&request.format) {
+        Ok(format) => format,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), e)))),
+    };
+    let asset = match asset_master.get_by_symbol(&request.symbol.to_uppercase()) {
+        Some(asset) => asset,
+        None => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), "Asset not found".to_string())))),
+    };
+    if format.fiat_only() && asset.asset_type != AssetType::Fiat {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), format!("{} statements are only available for fiat ledgers", format)))));
+    }
+    let (from, to) = match statement_period(&request.from, &request.to) {
+        Ok(period) => period,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), e)))),
+    };
+    let mut job = StatementJob::new(id, request.symbol.to_uppercase(), from, to, format);
+    match job_db.create(job.clone()).await {
+        Ok(_) => (),
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), e)))),
+    };
+    if period_days(&job.from, &job.to) <= SYNC_STATEMENT_MAX_DAYS {
+        run_statement_job(transaction_db.inner().as_ref(), job_db.inner().as_ref(), &mut job).await;
+        return Ok(Json(job.to_response()));
+    }
+    let transaction_db = transaction_db.inner().clone();
+    let job_db = job_db.inner().clone();
+    let response = job.to_response();
+    rocket::tokio::spawn(async move {
+        run_statement_job(transaction_db.as_ref(), job_db.as_ref(), &mut job).await;
+    });
+    Ok(Json(response))
+}
+
+#[openapi(tag = "Statements")]
+#[get("/statements/<job_id>", format = "json")]
+pub async fn get_statement_job(
+    job_id: String,
+    job_db: &State<Db<StatementJob>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<StatementJobPublic>, (Status, Json<ErrorResponse>)> {
+    let job = match job_db.get_by_id(&job_id).await {
+        Ok(job) => job,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), e)))),
+    };
+    if !can_access(_auth, &job.account_number, GrantScope::ViewTransactions) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), "You can only get your own account".to_string()))));
+    };
+    Ok(Json(job.to_response()))
+}
+
+#[openapi(tag = "Statements")]
+#[get("/statements/<job_id>/download")]
+pub async fn download_statement(
+    job_id: String,
+    job_db: &State<Db<StatementJob>>,
+    _auth: AuthorizedUser,
+) -> Result<Download, (Status, Json<ErrorResponse>)> {
+    let job = match job_db.get_by_id(&job_id).await {
+        Ok(job) => job,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), e)))),
+    };
+    if !can_access(_auth, &job.account_number, GrantScope::ViewTransactions) {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), "You can only get your own account".to_string()))));
+    };
+    let body = match (&job.status, job.content.clone()) {
+        (JobStatus::Done, Some(content)) => content,
+        _ => return Err((Status::BadRequest, Json(ErrorResponse::new("Statement".to_string(), format!("Statement is not ready: {:?}", job.status))))),
+    };
+    let content_type = match job.format {
+        StatementFormat::Json => ContentType::JSON,
+        StatementFormat::Csv => ContentType::CSV,
+        StatementFormat::Pdf => ContentType::PDF,
+        StatementFormat::Camt053 | StatementFormat::Camt052 => ContentType::XML,
+        StatementFormat::Mt940 => ContentType::Plain,
+        StatementFormat::Ofx => ContentType::new("application", "x-ofx"),
+    };
+    Ok(Download {
+        content_type,
+        filename: format!("statement_{}_{}.{}", job.account_number, job.asset, job.format.extension()),
+        body,
+    })
+}
+
+pub async fn build_statement(
+    transaction_db: &dyn VersionedStore<Transaction>,
+    account_number: &str,
+    symbol: &str,
+    from: &str,
+    to: &str,
+) -> Result<Statement, String> {
+    let history = transaction_db
+        .find(
+            doc! {
+                "asset": symbol,
+                "timestamp": {"$lte": to},
+                "$or": [{"from_wallet": account_number}, {"to_wallet": account_number}],
+            },
+            doc! {"timestamp": 1, "tx_id": 1},
+            0,
+        )
+        .await?;
+    Ok(Statement::build(
+        account_number.to_string(),
+        symbol.to_string(),
+        from.to_string(),
+        to.to_string(),
+        history,
+    ))
+}
+
+pub async fn run_statement_job(
+    transaction_db: &dyn VersionedStore<Transaction>,
+    job_db: &dyn Crud<StatementJob>,
+    job: &mut StatementJob,
+) {
+    job.status = JobStatus::Running;
+    if let Err(e) = job_db.update_by_id(&job.id.clone(), job.clone()).await {
+        println!("Error updating statement job {}: {}", job.id, e);
+    }
+    let result = match build_statement(transaction_db, &job.account_number, &job.asset, &job.from, &job.to).await {
+        Ok(statement) => render_statement(&statement, &job.format),
+        Err(e) => Err(e),
+    };
+    job.finish(result);
+    if let Err(e) = job_db.update_by_id(&job.id.clone(), job.clone()).await {
+        println!("Error updating statement job {}: {}", job.id, e);
+    }
+}
+
+fn statement_period(from: &str, to: &str) -> Result<(String, String), String> {
+    let from = normalize_date(from, false)?;
+    let to = normalize_date(to, true)?;
+    if from > to {
+        return Err("Statement period start must be before its end".to_string());
+    }
+    Ok((from, to))
+}
+
+fn period_days(from: &str, to: &str) -> i64 {
+    match (DateTime::parse_from_rfc3339(from), DateTime::parse_from_rfc3339(to)) {
+        (Ok(from), Ok(to)) => (to - from).num_days(),
+        _ => i64::MAX,
+    }
+}
@@ -1,6 +1,6 @@
-use mongodb::ClientSession;
 use revolt_rocket_okapi::openapi;
 use rocket::{
+    get,
     http::Status,
     post,
     serde::{json::Json, DeserializeOwned},
@@ -10,27 +10,119 @@ use serde::Serialize;
 
 use crate::{
     domain::{
+        account::{Account, OwnerPermission},
         asset::{Asset, AssetManager, AssetType},
+        audit::AuditEntry,
+        event::{DomainEvent, EventType},
+        grant::{AccessGrant, GrantScope},
         ledger::{Accounting, Crypto, Fiat, FungibleTradeable},
         transaction::{Transaction, TransactionStatus, TransactionType},
     },
-    dto::transaction::TransactionRequest,
-    fairings::auth::AuthorizedUser,
-    mongo::{Crud, Repository, Transactional},
-    response::error::ErrorResponse,
-    security::permissions::{can_continue, only_admin},
+    dto::transaction::{encode_cursor, TransactionFilter, TransactionRequest},
+    events::record,
+    fairings::{api_key::SignedJson, auth::{AuthorizedUser, Permitted, AccountsRead, TransactionsApprove}, precondition::IfMatch, request_context::RequestContext},
+    mongo::{finish, modify_in, Crud, Db, LedgerDb, LedgerOp, LedgerStore, Session, StoreError, Versioned, VersionedDb, VersionedStore},
+    response::{
+        custom::{CursorPagination, ETagged},
+        error::ErrorResponse,
+    },
+    security::{
+        audit,
+        permissions::{can_access, can_confirm, can_continue, can_operate, transfer_grant},
+    },
 };
 
+#[openapi(tag = "Transactions")]
+#[get("/transactions/<id>", format = "json")]
+pub async fn get_transaction(
+    id: String,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    _auth: AuthorizedUser,
+) -> Result<ETagged<Transaction>, (Status, Json<ErrorResponse>)> {
+    let transaction = match transaction_db.get_by_id(&id).await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            return Err((
+                Status::BadRequest,
+                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
+            ))
+        }
+    };
+    let from = transaction.clone().from_wallet.unwrap_or(" ".to_string());
+    let to = transaction.clone().to_wallet.unwrap_or(" ".to_string());
+    if !(can_access(_auth.clone(), &from, GrantScope::ViewTransactions) || can_access(_auth.clone(), &to, GrantScope::ViewTransactions)) {
+        return Err((
+            Status::BadRequest,
+            Json(ErrorResponse::new(
+                "Invalid transaction".to_string(),
+                "You are not allowed to get this transaction".to_string(),
+            )),
+        ));
+    }
+    Ok(ETagged::new(transaction))
+}
+
+#[openapi(tag = "Transactions")]
+#[get("/transactions?<filter..>", format = "json")]
+pub async fn search_transactions(
+    filter: TransactionFilter,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<AccountsRead>,
+    _auth: AuthorizedUser,
+) -> Result<Json<CursorPagination<Transaction>>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "transaction.search", "transactions");
+    let result = match query_transactions(transaction_db.inner().as_ref(), &filter).await {
+        Ok(page) => Ok(Json(page)),
+        Err(e) => Err((
+            Status::BadRequest,
+            Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
+        )),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+pub async fn query_transactions(
+    transaction_db: &dyn VersionedStore<Transaction>,
+    filter: &TransactionFilter,
+) -> Result<CursorPagination<Transaction>, String> {
+    let limit = filter.page_size();
+    let mut transactions = transaction_db
+        .find(filter.to_filter()?, filter.to_sort()?, limit + 1)
+        .await?;
+    let next_cursor = if transactions.len() > limit {
+        transactions.truncate(limit);
+        transactions.last().map(|last| {
+            let sort_value = match filter.sort_by.as_deref() {
+                Some("amount") => last.amount.to_string(),
+                _ => last.timestamp.clone(),
+            };
+            encode_cursor(&sort_value, &last.tx_id)
+        })
+    } else {
+        None
+    };
+    Ok(CursorPagination {
+        limit: limit as u64,
+        next_cursor,
+        result: transactions,
+    })
+}
+
 #[openapi(tag = "Transactions")]
 #[post("/transactions", format = "json", data = "<transaction>")]
 pub async fn submit_transaction(
-    transaction: Json<TransactionRequest>,
-    transaction_db: &State<Repository<Transaction>>,
-    fiat_db: &State<Repository<Fiat>>,
-    crypto_db: &State<Repository<Crypto>>,
+    transaction: SignedJson<TransactionRequest>,
+    account_db: &State<VersionedDb<Account>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    grant_db: &State<VersionedDb<AccessGrant>>,
+    outbox: &State<Db<DomainEvent>>,
     asset_master: &State<AssetManager>,
     _auth: AuthorizedUser,
-) -> Result<Json<Transaction>, (Status, Json<ErrorResponse>)> {
+) -> Result<ETagged<Transaction>, (Status, Json<ErrorResponse>)> {
     let req = transaction.0;
     let asset = match asset_master.get_by_symbol(&req.symbol) {
         Some(asset) => asset,
@@ -44,14 +136,36 @@ pub async fn submit_transaction(
             ))
         }
     };
+    let mut source = None;
+    for account_number in [&req.from, &req.to] {
+        match account_db.get_by_id(account_number).await {
+            Ok(account) if !account.is_open() => {
+                return Err((
+                    Status::BadRequest,
+                    Json(ErrorResponse::new(
+                        "Invalid transaction".to_string(),
+                        format!("Account {} is closed", account_number),
+                    )),
+                ))
+            }
+            Ok(account) if account_number == &req.from => source = Some(account),
+            Ok(_) => (),
+            Err(e) => {
+                return Err((
+                    Status::BadRequest,
+                    Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
+                ))
+            }
+        };
+    }
     let mut id_from = req.from.clone();
     id_from.push('_');
     id_from.push_str(&req.symbol);
     let mut id_to = req.to.clone();
     id_to.push('_');
     id_to.push_str(&req.symbol);
-    let sessions = match get_sessions(transaction_db, fiat_db, crypto_db).await {
-        Ok(sessions) => sessions,
+    let mut session = match transaction_db.get_session().await {
+        Ok(session) => session,
         Err(e) => {
             return Err((
                 Status::BadRequest,
@@ -59,94 +173,55 @@ pub async fn submit_transaction(
             ))
         }
     };
-    match &asset.asset_type {
-        AssetType::Crypto => {
-            match process_tx(&id_from, &id_to, crypto_db, transaction_db, asset, req).await {
-                Ok(transaction) => {
-                    match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Ok(Json(transaction))
-                }
-                Err(e) => {
-                    match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Err((
-                        Status::BadRequest,
-                        Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                    ))
-                }
-            }
-        }
-        AssetType::Fiat => {
-            match process_tx(&id_from, &id_to, fiat_db, transaction_db, asset, req).await {
-                Ok(transaction) => {
-                    match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Ok(Json(transaction))
-                }
-                Err(e) => {
-                    match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Err((
-                        Status::BadRequest,
-                        Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                    ))
-                }
-            }
+    let result = match &asset.asset_type {
+        AssetType::Crypto => process_tx(&mut *session, &id_from, &id_to, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, req.amount, "Basic Transfer").await,
+        AssetType::Fiat => process_tx(&mut *session, &id_from, &id_to, fiat_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, req.amount, "Basic Transfer").await,
+    };
+    // checked on what the transfer debits, fees included, and charged to the grant in the same session
+    let result = match (result, source) {
+        (Ok(transaction), Some(source)) => {
+            authorize_transfer(&mut *session, grant_db.inner().as_ref(), &_auth, &source, transaction).await
         }
+        (Ok(_), None) => Err(StoreError::NotFound(req.from.clone())),
+        (Err(e), _) => Err(e),
+    };
+    match finish(session, result).await {
+        Ok(transaction) => Ok(ETagged::new(transaction)),
+        Err(e) => Err(ErrorResponse::from_store("Invalid transaction", e)),
     }
 }
 #[openapi(tag = "Transactions")]
 #[post("/transactions/<id>/confirm", format = "json")]
 pub async fn confirm_transaction(
     id: String,
-    transaction_db: &State<Repository<Transaction>>,
-    fiat_db: &State<Repository<Fiat>>,
-    crypto_db: &State<Repository<Crypto>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
     asset_master: &State<AssetManager>,
+    if_match: IfMatch,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
     _auth: AuthorizedUser,
-) -> Result<Json<Transaction>, (Status, Json<ErrorResponse>)> {
-    let id_confirmer = "11111".to_string();
+) -> Result<ETagged<Transaction>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "transaction.confirm", &id);
     let transaction = match transaction_db.get_by_id(&id).await {
         Ok(transaction) => transaction,
         Err(e) => {
-            return Err((
-                Status::BadRequest,
-                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-            ))
+            return audit::outcome(
+                audit_db.inner().as_ref(),
+                entry,
+                Err((
+                    Status::BadRequest,
+                    Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
+                )),
+            )
+            .await
         }
     };
     let from = transaction.clone().from_wallet.unwrap_or(" ".to_string());
     let to = transaction.clone().to_wallet.unwrap_or(" ".to_string());
-    if !(can_continue(_auth.clone(), &from) || can_continue(_auth.clone(), &to)) {
+    if !can_confirm(_auth.clone(), &from, &to) {
+        audit::denied(audit_db.inner().as_ref(), entry).await;
         return Err((
             Status::BadRequest,
             Json(ErrorResponse::new(
@@ -155,6 +230,19 @@ pub async fn confirm_transaction(
             )),
         ));
     }
+    let result = confirm_loaded(transaction.clone(), transaction_db, fiat_db, crypto_db, asset_master, if_match).await;
+    audit::changed(audit_db.inner().as_ref(), entry, &transaction, result).await
+}
+
+async fn confirm_loaded(
+    transaction: Transaction,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    asset_master: &State<AssetManager>,
+    if_match: IfMatch,
+) -> Result<ETagged<Transaction>, (Status, Json<ErrorResponse>)> {
+    let id_confirmer = "11111".to_string();
     if transaction.transaction_status != TransactionStatus::Pending {
         return Err((
             Status::BadRequest,
@@ -164,6 +252,15 @@ pub async fn confirm_transaction(
             )),
         ));
     }
+    if !if_match.matches(transaction.version) {
+        return Err((
+            Status::PreconditionFailed,
+            Json(ErrorResponse::new(
+                "Invalid transaction".to_string(),
+                "Transaction was modified since it was read".to_string(),
+            )),
+        ));
+    }
     let asset = match asset_master.get_by_symbol(&transaction.asset) {
         Some(asset) => asset,
         None => {
@@ -176,8 +273,8 @@ pub async fn confirm_transaction(
             ))
         }
     };
-    let sessions = match get_sessions(transaction_db, fiat_db, crypto_db).await {
-        Ok(sessions) => sessions,
+    let mut session = match transaction_db.get_session().await {
+        Ok(session) => session,
         Err(e) => {
             return Err((
                 Status::BadRequest,
@@ -185,100 +282,59 @@ pub async fn confirm_transaction(
             ))
         }
     };
-    match &asset.asset_type {
-        AssetType::Crypto => {
-            match confirm_tx(crypto_db, transaction_db, asset, transaction, id_confirmer).await {
-                Ok(transaction) => {
-                    match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Ok(Json(transaction))
-                }
-                Err(e) => {
-                    match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Err((
-                        Status::BadRequest,
-                        Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                    ))
-                }
-            }
-        }
-        AssetType::Fiat => {
-            match confirm_tx(fiat_db, transaction_db, asset, transaction, id_confirmer).await {
-                Ok(transaction) => {
-                    match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Ok(Json(transaction))
-                }
-                Err(e) => {
-                    match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Err((
-                        Status::BadRequest,
-                        Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                    ))
-                }
-            }
-        }
+    let result = match &asset.asset_type {
+        AssetType::Crypto => confirm_tx(&mut *session, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), asset, transaction, id_confirmer).await,
+        AssetType::Fiat => confirm_tx(&mut *session, fiat_db.inner().as_ref(), transaction_db.inner().as_ref(), asset, transaction, id_confirmer).await,
+    };
+    match finish(session, result).await {
+        Ok(transaction) => Ok(ETagged::new(transaction)),
+        Err(e) => Err(ErrorResponse::from_store("Invalid transaction", e)),
     }
 }
 #[openapi(tag = "Transactions")]
 #[post("/transactions/<id>/complete", format = "json")]
 pub async fn complete_transaction(
     id: String,
-    transaction_db: &State<Repository<Transaction>>,
-    fiat_db: &State<Repository<Fiat>>,
-    crypto_db: &State<Repository<Crypto>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
     asset_master: &State<AssetManager>,
+    if_match: IfMatch,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<TransactionsApprove>,
     _auth: AuthorizedUser,
-) -> Result<Json<Transaction>, (Status, Json<ErrorResponse>)> {
-    let id_confirmer = "11111".to_string();
-    if !only_admin(_auth){
-        return Err((
-            Status::BadRequest,
-            Json(ErrorResponse::new(
-                "Invalid transaction".to_string(),
-                "You are not allowed to complete this transaction".to_string(),
-            )),
-        ));
-    }
+) -> Result<ETagged<Transaction>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "transaction.complete", &id);
     let transaction = match transaction_db.get_by_id(&id).await {
         Ok(transaction) => transaction,
         Err(e) => {
-            return Err((
-                Status::BadRequest,
-                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-            ))
+            return audit::outcome(
+                audit_db.inner().as_ref(),
+                entry,
+                Err((
+                    Status::BadRequest,
+                    Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
+                )),
+            )
+            .await
         }
     };
+    let result = complete_loaded(transaction.clone(), transaction_db, fiat_db, crypto_db, outbox, asset_master, if_match).await;
+    audit::changed(audit_db.inner().as_ref(), entry, &transaction, result).await
+}
+
+async fn complete_loaded(
+    transaction: Transaction,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
+    asset_master: &State<AssetManager>,
+    if_match: IfMatch,
+) -> Result<ETagged<Transaction>, (Status, Json<ErrorResponse>)> {
+    let id_confirmer = "11111".to_string();
     if transaction.transaction_status != TransactionStatus::Confirmed {
         return Err((
             Status::BadRequest,
@@ -288,6 +344,15 @@ pub async fn complete_transaction(
             )),
         ));
     }
+    if !if_match.matches(transaction.version) {
+        return Err((
+            Status::PreconditionFailed,
+            Json(ErrorResponse::new(
+                "Invalid transaction".to_string(),
+                "Transaction was modified since it was read".to_string(),
+            )),
+        ));
+    }
     let asset = match asset_master.get_by_symbol(&transaction.asset) {
         Some(asset) => asset,
         None => {
@@ -300,8 +365,8 @@ pub async fn complete_transaction(
             ))
         }
     };
-    let sessions = match get_sessions(transaction_db, fiat_db, crypto_db).await {
-        Ok(sessions) => sessions,
+    let mut session = match transaction_db.get_session().await {
+        Ok(session) => session,
         Err(e) => {
             return Err((
                 Status::BadRequest,
@@ -309,69 +374,13 @@ pub async fn complete_transaction(
             ))
         }
     };
-    match &asset.asset_type {
-        AssetType::Crypto => {
-            match complete_tx(crypto_db, transaction_db, asset, transaction, id_confirmer).await {
-                Ok(transaction) => {
-                    match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Ok(Json(transaction))
-                }
-                Err(e) => {
-                    match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Err((
-                        Status::BadRequest,
-                        Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                    ))
-                }
-            }
-        }
-        AssetType::Fiat => {
-            match complete_tx(fiat_db, transaction_db, asset, transaction, id_confirmer).await {
-                Ok(transaction) => {
-                    match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Ok(Json(transaction))
-                }
-                Err(e) => {
-                    match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err((
-                                Status::BadRequest,
-                                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                            ))
-                        }
-                    };
-                    Err((
-                        Status::BadRequest,
-                        Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                    ))
-                }
-            }
-        }
+    let result = match &asset.asset_type {
+        AssetType::Crypto => complete_tx(&mut *session, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, transaction, id_confirmer).await,
+        AssetType::Fiat => complete_tx(&mut *session, fiat_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, transaction, id_confirmer).await,
+    };
+    match finish(session, result).await {
+        Ok(transaction) => Ok(ETagged::new(transaction)),
+        Err(e) => Err(ErrorResponse::from_store("Invalid transaction", e)),
     }
 }
 
@@ -379,30 +388,45 @@ pub async fn complete_transaction(
 #[post("/transactions/<id>/fail", format = "json")]
 pub async fn fail_transaction(
     id: String,
-    transaction_db: &State<Repository<Transaction>>,
-    fiat_db: &State<Repository<Fiat>>,
-    crypto_db: &State<Repository<Crypto>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
     asset_master: &State<AssetManager>,
+    if_match: IfMatch,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<TransactionsApprove>,
     _auth: AuthorizedUser,
-) -> Result<Json<Transaction>, (Status, Json<ErrorResponse>)> {
-    if !only_admin(_auth){
-        return Err((
-            Status::BadRequest,
-            Json(ErrorResponse::new(
-                "Invalid transaction".to_string(),
-                "You are not allowed to fail this transaction".to_string(),
-            )),
-        ));
-    }
+) -> Result<ETagged<Transaction>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "transaction.fail", &id);
     let transaction = match transaction_db.get_by_id(&id).await {
         Ok(transaction) => transaction,
         Err(e) => {
-            return Err((
-                Status::BadRequest,
-                Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-            ))
+            return audit::outcome(
+                audit_db.inner().as_ref(),
+                entry,
+                Err((
+                    Status::BadRequest,
+                    Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
+                )),
+            )
+            .await
         }
     };
+    let result = fail_loaded(transaction.clone(), transaction_db, fiat_db, crypto_db, outbox, asset_master, if_match).await;
+    audit::changed(audit_db.inner().as_ref(), entry, &transaction, result).await
+}
+
+async fn fail_loaded(
+    transaction: Transaction,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
+    asset_master: &State<AssetManager>,
+    if_match: IfMatch,
+) -> Result<ETagged<Transaction>, (Status, Json<ErrorResponse>)> {
     if transaction.transaction_status != TransactionStatus::Confirmed {
         return Err((
             Status::BadRequest,
@@ -412,6 +436,15 @@ pub async fn fail_transaction(
             )),
         ));
     }
+    if !if_match.matches(transaction.version) {
+        return Err((
+            Status::PreconditionFailed,
+            Json(ErrorResponse::new(
+                "Invalid transaction".to_string(),
+                "Transaction was modified since it was read".to_string(),
+            )),
+        ));
+    }
     let asset = match asset_master.get_by_symbol(&transaction.asset) {
         Some(asset) => asset,
         None => {
@@ -424,8 +457,8 @@ pub async fn fail_transaction(
             ))
         }
     };
-    let sessions = match get_sessions(transaction_db, fiat_db, crypto_db).await {
-        Ok(sessions) => sessions,
+    let mut session = match transaction_db.get_session().await {
+        Ok(session) => session,
         Err(e) => {
             return Err((
                 Status::BadRequest,
@@ -433,77 +466,28 @@ pub async fn fail_transaction(
             ))
         }
     };
-    match &asset.asset_type {
-        AssetType::Crypto => match fail_tx(crypto_db, transaction_db, asset, transaction).await {
-            Ok(transaction) => {
-                match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err((
-                            Status::BadRequest,
-                            Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                        ))
-                    }
-                };
-                Ok(Json(transaction))
-            }
-            Err(e) => {
-                match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err((
-                            Status::BadRequest,
-                            Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                        ))
-                    }
-                };
-                Err((
-                    Status::BadRequest,
-                    Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                ))
-            }
-        },
-        AssetType::Fiat => match fail_tx(fiat_db, transaction_db, asset, transaction).await {
-            Ok(transaction) => {
-                match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err((
-                            Status::BadRequest,
-                            Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                        ))
-                    }
-                };
-                Ok(Json(transaction))
-            }
-            Err(e) => {
-                match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err((
-                            Status::BadRequest,
-                            Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                        ))
-                    }
-                };
-                Err((
-                    Status::BadRequest,
-                    Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                ))
-            }
-        },
+    let result = match &asset.asset_type {
+        AssetType::Crypto => fail_tx(&mut *session, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, transaction).await,
+        AssetType::Fiat => fail_tx(&mut *session, fiat_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, transaction).await,
+    };
+    match finish(session, result).await {
+        Ok(transaction) => Ok(ETagged::new(transaction)),
+        Err(e) => Err(ErrorResponse::from_store("Invalid transaction", e)),
     }
 }
 #[openapi(tag = "Transactions")]
 #[post("/transactions/<id>/cancel", format = "json")]
 pub async fn cancel_transaction(
     id: String,
-    transaction_db: &State<Repository<Transaction>>,
-    fiat_db: &State<Repository<Fiat>>,
-    crypto_db: &State<Repository<Crypto>>,
+    transaction_db: &State<VersionedDb<Transaction>>,
+    fiat_db: &State<LedgerDb<Fiat>>,
+    crypto_db: &State<LedgerDb<Crypto>>,
+    outbox: &State<Db<DomainEvent>>,
     asset_master: &State<AssetManager>,
+    if_match: IfMatch,
+    account_db: &State<VersionedDb<Account>>,
     _auth: AuthorizedUser,
-) -> Result<Json<Transaction>, (Status, Json<ErrorResponse>)> {    
+) -> Result<ETagged<Transaction>, (Status, Json<ErrorResponse>)> {
     let transaction = match transaction_db.get_by_id(&id).await {
         Ok(transaction) => transaction,
         Err(e) => {
@@ -515,12 +499,28 @@ pub async fn cancel_transaction(
     };
     let from = transaction.clone().from_wallet.unwrap_or(" ".to_string());
     let to = transaction.clone().to_wallet.unwrap_or(" ".to_string());
-    if !(can_continue(_auth.clone(), &from) || can_continue(_auth.clone(), &to)) {
+    let mut allowed = false;
+    for account_number in [&from, &to] {
+        if !can_continue(_auth.clone(), account_number) {
+            continue;
+        }
+        match account_db.get_by_id(account_number).await {
+            Ok(account) if can_operate(_auth.clone(), &account, OwnerPermission::Transact) => allowed = true,
+            Ok(_) => (),
+            Err(e) => {
+                return Err((
+                    Status::BadRequest,
+                    Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
+                ))
+            }
+        };
+    }
+    if !allowed {
         return Err((
             Status::BadRequest,
             Json(ErrorResponse::new(
                 "Invalid transaction".to_string(),
-                "You are not allowed to confirm this transaction".to_string(),
+                "You are not allowed to cancel this transaction".to_string(),
             )),
         ));
     }
@@ -533,6 +533,15 @@ pub async fn cancel_transaction(
             )),
         ));
     }
+    if !if_match.matches(transaction.version) {
+        return Err((
+            Status::PreconditionFailed,
+            Json(ErrorResponse::new(
+                "Invalid transaction".to_string(),
+                "Transaction was modified since it was read".to_string(),
+            )),
+        ));
+    }
     let asset = match asset_master.get_by_symbol(&transaction.asset) {
         Some(asset) => asset,
         None => {
@@ -545,8 +554,8 @@ pub async fn cancel_transaction(
             ))
         }
     };
-    let sessions = match get_sessions(transaction_db, fiat_db, crypto_db).await {
-        Ok(sessions) => sessions,
+    let mut session = match transaction_db.get_session().await {
+        Ok(session) => session,
         Err(e) => {
             return Err((
                 Status::BadRequest,
@@ -554,275 +563,256 @@ pub async fn cancel_transaction(
             ))
         }
     };
-    match &asset.asset_type {
-        AssetType::Crypto => match cancel_tx(crypto_db, transaction_db, asset, transaction).await {
-            Ok(transaction) => {
-                match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err((
-                            Status::BadRequest,
-                            Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                        ))
-                    }
-                };
-                Ok(Json(transaction))
-            }
-            Err(e) => {
-                match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err((
-                            Status::BadRequest,
-                            Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                        ))
-                    }
-                };
-                Err((
-                    Status::BadRequest,
-                    Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                ))
-            }
-        },
-        AssetType::Fiat => match cancel_tx(fiat_db, transaction_db, asset, transaction).await {
-            Ok(transaction) => {
-                match commit_transactions(sessions.0, sessions.1, sessions.2).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err((
-                            Status::BadRequest,
-                            Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                        ))
-                    }
-                };
-                Ok(Json(transaction))
-            }
-            Err(e) => {
-                match abort_transactions(sessions.0, sessions.1, sessions.2).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err((
-                            Status::BadRequest,
-                            Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                        ))
-                    }
-                };
-                Err((
-                    Status::BadRequest,
-                    Json(ErrorResponse::new("Invalid transaction".to_string(), e)),
-                ))
-            }
-        },
+    let result = match &asset.asset_type {
+        AssetType::Crypto => cancel_tx(&mut *session, crypto_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, transaction).await,
+        AssetType::Fiat => cancel_tx(&mut *session, fiat_db.inner().as_ref(), transaction_db.inner().as_ref(), outbox.inner().as_ref(), asset, transaction).await,
+    };
+    match finish(session, result).await {
+        Ok(transaction) => Ok(ETagged::new(transaction)),
+        Err(e) => Err(ErrorResponse::from_store("Invalid transaction", e)),
     }
 }
-async fn cancel_tx<
-    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable,
+pub async fn cancel_tx<
+    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable + Versioned,
 >(
-    ledger_db: &Repository<T>,
-    transaction_db: &State<Repository<Transaction>>,
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
     asset: Asset,
     mut transaction: Transaction,
-) -> Result<Transaction, String> {
+) -> Result<Transaction, StoreError> {
     if transaction.transaction_type == TransactionType::Transfer {
         let mut id_from = match transaction.from_wallet.clone() {
             Some(wallet) => wallet,
-            None => return Err("Invalid transaction".to_string()),
+            None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
         };
         let mut id_to = match transaction.to_wallet.clone() {
             Some(wallet) => wallet,
-            None => return Err("Invalid transaction".to_string()),
+            None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
         };
         id_from.push('_');
         id_from.push_str(&asset.symbol);
         id_to.push('_');
         id_to.push_str(&asset.symbol);
-        match get_accounts(ledger_db, &id_from, &id_to).await {
+        match get_accounts(session, ledger_db, &id_from, &id_to).await {
             Ok((mut from, mut to)) => {
-                from.confirm_deposit(transaction.total_amount)?;
-                to.confirm_withdraw(transaction.total_amount)?;
+                // the source held the total with fees, the destination only the amount
+                let (total_amount, amount) = (transaction.total_amount, transaction.amount);
+                from.cancel_withdraw(total_amount)?;
+                to.cancel_deposit(amount)?;
                 transaction.cancel_transaction()?;
-                ledger_db.update_by_id(&id_from, from).await?;
-                ledger_db.update_by_id(&id_to, to).await?;
-                match transaction_db
-                    .update_by_id(&transaction.tx_id, transaction.clone())
-                    .await
-                {
-                    Ok(_) => Ok(transaction),
-                    Err(e) => Err(e),
-                }
+                let transaction = transaction_db
+                    .update_versioned_in(session, &transaction.tx_id.clone(), transaction)
+                    .await?;
+                ledger_db.apply_in(session, &id_from, LedgerOp::CancelWithdraw(total_amount)).await?;
+                ledger_db.apply_in(session, &id_to, LedgerOp::CancelDeposit(amount)).await?;
+                record(session, outbox, EventType::TransactionCancelled, &transaction).await.map_err(StoreError::Database)?;
+                Ok(transaction)
             }
-            Err(e) => Err(e),
+            Err(e) => Err(StoreError::Database(e)),
         }
     } else {
-        Err("Wrong transaction type".to_string())
+        Err(StoreError::Rejected("Wrong transaction type".to_string()))
     }
 }
 
 async fn fail_tx<
-    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable,
+    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable + Versioned,
 >(
-    ledger_db: &Repository<T>,
-    transaction_db: &State<Repository<Transaction>>,
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
     asset: Asset,
     mut transaction: Transaction,
-) -> Result<Transaction, String> {
+) -> Result<Transaction, StoreError> {
     if transaction.transaction_type == TransactionType::Transfer {
         let mut id_from = match transaction.from_wallet.clone() {
             Some(wallet) => wallet,
-            None => return Err("Invalid transaction".to_string()),
+            None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
         };
         let mut id_to = match transaction.to_wallet.clone() {
             Some(wallet) => wallet,
-            None => return Err("Invalid transaction".to_string()),
+            None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
         };
         id_from.push('_');
         id_from.push_str(&asset.symbol);
         id_to.push('_');
         id_to.push_str(&asset.symbol);
-        match get_accounts(ledger_db, &id_from, &id_to).await {
+        match get_accounts(session, ledger_db, &id_from, &id_to).await {
             Ok((mut from, mut to)) => {
-                from.confirm_deposit(transaction.total_amount)?;
-                to.confirm_withdraw(transaction.total_amount)?;
+                // the source held the total with fees, the destination only the amount
+                let (total_amount, amount) = (transaction.total_amount, transaction.amount);
+                from.cancel_withdraw(total_amount)?;
+                to.cancel_deposit(amount)?;
                 transaction.fail_transaction()?;
-                ledger_db.update_by_id(&id_from, from).await?;
-                ledger_db.update_by_id(&id_to, to).await?;
-                match transaction_db
-                    .update_by_id(&transaction.tx_id, transaction.clone())
-                    .await
-                {
-                    Ok(_) => Ok(transaction),
-                    Err(e) => Err(e),
-                }
+                let transaction = transaction_db
+                    .update_versioned_in(session, &transaction.tx_id.clone(), transaction)
+                    .await?;
+                ledger_db.apply_in(session, &id_from, LedgerOp::CancelWithdraw(total_amount)).await?;
+                ledger_db.apply_in(session, &id_to, LedgerOp::CancelDeposit(amount)).await?;
+                record(session, outbox, EventType::TransactionFailed, &transaction).await.map_err(StoreError::Database)?;
+                Ok(transaction)
             }
-            Err(e) => Err(e),
+            Err(e) => Err(StoreError::Database(e)),
         }
     } else {
-        Err("Wrong transaction type".to_string())
+        Err(StoreError::Rejected("Wrong transaction type".to_string()))
     }
 }
-async fn confirm_tx<
-    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable,
+pub async fn confirm_tx<
+    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable + Versioned,
 >(
-    ledger_db: &Repository<T>,
-    transaction_db: &State<Repository<Transaction>>,
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    transaction_db: &dyn VersionedStore<Transaction>,
     asset: Asset,
     mut transaction: Transaction,
     id_confirmer: String,
-) -> Result<Transaction, String> {
+) -> Result<Transaction, StoreError> {
     if transaction.transaction_type == TransactionType::Transfer {
         let mut id_from = match transaction.from_wallet.clone() {
             Some(wallet) => wallet,
-            None => return Err("Invalid transaction".to_string()),
+            None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
         };
         let mut id_to = match transaction.to_wallet.clone() {
             Some(wallet) => wallet,
-            None => return Err("Invalid transaction".to_string()),
+            None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
         };
         id_from.push('_');
         id_from.push_str(&asset.symbol);
         id_to.push('_');
         id_to.push_str(&asset.symbol);
-        match get_accounts(ledger_db, &id_from, &id_to).await {
+        match get_accounts(session, ledger_db, &id_from, &id_to).await {
             Ok(_) => {
                 transaction.confirm_transaction(id_confirmer)?;
-                match transaction_db
-                    .update_by_id(&transaction.tx_id, transaction.clone())
+                transaction_db
+                    .update_versioned_in(session, &transaction.tx_id.clone(), transaction)
                     .await
-                {
-                    Ok(_) => Ok(transaction),
-                    Err(e) => Err(e),
-                }
             }
-            Err(e) => Err(e),
+            Err(e) => Err(StoreError::Database(e)),
         }
     } else {
-        Err("Wrong transaction type".to_string())
+        Err(StoreError::Rejected("Wrong transaction type".to_string()))
     }
 }
-async fn complete_tx<
-    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable,
+pub async fn complete_tx<
+    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable + Versioned,
 >(
-    ledger_db: &Repository<T>,
-    transaction_db: &State<Repository<Transaction>>,
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
     asset: Asset,
     mut transaction: Transaction,
     id_confirmer: String,
-) -> Result<Transaction, String> {
+) -> Result<Transaction, StoreError> {
     if transaction.transaction_type == TransactionType::Transfer {
         let mut id_from = match transaction.from_wallet.clone() {
             Some(wallet) => wallet,
-            None => return Err("Invalid transaction".to_string()),
+            None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
         };
         let mut id_to = match transaction.to_wallet.clone() {
             Some(wallet) => wallet,
-            None => return Err("Invalid transaction".to_string()),
+            None => return Err(StoreError::Rejected("Invalid transaction".to_string())),
         };
         id_from.push('_');
         id_from.push_str(&asset.symbol);
         id_to.push('_');
         id_to.push_str(&asset.symbol);
-        match get_accounts(ledger_db, &id_from, &id_to).await {
+        match get_accounts(session, ledger_db, &id_from, &id_to).await {
             Ok((mut from, mut to)) => {
-                from.confirm_withdraw(transaction.total_amount)?;
-                to.confirm_deposit(transaction.amount)?;
+                let (total_amount, amount) = (transaction.total_amount, transaction.amount);
+                from.confirm_withdraw(total_amount)?;
+                to.confirm_deposit(amount)?;
                 transaction.complete_transaction(id_confirmer)?;
-                ledger_db.update_by_id(&id_from, from).await?;
-                ledger_db.update_by_id(&id_to, to).await?;
-                match transaction_db
-                    .update_by_id(&transaction.tx_id, transaction.clone())
-                    .await
-                {
-                    Ok(_) => Ok(transaction),
-                    Err(e) => Err(e),
-                }
+                let transaction = transaction_db
+                    .update_versioned_in(session, &transaction.tx_id.clone(), transaction)
+                    .await?;
+                ledger_db.apply_in(session, &id_from, LedgerOp::ConfirmWithdraw(total_amount)).await?;
+                ledger_db.apply_in(session, &id_to, LedgerOp::ConfirmDeposit(amount)).await?;
+                record(session, outbox, EventType::TransactionCompleted, &transaction).await.map_err(StoreError::Database)?;
+                Ok(transaction)
             }
-            Err(e) => Err(e),
+            Err(e) => Err(StoreError::Database(e)),
         }
     } else {
-        Err("Wrong transaction type".to_string())
+        Err(StoreError::Rejected("Wrong transaction type".to_string()))
     }
 }
-async fn process_tx<
-    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable,
+/// Lets `transaction` out of `source` through when the caller may transact on it as an owner,
+/// or counts it against the first grant whose limits it fits.
+async fn authorize_transfer(
+    session: &mut dyn Session,
+    grant_db: &dyn VersionedStore<AccessGrant>,
+    auth: &AuthorizedUser,
+    source: &Account,
+    transaction: Transaction,
+) -> Result<Transaction, StoreError> {
+    let total = transaction.total_amount;
+    if can_operate(auth.clone(), source, OwnerPermission::Transact) {
+        return Ok(transaction);
+    }
+    match transfer_grant(auth, source, total) {
+        Some(grant) => {
+            modify_in(grant_db, session, &grant.id, |grant: &mut AccessGrant| grant.charge(total)).await?;
+            Ok(transaction)
+        }
+        None => Err(StoreError::Rejected("Your permission on the source account does not allow it".to_string())),
+    }
+}
+
+/// Submits a transfer of `amount` from `id_from` to `id_to`, holding it on both ledgers.
+pub async fn process_tx<
+    T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Accounting + FungibleTradeable + Versioned,
 >(
+    session: &mut dyn Session,
     id_from: &str,
     id_to: &str,
-    ledger_db: &Repository<T>,
-    transaction_db: &State<Repository<Transaction>>,
+    ledger_db: &dyn LedgerStore<T>,
+    transaction_db: &dyn VersionedStore<Transaction>,
+    outbox: &dyn Crud<DomainEvent>,
     asset: Asset,
-    req: TransactionRequest,
-) -> Result<Transaction, String> {
-    match get_accounts(ledger_db, id_from, id_to).await {
-        Ok((mut from, mut to)) => {
+    amount: f64,
+    description: &str,
+) -> Result<Transaction, StoreError> {
+    match get_accounts(session, ledger_db, id_from, id_to).await {
+        Ok((from, to)) => {
             let transaction = Transaction::new_transfer(
                 asset.symbol,
-                req.amount,
+                amount,
                 from.get_account_number(),
                 to.get_account_number(),
-                "Basic Transfer".to_string(),
+                description.to_string(),
                 1,
             );
             // let source_amount = req.amount * 0.01;
             // transaction.add_fee("Tx Fee".to_string(), source_amount);
-            from.withdraw(transaction.total_amount)?;
-            to.deposit(transaction.amount)?;
-            ledger_db.update_by_id(id_from, from).await?;
-            ledger_db.update_by_id(id_to, to).await?;
-            match transaction_db.create(transaction.clone()).await {
-                Ok(_) => Ok(transaction),
-                Err(e) => Err(e),
+            ledger_db.apply_in(session, id_from, LedgerOp::Withdraw(transaction.total_amount)).await?;
+            if let Err(e) = ledger_db.apply_in(session, id_to, LedgerOp::Deposit(transaction.amount)).await {
+                // releases the source's hold even where the backend keeps writes of an aborted session
+                ledger_db.apply_in(session, id_from, LedgerOp::CancelWithdraw(transaction.total_amount)).await?;
+                return Err(e);
             }
-        }
-        Err(e) => Err(e),
+            match transaction_db.create_in(session, transaction.clone()).await {
+                Ok(_) => (),
+                Err(e) => return Err(StoreError::Database(e)),
+            };
+            record(session, outbox, EventType::TransferSubmitted, &transaction).await.map_err(StoreError::Database)?;
+            Ok(transaction)
+        }
+        Err(e) => Err(StoreError::Database(e)),
     }
 }
-async fn get_accounts<T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned>(
-    ledger_db: &Repository<T>,
+async fn get_accounts<T: Send + Sync + Clone + Serialize + Unpin + DeserializeOwned + Versioned>(
+    session: &mut dyn Session,
+    ledger_db: &dyn LedgerStore<T>,
     id_from: &str,
     id_to: &str,
 ) -> Result<(T, T), String> {
-    let from_result = ledger_db.get_by_id(id_from).await;
-    let to_result = ledger_db.get_by_id(id_to).await;
+    let from_result = ledger_db.get_by_id_in(session, id_from).await;
+    let to_result = ledger_db.get_by_id_in(session, id_to).await;
     let from = match from_result {
         Ok(from) => from,
         Err(e) => return Err(e),
@@ -833,96 +823,68 @@ async fn get_accounts<T: Send + Sync + Clone + Serialize + Unpin + DeserializeOw
     };
     Ok((from, to))
 }
-async fn get_sessions(
-    tx_db: &Repository<Transaction>,
-    fiat_db: &Repository<Fiat>,
-    crypto_db: &Repository<Crypto>,
-) -> Result<(ClientSession, ClientSession, ClientSession), String> {
-    let tx_session_result = tx_db.get_session().await;
-    let fiat_session_result = fiat_db.get_session().await;
-    let crypto_db_result = crypto_db.get_session().await;
-    let tx_session = match tx_session_result {
-        Ok(mut tx_session) => match tx_session.start_transaction(None).await {
-            Ok(_) => tx_session,
-            Err(e) => {
-                return Err(e.to_string());
-            }
-        },
-        Err(e) => {
-            return Err(e);
-        }
-    };
-    let fiat_session = match fiat_session_result {
-        Ok(mut fiat) => match fiat.start_transaction(None).await {
-            Ok(_) => fiat,
-            Err(e) => {
-                return Err(e.to_string());
-            }
-        },
-        Err(e) => {
-            return Err(e);
-        }
-    };
-    let crypto_session = match crypto_db_result {
-        Ok(mut crypto) => match crypto.start_transaction(None).await {
-            Ok(_) => crypto,
-            Err(e) => {
-                return Err(e.to_string());
-            }
-        },
-        Err(e) => {
-            return Err(e);
-        }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::{
+        memory::MemoryRepository,
+        mongo::{ledger_suite, LedgerOperations, Transactional, VersionedCrud},
     };
-    Ok((tx_session, fiat_session, crypto_session))
-}
-async fn abort_transactions(
-    mut tx: ClientSession,
-    mut fiat: ClientSession,
-    mut crypto: ClientSession,
-) -> Result<(), String> {
-    match tx.abort_transaction().await {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(e.to_string());
-        }
-    }
-    match fiat.abort_transaction().await {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(e.to_string());
-        }
-    }
-    match crypto.abort_transaction().await {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(e.to_string());
+
+    fn usd() -> Asset {
+        Asset {
+            name: "United State Dollar".to_string(),
+            symbol: "USD".to_string(),
+            asset_type: AssetType::Fiat,
         }
     }
-    Ok(())
-}
-async fn commit_transactions(
-    mut tx: ClientSession,
-    mut fiat: ClientSession,
-    mut crypto: ClientSession,
-) -> Result<(), String> {
-    match tx.commit_transaction().await {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(e.to_string());
-        }
+
+    /// Two USD ledgers holding 100 each, `acc1_USD` and `acc2_USD`.
+    async fn ledgers() -> MemoryRepository<Fiat> {
+        env::set_var("HASH_CHAIN_SECRET", "chain-test-secret");
+        let repo = MemoryRepository::new("id".to_string());
+        ledger_suite::fund(&repo).await;
+        repo
     }
-    match fiat.commit_transaction().await {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(e.to_string());
-        }
+
+    #[rocket::async_test]
+    async fn cancelling_releases_what_each_side_held() {
+        let ledgers = ledgers().await;
+        let transactions = MemoryRepository::<Transaction>::new("tx_id".to_string());
+        let outbox = MemoryRepository::<DomainEvent>::new("id".to_string());
+        let mut transaction = Transaction::new_transfer("USD".to_string(), 10.0, "acc1".to_string(), "acc2".to_string(), "Rent".to_string(), 1);
+        transaction.add_fee("Tx Fee".to_string(), 1.0);
+        ledgers.apply("acc1_USD", LedgerOp::Withdraw(transaction.total_amount)).await.unwrap();
+        ledgers.apply("acc2_USD", LedgerOp::Deposit(transaction.amount)).await.unwrap();
+        transactions.create(transaction.clone()).await.unwrap();
+
+        let mut session = ledgers.get_session().await.unwrap();
+        let result = cancel_tx(&mut *session, &ledgers, &transactions, &outbox, usd(), transaction).await;
+        assert_eq!(finish(session, result).await.unwrap().transaction_status, TransactionStatus::Cancelled);
+        let (from, to) = (ledgers.get_by_id("acc1_USD").await.unwrap(), ledgers.get_by_id("acc2_USD").await.unwrap());
+        assert_eq!((from.balance, from.hold), (100.0, 0.0));
+        assert_eq!((to.balance, to.hold), (100.0, 0.0));
     }
-    match crypto.commit_transaction().await {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(e.to_string());
-        }
+
+    #[rocket::async_test]
+    async fn a_rejected_deposit_releases_the_source() {
+        let ledgers = ledgers().await;
+        let transactions = MemoryRepository::<Transaction>::new("tx_id".to_string());
+        let outbox = MemoryRepository::<DomainEvent>::new("id".to_string());
+        let mut closed = ledgers.get_by_id("acc2_USD").await.unwrap();
+        closed.balance = 0.0;
+        closed.close().unwrap();
+        ledgers.update_versioned("acc2_USD", closed).await.unwrap();
+
+        let mut session = ledgers.get_session().await.unwrap();
+        let result = process_tx(&mut *session, "acc1_USD", "acc2_USD", &ledgers, &transactions, &outbox, usd(), 10.0, "Rent").await;
+        assert!(matches!(result, Err(StoreError::Rejected(_))));
+        let from = ledgers.get_by_id_in(&mut *session, "acc1_USD").await.unwrap();
+        assert_eq!((from.balance, from.hold), (100.0, 0.0));
+        assert!(finish(session, result).await.is_err());
+        assert_eq!(transactions.count().await, 0);
     }
-    Ok(())
 }
@@ -0,0 +1,47 @@
+use revolt_rocket_okapi::openapi;
+use rocket::{http::Status, post, serde::json::Json, State};
+
+use crate::{
+    domain::{
+        audit::AuditEntry,
+        user::{User, UserPublic},
+    },
+    dto::user::RoleRequest,
+    fairings::{
+        api_key::SignedJson,
+        auth::{AuthorizedUser, Permitted, UsersManage},
+        request_context::RequestContext,
+    },
+    mongo::Db,
+    response::error::ErrorResponse,
+    security::audit,
+};
+
+/// Gives a user another role, it applies from their next login or token refresh.
+#[openapi(tag = "Users")]
+#[post("/users/<id>/role", format = "json", data = "<request>")]
+pub async fn set_user_role(
+    id: String,
+    request: SignedJson<RoleRequest>,
+    user_db: &State<Db<User>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _permitted: Permitted<UsersManage>,
+    _auth: AuthorizedUser,
+) -> Result<Json<UserPublic>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "user.role", &format!("users/{}", id));
+    if id == _auth.user_id {
+        return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("User".to_string(), "You cannot change your own role".to_string()))))).await;
+    }
+    let before = match user_db.get_by_id(&id).await {
+        Ok(user) => user,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err((Status::BadRequest, Json(ErrorResponse::new("User".to_string(), e))))).await,
+    };
+    let mut user = before.clone();
+    user.role = request.role.clone();
+    let (entry, result) = match user_db.update_by_id(&id, user).await {
+        Ok(user) => (entry.changes(&before, &user), Ok(Json(user.to_response()))),
+        Err(e) => (entry, Err((Status::BadRequest, Json(ErrorResponse::new("User".to_string(), e))))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
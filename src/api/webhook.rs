@@ -0,0 +1,234 @@
+use mongodb::bson::doc;
+use revolt_rocket_okapi::openapi;
+use rocket::{delete, get, http::Status, post, serde::json::Json, State};
+
+use crate::{
+    domain::{
+        account::Account,
+        audit::AuditEntry,
+        user::Permission,
+        webhook::{WebhookDelivery, WebhookSubscription},
+    },
+    dto::webhook::WebhookRequest,
+    fairings::{api_key::SignedJson, auth::AuthorizedUser, request_context::RequestContext},
+    mongo::{Db, VersionedDb},
+    response::error::ErrorResponse,
+    security::{
+        audit,
+        permissions::can_continue,
+    },
+};
+
+#[openapi(tag = "Webhooks")]
+#[post("/webhooks", format = "json", data = "<request>")]
+pub async fn create_webhook(
+    request: SignedJson<WebhookRequest>,
+    account_db: &State<VersionedDb<Account>>,
+    webhook_db: &State<Db<WebhookSubscription>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<WebhookSubscription>, (Status, Json<ErrorResponse>)> {
+    let mut entry = audit::entry(&context, Some(&_auth), "webhook.create", "webhooks");
+    let result = subscribe(request, account_db, webhook_db, _auth).await;
+    if let Ok(subscription) = &result {
+        entry.resource = format!("webhooks/{}", subscription.id);
+    }
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+async fn subscribe(
+    request: SignedJson<WebhookRequest>,
+    account_db: &State<VersionedDb<Account>>,
+    webhook_db: &State<Db<WebhookSubscription>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<WebhookSubscription>, (Status, Json<ErrorResponse>)> {
+    let request = request.0;
+    match &request.account_number {
+        Some(account_number) => {
+            if !can_continue(_auth.clone(), account_number) {
+                return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), "You can only subscribe to your own account".to_string()))));
+            }
+            match account_db.get_by_id(account_number).await {
+                Ok(account) => account,
+                Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e)))),
+            };
+        }
+        None => {
+            if !_auth.has(Permission::AccountsRead) {
+                return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), "Only accounts:read can subscribe to every account".to_string()))));
+            }
+        }
+    }
+    let subscription = match WebhookSubscription::new(request.account_number, request.url, request.event_types, _auth.user_id) {
+        Ok(subscription) => subscription,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e)))),
+    };
+    match webhook_db.create(subscription.clone()).await {
+        Ok(_) => Ok(Json(subscription)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e)))),
+    }
+}
+
+#[openapi(tag = "Webhooks")]
+#[get("/webhooks?<account_number>", format = "json")]
+pub async fn get_webhooks(
+    account_number: Option<String>,
+    webhook_db: &State<Db<WebhookSubscription>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<WebhookSubscription>>, (Status, Json<ErrorResponse>)> {
+    let filter = match account_number {
+        Some(account_number) => {
+            if !can_continue(_auth, &account_number) {
+                return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), "You can only get your own webhooks".to_string()))));
+            }
+            doc! {"account_number": account_number}
+        }
+        None => {
+            if !_auth.has(Permission::AccountsRead) {
+                return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), "Only accounts:read can get every webhook".to_string()))));
+            }
+            doc! {}
+        }
+    };
+    match webhook_db.find(filter, doc! {"created_at": 1}, 0).await {
+        Ok(subscriptions) => Ok(Json(subscriptions.iter().map(WebhookSubscription::redacted).collect())),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e)))),
+    }
+}
+
+#[openapi(tag = "Webhooks")]
+#[get("/webhooks/<id>", format = "json")]
+pub async fn get_webhook(
+    id: String,
+    webhook_db: &State<Db<WebhookSubscription>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<WebhookSubscription>, (Status, Json<ErrorResponse>)> {
+    match owned_subscription(webhook_db, &id, _auth).await {
+        Ok(subscription) => Ok(Json(subscription.redacted())),
+        Err(e) => Err(e),
+    }
+}
+
+#[openapi(tag = "Webhooks")]
+#[delete("/webhooks/<id>", format = "json")]
+pub async fn disable_webhook(
+    id: String,
+    webhook_db: &State<Db<WebhookSubscription>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<WebhookSubscription>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "webhook.disable", &format!("webhooks/{}", id));
+    let before = match owned_subscription(webhook_db, &id, _auth).await {
+        Ok(subscription) => subscription,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err(e)).await,
+    };
+    let mut subscription = before.clone();
+    subscription.active = false;
+    let (entry, result) = match webhook_db.update_by_id(&id, subscription).await {
+        Ok(subscription) => (entry.changes(&before, &subscription), Ok(Json(subscription.redacted()))),
+        Err(e) => (entry, Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e))))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+#[openapi(tag = "Webhooks")]
+#[post("/webhooks/<id>/secret", format = "json")]
+pub async fn rotate_webhook_secret(
+    id: String,
+    webhook_db: &State<Db<WebhookSubscription>>,
+    audit_db: &State<Db<AuditEntry>>,
+    context: RequestContext,
+    _auth: AuthorizedUser,
+) -> Result<Json<WebhookSubscription>, (Status, Json<ErrorResponse>)> {
+    let entry = audit::entry(&context, Some(&_auth), "webhook.rotate_secret", &format!("webhooks/{}", id));
+    let before = match owned_subscription(webhook_db, &id, _auth).await {
+        Ok(subscription) => subscription,
+        Err(e) => return audit::outcome(audit_db.inner().as_ref(), entry, Err(e)).await,
+    };
+    let mut subscription = before.clone();
+    subscription.rotate_secret();
+    let (entry, result) = match webhook_db.update_by_id(&id, subscription).await {
+        Ok(subscription) => (entry.changes(&before, &subscription), Ok(Json(subscription))),
+        Err(e) => (entry, Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e))))),
+    };
+    audit::outcome(audit_db.inner().as_ref(), entry, result).await
+}
+
+#[openapi(tag = "Webhooks")]
+#[get("/webhooks/<id>/deliveries?<status>&<limit>", format = "json")]
+pub async fn get_webhook_deliveries(
+    id: String,
+    status: Option<String>,
+    limit: Option<usize>,
+    webhook_db: &State<Db<WebhookSubscription>>,
+    delivery_db: &State<Db<WebhookDelivery>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<Vec<WebhookDelivery>>, (Status, Json<ErrorResponse>)> {
+    if let Err(e) = owned_subscription(webhook_db, &id, _auth).await {
+        return Err(e);
+    }
+    let mut filter = doc! {"subscription_id": id};
+    if let Some(status) = status {
+        filter.insert("status", status);
+    }
+    match delivery_db.find(filter, doc! {"created_at": -1}, limit.unwrap_or(50)).await {
+        Ok(deliveries) => Ok(Json(deliveries)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e)))),
+    }
+}
+
+#[openapi(tag = "Webhooks")]
+#[post("/webhooks/<id>/deliveries/<delivery_id>/replay", format = "json")]
+pub async fn replay_webhook_delivery(
+    id: String,
+    delivery_id: String,
+    webhook_db: &State<Db<WebhookSubscription>>,
+    delivery_db: &State<Db<WebhookDelivery>>,
+    _auth: AuthorizedUser,
+) -> Result<Json<WebhookDelivery>, (Status, Json<ErrorResponse>)> {
+    let subscription = match owned_subscription(webhook_db, &id, _auth).await {
+        Ok(subscription) => subscription,
+        Err(e) => return Err(e),
+    };
+    if !subscription.active {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), "Webhook is disabled".to_string()))));
+    }
+    let mut delivery = match delivery_db.get_by_id(&delivery_id).await {
+        Ok(delivery) => delivery,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e)))),
+    };
+    if delivery.subscription_id != id {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), "Delivery not found".to_string()))));
+    }
+    delivery.replay();
+    match delivery_db.update_by_id(&delivery_id, delivery).await {
+        Ok(delivery) => Ok(Json(delivery)),
+        Err(e) => Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e)))),
+    }
+}
+
+/// Global subscriptions belong to whoever made them, or anyone with accounts:operate, as long as
+/// they can still read every account. The others to whoever can act on their account.
+async fn owned_subscription(
+    webhook_db: &State<Db<WebhookSubscription>>,
+    id: &str,
+    auth: AuthorizedUser,
+) -> Result<WebhookSubscription, (Status, Json<ErrorResponse>)> {
+    let subscription = match webhook_db.get_by_id(id).await {
+        Ok(subscription) => subscription,
+        Err(e) => return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), e)))),
+    };
+    let allowed = match &subscription.account_number {
+        Some(account_number) => can_continue(auth, account_number),
+        None => {
+            auth.has(Permission::AccountsRead)
+                && (subscription.created_by == auth.user_id || auth.has(Permission::AccountsOperate))
+        }
+    };
+    if !allowed {
+        return Err((Status::BadRequest, Json(ErrorResponse::new("Webhook".to_string(), "You can only manage your own webhooks".to_string()))));
+    }
+    Ok(subscription)
+}
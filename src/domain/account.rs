@@ -1,4 +1,5 @@
 
+use chrono::Utc;
 use rand::{distributions::Alphanumeric, Rng};
 use revolt_rocket_okapi::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -6,6 +7,9 @@ use std::{borrow::Borrow, collections::HashMap};
 
 use crate::domain::{asset::{AssetManager, AssetType, Asset}};
 
+use crate::mongo::Versioned;
+use crate::domain::event::Aggregate;
+
 use super::ledger::{Crypto, Fiat};
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Balance {
@@ -14,29 +18,95 @@ pub struct Balance {
     pub hold: f64,
 }
 
+/// What a co-owner may do with a joint account, each level includes the ones before it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+pub enum OwnerPermission {
+    /// Balances, ledgers, transactions and statements.
+    View,
+    /// Also deposits, withdrawals, transfers and new ledgers.
+    Transact,
+    /// Also managing the owners and closing the account.
+    Full,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum AccountStatus {
+    #[default]
+    Open,
+    /// No new activity, waiting for pending transactions before the balances are swept.
+    Closing,
+    Closed,
+}
+
+/// Progress of closing an account, kept on the account once closed as part of its history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AccountClosure {
+    pub requested_by: String,
+    pub requested_at: String,
+    pub destination: Option<String>,
+    /// Transactions that have to finish before the account can be swept.
+    pub waiting_for: Vec<String>,
+    pub cancelled: Vec<String>,
+    pub sweeps: Vec<String>,
+    pub statements: Vec<String>,
+    pub closed_at: Option<String>,
+}
+impl AccountClosure {
+    pub fn new(requested_by: String, destination: Option<String>) -> AccountClosure {
+        AccountClosure {
+            requested_by,
+            requested_at: Utc::now().to_rfc3339(),
+            destination,
+            waiting_for: Vec::new(),
+            cancelled: Vec::new(),
+            sweeps: Vec::new(),
+            statements: Vec::new(),
+            closed_at: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Account {
+    /// Primary owner, the user who opened the account until they leave it.
     pub user_owner_id: String,
     pub account_number: String,
+    #[serde(default)]
+    pub name: String,
+    /// Every owner by user id, accounts from before joint ownership only have `user_owner_id`.
+    #[serde(default)]
+    pub owners: HashMap<String, OwnerPermission>,
     pub accounts_fiat: HashMap<String, String>,
     pub accounts_crypto: HashMap<String, String>,
     pub constraints: HashMap<String, String>,
     pub active: bool,
+    #[serde(default)]
+    pub status: AccountStatus,
+    #[serde(default)]
+    pub closure: Option<AccountClosure>,
+    #[serde(default)]
+    pub version: u64,
 }
 impl Account {
     pub fn init(
         asset_master: &AssetManager,
         default_assets: &Vec<String>,
         owner_id: String,
+        name: String,
     ) -> Result<Account, String> {
         let id = account_number_generator().to_uppercase();
         let mut account = Account {
-            user_owner_id: owner_id,
+            user_owner_id: owner_id.clone(),
             account_number: id,
+            name,
+            owners: HashMap::from([(owner_id, OwnerPermission::Full)]),
             accounts_fiat: HashMap::new(),
             accounts_crypto: HashMap::new(),
             constraints: HashMap::new(),
             active: false,
+            status: AccountStatus::Open,
+            closure: None,
+            version: 0,
         };
         for asset in default_assets {
             let default_asset = match asset_master.get_by_symbol(asset.borrow()) {
@@ -54,6 +124,43 @@ impl Account {
         }
         Ok(account)
     }
+    /// Closing and closed accounts take no new deposits, withdrawals, transfers or ledgers.
+    pub fn is_open(&self) -> bool {
+        self.status == AccountStatus::Open
+    }
+    pub fn permission_of(&self, user_id: &str) -> Option<OwnerPermission> {
+        match self.owners.get(user_id) {
+            Some(permission) => Some(permission.clone()),
+            None if self.user_owner_id == user_id => Some(OwnerPermission::Full),
+            None => None,
+        }
+    }
+    pub fn allows(&self, user_id: &str, needed: &OwnerPermission) -> bool {
+        match self.permission_of(user_id) {
+            Some(permission) => permission >= *needed,
+            None => false,
+        }
+    }
+    /// Adds or changes an owner, the account always keeps at least one `Full` owner.
+    pub fn set_owner(&mut self, user_id: String, permission: Option<OwnerPermission>) -> Result<(), String> {
+        if self.owners.is_empty() {
+            self.owners.insert(self.user_owner_id.clone(), OwnerPermission::Full);
+        }
+        match permission {
+            Some(permission) => self.owners.insert(user_id, permission),
+            None => self.owners.remove(&user_id),
+        };
+        if !self.owners.values().any(|permission| *permission == OwnerPermission::Full) {
+            return Err("The account needs at least one owner with Full permission".to_string());
+        }
+        if !self.owners.contains_key(&self.user_owner_id) {
+            // the primary owner left, hand it to another Full owner
+            let mut full: Vec<&String> = self.owners.iter().filter(|(_, p)| **p == OwnerPermission::Full).map(|(id, _)| id).collect();
+            full.sort();
+            self.user_owner_id = full[0].clone();
+        }
+        Ok(())
+    }
     pub fn balance(fiats: Vec<Fiat>, crypto: Vec<Crypto>) -> HashMap<String, Balance> {
         let mut balances = HashMap::new();
         for fiat in fiats {
@@ -94,6 +201,26 @@ impl Account {
     }
 }
 
+impl Versioned for Account {
+    fn version(&self) -> u64 {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+impl Aggregate for Account {
+    fn aggregate_type(&self) -> &'static str {
+        "Account"
+    }
+    fn aggregate_id(&self) -> String {
+        self.account_number.clone()
+    }
+    fn accounts(&self) -> Vec<String> {
+        vec![self.account_number.clone()]
+    }
+}
+
 fn account_number_generator() -> String {
     let mut rng = rand::thread_rng();
     (0..12)
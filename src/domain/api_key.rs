@@ -0,0 +1,241 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use super::user::Permission;
+
+/// Credential of a server-to-server client. Requests are signed with `secret` instead of
+/// sending it, see `ApiKey::verify`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// User or service account the key acts as.
+    pub user_id: String,
+    pub secret: String,
+    /// Permissions of the user's role the key keeps, none of them when empty.
+    pub scopes: Vec<Permission>,
+    /// Accounts the key reaches among the user's, all of them when empty.
+    pub accounts: Vec<String>,
+    /// Addresses (`10.0.0.7`) or CIDR ranges (`10.0.0.0/24`) allowed to use the key, any when empty.
+    pub ip_allow_list: Vec<String>,
+    pub expires_at: Option<String>,
+    pub created_by: String,
+    pub created_at: String,
+    pub rotated_at: Option<String>,
+    pub revoked_at: Option<String>,
+}
+impl ApiKey {
+    pub fn new(
+        name: String,
+        user_id: String,
+        scopes: Vec<Permission>,
+        accounts: Vec<String>,
+        ip_allow_list: Vec<String>,
+        expires_at: Option<String>,
+        created_by: String,
+    ) -> Result<ApiKey, String> {
+        for range in &ip_allow_list {
+            if parse_range(range).is_none() {
+                return Err(format!("{} is not an IP address or CIDR range", range));
+            }
+        }
+        if let Some(expires_at) = &expires_at {
+            match DateTime::parse_from_rfc3339(expires_at) {
+                Ok(expires_at) if expires_at <= Utc::now() => return Err("Expiry must be in the future".to_string()),
+                Ok(_) => (),
+                Err(_) => return Err("Expiry must be an RFC 3339 date".to_string()),
+            };
+        }
+        Ok(ApiKey {
+            id: format!("key_{}", Uuid::new_v4().simple()),
+            name,
+            user_id,
+            secret: secret_generator(),
+            scopes,
+            accounts,
+            ip_allow_list,
+            expires_at,
+            created_by,
+            created_at: Utc::now().to_rfc3339(),
+            rotated_at: None,
+            revoked_at: None,
+        })
+    }
+    pub fn is_active(&self) -> bool {
+        if self.revoked_at.is_some() {
+            return false;
+        }
+        match &self.expires_at {
+            Some(expires_at) => match DateTime::parse_from_rfc3339(expires_at) {
+                Ok(expires_at) => expires_at > Utc::now(),
+                Err(_) => false,
+            },
+            None => true,
+        }
+    }
+    pub fn allows_ip(&self, ip: Option<IpAddr>) -> bool {
+        if self.ip_allow_list.is_empty() {
+            return true;
+        }
+        let ip = match ip {
+            Some(ip) => ip,
+            None => return false,
+        };
+        self.ip_allow_list.iter().filter_map(|range| parse_range(range)).any(|(network, prefix)| in_range(ip, network, prefix))
+    }
+    /// Checks `signature` (`v1=<hex>`) is the HMAC-SHA256 under the key secret of
+    /// `<METHOD>\n<path and query>\n<timestamp>\n<hex sha256 of the body>`.
+    pub fn verify(&self, method: &str, path: &str, timestamp: &str, body_sha256: &str, signature: &str) -> bool {
+        let signature = match signature.strip_prefix("v1=").map(hex::decode) {
+            Some(Ok(signature)) => signature,
+            _ => return false,
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}\n{}\n{}\n{}", method, path, timestamp, body_sha256).as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+    pub fn rotate_secret(&mut self) {
+        self.secret = secret_generator();
+        self.rotated_at = Some(Utc::now().to_rfc3339());
+    }
+    pub fn revoke(&mut self) -> Result<(), String> {
+        if self.revoked_at.is_some() {
+            return Err("API key is already revoked".to_string());
+        }
+        self.revoked_at = Some(Utc::now().to_rfc3339());
+        Ok(())
+    }
+    /// Copy safe to list, the secret is only shown when it is created or rotated.
+    pub fn redacted(&self) -> ApiKey {
+        ApiKey {
+            secret: "********".to_string(),
+            ..self.clone()
+        }
+    }
+}
+
+fn parse_range(range: &str) -> Option<(IpAddr, u32)> {
+    let (address, prefix) = match range.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (range, None),
+    };
+    let address: IpAddr = address.trim().parse().ok()?;
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.trim().parse().ok().filter(|prefix| *prefix <= bits)?,
+        None => bits,
+    };
+    Some((address, prefix))
+}
+
+fn in_range(ip: IpAddr, network: IpAddr, prefix: u32) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+fn secret_generator() -> String {
+    let mut rng = rand::thread_rng();
+    (0..40)
+        .map(|_| rng.sample(Alphanumeric))
+        .map(|x| (x) as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(secret: &str, message: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(message.as_bytes());
+        format!("v1={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn key(ip_allow_list: Vec<&str>) -> ApiKey {
+        ApiKey::new(
+            "billing".to_string(),
+            "user".to_string(),
+            vec![Permission::AccountsRead],
+            Vec::new(),
+            ip_allow_list.iter().map(|range| range.to_string()).collect(),
+            None,
+            "user".to_string(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn verifies_the_signed_request() {
+        let key = key(vec![]);
+        let signature = sign(&key.secret, "POST\n/v1/transactions\n1700000000\nabc");
+        assert!(key.verify("POST", "/v1/transactions", "1700000000", "abc", &signature));
+    }
+
+    #[test]
+    fn rejects_any_change_to_the_request() {
+        let key = key(vec![]);
+        let signature = sign(&key.secret, "POST\n/v1/transactions\n1700000000\nabc");
+        assert!(!key.verify("GET", "/v1/transactions", "1700000000", "abc", &signature));
+        assert!(!key.verify("POST", "/v1/transactions?x=1", "1700000000", "abc", &signature));
+        assert!(!key.verify("POST", "/v1/transactions", "1700000001", "abc", &signature));
+        assert!(!key.verify("POST", "/v1/transactions", "1700000000", "abd", &signature));
+        assert!(!key.verify("POST", "/v1/transactions", "1700000000", "abc", &signature.replace("v1=", "")));
+        assert!(!key.verify("POST", "/v1/transactions", "1700000000", "abc", "v1=zz"));
+    }
+
+    #[test]
+    fn rotation_invalidates_the_old_secret() {
+        let mut key = key(vec![]);
+        let signature = sign(&key.secret, "GET\n/v1/accounts\n1700000000\n");
+        key.rotate_secret();
+        assert!(key.rotated_at.is_some());
+        assert!(!key.verify("GET", "/v1/accounts", "1700000000", "", &signature));
+        let signature = sign(&key.secret, "GET\n/v1/accounts\n1700000000\n");
+        assert!(key.verify("GET", "/v1/accounts", "1700000000", "", &signature));
+    }
+
+    #[test]
+    fn revoked_and_expired_keys_are_inactive() {
+        let mut key = key(vec![]);
+        assert!(key.is_active());
+        key.revoke().unwrap();
+        assert!(!key.is_active());
+        assert!(key.revoke().is_err());
+        let mut key = self::key(vec![]);
+        key.expires_at = Some("2000-01-01T00:00:00Z".to_string());
+        assert!(!key.is_active());
+    }
+
+    #[test]
+    fn checks_the_ip_allow_list() {
+        let key = key(vec!["10.0.0.0/24", "2001:db8::1"]);
+        assert!(key.allows_ip("10.0.0.7".parse().ok()));
+        assert!(!key.allows_ip("10.0.1.7".parse().ok()));
+        assert!(key.allows_ip("2001:db8::1".parse().ok()));
+        assert!(!key.allows_ip(None));
+        assert!(self::key(vec![]).allows_ip(None));
+        assert!(ApiKey::new("x".to_string(), "u".to_string(), vec![], vec![], vec!["10.0.0.0/33".to_string()], None, "u".to_string()).is_err());
+    }
+
+    #[test]
+    fn redacts_the_secret() {
+        let key = key(vec![]);
+        assert_eq!(key.redacted().secret, "********");
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt::Display;
+
+use chrono::Utc;
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Fields whose values never reach the audit log, only the fact that they changed.
+const REDACTED_FIELDS: &[&str] = &["password", "secret"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+    Denied,
+}
+impl Display for AuditOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditOutcome::Success => write!(f, "Success"),
+            AuditOutcome::Failure => write!(f, "Failure"),
+            AuditOutcome::Denied => write!(f, "Denied"),
+        }
+    }
+}
+
+/// Top level field of the target that changed, values as json.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// One privileged operation or authentication event. Entries are only ever appended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AuditEntry {
+    pub id: String,
+    pub occurred_at: String,
+    pub actor_id: Option<String>,
+    pub actor_role: Option<String>,
+    pub action: String,
+    pub resource: String,
+    pub outcome: AuditOutcome,
+    pub reason: Option<String>,
+    pub changes: Vec<FieldChange>,
+    pub request_id: String,
+    pub ip: Option<String>,
+}
+impl AuditEntry {
+    pub fn new(action: &str, resource: &str, request_id: String, ip: Option<String>) -> AuditEntry {
+        AuditEntry {
+            id: Uuid::new_v4().to_string(),
+            occurred_at: Utc::now().to_rfc3339(),
+            actor_id: None,
+            actor_role: None,
+            action: action.to_string(),
+            resource: resource.to_string(),
+            outcome: AuditOutcome::Success,
+            reason: None,
+            changes: Vec::new(),
+            request_id,
+            ip,
+        }
+    }
+    pub fn by(mut self, actor_id: String, actor_role: String) -> AuditEntry {
+        self.actor_id = Some(actor_id);
+        self.actor_role = Some(actor_role);
+        self
+    }
+    pub fn outcome(mut self, outcome: AuditOutcome, reason: Option<String>) -> AuditEntry {
+        self.outcome = outcome;
+        self.reason = reason;
+        self
+    }
+    pub fn changes<T: Serialize>(mut self, before: &T, after: &T) -> AuditEntry {
+        match (serde_json::to_value(before), serde_json::to_value(after)) {
+            (Ok(before), Ok(after)) => self.changes = diff(&before, &after),
+            (Err(e), _) | (_, Err(e)) => self.reason = Some(format!("Error diffing resource: {}", e)),
+        }
+        self
+    }
+}
+
+fn diff(before: &Value, after: &Value) -> Vec<FieldChange> {
+    let (before, after) = match (before.as_object(), after.as_object()) {
+        (Some(before), Some(after)) => (before, after),
+        _ => {
+            return vec![FieldChange {
+                field: String::new(),
+                before: Some(before.to_string()),
+                after: Some(after.to_string()),
+            }]
+        }
+    };
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter(|field| before.get(*field) != after.get(*field))
+        .map(|field| {
+            let shown = |value: Option<&Value>| match value {
+                Some(_) if REDACTED_FIELDS.contains(&field.as_str()) => Some("\"********\"".to_string()),
+                Some(value) => Some(value.to_string()),
+                None => None,
+            };
+            FieldChange {
+                field: field.clone(),
+                before: shown(before.get(field)),
+                after: shown(after.get(field)),
+            }
+        })
+        .collect()
+}
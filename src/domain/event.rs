@@ -0,0 +1,106 @@
+use std::fmt::Display;
+
+use chrono::Utc;
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::mongo::Versioned;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum EventType {
+    AccountCreated,
+    LedgerCreated,
+    DepositPending,
+    DepositConfirmed,
+    TransferSubmitted,
+    TransactionCompleted,
+    TransactionFailed,
+    TransactionCancelled,
+    WithdrawalReleased,
+    AccountOwnersChanged,
+    AccountClosing,
+    AccountClosed,
+}
+impl Display for EventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventType::AccountCreated => write!(f, "AccountCreated"),
+            EventType::LedgerCreated => write!(f, "LedgerCreated"),
+            EventType::DepositPending => write!(f, "DepositPending"),
+            EventType::DepositConfirmed => write!(f, "DepositConfirmed"),
+            EventType::TransferSubmitted => write!(f, "TransferSubmitted"),
+            EventType::TransactionCompleted => write!(f, "TransactionCompleted"),
+            EventType::TransactionFailed => write!(f, "TransactionFailed"),
+            EventType::TransactionCancelled => write!(f, "TransactionCancelled"),
+            EventType::WithdrawalReleased => write!(f, "WithdrawalReleased"),
+            EventType::AccountOwnersChanged => write!(f, "AccountOwnersChanged"),
+            EventType::AccountClosing => write!(f, "AccountClosing"),
+            EventType::AccountClosed => write!(f, "AccountClosed"),
+        }
+    }
+}
+
+/// Entity whose state changes are published as events. The version doubles as the event
+/// sequence, so consumers can order and deduplicate the events of one aggregate.
+pub trait Aggregate: Serialize + Versioned {
+    fn aggregate_type(&self) -> &'static str;
+    fn aggregate_id(&self) -> String;
+    /// Accounts the aggregate belongs to, used to route its events to per-account subscribers.
+    fn accounts(&self) -> Vec<String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DomainEvent {
+    pub id: String,
+    pub event_type: EventType,
+    pub aggregate_type: String,
+    pub aggregate_id: String,
+    pub sequence: u64,
+    #[serde(default)]
+    pub accounts: Vec<String>,
+    pub payload: String,
+    pub occurred_at: String,
+    pub published: bool,
+    pub published_at: Option<String>,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+impl DomainEvent {
+    /// Event carrying a snapshot of `aggregate` as it is after the change.
+    pub fn of<T: Aggregate>(event_type: EventType, aggregate: &T) -> Result<DomainEvent, String> {
+        let payload = match serde_json::to_string(aggregate) {
+            Ok(payload) => payload,
+            Err(e) => return Err(format!("Error serializing event: {}", e)),
+        };
+        let aggregate_id = aggregate.aggregate_id();
+        let sequence = aggregate.version();
+        Ok(DomainEvent {
+            id: format!("{}:{}:{}", aggregate.aggregate_type(), aggregate_id, sequence),
+            event_type,
+            aggregate_type: aggregate.aggregate_type().to_string(),
+            aggregate_id,
+            sequence,
+            accounts: aggregate.accounts(),
+            payload,
+            occurred_at: Utc::now().to_rfc3339(),
+            published: false,
+            published_at: None,
+            attempts: 0,
+            last_error: None,
+        })
+    }
+    /// Routing name such as `transaction.DepositConfirmed`.
+    pub fn subject(&self) -> String {
+        format!("{}.{}", self.aggregate_type.to_lowercase(), self.event_type)
+    }
+    pub fn mark_published(&mut self) {
+        self.published = true;
+        self.published_at = Some(Utc::now().to_rfc3339());
+        self.attempts += 1;
+        self.last_error = None;
+    }
+    pub fn mark_failed(&mut self, error: String) {
+        self.attempts += 1;
+        self.last_error = Some(error);
+    }
+}
@@ -0,0 +1,154 @@
+use chrono::{DateTime, Utc};
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    domain::account::{Account, OwnerPermission},
+    mongo::Versioned,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum GrantScope {
+    ViewBalances,
+    ViewTransactions,
+    /// Transfers out of the account, each one up to `transfer_limit` and all of them together
+    /// up to `total_limit`.
+    InitiateTransfers,
+    ApproveTransfers,
+}
+
+/// Access an owner gives another user to one of their accounts, checked on every request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AccessGrant {
+    pub id: String,
+    pub account_number: String,
+    pub grantor: String,
+    pub grantee: String,
+    pub scopes: Vec<GrantScope>,
+    pub transfer_limit: Option<f64>,
+    /// Cap on everything transferred under the grant, none when only `transfer_limit` applies.
+    #[serde(default)]
+    pub total_limit: Option<f64>,
+    /// Total debited by the transfers submitted under the grant so far, fees included.
+    #[serde(default)]
+    pub transferred: f64,
+    pub expires_at: Option<String>,
+    pub created_at: String,
+    pub revoked_at: Option<String>,
+    pub revoked_by: Option<String>,
+    #[serde(default)]
+    pub version: u64,
+}
+impl AccessGrant {
+    pub fn new(
+        account_number: String,
+        grantor: String,
+        grantee: String,
+        scopes: Vec<GrantScope>,
+        transfer_limit: Option<f64>,
+        total_limit: Option<f64>,
+        expires_at: Option<String>,
+    ) -> Result<AccessGrant, String> {
+        if scopes.is_empty() {
+            return Err("A grant needs at least one scope".to_string());
+        }
+        if grantor == grantee {
+            return Err("You cannot grant access to yourself".to_string());
+        }
+        match transfer_limit {
+            Some(limit) if limit <= 0.0 => return Err("Transfer limit must be positive".to_string()),
+            None if scopes.contains(&GrantScope::InitiateTransfers) => {
+                return Err("Initiating transfers needs a transfer limit".to_string())
+            }
+            _ => (),
+        };
+        match total_limit {
+            Some(limit) if limit <= 0.0 => return Err("Total limit must be positive".to_string()),
+            Some(limit) if limit < transfer_limit.unwrap_or(0.0) => {
+                return Err("Total limit cannot be below the transfer limit".to_string())
+            }
+            _ => (),
+        };
+        if let Some(expires_at) = &expires_at {
+            match DateTime::parse_from_rfc3339(expires_at) {
+                Ok(expires_at) if expires_at <= Utc::now() => return Err("Expiry must be in the future".to_string()),
+                Ok(_) => (),
+                Err(_) => return Err("Expiry must be an RFC 3339 date".to_string()),
+            };
+        }
+        Ok(AccessGrant {
+            id: Uuid::new_v4().to_string(),
+            account_number,
+            grantor,
+            grantee,
+            scopes,
+            transfer_limit,
+            total_limit,
+            transferred: 0.0,
+            expires_at,
+            created_at: Utc::now().to_rfc3339(),
+            revoked_at: None,
+            revoked_by: None,
+            version: 0,
+        })
+    }
+    pub fn is_active(&self) -> bool {
+        if self.revoked_at.is_some() {
+            return false;
+        }
+        match &self.expires_at {
+            Some(expires_at) => match DateTime::parse_from_rfc3339(expires_at) {
+                Ok(expires_at) => expires_at > Utc::now(),
+                Err(_) => false,
+            },
+            None => true,
+        }
+    }
+    /// Whether the grant gives `scope` on `account`, read from the store. Grants stop working
+    /// once their grantor is no longer a Full owner of the account.
+    pub fn allows(&self, account: &Account, scope: &GrantScope) -> bool {
+        self.covers(&account.account_number, scope) && self.granted_by_owner(account)
+    }
+    /// `allows` without the grantor check, for callers that only have the account number and
+    /// grants already checked with `granted_by_owner`.
+    pub fn covers(&self, account_number: &str, scope: &GrantScope) -> bool {
+        self.account_number == account_number && self.scopes.contains(scope) && self.is_active()
+    }
+    pub fn granted_by_owner(&self, account: &Account) -> bool {
+        self.account_number == account.account_number && account.allows(&self.grantor, &OwnerPermission::Full)
+    }
+    /// Whether a transfer debiting `total` fits the transfer limit and what is left of the total limit.
+    pub fn fits(&self, total: f64) -> bool {
+        let left = match self.total_limit {
+            Some(limit) => limit - self.transferred,
+            None => f64::INFINITY,
+        };
+        total <= self.transfer_limit.unwrap_or(0.0) && total <= left
+    }
+    /// Counts a transfer debiting `total` against the grant's limits.
+    pub fn charge(&mut self, total: f64) -> Result<(), String> {
+        if !self.fits(total) {
+            return Err("Transfer exceeds the limits of the grant".to_string());
+        }
+        self.transferred += total;
+        Ok(())
+    }
+    pub fn revoke(&mut self, revoked_by: String) -> Result<(), String> {
+        if self.revoked_at.is_some() {
+            return Err("Grant is already revoked".to_string());
+        }
+        self.revoked_at = Some(Utc::now().to_rfc3339());
+        self.revoked_by = Some(revoked_by);
+        Ok(())
+    }
+}
+
+impl Versioned for AccessGrant {
+    fn version(&self) -> u64 {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
@@ -0,0 +1,58 @@
+use chrono::Utc;
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+    statement::JobStatus,
+    transaction::{ChainFinding, ChainStatus},
+};
+
+/// Result of re-verifying the hash chain of every stored transaction.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IntegrityReport {
+    pub id: String,
+    pub requested_by: String,
+    pub status: JobStatus,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    pub checked: u64,
+    pub valid: u64,
+    pub unchained: u64,
+    pub broken: Vec<ChainFinding>,
+    pub error: Option<String>,
+}
+impl IntegrityReport {
+    pub fn new(requested_by: String) -> IntegrityReport {
+        IntegrityReport {
+            id: Uuid::new_v4().to_string(),
+            requested_by,
+            status: JobStatus::Pending,
+            created_at: Utc::now().to_rfc3339(),
+            finished_at: None,
+            checked: 0,
+            valid: 0,
+            unchained: 0,
+            broken: Vec::new(),
+            error: None,
+        }
+    }
+    pub fn add(&mut self, finding: ChainFinding) {
+        self.checked += 1;
+        match finding.status {
+            ChainStatus::Valid => self.valid += 1,
+            ChainStatus::Unchained => self.unchained += 1,
+            ChainStatus::Broken => self.broken.push(finding),
+        }
+    }
+    pub fn finish(&mut self, result: Result<(), String>) {
+        match result {
+            Ok(_) => self.status = JobStatus::Done,
+            Err(e) => {
+                self.status = JobStatus::Failed;
+                self.error = Some(e);
+            }
+        }
+        self.finished_at = Some(Utc::now().to_rfc3339());
+    }
+}
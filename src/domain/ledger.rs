@@ -1,10 +1,18 @@
 use revolt_rocket_okapi::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::mongo::Versioned;
+use crate::domain::event::Aggregate;
+
 use super::asset::{Asset, AssetType};
 
 pub trait Accounting {
     fn get_account_number(&self)->String;
+    fn get_symbol(&self)->String;
+    fn get_balance(&self)->f64;
+    fn get_hold(&self)->f64;
+    /// Closes an emptied ledger, it keeps its history but takes no more movements.
+    fn close(&mut self)->Result<(),String>;
 }
 pub trait FungibleTradeable {
     fn deposit(&mut self, amount: f64)->Result<(),String>;
@@ -22,6 +30,10 @@ pub struct Fiat {
     pub asset: Asset,
     pub balance: f64,
     pub hold: f64,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub version: u64,
 }
 impl Fiat {
     pub fn new(account_id:String, asset: Asset)->Result<Fiat,String> {
@@ -40,15 +52,52 @@ impl Fiat {
                 asset_type: AssetType::Fiat,
                 balance: 0.0,
                 hold: 0.0,
+                closed: false,
+                version: 0,
             }),
             _ => Err("Asset type must be Fiat".to_string()),
         }
     }
 }
+impl Versioned for Fiat {
+    fn version(&self) -> u64 {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+impl Aggregate for Fiat {
+    fn aggregate_type(&self) -> &'static str {
+        "FiatLedger"
+    }
+    fn aggregate_id(&self) -> String {
+        self.id.clone()
+    }
+    fn accounts(&self) -> Vec<String> {
+        vec![self.account_number.clone()]
+    }
+}
 impl Accounting for Fiat {
     fn get_account_number(&self)->String {
         self.account_number.clone()
     }
+    fn get_symbol(&self)->String {
+        self.asset.symbol.clone()
+    }
+    fn get_balance(&self)->f64 {
+        self.balance
+    }
+    fn get_hold(&self)->f64 {
+        self.hold
+    }
+    fn close(&mut self)->Result<(),String> {
+        if self.balance != 0.0 || self.hold != 0.0 {
+            return Err(format!("Ledger {} still holds funds", self.id));
+        }
+        self.closed = true;
+        Ok(())
+    }
 }
 impl FungibleTradeable for Fiat {
     fn deposit(&mut self, amount: f64)->Result<(),String> {
@@ -124,6 +173,10 @@ pub struct Crypto {
     pub asset: Asset,
     pub balance: f64,
     pub hold: f64,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub version: u64,
 }
 impl Crypto {
     pub fn new(account_id:String, asset: Asset, network: String, address_in_chain: String)->Result<Crypto,String> {
@@ -144,15 +197,52 @@ impl Crypto {
                 asset_type: AssetType::Crypto,
                 balance: 0.0,
                 hold: 0.0,
+                closed: false,
+                version: 0,
             }),
             _ => Err("Asset type must be Crypto".to_string()),
         }
     }
 }
+impl Versioned for Crypto {
+    fn version(&self) -> u64 {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+impl Aggregate for Crypto {
+    fn aggregate_type(&self) -> &'static str {
+        "CryptoLedger"
+    }
+    fn aggregate_id(&self) -> String {
+        self.id.clone()
+    }
+    fn accounts(&self) -> Vec<String> {
+        vec![self.account_number.clone()]
+    }
+}
 impl Accounting for Crypto {
     fn get_account_number(&self)->String {
         self.account_number.clone()
     }
+    fn get_symbol(&self)->String {
+        self.asset.symbol.clone()
+    }
+    fn get_balance(&self)->f64 {
+        self.balance
+    }
+    fn get_hold(&self)->f64 {
+        self.hold
+    }
+    fn close(&mut self)->Result<(),String> {
+        if self.balance != 0.0 || self.hold != 0.0 {
+            return Err(format!("Ledger {} still holds funds", self.id));
+        }
+        self.closed = true;
+        Ok(())
+    }
 }
 impl FungibleTradeable for Crypto {
     fn deposit(&mut self, amount: f64)->Result<(),String> {
@@ -0,0 +1,260 @@
+use chrono::Utc;
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::transaction::Transaction;
+
+// domain separation keeps a leaf from ever hashing like an inner node
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// A completed transaction's place in the global append-only log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LedgerEntry {
+    pub id: String,
+    pub sequence: u64,
+    pub tx_id: String,
+    /// Last hash of the transaction's own chain, committing to every field it had.
+    pub chain_head: String,
+    pub leaf: String,
+    pub day: String,
+    pub appended_at: String,
+}
+impl LedgerEntry {
+    pub fn new(sequence: u64, transaction: &Transaction) -> Result<LedgerEntry, String> {
+        let chain_head = match transaction.hash.last() {
+            Some(event) => event.hash.clone(),
+            None => return Err(format!("Transaction {} has no hash events", transaction.tx_id)),
+        };
+        let now = Utc::now();
+        Ok(LedgerEntry {
+            // zero padded so the id sorts like the sequence and a taken sequence cannot be reused
+            id: format!("{:020}", sequence),
+            sequence,
+            tx_id: transaction.tx_id.clone(),
+            leaf: LedgerEntry::leaf_for(sequence, &transaction.tx_id, &chain_head),
+            chain_head,
+            day: now.format("%Y-%m-%d").to_string(),
+            appended_at: now.to_rfc3339(),
+        })
+    }
+    /// Hex leaf hash of `<sequence>|<tx_id>|<chain_head>`.
+    pub fn leaf_for(sequence: u64, tx_id: &str, chain_head: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update([LEAF_PREFIX]);
+        hasher.update(format!("{}|{}|{}", sequence, tx_id, chain_head));
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// Merkle root over one UTC day of ledger entries, chained to the previous day's root and
+/// signed with the ledger key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DailyRoot {
+    pub id: String,
+    pub day: String,
+    pub first_sequence: u64,
+    pub last_sequence: u64,
+    pub size: usize,
+    pub root: String,
+    pub previous_root: Option<String>,
+    pub public_key: String,
+    pub signature: String,
+    pub sealed_at: String,
+}
+impl DailyRoot {
+    /// Unsigned root for `entries`, which must all be of `day` and sorted by sequence.
+    pub fn new(day: String, entries: &[LedgerEntry], previous: Option<&DailyRoot>, public_key: String) -> Result<DailyRoot, String> {
+        let (first, last) = match (entries.first(), entries.last()) {
+            (Some(first), Some(last)) => (first.sequence, last.sequence),
+            _ => return Err(format!("No ledger entries for {}", day)),
+        };
+        let leaves: Vec<String> = entries.iter().map(|entry| entry.leaf.clone()).collect();
+        Ok(DailyRoot {
+            id: day.clone(),
+            day,
+            first_sequence: first,
+            last_sequence: last,
+            size: entries.len(),
+            root: root(&leaves)?,
+            previous_root: previous.map(|previous| previous.root.clone()),
+            public_key,
+            signature: String::new(),
+            sealed_at: Utc::now().to_rfc3339(),
+        })
+    }
+    /// What the signature covers: `<day>|<first>|<last>|<size>|<root>|<previous root or None>`.
+    pub fn message(&self) -> String {
+        format!(
+            "{}|{}|{}|{}|{}|{}",
+            self.day,
+            self.first_sequence,
+            self.last_sequence,
+            self.size,
+            self.root,
+            self.previous_root.clone().unwrap_or("None".to_string())
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Sibling hash to combine with on the way up, on the given side of the running hash.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ProofStep {
+    pub hash: String,
+    pub side: Side,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct InclusionProof {
+    pub entry: LedgerEntry,
+    pub index: usize,
+    pub path: Vec<ProofStep>,
+    pub root: DailyRoot,
+}
+
+pub fn root(leaves: &[String]) -> Result<String, String> {
+    let mut level = decode(leaves)?;
+    if level.is_empty() {
+        return Err("Cannot build a Merkle tree without leaves".to_string());
+    }
+    while level.len() > 1 {
+        level = parents(&level);
+    }
+    Ok(hex::encode(&level[0]))
+}
+
+pub fn proof(leaves: &[String], index: usize) -> Result<Vec<ProofStep>, String> {
+    let mut level = decode(leaves)?;
+    if index >= level.len() {
+        return Err(format!("Leaf {} is outside a tree of {}", index, level.len()));
+    }
+    let mut index = index;
+    let mut path = Vec::new();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        // the last node of an odd level has no sibling and moves up unchanged
+        if sibling < level.len() {
+            path.push(ProofStep {
+                hash: hex::encode(&level[sibling]),
+                side: if sibling < index { Side::Left } else { Side::Right },
+            });
+        }
+        level = parents(&level);
+        index /= 2;
+    }
+    Ok(path)
+}
+
+pub fn verify(leaf: &str, path: &[ProofStep], root: &str) -> bool {
+    let mut current = match hex::decode(leaf) {
+        Ok(current) => current,
+        Err(_) => return false,
+    };
+    for step in path {
+        let sibling = match hex::decode(&step.hash) {
+            Ok(sibling) => sibling,
+            Err(_) => return false,
+        };
+        current = match step.side {
+            Side::Left => node(&sibling, &current),
+            Side::Right => node(&current, &sibling),
+        };
+    }
+    hex::encode(current) == root
+}
+
+fn decode(leaves: &[String]) -> Result<Vec<Vec<u8>>, String> {
+    leaves
+        .iter()
+        .map(|leaf| hex::decode(leaf).map_err(|e| format!("Invalid leaf {}: {}", leaf, e)))
+        .collect()
+}
+
+fn parents(level: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node(left, right),
+            _ => pair[0].clone(),
+        })
+        .collect()
+}
+
+fn node(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: u64) -> Vec<String> {
+        (1..=count).map(|sequence| LedgerEntry::leaf_for(sequence, &format!("tx{}", sequence), "head")).collect()
+    }
+
+    #[test]
+    fn roots_of_small_trees() {
+        let leaves = leaves(3);
+        assert_eq!(root(&leaves[..1]).unwrap(), leaves[0]);
+        let decoded = decode(&leaves).unwrap();
+        let pair = node(&decoded[0], &decoded[1]);
+        assert_eq!(root(&leaves[..2]).unwrap(), hex::encode(&pair));
+        // the odd leaf moves up unchanged
+        assert_eq!(root(&leaves).unwrap(), hex::encode(node(&pair, &decoded[2])));
+        assert!(root(&[]).is_err());
+        assert!(root(&["zz".to_string()]).is_err());
+    }
+
+    #[test]
+    fn every_leaf_proves_its_inclusion() {
+        for size in 1..=9 {
+            let leaves = leaves(size);
+            let root = root(&leaves).unwrap();
+            for (index, leaf) in leaves.iter().enumerate() {
+                let path = proof(&leaves, index).unwrap();
+                assert!(verify(leaf, &path, &root), "leaf {} of {}", index, size);
+            }
+            assert!(proof(&leaves, leaves.len()).is_err());
+        }
+    }
+
+    #[test]
+    fn proofs_fail_for_other_leaves_and_roots() {
+        let leaves = leaves(5);
+        let root = root(&leaves).unwrap();
+        let path = proof(&leaves, 2).unwrap();
+        assert!(!verify(&leaves[3], &path, &root));
+        assert!(!verify(&leaves[2], &path, &leaves[0]));
+        let mut swapped = path.clone();
+        swapped[0].side = match swapped[0].side {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        };
+        assert!(!verify(&leaves[2], &swapped, &root));
+        assert!(!verify(&leaves[2], &path[1..], &root));
+    }
+
+    #[test]
+    fn leaves_never_hash_like_inner_nodes() {
+        let leaves = leaves(2);
+        let decoded = decode(&leaves).unwrap();
+        // a forged "leaf" made of two real ones cannot stand in for their parent
+        let inner = hex::encode(node(&decoded[0], &decoded[1]));
+        let mut hasher = Sha256::new();
+        hasher.update([LEAF_PREFIX]);
+        hasher.update(&decoded[0]);
+        hasher.update(&decoded[1]);
+        assert_ne!(hex::encode(hasher.finalize()), inner);
+    }
+}
@@ -2,4 +2,16 @@ pub mod account;
 pub mod asset;
 pub mod transaction;
 pub mod ledger;
-pub mod user;
\ No newline at end of file
+pub mod user;
+pub mod statement;
+pub mod reconciliation;
+pub mod payment_reference;
+pub mod event;
+pub mod webhook;
+pub mod integrity;
+pub mod merkle;
+pub mod audit;
+pub mod grant;
+pub mod api_key;
+pub mod session;
+pub mod password_reset;
\ No newline at end of file
@@ -0,0 +1,100 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::mongo::Versioned;
+
+/// A pending password reset. Only the SHA-256 of the token is kept, and it is the id, so the
+/// token sent to the user is looked up directly and a leaked store cannot be replayed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PasswordReset {
+    pub id: String,
+    pub user_id: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub used_at: Option<String>,
+    pub version: u64,
+}
+impl PasswordReset {
+    /// The reset and the token to send, which is not stored.
+    pub fn new(user_id: String, ttl_seconds: i64) -> (PasswordReset, String) {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect();
+        let now = Utc::now();
+        let reset = PasswordReset {
+            id: PasswordReset::id_of(&token),
+            user_id,
+            created_at: now.to_rfc3339(),
+            expires_at: (now + Duration::seconds(ttl_seconds)).to_rfc3339(),
+            used_at: None,
+            version: 0,
+        };
+        (reset, token)
+    }
+    pub fn id_of(token: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(token.trim().as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+    pub fn is_usable(&self) -> bool {
+        if self.used_at.is_some() {
+            return false;
+        }
+        match DateTime::parse_from_rfc3339(&self.expires_at) {
+            Ok(expires_at) => expires_at > Utc::now(),
+            Err(_) => false,
+        }
+    }
+    pub fn consume(&mut self) -> Result<(), String> {
+        if !self.is_usable() {
+            return Err("Reset token is used or expired".to_string());
+        }
+        self.used_at = Some(Utc::now().to_rfc3339());
+        Ok(())
+    }
+}
+
+impl Versioned for PasswordReset {
+    fn version(&self) -> u64 {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_hash_of_the_token_is_kept() {
+        let (reset, token) = PasswordReset::new("user".to_string(), 60);
+        assert_eq!(reset.id, PasswordReset::id_of(&token));
+        assert_ne!(reset.id, token);
+        assert!(!serde_json::to_string(&reset).unwrap().contains(&token));
+    }
+
+    #[test]
+    fn tokens_work_once() {
+        let (mut reset, _) = PasswordReset::new("user".to_string(), 60);
+        assert!(reset.is_usable());
+        assert!(reset.consume().is_ok());
+        assert!(!reset.is_usable());
+        assert!(reset.consume().is_err());
+    }
+
+    #[test]
+    fn expired_tokens_do_not_work() {
+        let (mut reset, _) = PasswordReset::new("user".to_string(), -1);
+        assert!(!reset.is_usable());
+        assert!(reset.consume().is_err());
+        reset.expires_at = "not a date".to_string();
+        assert!(!reset.is_usable());
+    }
+}
@@ -0,0 +1,75 @@
+use rand::{distributions::Alphanumeric, Rng};
+
+// ISO 11649 structured creditor reference: "RF" + 2 check digits + up to 21 alphanumerics
+pub fn generate() -> String {
+    let mut rng = rand::thread_rng();
+    let reference: String = (0..15)
+        .map(|_| rng.sample(Alphanumeric))
+        .map(|x| (x as char).to_ascii_uppercase())
+        .collect();
+    creditor_reference(&reference)
+}
+
+pub fn creditor_reference(reference: &str) -> String {
+    let check = 98 - mod97(&format!("{}RF00", reference));
+    format!("RF{:02}{}", check, reference)
+}
+
+pub fn is_valid(reference: &str) -> bool {
+    let reference = compact(reference);
+    if reference.len() < 5 || reference.len() > 25 || !reference.starts_with("RF") {
+        return false;
+    }
+    if !reference.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return false;
+    }
+    let rearranged = format!("{}{}", &reference[4..], &reference[..4]);
+    mod97(&rearranged) == 1
+}
+
+pub fn compact(reference: &str) -> String {
+    reference
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+// finds every valid creditor reference in free text, tolerating the usual 4 character grouping
+pub fn find_all(text: &str) -> Vec<String> {
+    let text = compact(text);
+    let mut found = Vec::new();
+    let mut start = 0;
+    while let Some(position) = text[start..].find("RF") {
+        let begin = start + position;
+        let candidate: String = text[begin..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .take(25)
+            .collect();
+        for end in (5..=candidate.len()).rev() {
+            if is_valid(&candidate[..end]) {
+                found.push(candidate[..end].to_string());
+                break;
+            }
+        }
+        start = begin + 2;
+    }
+    found
+}
+
+fn mod97(value: &str) -> u32 {
+    let mut remainder: u32 = 0;
+    for c in value.chars() {
+        let digits = match c.to_digit(36) {
+            Some(digit) => digit,
+            None => continue,
+        };
+        if digits >= 10 {
+            remainder = (remainder * 100 + digits) % 97;
+        } else {
+            remainder = (remainder * 10 + digits) % 97;
+        }
+    }
+    remainder
+}
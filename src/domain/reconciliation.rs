@@ -0,0 +1,142 @@
+use chrono::Utc;
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::mongo::Versioned;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BankLine {
+    pub booked_at: String,
+    pub amount: f64,
+    pub currency: Option<String>,
+    pub credit: bool,
+    pub bank_reference: String,
+    pub remittance: String,
+}
+impl BankLine {
+    /// Candidate deposit references found in the remittance information.
+    pub fn reference_tokens(&self) -> Vec<String> {
+        self.remittance
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|token| token.len() >= 8)
+            .map(|token| token.to_string())
+            .collect()
+    }
+    /// The bank's reference of the line, none when it is blank or the NONREF placeholder banks
+    /// send for entries without one.
+    pub fn reference(&self) -> Option<&str> {
+        match self.bank_reference.trim() {
+            "" | "NONREF" => None,
+            reference => Some(reference),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum ImportFormat {
+    Csv,
+    Camt054,
+    Mt940,
+}
+impl ImportFormat {
+    pub fn from_str(s: &str) -> Result<ImportFormat, String> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ImportFormat::Csv),
+            "camt054" | "camt.054" => Ok(ImportFormat::Camt054),
+            "mt940" => Ok(ImportFormat::Mt940),
+            _ => Err(format!("Unsupported import format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MatchedLine {
+    pub tx_id: String,
+    pub bank_reference: String,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementImport {
+    pub id: String,
+    pub format: ImportFormat,
+    pub uploaded_by: String,
+    pub created_at: String,
+    pub total_lines: usize,
+    pub skipped_debits: usize,
+    pub duplicates: usize,
+    pub matched: Vec<MatchedLine>,
+    pub unmatched: Vec<String>,
+}
+impl StatementImport {
+    pub fn new(format: ImportFormat, uploaded_by: String, total_lines: usize) -> StatementImport {
+        StatementImport {
+            id: Uuid::new_v4().to_string(),
+            format,
+            uploaded_by,
+            created_at: Utc::now().to_rfc3339(),
+            total_lines,
+            skipped_debits: 0,
+            duplicates: 0,
+            matched: Vec::new(),
+            unmatched: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum ReviewStatus {
+    Open,
+    Resolved,
+    Dismissed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReviewItem {
+    pub id: String,
+    pub import_id: String,
+    pub line: BankLine,
+    pub reason: String,
+    pub status: ReviewStatus,
+    pub created_at: String,
+    pub resolved_by: Option<String>,
+    pub resolved_at: Option<String>,
+    pub tx_id: Option<String>,
+    #[serde(default)]
+    pub version: u64,
+}
+impl ReviewItem {
+    pub fn new(import_id: String, line: BankLine, reason: String) -> ReviewItem {
+        ReviewItem {
+            id: Uuid::new_v4().to_string(),
+            import_id,
+            line,
+            reason,
+            status: ReviewStatus::Open,
+            created_at: Utc::now().to_rfc3339(),
+            resolved_by: None,
+            resolved_at: None,
+            tx_id: None,
+            version: 0,
+        }
+    }
+    pub fn close(&mut self, status: ReviewStatus, resolved_by: String, tx_id: Option<String>) -> Result<(), String> {
+        if self.status != ReviewStatus::Open {
+            return Err("Review item is not open".to_string());
+        }
+        self.status = status;
+        self.resolved_by = Some(resolved_by);
+        self.resolved_at = Some(Utc::now().to_rfc3339());
+        self.tx_id = tx_id;
+        Ok(())
+    }
+}
+impl Versioned for ReviewItem {
+    fn version(&self) -> u64 {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
@@ -0,0 +1,149 @@
+use chrono::{DateTime, Duration, Utc};
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::mongo::Versioned;
+
+/// A login and the family of refresh tokens it hands out. Only the latest token of the family is
+/// accepted; presenting an earlier one means it leaked, and the whole family is revoked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UserSession {
+    pub id: String,
+    pub user_id: String,
+    /// Id (`jti`) of the refresh token the next refresh has to present.
+    pub refresh_token_id: String,
+    /// User agent of the client that logged in.
+    pub device: Option<String>,
+    /// Address of the last login or refresh.
+    pub ip: Option<String>,
+    pub created_at: String,
+    pub last_used_at: String,
+    pub expires_at: String,
+    pub revoked_at: Option<String>,
+    pub revoked_reason: Option<String>,
+    pub version: u64,
+}
+impl UserSession {
+    pub fn new(user_id: String, device: Option<String>, ip: Option<String>, ttl_seconds: i64) -> UserSession {
+        let now = Utc::now();
+        UserSession {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            refresh_token_id: Uuid::new_v4().to_string(),
+            device,
+            ip,
+            created_at: now.to_rfc3339(),
+            last_used_at: now.to_rfc3339(),
+            expires_at: (now + Duration::seconds(ttl_seconds)).to_rfc3339(),
+            revoked_at: None,
+            revoked_reason: None,
+            version: 0,
+        }
+    }
+    pub fn is_active(&self) -> bool {
+        if self.revoked_at.is_some() {
+            return false;
+        }
+        match DateTime::parse_from_rfc3339(&self.expires_at) {
+            Ok(expires_at) => expires_at > Utc::now(),
+            Err(_) => false,
+        }
+    }
+    /// Replaces the accepted refresh token and extends the session from now, never past
+    /// `max_lifetime_seconds` after the login.
+    pub fn rotate(&mut self, ip: Option<String>, ttl_seconds: i64, max_lifetime_seconds: i64) -> Result<(), String> {
+        if !self.is_active() {
+            return Err("Session is revoked or expired".to_string());
+        }
+        let created_at = match DateTime::parse_from_rfc3339(&self.created_at) {
+            Ok(created_at) => created_at.with_timezone(&Utc),
+            Err(_) => return Err("Session has an invalid creation date".to_string()),
+        };
+        let now = Utc::now();
+        self.refresh_token_id = Uuid::new_v4().to_string();
+        self.ip = ip.or(self.ip.take());
+        self.last_used_at = now.to_rfc3339();
+        self.expires_at = (now + Duration::seconds(ttl_seconds))
+            .min(created_at + Duration::seconds(max_lifetime_seconds))
+            .to_rfc3339();
+        Ok(())
+    }
+    pub fn revoke(&mut self, reason: &str) -> Result<(), String> {
+        if self.revoked_at.is_some() {
+            return Err("Session is already revoked".to_string());
+        }
+        self.revoked_at = Some(Utc::now().to_rfc3339());
+        self.revoked_reason = Some(reason.to_string());
+        Ok(())
+    }
+    pub fn to_response(&self, current_session: Option<&str>) -> SessionPublic {
+        SessionPublic {
+            id: self.id.to_owned(),
+            device: self.device.to_owned(),
+            ip: self.ip.to_owned(),
+            created_at: self.created_at.to_owned(),
+            last_used_at: self.last_used_at.to_owned(),
+            expires_at: self.expires_at.to_owned(),
+            current: current_session == Some(self.id.as_str()),
+        }
+    }
+}
+
+impl Versioned for UserSession {
+    fn version(&self) -> u64 {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SessionPublic {
+    pub id: String,
+    pub device: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: String,
+    pub last_used_at: String,
+    pub expires_at: String,
+    /// The session of the token making the request.
+    pub current: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_replaces_the_refresh_token() {
+        let mut session = UserSession::new("user".to_string(), None, Some("10.0.0.1".to_string()), 60);
+        let before = session.refresh_token_id.clone();
+        session.rotate(None, 60, 3600).unwrap();
+        assert_ne!(session.refresh_token_id, before);
+        assert_eq!(session.ip.as_deref(), Some("10.0.0.1"));
+    }
+
+    #[test]
+    fn rotation_does_not_extend_past_the_max_lifetime() {
+        let mut session = UserSession::new("user".to_string(), None, None, 60);
+        session.created_at = (Utc::now() - Duration::seconds(3590)).to_rfc3339();
+        session.rotate(None, 60, 3600).unwrap();
+        let expires_at = DateTime::parse_from_rfc3339(&session.expires_at).unwrap();
+        assert!(expires_at <= Utc::now() + Duration::seconds(10));
+
+        session.created_at = (Utc::now() - Duration::seconds(3601)).to_rfc3339();
+        session.rotate(None, 60, 3600).unwrap();
+        assert!(!session.is_active());
+        assert!(session.rotate(None, 60, 3600).is_err());
+    }
+
+    #[test]
+    fn revoked_sessions_do_not_rotate() {
+        let mut session = UserSession::new("user".to_string(), None, None, 60);
+        session.revoke("logout").unwrap();
+        assert!(!session.is_active());
+        assert!(session.revoke("logout").is_err());
+        assert!(session.rotate(None, 60, 3600).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt::Display;
+
+use chrono::Utc;
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::transaction::{Transaction, TransactionStatus, TransactionType};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementLine {
+    pub tx_id: String,
+    pub external_id: Option<String>,
+    pub booked_at: String,
+    pub transaction_type: TransactionType,
+    pub memo: String,
+    pub credit: f64,
+    pub debit: f64,
+    pub fee: f64,
+    pub balance: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementHold {
+    pub tx_id: String,
+    pub timestamp: String,
+    pub transaction_type: TransactionType,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Statement {
+    pub account_number: String,
+    pub asset: String,
+    pub from: String,
+    pub to: String,
+    pub generated_at: String,
+    pub opening_balance: f64,
+    pub closing_balance: f64,
+    pub total_credit: f64,
+    pub total_debit: f64,
+    pub total_fees: f64,
+    pub total_hold: f64,
+    pub lines: Vec<StatementLine>,
+    pub holds: Vec<StatementHold>,
+}
+impl Statement {
+    /// Builds a statement from every transaction of the account in `asset` created up to `to`.
+    /// Completed transactions booked before `from` only contribute to the opening balance.
+    pub fn build(
+        account_number: String,
+        asset: String,
+        from: String,
+        to: String,
+        mut history: Vec<Transaction>,
+    ) -> Statement {
+        history.sort_by(|a, b| a.completed_at().cmp(&b.completed_at()));
+        let mut statement = Statement {
+            account_number,
+            asset,
+            from,
+            to,
+            generated_at: Utc::now().to_rfc3339(),
+            opening_balance: 0.0,
+            closing_balance: 0.0,
+            total_credit: 0.0,
+            total_debit: 0.0,
+            total_fees: 0.0,
+            total_hold: 0.0,
+            lines: Vec::new(),
+            holds: Vec::new(),
+        };
+        for tx in history {
+            match tx.transaction_status {
+                TransactionStatus::Completed => {
+                    let booked_at = tx.completed_at();
+                    if booked_at > statement.to {
+                        continue;
+                    }
+                    let (credit, debit, fee) = statement.movement(&tx);
+                    if booked_at < statement.from {
+                        statement.opening_balance += credit - debit - fee;
+                        continue;
+                    }
+                    statement.total_credit += credit;
+                    statement.total_debit += debit;
+                    statement.total_fees += fee;
+                    let balance = statement.opening_balance + statement.total_credit
+                        - statement.total_debit
+                        - statement.total_fees;
+                    statement.lines.push(StatementLine {
+                        tx_id: tx.tx_id.clone(),
+                        external_id: tx.external_id.clone(),
+                        booked_at,
+                        transaction_type: tx.transaction_type.clone(),
+                        memo: tx.memo.clone(),
+                        credit,
+                        debit,
+                        fee,
+                        balance,
+                    });
+                }
+                TransactionStatus::Pending | TransactionStatus::Confirmed => {
+                    if tx.timestamp < statement.from || tx.timestamp > statement.to {
+                        continue;
+                    }
+                    let (credit, debit, fee) = statement.movement(&tx);
+                    statement.total_hold += credit + debit + fee;
+                    statement.holds.push(StatementHold {
+                        tx_id: tx.tx_id.clone(),
+                        timestamp: tx.timestamp.clone(),
+                        transaction_type: tx.transaction_type.clone(),
+                        amount: credit + debit + fee,
+                    });
+                }
+                _ => {}
+            }
+        }
+        statement.closing_balance = statement.opening_balance + statement.total_credit
+            - statement.total_debit
+            - statement.total_fees;
+        statement
+    }
+    /// Returns the (credit, debit, fee) effect of a transaction on this statement's account.
+    fn movement(&self, tx: &Transaction) -> (f64, f64, f64) {
+        let fee = tx.total_amount - tx.amount;
+        if tx.from_wallet.as_deref() == Some(self.account_number.as_str()) {
+            (0.0, tx.amount, fee)
+        } else if tx.to_wallet.as_deref() == Some(self.account_number.as_str()) {
+            (tx.amount, 0.0, 0.0)
+        } else {
+            (0.0, 0.0, 0.0)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum StatementFormat {
+    Json,
+    Csv,
+    Pdf,
+    Camt053,
+    Camt052,
+    Mt940,
+    Ofx,
+}
+impl StatementFormat {
+    pub fn from_str(s: &str) -> Result<StatementFormat, String> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(StatementFormat::Json),
+            "csv" => Ok(StatementFormat::Csv),
+            "pdf" => Ok(StatementFormat::Pdf),
+            "camt053" | "camt.053" => Ok(StatementFormat::Camt053),
+            "camt052" | "camt.052" => Ok(StatementFormat::Camt052),
+            "mt940" => Ok(StatementFormat::Mt940),
+            "ofx" | "qfx" => Ok(StatementFormat::Ofx),
+            _ => Err(format!("Unsupported statement format: {}", s)),
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StatementFormat::Json => "json",
+            StatementFormat::Csv => "csv",
+            StatementFormat::Pdf => "pdf",
+            StatementFormat::Camt053 | StatementFormat::Camt052 => "xml",
+            StatementFormat::Mt940 => "sta",
+            StatementFormat::Ofx => "ofx",
+        }
+    }
+    pub fn fiat_only(&self) -> bool {
+        matches!(
+            self,
+            StatementFormat::Camt053 | StatementFormat::Camt052 | StatementFormat::Mt940 | StatementFormat::Ofx
+        )
+    }
+}
+impl Display for StatementFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatementFormat::Json => write!(f, "json"),
+            StatementFormat::Csv => write!(f, "csv"),
+            StatementFormat::Pdf => write!(f, "pdf"),
+            StatementFormat::Camt053 => write!(f, "camt.053"),
+            StatementFormat::Camt052 => write!(f, "camt.052"),
+            StatementFormat::Mt940 => write!(f, "mt940"),
+            StatementFormat::Ofx => write!(f, "ofx"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementJob {
+    pub id: String,
+    pub account_number: String,
+    pub asset: String,
+    pub from: String,
+    pub to: String,
+    pub format: StatementFormat,
+    pub status: JobStatus,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    pub error: Option<String>,
+    pub content: Option<String>,
+}
+impl StatementJob {
+    pub fn new(
+        account_number: String,
+        asset: String,
+        from: String,
+        to: String,
+        format: StatementFormat,
+    ) -> StatementJob {
+        StatementJob {
+            id: Uuid::new_v4().to_string(),
+            account_number,
+            asset,
+            from,
+            to,
+            format,
+            status: JobStatus::Pending,
+            created_at: Utc::now().to_rfc3339(),
+            finished_at: None,
+            error: None,
+            content: None,
+        }
+    }
+    pub fn finish(&mut self, result: Result<String, String>) {
+        match result {
+            Ok(content) => {
+                self.status = JobStatus::Done;
+                self.content = Some(content);
+            }
+            Err(e) => {
+                self.status = JobStatus::Failed;
+                self.error = Some(e);
+            }
+        }
+        self.finished_at = Some(Utc::now().to_rfc3339());
+    }
+    pub fn to_response(&self) -> StatementJobPublic {
+        StatementJobPublic {
+            id: self.id.to_owned(),
+            account_number: self.account_number.to_owned(),
+            asset: self.asset.to_owned(),
+            from: self.from.to_owned(),
+            to: self.to.to_owned(),
+            format: self.format.clone(),
+            status: self.status.clone(),
+            created_at: self.created_at.to_owned(),
+            finished_at: self.finished_at.to_owned(),
+            error: self.error.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementJobPublic {
+    pub id: String,
+    pub account_number: String,
+    pub asset: String,
+    pub from: String,
+    pub to: String,
+    pub format: StatementFormat,
+    pub status: JobStatus,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    pub error: Option<String>,
+}
@@ -1,10 +1,15 @@
-use std::{time::SystemTime, fmt::Display};
+use std::{env, time::SystemTime, fmt::Display};
 
 use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
 use rand::{distributions::Alphanumeric, Rng};
 use revolt_rocket_okapi::JsonSchema;
 use serde::{Serialize, Deserialize};
-use sha2::Digest;
+use sha2::{Digest, Sha256};
+
+use crate::mongo::Versioned;
+use crate::domain::event::Aggregate;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum TransactionType {
@@ -47,12 +52,60 @@ pub struct Confirmed {
     pub id_confirmer: String,
     pub timestamp: String,
 }
+lazy_static! {
+    /// Keys the hash chain, so whoever can write the store still cannot write links that verify.
+    pub static ref HASH_CHAIN_SECRET: String =
+        env::var("HASH_CHAIN_SECRET").expect("Error loading env variable: HASH_CHAIN_SECRET");
+}
+
+/// One link of the transaction's hash chain. `state` is `hash_generator` right after the
+/// change and `hash` commits to the previous link, so editing any stored field or event
+/// breaks every link after it. Events written before chaining have no `state`, and those
+/// written before keying are plain SHA-256 and have `keyed` false.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct HashEvents {
     pub hash: String,
     pub timestamp: String,
     pub field_changed: String,
     pub value: String,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub keyed: bool,
+}
+impl HashEvents {
+    /// HMAC-SHA256 with `HASH_CHAIN_SECRET` over the previous link and this one's fields.
+    fn link(previous: &str, state: &str, timestamp: &str, field_changed: &str, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(HASH_CHAIN_SECRET.as_bytes()).expect("HMAC accepts keys of any length");
+        for part in [previous, state, timestamp, field_changed, value] {
+            mac.update(part.as_bytes());
+            mac.update(&[0u8]);
+        }
+        hex::encode(mac.finalize().into_bytes())
+    }
+    /// How links were hashed before the chain was keyed. Anyone can recompute these, so a chain
+    /// made only of them is never reported valid.
+    fn unkeyed_link(previous: &str, state: &str, timestamp: &str, field_changed: &str, value: &str) -> String {
+        let mut hasher = Sha256::new();
+        for part in [previous, state, timestamp, field_changed, value] {
+            hasher.update(part);
+            hasher.update([0u8]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum ChainStatus {
+    Valid,
+    Unchained,
+    Broken,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ChainFinding {
+    pub tx_id: String,
+    pub status: ChainStatus,
+    pub reason: Option<String>,
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct FeeReason {
@@ -78,6 +131,10 @@ pub struct Transaction {
     pub confirmations: Vec<Confirmed>,
     pub confirmations_required: u32,
     pub hash: Vec<HashEvents>,
+    pub payment_reference: Option<String>,
+    pub expires_at: Option<String>,
+    #[serde(default)]
+    pub version: u64,
 }
 impl Transaction {
     pub fn new_transfer(
@@ -88,7 +145,7 @@ impl Transaction {
         memo: String,
         confirmations_required: u32,
     ) -> Transaction {
-        Transaction {
+        let mut transaction = Transaction {
             tx_id: transaction_id_generator(),
             external_id: None,
             transaction_type: TransactionType::Transfer,
@@ -106,7 +163,12 @@ impl Transaction {
             confirmations: Vec::new(),
             confirmations_required,
             hash: Vec::new(),
-        }
+            payment_reference: None,
+            expires_at: None,
+            version: 0,
+        };
+        transaction.create_hash_event("created".to_string(), transaction.transaction_type.to_string());
+        transaction
     }
     pub fn new_deposit(
         asset: String,
@@ -114,7 +176,7 @@ impl Transaction {
         to_wallet: String,
         confirmations_required: u32,
     ) -> Transaction {
-        Transaction {
+        let mut transaction = Transaction {
             tx_id: transaction_id_generator(),
             external_id: None,
             transaction_type: TransactionType::Deposit,
@@ -132,7 +194,12 @@ impl Transaction {
             confirmations: Vec::new(),
             confirmations_required,
             hash: Vec::new(),
-        }
+            payment_reference: None,
+            expires_at: None,
+            version: 0,
+        };
+        transaction.create_hash_event("created".to_string(), transaction.transaction_type.to_string());
+        transaction
     }
     pub fn new_withdraw(
         asset: String,
@@ -140,7 +207,7 @@ impl Transaction {
         from_wallet: String,
         confirmations_required: u32,
     ) -> Transaction {
-        Transaction {
+        let mut transaction = Transaction {
             tx_id: transaction_id_generator(),
             external_id: None,
             transaction_type: TransactionType::Withdraw,
@@ -158,8 +225,43 @@ impl Transaction {
             confirmations: Vec::new(),
             confirmations_required,
             hash: Vec::new(),
+            payment_reference: None,
+            expires_at: None,
+            version: 0,
+        };
+        transaction.create_hash_event("created".to_string(), transaction.transaction_type.to_string());
+        transaction
+    }
+    pub fn set_payment_reference(&mut self, payment_reference: String, expires_at: String) {
+        self.payment_reference = Some(payment_reference.clone());
+        self.create_hash_event("payment_reference".to_string(), payment_reference);
+        self.expires_at = Some(expires_at.clone());
+        self.create_hash_event("expires_at".to_string(), expires_at);
+    }
+    pub fn is_expired(&self) -> bool {
+        match &self.expires_at {
+            Some(expires_at) => {
+                self.transaction_status == TransactionStatus::Pending
+                    && expires_at.as_str() < timestamp_generator().as_str()
+            }
+            None => false,
         }
     }
+    pub fn adjust_deposit_amount(&mut self, amount: f64) -> Result<(), String> {
+        if self.transaction_type != TransactionType::Deposit {
+            return Err("Only deposits can be adjusted".to_string());
+        }
+        if self.transaction_status != TransactionStatus::Pending {
+            return Err("Transaction is not pending".to_string());
+        }
+        if amount <= 0.0 {
+            return Err("Amount must be positive".to_string());
+        }
+        self.amount = amount;
+        self.total_amount = amount + self.fee.iter().map(|fee| fee.amount).sum::<f64>();
+        self.create_hash_event("amount".to_string(), amount.to_string());
+        Ok(())
+    }
     pub fn add_fee(&mut self, reason: String, amount: f64) {
         self.fee.push(FeeReason { reason, amount });
         self.total_amount+=amount;
@@ -226,19 +328,87 @@ impl Transaction {
             Err("Transaction is not pending or confirmed".to_string())
         }
     }
+    pub fn completed_at(&self) -> String {
+        self.hash
+            .iter()
+            .rev()
+            .find(|event| {
+                event.field_changed == "transaction_status"
+                    && event.value == TransactionStatus::Completed.to_string()
+            })
+            .map(|event| event.timestamp.clone())
+            .unwrap_or(self.timestamp.clone())
+    }
     pub fn create_hash_event(&mut self, field_changed: String, value: String) {
+        let previous = match self.hash.last() {
+            Some(event) => event.hash.clone(),
+            None => self.tx_id.clone(),
+        };
+        let state = self.hash_generator();
+        let timestamp = timestamp_generator();
         self.hash.push(HashEvents {
-            hash: self.hash_generator(),
-            timestamp: timestamp_generator(),
+            hash: HashEvents::link(&previous, &state, &timestamp, &field_changed, &value),
+            timestamp,
             field_changed,
             value,
+            state: Some(state),
+            keyed: true,
         })
     }
+    /// Walks the hash chain from the first event and checks the stored fields against the
+    /// state committed by the last one. Once a link is keyed every later one must be, and the
+    /// chain is only valid when its last link is.
+    pub fn verify_hash_chain(&self) -> ChainFinding {
+        let finding = |status, reason: Option<String>| ChainFinding {
+            tx_id: self.tx_id.clone(),
+            status,
+            reason,
+        };
+        let mut previous = self.tx_id.clone();
+        let mut last_state = None;
+        let mut keyed = false;
+        for (position, event) in self.hash.iter().enumerate() {
+            let state = match &event.state {
+                Some(state) => state,
+                None if last_state.is_none() => {
+                    return finding(ChainStatus::Unchained, Some("Hash events predate chaining".to_string()))
+                }
+                None => {
+                    return finding(ChainStatus::Broken, Some(format!("Hash event {} lost its state", position)))
+                }
+            };
+            if keyed && !event.keyed {
+                return finding(ChainStatus::Broken, Some(format!("Hash event {} is not keyed", position)));
+            }
+            keyed = event.keyed;
+            let expected = match event.keyed {
+                true => HashEvents::link(&previous, state, &event.timestamp, &event.field_changed, &event.value),
+                false => HashEvents::unkeyed_link(&previous, state, &event.timestamp, &event.field_changed, &event.value),
+            };
+            if event.hash != expected {
+                return finding(
+                    ChainStatus::Broken,
+                    Some(format!("Hash event {} ({}) does not match the chain", position, event.field_changed)),
+                );
+            }
+            previous = event.hash.clone();
+            last_state = Some(state);
+        }
+        match last_state {
+            Some(state) if *state != self.hash_generator() => finding(
+                ChainStatus::Broken,
+                Some("Stored fields do not match the last hash event".to_string()),
+            ),
+            Some(_) if keyed => finding(ChainStatus::Valid, None),
+            Some(_) => finding(ChainStatus::Unchained, Some("Hash events predate keyed chaining".to_string())),
+            None => finding(ChainStatus::Unchained, Some("Transaction has no hash events".to_string())),
+        }
+    }
     pub fn hash_generator(&self) -> String {
         let fee_string = self
             .fee
             .iter()
-            .map(|x| x.amount.to_string())
+            .map(|x| format!("{}{}", x.reason, x.amount))
             .collect::<Vec<String>>()
             .join("");
         let hash_string = match &self.hash_chain {
@@ -265,6 +435,7 @@ impl Transaction {
         full_string.push_str(&self.transaction_status.to_string());
         full_string.push_str(&self.asset);
         full_string.push_str(&self.amount.to_string());
+        full_string.push_str(&self.total_amount.to_string());
         full_string.push_str(&self.from_wallet.clone().unwrap_or("Deposit".to_string()));
         full_string.push_str(&self.to_wallet.clone().unwrap_or("Withdrawal".to_string()));
         full_string.push_str(&self.timestamp);
@@ -274,6 +445,8 @@ impl Transaction {
         full_string.push_str(&block_string);
         full_string.push_str(&confirmation_string);
         full_string.push_str(&self.confirmations_required.to_string());
+        full_string.push_str(&self.payment_reference.clone().unwrap_or("None".to_string()));
+        full_string.push_str(&self.expires_at.clone().unwrap_or("None".to_string()));
 
         let mut hasher = sha2::Sha256::new();
         hasher.update(full_string);
@@ -282,6 +455,25 @@ impl Transaction {
         hash_string
     }
 }
+impl Versioned for Transaction {
+    fn version(&self) -> u64 {
+        self.version
+    }
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+impl Aggregate for Transaction {
+    fn aggregate_type(&self) -> &'static str {
+        "Transaction"
+    }
+    fn aggregate_id(&self) -> String {
+        self.tx_id.clone()
+    }
+    fn accounts(&self) -> Vec<String> {
+        self.from_wallet.iter().chain(self.to_wallet.iter()).cloned().collect()
+    }
+}
 fn transaction_id_generator() -> String {
     let mut rng = rand::thread_rng();
     (0..32)
@@ -294,3 +486,64 @@ fn timestamp_generator() -> String {
     let datetime = DateTime::<Utc>::from(now);
     datetime.to_rfc3339()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completed() -> Transaction {
+        env::set_var("HASH_CHAIN_SECRET", "chain-test-secret");
+        let mut transaction = Transaction::new_transfer("USD".to_string(), 10.0, "a".to_string(), "b".to_string(), "Rent".to_string(), 1);
+        transaction.confirm_transaction("admin".to_string()).unwrap();
+        transaction.complete_transaction("ext-1".to_string()).unwrap();
+        transaction
+    }
+
+    #[test]
+    fn untouched_chains_are_valid() {
+        let transaction = completed();
+        assert!(transaction.hash.iter().all(|event| event.keyed));
+        assert_eq!(transaction.verify_hash_chain().status, ChainStatus::Valid);
+    }
+
+    #[test]
+    fn edited_fields_break_the_chain() {
+        let mut transaction = completed();
+        transaction.amount = 1000.0;
+        assert_eq!(transaction.verify_hash_chain().status, ChainStatus::Broken);
+        let mut transaction = completed();
+        transaction.hash[1].value = "Cancelled".to_string();
+        assert_eq!(transaction.verify_hash_chain().status, ChainStatus::Broken);
+    }
+
+    #[test]
+    fn chains_rebuilt_without_the_secret_do_not_verify() {
+        let mut transaction = completed();
+        transaction.amount = 1000.0;
+        // what someone with write access to the store but not the secret can do
+        let mut previous = transaction.tx_id.clone();
+        let state = transaction.hash_generator();
+        for event in transaction.hash.iter_mut() {
+            event.state = Some(state.clone());
+            event.hash = HashEvents::unkeyed_link(&previous, &state, &event.timestamp, &event.field_changed, &event.value);
+            previous = event.hash.clone();
+        }
+        assert_eq!(transaction.verify_hash_chain().status, ChainStatus::Broken);
+        for event in transaction.hash.iter_mut() {
+            event.keyed = false;
+        }
+        assert_eq!(transaction.verify_hash_chain().status, ChainStatus::Unchained);
+    }
+
+    #[test]
+    fn keyed_links_cannot_be_followed_by_unkeyed_ones() {
+        let mut transaction = completed();
+        let last = transaction.hash.len() - 1;
+        let previous = transaction.hash[last - 1].hash.clone();
+        let event = &mut transaction.hash[last];
+        event.keyed = false;
+        event.hash = HashEvents::unkeyed_link(&previous, event.state.as_ref().unwrap(), &event.timestamp, &event.field_changed, &event.value);
+        let finding = transaction.verify_hash_chain();
+        assert_eq!(finding.status, ChainStatus::Broken);
+    }
+}
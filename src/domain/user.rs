@@ -8,21 +8,88 @@ use serde::{Serialize, Deserialize};
 pub enum Role{
     Admin,
     User,
+    Operator,
+    Compliance,
+    Auditor,
+    Support,
 }
 impl Role {
     pub fn from_str(s: &str) -> Role {
         match s.to_lowercase().as_str() {
             "admin" => Role::Admin,
             "user" => Role::User,
+            "operator" => Role::Operator,
+            "compliance" => Role::Compliance,
+            "auditor" => Role::Auditor,
+            "support" => Role::Support,
             _ => Role::User,
         }
     }
+    /// What the role can do beyond the accounts its user owns or was granted.
+    pub fn permissions(&self) -> Vec<Permission> {
+        match self {
+            Role::Admin => Permission::ALL.to_vec(),
+            Role::User => vec![],
+            Role::Operator => vec![Permission::AccountsRead, Permission::AccountsFreeze, Permission::TransactionsApprove],
+            Role::Compliance => vec![Permission::AccountsRead, Permission::AccountsFreeze, Permission::ReportsRead],
+            Role::Auditor => vec![Permission::AccountsRead, Permission::ReportsRead],
+            Role::Support => vec![Permission::AccountsRead],
+        }
+    }
 }
 impl Display for Role {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             Role::Admin => write!(f, "admin"),
             Role::User => write!(f, "user"),
+            Role::Operator => write!(f, "operator"),
+            Role::Compliance => write!(f, "compliance"),
+            Role::Auditor => write!(f, "auditor"),
+            Role::Support => write!(f, "support"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+pub enum Permission {
+    /// Any account, its ledgers, transactions and statements.
+    AccountsRead,
+    /// Disabling and enabling accounts.
+    AccountsFreeze,
+    /// Acting on any account as its owner would: deposits, withdrawals, closures and webhooks.
+    AccountsOperate,
+    /// Confirming, completing and failing transactions, expiring deposits and reconciling.
+    TransactionsApprove,
+    AssetsManage,
+    /// Changing the role of users.
+    UsersManage,
+    /// Audit log, integrity reports and reconciliation imports.
+    ReportsRead,
+}
+impl Permission {
+    pub const ALL: [Permission; 7] = [
+        Permission::AccountsRead,
+        Permission::AccountsFreeze,
+        Permission::AccountsOperate,
+        Permission::TransactionsApprove,
+        Permission::AssetsManage,
+        Permission::UsersManage,
+        Permission::ReportsRead,
+    ];
+    pub fn from_str(s: &str) -> Option<Permission> {
+        Permission::ALL.iter().find(|permission| permission.to_string() == s).copied()
+    }
+}
+impl Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Permission::AccountsRead => write!(f, "accounts:read"),
+            Permission::AccountsFreeze => write!(f, "accounts:freeze"),
+            Permission::AccountsOperate => write!(f, "accounts:operate"),
+            Permission::TransactionsApprove => write!(f, "transactions:approve"),
+            Permission::AssetsManage => write!(f, "assets:manage"),
+            Permission::UsersManage => write!(f, "users:manage"),
+            Permission::ReportsRead => write!(f, "reports:read"),
         }
     }
 }
@@ -46,6 +113,7 @@ impl User {
             name: self.name.to_owned(),
             location: self.location.to_owned(),
             title: self.title.to_owned(),
+            role: self.role.clone(),
         }
     }
 }
@@ -57,4 +125,5 @@ pub struct UserPublic {
     pub name: String,
     pub location: String,
     pub title: String,
+    pub role: Role,
 }
\ No newline at end of file
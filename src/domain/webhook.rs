@@ -0,0 +1,275 @@
+use std::net::IpAddr;
+
+use chrono::{Duration, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::Url;
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use url::Host;
+use uuid::Uuid;
+
+use super::event::{DomainEvent, EventType};
+
+const MAX_BACKOFF_SECONDS: i64 = 6 * 3600;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookSubscription {
+    pub id: String,
+    /// None subscribes to the events of every account, which needs accounts:read.
+    pub account_number: Option<String>,
+    pub url: String,
+    /// Empty subscribes to every event type.
+    pub event_types: Vec<EventType>,
+    pub secret: String,
+    pub active: bool,
+    pub created_by: String,
+    pub created_at: String,
+}
+impl WebhookSubscription {
+    pub fn new(
+        account_number: Option<String>,
+        url: String,
+        event_types: Vec<EventType>,
+        created_by: String,
+    ) -> Result<WebhookSubscription, String> {
+        let parsed = match Url::parse(&url) {
+            Ok(parsed) => parsed,
+            Err(e) => return Err(format!("Invalid webhook url: {}", e)),
+        };
+        if parsed.scheme() != "https" {
+            return Err("Webhook url must be https".to_string());
+        }
+        // names are checked again once resolved, on every delivery
+        match parsed.host() {
+            Some(Host::Ipv4(ip)) if !is_public(&IpAddr::V4(ip)) => return Err("Webhook url must not point to a private address".to_string()),
+            Some(Host::Ipv6(ip)) if !is_public(&IpAddr::V6(ip)) => return Err("Webhook url must not point to a private address".to_string()),
+            Some(Host::Domain("localhost")) => return Err("Webhook url must not point to a private address".to_string()),
+            Some(_) => (),
+            None => return Err("Webhook url has no host".to_string()),
+        };
+        Ok(WebhookSubscription {
+            id: Uuid::new_v4().to_string(),
+            account_number,
+            url,
+            event_types,
+            secret: secret_generator(),
+            active: true,
+            created_by,
+            created_at: Utc::now().to_rfc3339(),
+        })
+    }
+    pub fn wants(&self, event: &DomainEvent) -> bool {
+        if !self.active {
+            return false;
+        }
+        if !self.event_types.is_empty() && !self.event_types.contains(&event.event_type) {
+            return false;
+        }
+        match &self.account_number {
+            Some(account) => event.accounts.contains(account),
+            None => true,
+        }
+    }
+    pub fn rotate_secret(&mut self) {
+        self.secret = secret_generator();
+    }
+    /// Copy safe to list, the secret is only shown when it is created or rotated.
+    pub fn redacted(&self) -> WebhookSubscription {
+        WebhookSubscription {
+            secret: "********".to_string(),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DeliveryAttempt {
+    pub at: String,
+    pub response_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+impl DeliveryAttempt {
+    pub fn succeeded(&self) -> bool {
+        matches!(self.response_code, Some(code) if (200..300).contains(&code))
+    }
+}
+
+/// How often a failed delivery is tried again, waiting `backoff_seconds` doubled on each retry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff_seconds: i64,
+}
+impl RetryPolicy {
+    pub fn delay(&self, retries: u32) -> Duration {
+        let factor = 2_i64.saturating_pow(retries.saturating_sub(1));
+        Duration::seconds(self.backoff_seconds.saturating_mul(factor).min(MAX_BACKOFF_SECONDS))
+    }
+}
+
+/// One event sent to one subscription. The body is fixed when the delivery is created so
+/// retries and replays send the same bytes, only the timestamp and signature change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub subscription_id: String,
+    pub event_id: String,
+    pub event_type: EventType,
+    pub body: String,
+    pub status: DeliveryStatus,
+    /// Attempts since the delivery was created or last replayed, what the retry policy counts.
+    pub retries: u32,
+    pub next_attempt_at: String,
+    pub created_at: String,
+    pub delivered_at: Option<String>,
+    pub attempts: Vec<DeliveryAttempt>,
+}
+impl WebhookDelivery {
+    pub fn new(subscription: &WebhookSubscription, event: &DomainEvent) -> Result<WebhookDelivery, String> {
+        let data: Value = match serde_json::from_str(&event.payload) {
+            Ok(data) => data,
+            Err(e) => return Err(format!("Error reading event payload: {}", e)),
+        };
+        let body = json!({
+            "id": event.id,
+            "event_type": event.event_type,
+            "aggregate_type": event.aggregate_type,
+            "aggregate_id": event.aggregate_id,
+            "sequence": event.sequence,
+            "occurred_at": event.occurred_at,
+            "data": data,
+        });
+        let now = Utc::now().to_rfc3339();
+        Ok(WebhookDelivery {
+            id: WebhookDelivery::id_for(&subscription.id, &event.id),
+            subscription_id: subscription.id.clone(),
+            event_id: event.id.clone(),
+            event_type: event.event_type.clone(),
+            body: body.to_string(),
+            status: DeliveryStatus::Pending,
+            retries: 0,
+            next_attempt_at: now.clone(),
+            created_at: now,
+            delivered_at: None,
+            attempts: Vec::new(),
+        })
+    }
+    /// Deterministic, so an event relayed twice is still delivered once per subscription.
+    pub fn id_for(subscription_id: &str, event_id: &str) -> String {
+        format!("{}:{}", subscription_id, event_id)
+    }
+    pub fn record(&mut self, attempt: DeliveryAttempt, policy: &RetryPolicy) {
+        self.retries += 1;
+        if attempt.succeeded() {
+            self.status = DeliveryStatus::Delivered;
+            self.delivered_at = Some(attempt.at.clone());
+        } else if self.retries >= policy.max_attempts {
+            self.status = DeliveryStatus::Failed;
+        } else {
+            self.next_attempt_at = (Utc::now() + policy.delay(self.retries)).to_rfc3339();
+        }
+        self.attempts.push(attempt);
+    }
+    /// Gives up on a delivery whose subscription is gone or disabled.
+    pub fn abandon(&mut self, reason: String) {
+        self.status = DeliveryStatus::Failed;
+        self.attempts.push(DeliveryAttempt {
+            at: Utc::now().to_rfc3339(),
+            response_code: None,
+            error: Some(reason),
+            duration_ms: 0,
+        });
+    }
+    /// Queues the delivery again right away with a fresh retry budget, keeping its log.
+    pub fn replay(&mut self) {
+        self.status = DeliveryStatus::Pending;
+        self.retries = 0;
+        self.next_attempt_at = Utc::now().to_rfc3339();
+    }
+}
+
+/// False for loopback, private, link-local and other addresses that do not belong to a public
+/// host, which webhooks must never be sent to.
+pub fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // shared address space, carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_public(&IpAddr::V4(mapped));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+fn secret_generator() -> String {
+    let mut rng = rand::thread_rng();
+    (0..40)
+        .map(|_| rng.sample(Alphanumeric))
+        .map(|x| (x) as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscribe(url: &str) -> Result<WebhookSubscription, String> {
+        WebhookSubscription::new(None, url.to_string(), Vec::new(), "admin".to_string())
+    }
+
+    #[test]
+    fn subscriptions_need_public_https_urls() {
+        assert!(subscribe("https://hooks.example.com/bank").is_ok());
+        for url in ["http://hooks.example.com/bank", "ftp://hooks.example.com", "https://10.0.0.7/hook", "https://[fd00::1]/hook", "https://localhost/hook", "not a url"] {
+            assert!(subscribe(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn tells_public_addresses_apart() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fe80::1", "fc00::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn retries_back_off_exponentially_up_to_a_cap() {
+        let policy = RetryPolicy { max_attempts: 8, backoff_seconds: 30 };
+        assert_eq!(policy.delay(1), Duration::seconds(30));
+        assert_eq!(policy.delay(2), Duration::seconds(60));
+        assert_eq!(policy.delay(4), Duration::seconds(240));
+        assert_eq!(policy.delay(40), Duration::seconds(MAX_BACKOFF_SECONDS));
+        assert_eq!(policy.delay(0), Duration::seconds(30));
+    }
+}
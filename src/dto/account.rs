@@ -0,0 +1,33 @@
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::domain::account::OwnerPermission;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AccountCreationRequest {
+    /// Label shown to the owners, e.g. "Savings".
+    pub name: Option<String>,
+    /// Symbols of the ledgers opened with the account, USD, BTC and EUR when missing.
+    pub assets: Option<Vec<String>>,
+    /// Co-owners besides the caller, by user id.
+    pub owners: Option<Vec<AccountOwnerRequest>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AccountOwnerRequest {
+    pub user_id: String,
+    pub permission: OwnerPermission,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AccountRenameRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ClosureRequest {
+    /// Account receiving the remaining balances, required while any ledger holds funds.
+    pub destination: Option<String>,
+    /// Cancel pending transactions instead of waiting for them. Confirmed ones are always waited for.
+    pub cancel_pending: Option<bool>,
+}
@@ -0,0 +1,15 @@
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::domain::user::Permission;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ApiKeyRequest {
+    pub name: String,
+    /// User or service account the key acts as, the caller when missing. Needs users:manage.
+    pub user_id: Option<String>,
+    pub scopes: Option<Vec<Permission>>,
+    pub accounts: Option<Vec<String>>,
+    pub ip_allow_list: Option<Vec<String>>,
+    pub expires_at: Option<String>,
+}
@@ -0,0 +1,58 @@
+use mongodb::bson::{doc, Document};
+use revolt_rocket_okapi::JsonSchema;
+use rocket::FromForm;
+use serde::{Deserialize, Serialize};
+
+use super::transaction::normalize_date;
+
+/// Most entries a single search or export returns.
+const MAX_AUDIT_LIMIT: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub resource: Option<String>,
+    pub outcome: Option<String>,
+    pub request_id: Option<String>,
+    pub from_date: Option<String>,
+    pub to_date: Option<String>,
+    pub limit: Option<usize>,
+}
+impl AuditFilter {
+    pub fn page_size(&self, default: usize) -> usize {
+        self.limit.unwrap_or(default).clamp(1, MAX_AUDIT_LIMIT)
+    }
+    pub fn to_filter(&self) -> Result<Document, String> {
+        let mut conditions: Vec<Document> = Vec::new();
+        if let Some(actor) = &self.actor {
+            conditions.push(doc! {"actor_id": actor});
+        }
+        if let Some(action) = &self.action {
+            conditions.push(doc! {"action": action});
+        }
+        if let Some(resource) = &self.resource {
+            conditions.push(doc! {"resource": resource});
+        }
+        if let Some(outcome) = &self.outcome {
+            match outcome.as_str() {
+                "Success" | "Failure" | "Denied" => conditions.push(doc! {"outcome": outcome}),
+                other => return Err(format!("Invalid outcome: {}", other)),
+            }
+        }
+        if let Some(request_id) = &self.request_id {
+            conditions.push(doc! {"request_id": request_id});
+        }
+        if let Some(from_date) = &self.from_date {
+            conditions.push(doc! {"occurred_at": {"$gte": normalize_date(from_date, false)?}});
+        }
+        if let Some(to_date) = &self.to_date {
+            conditions.push(doc! {"occurred_at": {"$lte": normalize_date(to_date, true)?}});
+        }
+        if conditions.is_empty() {
+            Ok(doc! {})
+        } else {
+            Ok(doc! {"$and": conditions})
+        }
+    }
+}
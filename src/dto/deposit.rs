@@ -1,5 +1,7 @@
+use lazy_static::lazy_static;
 use revolt_rocket_okapi::JsonSchema;
 use serde::{Serialize, Deserialize};
+use std::env;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Deposit{
@@ -13,6 +15,44 @@ pub struct DepositCreation<T>
 where T: Clone + PartialEq + Serialize {
     pub account: T,
     pub tx_id: String,
+    pub instructions: Option<DepositInstructions>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DepositInstructions {
+    pub beneficiary_name: String,
+    pub iban: String,
+    pub bic: String,
+    pub reference: String,
+    pub amount: f64,
+    pub currency: String,
+    pub expires_at: String,
+}
+impl DepositInstructions {
+    pub fn new(reference: String, amount: f64, currency: String, expires_at: String) -> DepositInstructions {
+        DepositInstructions {
+            beneficiary_name: DEPOSIT_BENEFICIARY_NAME.to_string(),
+            iban: DEPOSIT_IBAN.to_string(),
+            bic: DEPOSIT_BIC.to_string(),
+            reference,
+            amount,
+            currency,
+            expires_at,
+        }
+    }
+}
+lazy_static! {
+    static ref DEPOSIT_BENEFICIARY_NAME: String =
+        env::var("DEPOSIT_BENEFICIARY_NAME").unwrap_or("My Bank".to_string());
+    static ref DEPOSIT_IBAN: String =
+        env::var("DEPOSIT_IBAN").unwrap_or("".to_string());
+    static ref DEPOSIT_BIC: String =
+        env::var("DEPOSIT_BIC").unwrap_or("".to_string());
+    pub static ref DEPOSIT_EXPIRES_IN: i64 =
+        env::var("DEPOSIT_EXPIRES_IN")
+            .unwrap_or("604800".to_string())
+            .parse()
+            .expect("Error parsing env variable: DEPOSIT_EXPIRES_IN");
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
@@ -0,0 +1,16 @@
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::domain::grant::GrantScope;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GrantRequest {
+    pub grantee: String,
+    pub scopes: Vec<GrantScope>,
+    /// Largest single transfer the grantee can initiate, required with `InitiateTransfers`.
+    pub transfer_limit: Option<f64>,
+    /// Cap on all the transfers the grantee initiates together, fees included, none when missing.
+    pub total_limit: Option<f64>,
+    /// RFC 3339 date after which the grant stops working, never when missing.
+    pub expires_at: Option<String>,
+}
@@ -1,3 +1,10 @@
+pub mod account;
+pub mod api_key;
+pub mod audit;
 pub mod deposit;
+pub mod grant;
+pub mod reconciliation;
+pub mod statement;
 pub mod transaction;
-pub mod user;
\ No newline at end of file
+pub mod user;
+pub mod webhook;
\ No newline at end of file
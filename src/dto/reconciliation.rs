@@ -0,0 +1,27 @@
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CsvMapping {
+    pub delimiter: Option<char>,
+    pub has_header: bool,
+    pub date: String,
+    pub amount: String,
+    pub bank_reference: String,
+    pub remittance: String,
+    pub currency: Option<String>,
+    pub credit_debit: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementImportRequest {
+    pub format: String,
+    pub content: String,
+    pub mapping: Option<CsvMapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReviewResolution {
+    pub tx_id: String,
+    pub accept_received_amount: Option<bool>,
+}
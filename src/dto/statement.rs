@@ -0,0 +1,10 @@
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatementRequest {
+    pub symbol: String,
+    pub from: String,
+    pub to: String,
+    pub format: String,
+}
@@ -1,4 +1,6 @@
+use mongodb::bson::{doc, Bson, Document};
 use revolt_rocket_okapi::JsonSchema;
+use rocket::FromForm;
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
@@ -8,3 +10,170 @@ pub struct TransactionRequest{
     pub from: String,
     pub to: String,
 }
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct TransactionFilter {
+    pub account: Option<String>,
+    pub transaction_type: Option<String>,
+    pub status: Option<String>,
+    pub asset: Option<String>,
+    pub direction: Option<String>,
+    pub min_amount: Option<f64>,
+    pub max_amount: Option<f64>,
+    pub from_date: Option<String>,
+    pub to_date: Option<String>,
+    pub external_id: Option<String>,
+    pub memo: Option<String>,
+    pub sort_by: Option<String>,
+    pub order: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+impl TransactionFilter {
+    pub fn page_size(&self) -> usize {
+        self.limit.unwrap_or(10).clamp(1, 100)
+    }
+    fn sort_field(&self) -> Result<&'static str, String> {
+        match self.sort_by.as_deref().unwrap_or("timestamp") {
+            "timestamp" => Ok("timestamp"),
+            "amount" => Ok("amount"),
+            other => Err(format!("Invalid sort field: {}", other)),
+        }
+    }
+    fn descending(&self) -> Result<bool, String> {
+        match self.order.as_deref().unwrap_or("desc") {
+            "desc" => Ok(true),
+            "asc" => Ok(false),
+            other => Err(format!("Invalid order: {}", other)),
+        }
+    }
+    pub fn to_sort(&self) -> Result<Document, String> {
+        let direction = if self.descending()? { -1 } else { 1 };
+        let mut sort = Document::new();
+        sort.insert(self.sort_field()?, direction);
+        sort.insert("tx_id", direction);
+        Ok(sort)
+    }
+    pub fn to_filter(&self) -> Result<Document, String> {
+        let mut conditions: Vec<Document> = Vec::new();
+        if let Some(account) = &self.account {
+            let direction = match self.direction.as_deref().unwrap_or("all") {
+                "in" => doc! {"to_wallet": account},
+                "out" => doc! {"from_wallet": account},
+                "all" => doc! {"$or": [{"from_wallet": account}, {"to_wallet": account}]},
+                other => return Err(format!("Invalid direction: {}", other)),
+            };
+            conditions.push(direction);
+        }
+        if let Some(transaction_type) = &self.transaction_type {
+            match transaction_type.as_str() {
+                "Deposit" | "Withdraw" | "Transfer" | "Trading" => {
+                    conditions.push(doc! {"transaction_type": transaction_type})
+                }
+                other => return Err(format!("Invalid transaction type: {}", other)),
+            }
+        }
+        if let Some(status) = &self.status {
+            match status.as_str() {
+                "Pending" | "Confirmed" | "Completed" | "Cancelled" | "Failed" => {
+                    conditions.push(doc! {"transaction_status": status})
+                }
+                other => return Err(format!("Invalid transaction status: {}", other)),
+            }
+        }
+        if let Some(asset) = &self.asset {
+            conditions.push(doc! {"asset": asset.to_uppercase()});
+        }
+        if let Some(min_amount) = self.min_amount {
+            conditions.push(doc! {"amount": {"$gte": min_amount}});
+        }
+        if let Some(max_amount) = self.max_amount {
+            conditions.push(doc! {"amount": {"$lte": max_amount}});
+        }
+        if let Some(from_date) = &self.from_date {
+            conditions.push(doc! {"timestamp": {"$gte": normalize_date(from_date, false)?}});
+        }
+        if let Some(to_date) = &self.to_date {
+            conditions.push(doc! {"timestamp": {"$lte": normalize_date(to_date, true)?}});
+        }
+        if let Some(external_id) = &self.external_id {
+            conditions.push(doc! {"external_id": external_id});
+        }
+        if let Some(memo) = &self.memo {
+            conditions.push(doc! {"memo": {"$regex": escape_regex(memo), "$options": "i"}});
+        }
+        if let Some(cursor) = &self.cursor {
+            let (value, tx_id) = decode_cursor(cursor)?;
+            let field = self.sort_field()?;
+            let value = match field {
+                "amount" => match value.parse::<f64>() {
+                    Ok(amount) => Bson::Double(amount),
+                    Err(_) => return Err("Invalid cursor".to_string()),
+                },
+                _ => Bson::String(value),
+            };
+            let operator = if self.descending()? { "$lt" } else { "$gt" };
+            let mut after_value = Document::new();
+            after_value.insert(field, doc! {operator: value.clone()});
+            let mut same_value = Document::new();
+            same_value.insert(field, value);
+            same_value.insert("tx_id", doc! {operator: tx_id});
+            conditions.push(doc! {"$or": [after_value, same_value]});
+        }
+        if conditions.is_empty() {
+            Ok(doc! {})
+        } else {
+            Ok(doc! {"$and": conditions})
+        }
+    }
+}
+
+pub fn normalize_date(date: &str, end_of_day: bool) -> Result<String, String> {
+    match chrono::DateTime::parse_from_rfc3339(date) {
+        Ok(date) => Ok(date.with_timezone(&chrono::Utc).to_rfc3339()),
+        Err(_) => match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(date) if end_of_day => Ok(format!("{}T23:59:59.999999999+00:00", date.format("%Y-%m-%d"))),
+            Ok(date) => Ok(format!("{}T00:00:00+00:00", date.format("%Y-%m-%d"))),
+            Err(_) => Err(format!("Invalid date: {}", date)),
+        },
+    }
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn encode_cursor(sort_value: &str, tx_id: &str) -> String {
+    format!("{}|{}", sort_value, tx_id)
+        .bytes()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn decode_cursor(cursor: &str) -> Result<(String, String), String> {
+    if !cursor.is_ascii() || cursor.len() % 2 != 0 {
+        return Err("Invalid cursor".to_string());
+    }
+    let mut bytes = Vec::new();
+    for i in (0..cursor.len()).step_by(2) {
+        match u8::from_str_radix(&cursor[i..i + 2], 16) {
+            Ok(b) => bytes.push(b),
+            Err(_) => return Err("Invalid cursor".to_string()),
+        }
+    }
+    let decoded = match String::from_utf8(bytes) {
+        Ok(decoded) => decoded,
+        Err(_) => return Err("Invalid cursor".to_string()),
+    };
+    match decoded.rsplit_once('|') {
+        Some((value, tx_id)) => Ok((value.to_string(), tx_id.to_string())),
+        None => Err("Invalid cursor".to_string()),
+    }
+}
@@ -1,6 +1,8 @@
 use revolt_rocket_okapi::JsonSchema;
 use serde::{Serialize, Deserialize};
 
+use crate::domain::user::Role;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct UserRegisterRequest {
     pub id: Option<String>,
@@ -27,3 +29,31 @@ pub struct Token {
     pub token: String,
     pub refresh_token: String,
 }
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RoleRequest {
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// Same answer whether the email is registered or not.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PasswordResetRequested {
+    pub message: String,
+}
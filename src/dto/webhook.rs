@@ -0,0 +1,11 @@
+use revolt_rocket_okapi::JsonSchema;
+use serde::{Serialize, Deserialize};
+
+use crate::domain::event::EventType;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookRequest {
+    pub account_number: Option<String>,
+    pub url: String,
+    pub event_types: Vec<EventType>,
+}
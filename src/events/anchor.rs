@@ -0,0 +1,134 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use mongodb::bson::doc;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::time::sleep,
+    Orbit, Rocket,
+};
+
+use crate::{
+    domain::{
+        event::{DomainEvent, EventType},
+        merkle::{DailyRoot, LedgerEntry},
+        transaction::{Transaction, TransactionStatus},
+    },
+    events::publisher::Publisher,
+    mongo::{Crud, Db},
+    security::signing::RootSigner,
+};
+
+/// Entries of a day can still be appending right after midnight, sealing waits this long.
+const SEAL_DELAY_MINUTES: i64 = 10;
+
+/// Appends every completed transaction to the global ledger as its completion event is relayed.
+pub struct LedgerAppender {
+    entries: Db<LedgerEntry>,
+}
+impl LedgerAppender {
+    pub fn new(entries: Db<LedgerEntry>) -> LedgerAppender {
+        LedgerAppender { entries }
+    }
+}
+#[async_trait]
+impl Publisher for LedgerAppender {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), String> {
+        match event.event_type {
+            EventType::TransactionCompleted | EventType::DepositConfirmed | EventType::WithdrawalReleased => (),
+            _ => return Ok(()),
+        }
+        let transaction: Transaction = match serde_json::from_str(&event.payload) {
+            Ok(transaction) => transaction,
+            Err(e) => return Err(format!("Error reading event payload: {}", e)),
+        };
+        if transaction.transaction_status != TransactionStatus::Completed {
+            return Ok(());
+        }
+        append(self.entries.as_ref(), &transaction).await
+    }
+}
+
+/// Gives the transaction the next sequence number. Entry ids are the sequence, so a
+/// concurrent appender taking the same number fails to create and the relay retries.
+pub async fn append(entries: &dyn Crud<LedgerEntry>, transaction: &Transaction) -> Result<(), String> {
+    if !entries.find(doc! {"tx_id": &transaction.tx_id}, doc! {}, 1).await?.is_empty() {
+        return Ok(());
+    }
+    let sequence = match entries.find(doc! {}, doc! {"sequence": -1}, 1).await?.first() {
+        Some(last) => last.sequence + 1,
+        None => 1,
+    };
+    entries.create(LedgerEntry::new(sequence, transaction)?).await?;
+    Ok(())
+}
+
+/// Seals each finished UTC day into a signed Merkle root in the background once Rocket is up.
+pub struct RootSealer {
+    entries: Db<LedgerEntry>,
+    roots: Db<DailyRoot>,
+    signer: Arc<RootSigner>,
+    interval: Duration,
+}
+impl RootSealer {
+    pub fn new(entries: Db<LedgerEntry>, roots: Db<DailyRoot>, signer: Arc<RootSigner>, interval: Duration) -> RootSealer {
+        RootSealer {
+            entries,
+            roots,
+            signer,
+            interval,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RootSealer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Ledger root sealer",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, _: &Rocket<Orbit>) {
+        let entries = self.entries.clone();
+        let roots = self.roots.clone();
+        let signer = self.signer.clone();
+        let interval = self.interval;
+        rocket::tokio::spawn(async move {
+            loop {
+                if let Err(e) = seal(entries.as_ref(), roots.as_ref(), signer.as_ref()).await {
+                    println!("Error sealing ledger roots: {}", e);
+                }
+                sleep(interval).await;
+            }
+        });
+    }
+}
+
+/// Seals every closed day with entries after the last published root, oldest first, and
+/// returns how many roots were published. Days without entries get no root.
+pub async fn seal(entries: &dyn Crud<LedgerEntry>, roots: &dyn Crud<DailyRoot>, signer: &RootSigner) -> Result<usize, String> {
+    let open_day = (Utc::now() - chrono::Duration::minutes(SEAL_DELAY_MINUTES))
+        .format("%Y-%m-%d")
+        .to_string();
+    let mut previous = roots.find(doc! {}, doc! {"day": -1}, 1).await?.into_iter().next();
+    let mut sealed = 0;
+    loop {
+        let after = previous.as_ref().map(|root| root.day.clone()).unwrap_or_default();
+        let next = entries
+            .find(doc! {"day": {"$gt": &after, "$lt": &open_day}}, doc! {"sequence": 1}, 1)
+            .await?;
+        let day = match next.first() {
+            Some(entry) => entry.day.clone(),
+            None => return Ok(sealed),
+        };
+        let day_entries = entries.find(doc! {"day": &day}, doc! {"sequence": 1}, 0).await?;
+        let mut root = DailyRoot::new(day, &day_entries, previous.as_ref(), signer.public_key())?;
+        root.signature = signer.sign(&root.message());
+        roots.create(root.clone()).await?;
+        previous = Some(root);
+        sealed += 1;
+    }
+}
@@ -0,0 +1,25 @@
+pub mod anchor;
+pub mod publisher;
+pub mod relay;
+pub mod webhook;
+
+use crate::{
+    domain::event::{Aggregate, DomainEvent, EventType},
+    mongo::{Crud, Session},
+};
+
+/// Writes the event for the latest change of `aggregate` to the outbox as part of `session`,
+/// the one the change is written in, so the event is kept exactly when the change is.
+/// The relay publishes it from there.
+pub async fn record<T: Aggregate + Sync>(
+    session: &mut dyn Session,
+    outbox: &dyn Crud<DomainEvent>,
+    event_type: EventType,
+    aggregate: &T,
+) -> Result<(), String> {
+    let event = DomainEvent::of(event_type, aggregate)?;
+    match outbox.create_in(session, event).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Error recording event: {}", e)),
+    }
+}
@@ -0,0 +1,199 @@
+use std::{env, sync::Arc};
+
+use async_trait::async_trait;
+use rocket::tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::{broadcast, Mutex},
+};
+
+use crate::domain::event::DomainEvent;
+
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    /// Returns once the event is durably accepted; anything that errors is retried by the relay.
+    async fn publish(&self, event: &DomainEvent) -> Result<(), String>;
+}
+
+/// Picks the publisher named by `EVENT_PUBLISHER`: file (default), memory, nats or kafka.
+pub async fn from_env() -> Result<Arc<dyn Publisher>, String> {
+    let publisher = env::var("EVENT_PUBLISHER").unwrap_or("file".to_string());
+    match publisher.as_str() {
+        "file" => {
+            let path = env::var("EVENT_FILE").unwrap_or("events.jsonl".to_string());
+            Ok(Arc::new(FilePublisher::new(path)))
+        }
+        "memory" => Ok(Arc::new(MemoryPublisher::new(1024))),
+        #[cfg(feature = "nats")]
+        "nats" => {
+            let url = env::var("NATS_URL").unwrap_or("nats://localhost:4222".to_string());
+            let prefix = env::var("NATS_SUBJECT_PREFIX").unwrap_or("bank".to_string());
+            Ok(Arc::new(NatsPublisher::connect(&url, prefix).await?))
+        }
+        #[cfg(feature = "kafka")]
+        "kafka" => {
+            let brokers = env::var("KAFKA_BROKERS").unwrap_or("localhost:9092".to_string());
+            let topic = env::var("KAFKA_TOPIC").unwrap_or("bank.events".to_string());
+            Ok(Arc::new(KafkaPublisher::new(&brokers, topic)?))
+        }
+        other => Err(format!(
+            "Unknown EVENT_PUBLISHER {}, nats and kafka need the cargo feature of the same name",
+            other
+        )),
+    }
+}
+
+fn to_json(event: &DomainEvent) -> Result<String, String> {
+    serde_json::to_string(event).map_err(|e| format!("Error serializing event: {}", e))
+}
+
+/// Hands every event to each publisher in turn. An error from any of them sends the event
+/// round again, so each one must tolerate seeing an event twice.
+pub struct Chain {
+    publishers: Vec<Arc<dyn Publisher>>,
+}
+impl Chain {
+    pub fn new(publishers: Vec<Arc<dyn Publisher>>) -> Chain {
+        Chain { publishers }
+    }
+}
+#[async_trait]
+impl Publisher for Chain {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), String> {
+        for publisher in &self.publishers {
+            publisher.publish(event).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends events to a json lines file, for local runs without a broker.
+pub struct FilePublisher {
+    path: String,
+    lock: Mutex<()>,
+}
+impl FilePublisher {
+    pub fn new(path: String) -> FilePublisher {
+        FilePublisher {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+#[async_trait]
+impl Publisher for FilePublisher {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), String> {
+        let mut line = to_json(event)?;
+        line.push('\n');
+        let _guard = self.lock.lock().await;
+        let mut file = match OpenOptions::new().create(true).append(true).open(&self.path).await {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Error opening {}: {}", self.path, e)),
+        };
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            return Err(format!("Error writing {}: {}", self.path, e));
+        }
+        file.sync_data().await.map_err(|e| format!("Error writing {}: {}", self.path, e))
+    }
+}
+
+/// Hands events to subscribers inside the process. Nothing is kept for late subscribers.
+pub struct MemoryPublisher {
+    sender: broadcast::Sender<DomainEvent>,
+}
+impl MemoryPublisher {
+    pub fn new(capacity: usize) -> MemoryPublisher {
+        let (sender, _) = broadcast::channel(capacity);
+        MemoryPublisher { sender }
+    }
+    pub fn subscribe(&self) -> broadcast::Receiver<DomainEvent> {
+        self.sender.subscribe()
+    }
+}
+#[async_trait]
+impl Publisher for MemoryPublisher {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), String> {
+        // sending only fails when nobody is subscribed, which is not an error here
+        let _ = self.sender.send(event.clone());
+        Ok(())
+    }
+}
+
+/// Publishes to JetStream as `<prefix>.<aggregate>.<event>` and waits for the stream's ack.
+#[cfg(feature = "nats")]
+pub struct NatsPublisher {
+    jetstream: async_nats::jetstream::Context,
+    prefix: String,
+}
+#[cfg(feature = "nats")]
+impl NatsPublisher {
+    pub async fn connect(url: &str, prefix: String) -> Result<NatsPublisher, String> {
+        let client = match async_nats::connect(url).await {
+            Ok(client) => client,
+            Err(e) => return Err(format!("Error connecting to NATS: {}", e)),
+        };
+        Ok(NatsPublisher {
+            jetstream: async_nats::jetstream::new(client),
+            prefix,
+        })
+    }
+}
+#[cfg(feature = "nats")]
+#[async_trait]
+impl Publisher for NatsPublisher {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), String> {
+        let payload = to_json(event)?;
+        let mut headers = async_nats::HeaderMap::new();
+        // JetStream drops a republished event with the same id inside its duplicate window
+        headers.insert("Nats-Msg-Id", event.id.as_str());
+        let subject = format!("{}.{}", self.prefix, event.subject());
+        let ack = match self
+            .jetstream
+            .publish_with_headers(subject, headers, payload.into_bytes().into())
+            .await
+        {
+            Ok(ack) => ack,
+            Err(e) => return Err(format!("Error publishing to NATS: {}", e)),
+        };
+        match ack.await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Error publishing to NATS: {}", e)),
+        }
+    }
+}
+
+/// Publishes to one topic keyed by aggregate id, so each aggregate stays on one partition in order.
+#[cfg(feature = "kafka")]
+pub struct KafkaPublisher {
+    producer: rdkafka::producer::FutureProducer,
+    topic: String,
+}
+#[cfg(feature = "kafka")]
+impl KafkaPublisher {
+    pub fn new(brokers: &str, topic: String) -> Result<KafkaPublisher, String> {
+        let producer = match rdkafka::ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("enable.idempotence", "true")
+            .set("acks", "all")
+            .create()
+        {
+            Ok(producer) => producer,
+            Err(e) => return Err(format!("Error connecting to Kafka: {}", e)),
+        };
+        Ok(KafkaPublisher { producer, topic })
+    }
+}
+#[cfg(feature = "kafka")]
+#[async_trait]
+impl Publisher for KafkaPublisher {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), String> {
+        let payload = to_json(event)?;
+        let record = rdkafka::producer::FutureRecord::to(&self.topic)
+            .key(event.aggregate_id.as_str())
+            .payload(&payload);
+        match self.producer.send(record, std::time::Duration::from_secs(5)).await {
+            Ok(_) => Ok(()),
+            Err((e, _)) => Err(format!("Error publishing to Kafka: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use mongodb::bson::doc;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::time::sleep,
+    Orbit, Rocket,
+};
+
+use crate::{
+    domain::event::DomainEvent,
+    events::publisher::Publisher,
+    mongo::{Crud, Db},
+};
+
+/// Publishes outbox events in the background once Rocket is up. Delivery is at least once:
+/// an event is marked published only after the publisher accepted it, so a crash in between
+/// sends it again. Consumers deduplicate on the event id.
+pub struct OutboxRelay {
+    outbox: Db<DomainEvent>,
+    publisher: Arc<dyn Publisher>,
+    interval: Duration,
+    batch: usize,
+}
+impl OutboxRelay {
+    pub fn new(outbox: Db<DomainEvent>, publisher: Arc<dyn Publisher>, interval: Duration, batch: usize) -> OutboxRelay {
+        OutboxRelay {
+            outbox,
+            publisher,
+            interval,
+            batch,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for OutboxRelay {
+    fn info(&self) -> Info {
+        Info {
+            name: "Outbox relay",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, _: &Rocket<Orbit>) {
+        let outbox = self.outbox.clone();
+        let publisher = self.publisher.clone();
+        let (interval, batch) = (self.interval, self.batch);
+        rocket::tokio::spawn(async move {
+            loop {
+                if let Err(e) = relay(outbox.as_ref(), publisher.as_ref(), batch).await {
+                    println!("Error relaying outbox: {}", e);
+                }
+                sleep(interval).await;
+            }
+        });
+    }
+}
+
+/// Publishes up to `batch` pending events, oldest first, and returns how many went out.
+/// Once an event fails, later events of the same aggregate wait for the next round so
+/// each aggregate's events are always published in sequence.
+pub async fn relay(outbox: &dyn Crud<DomainEvent>, publisher: &dyn Publisher, batch: usize) -> Result<usize, String> {
+    let pending = outbox
+        .find(doc! {"published": false}, doc! {"occurred_at": 1, "sequence": 1}, batch)
+        .await?;
+    let mut blocked = HashSet::new();
+    let mut published = 0;
+    for mut event in pending {
+        if blocked.contains(&event.aggregate_id) {
+            continue;
+        }
+        match publisher.publish(&event).await {
+            Ok(_) => {
+                event.mark_published();
+                published += 1;
+            }
+            Err(e) => {
+                blocked.insert(event.aggregate_id.clone());
+                event.mark_failed(e);
+            }
+        }
+        outbox.update_by_id(&event.id.clone(), event).await?;
+    }
+    Ok(published)
+}
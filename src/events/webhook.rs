@@ -0,0 +1,265 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use mongodb::bson::doc;
+use reqwest::{redirect::Policy, Client, Url};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::{net::lookup_host, time::sleep},
+    Orbit, Rocket,
+};
+use sha2::Sha256;
+use url::Host;
+
+use crate::{
+    domain::{
+        event::DomainEvent,
+        webhook::{is_public, DeliveryAttempt, DeliveryStatus, RetryPolicy, WebhookDelivery, WebhookSubscription},
+    },
+    events::publisher::Publisher,
+    mongo::{Crud, Db},
+};
+
+pub const ID_HEADER: &str = "X-Webhook-Id";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Hex HMAC-SHA256 of `<timestamp>.<body>` under the subscription secret. Receivers recompute
+/// it and reject old timestamps, so a captured request cannot be replayed later.
+pub fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Turns each relayed event into one pending delivery per matching subscription.
+/// Delivery ids are derived from the event id, so an event relayed twice is queued once.
+pub struct WebhookFanout {
+    subscriptions: Db<WebhookSubscription>,
+    deliveries: Db<WebhookDelivery>,
+}
+impl WebhookFanout {
+    pub fn new(subscriptions: Db<WebhookSubscription>, deliveries: Db<WebhookDelivery>) -> WebhookFanout {
+        WebhookFanout {
+            subscriptions,
+            deliveries,
+        }
+    }
+}
+#[async_trait]
+impl Publisher for WebhookFanout {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), String> {
+        let subscriptions = self.subscriptions.find(doc! {"active": true}, doc! {}, 0).await?;
+        for subscription in subscriptions.iter().filter(|subscription| subscription.wants(event)) {
+            let id = WebhookDelivery::id_for(&subscription.id, &event.id);
+            if self.deliveries.get_by_id(&id).await.is_ok() {
+                continue;
+            }
+            let delivery = WebhookDelivery::new(subscription, event)?;
+            self.deliveries.create(delivery).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends due deliveries in the background once Rocket is up, retrying failures with
+/// exponential backoff until the policy gives up.
+pub struct WebhookDispatcher {
+    subscriptions: Db<WebhookSubscription>,
+    deliveries: Db<WebhookDelivery>,
+    policy: RetryPolicy,
+    interval: Duration,
+    batch: usize,
+}
+impl WebhookDispatcher {
+    pub fn new(
+        subscriptions: Db<WebhookSubscription>,
+        deliveries: Db<WebhookDelivery>,
+        policy: RetryPolicy,
+        interval: Duration,
+        batch: usize,
+    ) -> WebhookDispatcher {
+        WebhookDispatcher {
+            subscriptions,
+            deliveries,
+            policy,
+            interval,
+            batch,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for WebhookDispatcher {
+    fn info(&self) -> Info {
+        Info {
+            name: "Webhook dispatcher",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, _: &Rocket<Orbit>) {
+        let subscriptions = self.subscriptions.clone();
+        let deliveries = self.deliveries.clone();
+        let (policy, interval, batch) = (self.policy, self.interval, self.batch);
+        rocket::tokio::spawn(async move {
+            loop {
+                if let Err(e) = dispatch(subscriptions.as_ref(), deliveries.as_ref(), &policy, batch).await {
+                    println!("Error dispatching webhooks: {}", e);
+                }
+                sleep(interval).await;
+            }
+        });
+    }
+}
+
+/// Attempts up to `batch` due deliveries, oldest first, and returns how many succeeded.
+pub async fn dispatch(
+    subscriptions: &dyn Crud<WebhookSubscription>,
+    deliveries: &dyn Crud<WebhookDelivery>,
+    policy: &RetryPolicy,
+    batch: usize,
+) -> Result<usize, String> {
+    let due = deliveries
+        .find(
+            doc! {"status": "Pending", "next_attempt_at": {"$lte": Utc::now().to_rfc3339()}},
+            doc! {"next_attempt_at": 1},
+            batch,
+        )
+        .await?;
+    let mut delivered = 0;
+    for mut delivery in due {
+        // subscriptions are only ever disabled, so a failed read is transient and retried next round
+        let subscription = match subscriptions.get_by_id(&delivery.subscription_id).await {
+            Ok(subscription) => subscription,
+            Err(_) => continue,
+        };
+        if subscription.active {
+            let attempt = deliver(&subscription, &delivery).await;
+            delivery.record(attempt, policy);
+            if delivery.status == DeliveryStatus::Delivered {
+                delivered += 1;
+            }
+        } else {
+            delivery.abandon("Subscription is disabled".to_string());
+        }
+        deliveries.update_by_id(&delivery.id.clone(), delivery).await?;
+    }
+    Ok(delivered)
+}
+
+/// A client that can only reach the subscription's host at an address checked to be public.
+/// The name is resolved here and the connection pinned to that address, so a second lookup
+/// cannot swap in an internal one, and redirects are not followed.
+async fn client_for(url: &str) -> Result<Client, String> {
+    let parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(e) => return Err(format!("Invalid webhook url: {}", e)),
+    };
+    if parsed.scheme() != "https" {
+        return Err("Webhook url must be https".to_string());
+    }
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    let builder = Client::builder().timeout(REQUEST_TIMEOUT).redirect(Policy::none());
+    let builder = match parsed.host() {
+        Some(Host::Domain(domain)) => {
+            let addresses: Vec<SocketAddr> = match lookup_host((domain, port)).await {
+                Ok(addresses) => addresses.collect(),
+                Err(e) => return Err(format!("Error resolving {}: {}", domain, e)),
+            };
+            if let Some(address) = addresses.iter().find(|address| !is_public(&address.ip())) {
+                return Err(format!("Webhook host {} resolves to non-public address {}", domain, address.ip()));
+            }
+            match addresses.first() {
+                Some(address) => builder.resolve(domain, *address),
+                None => return Err(format!("Webhook host {} resolves to no address", domain)),
+            }
+        }
+        Some(Host::Ipv4(ip)) if is_public(&IpAddr::V4(ip)) => builder,
+        Some(Host::Ipv6(ip)) if is_public(&IpAddr::V6(ip)) => builder,
+        Some(_) => return Err("Webhook url points to a non-public address".to_string()),
+        None => return Err("Webhook url has no host".to_string()),
+    };
+    builder.build().map_err(|e| format!("Error creating webhook client: {}", e))
+}
+
+async fn deliver(subscription: &WebhookSubscription, delivery: &WebhookDelivery) -> DeliveryAttempt {
+    let at = Utc::now();
+    let timestamp = at.timestamp().to_string();
+    let signature = sign(&subscription.secret, &timestamp, &delivery.body);
+    let started = Instant::now();
+    let client = match client_for(&subscription.url).await {
+        Ok(client) => client,
+        Err(e) => {
+            return DeliveryAttempt {
+                at: at.to_rfc3339(),
+                response_code: None,
+                error: Some(e),
+                duration_ms: 0,
+            }
+        }
+    };
+    let sent = client
+        .post(&subscription.url)
+        .header("Content-Type", "application/json")
+        .header(ID_HEADER, delivery.id.as_str())
+        .header(EVENT_HEADER, delivery.event_type.to_string())
+        .header(TIMESTAMP_HEADER, timestamp.as_str())
+        .header(SIGNATURE_HEADER, format!("v1={}", signature))
+        .body(delivery.body.clone())
+        .send()
+        .await;
+    let duration_ms = started.elapsed().as_millis() as u64;
+    match sent {
+        Ok(response) => {
+            let code = response.status().as_u16();
+            let error = if response.status().is_success() {
+                None
+            } else {
+                Some(format!("Endpoint answered {}", code))
+            };
+            DeliveryAttempt {
+                at: at.to_rfc3339(),
+                response_code: Some(code),
+                error,
+                duration_ms,
+            }
+        }
+        Err(e) => DeliveryAttempt {
+            at: at.to_rfc3339(),
+            response_code: None,
+            error: Some(format!("Error sending webhook: {}", e)),
+            duration_ms,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        let signature = sign("whsec", "1700000000", r#"{"id":"e1"}"#);
+        assert_eq!(signature, "7e6859a1a93752955693040d16abace18dbb53d905b914ceaf32e06b3b43bdf0");
+        assert_ne!(sign("whsec", "1700000001", r#"{"id":"e1"}"#), signature);
+        assert_ne!(sign("other", "1700000000", r#"{"id":"e1"}"#), signature);
+    }
+
+    #[rocket::async_test]
+    async fn refuses_non_public_hosts() {
+        for url in ["http://93.184.216.34/hook", "https://127.0.0.1/hook", "https://[::1]/hook", "https://169.254.169.254/latest", "https://localhost:8443/hook"] {
+            assert!(client_for(url).await.is_err(), "{}", url);
+        }
+        assert!(client_for("https://93.184.216.34/hook").await.is_ok());
+    }
+}
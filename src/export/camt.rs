@@ -0,0 +1,188 @@
+use chrono::Utc;
+
+use crate::domain::statement::Statement;
+
+use super::{currency_decimals, escape_xml as escape};
+
+pub enum CamtMessage {
+    Statement053,
+    Report052,
+}
+impl CamtMessage {
+    fn namespace(&self) -> &'static str {
+        match self {
+            CamtMessage::Statement053 => "urn:iso:std:iso:20022:tech:xsd:camt.053.001.02",
+            CamtMessage::Report052 => "urn:iso:std:iso:20022:tech:xsd:camt.052.001.02",
+        }
+    }
+    fn root(&self) -> &'static str {
+        match self {
+            CamtMessage::Statement053 => "BkToCstmrStmt",
+            CamtMessage::Report052 => "BkToCstmrAcctRpt",
+        }
+    }
+    fn body(&self) -> &'static str {
+        match self {
+            CamtMessage::Statement053 => "Stmt",
+            CamtMessage::Report052 => "Rpt",
+        }
+    }
+    fn closing_balance(&self) -> &'static str {
+        match self {
+            CamtMessage::Statement053 => "CLBD",
+            CamtMessage::Report052 => "ITBD",
+        }
+    }
+}
+
+pub fn statement_camt(statement: &Statement, message: CamtMessage) -> String {
+    let ccy = &statement.asset;
+    let created = Utc::now().format("%Y-%m-%dT%H:%M:%S").to_string();
+    let message_id = max_text(
+        &format!("{}{}", statement.account_number, Utc::now().format("%Y%m%d%H%M%S")),
+        35,
+    );
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<Document xmlns=\"{}\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">\n",
+        message.namespace()
+    ));
+    xml.push_str(&format!("  <{}>\n", message.root()));
+    xml.push_str("    <GrpHdr>\n");
+    xml.push_str(&format!("      <MsgId>{}</MsgId>\n", escape(&message_id)));
+    xml.push_str(&format!("      <CreDtTm>{}</CreDtTm>\n", created));
+    xml.push_str("    </GrpHdr>\n");
+    xml.push_str(&format!("    <{}>\n", message.body()));
+    xml.push_str(&format!("      <Id>{}</Id>\n", escape(&message_id)));
+    xml.push_str(&format!("      <CreDtTm>{}</CreDtTm>\n", created));
+    xml.push_str("      <FrToDt>\n");
+    xml.push_str(&format!("        <FrDtTm>{}</FrDtTm>\n", date_time(&statement.from)));
+    xml.push_str(&format!("        <ToDtTm>{}</ToDtTm>\n", date_time(&statement.to)));
+    xml.push_str("      </FrToDt>\n");
+    xml.push_str("      <Acct>\n");
+    xml.push_str(&format!(
+        "        <Id><Othr><Id>{}</Id></Othr></Id>\n",
+        escape(&statement.account_number)
+    ));
+    xml.push_str(&format!("        <Ccy>{}</Ccy>\n", escape(ccy)));
+    xml.push_str("      </Acct>\n");
+    xml.push_str(&balance("OPBD", statement.opening_balance, ccy, &statement.from));
+    xml.push_str(&balance(
+        message.closing_balance(),
+        statement.closing_balance,
+        ccy,
+        &statement.to,
+    ));
+    let credits = statement.lines.iter().filter(|line| line.credit > 0.0);
+    let debits = statement.lines.iter().filter(|line| line.credit <= 0.0);
+    xml.push_str("      <TxsSummry>\n");
+    xml.push_str(&format!(
+        "        <TtlNtries><NbOfNtries>{}</NbOfNtries></TtlNtries>\n",
+        statement.lines.len()
+    ));
+    xml.push_str(&format!(
+        "        <TtlCdtNtries><NbOfNtries>{}</NbOfNtries><Sum>{}</Sum></TtlCdtNtries>\n",
+        credits.clone().count(),
+        amount(statement.total_credit, ccy)
+    ));
+    xml.push_str(&format!(
+        "        <TtlDbtNtries><NbOfNtries>{}</NbOfNtries><Sum>{}</Sum></TtlDbtNtries>\n",
+        debits.clone().count(),
+        amount(statement.total_debit + statement.total_fees, ccy)
+    ));
+    xml.push_str("      </TxsSummry>\n");
+    for line in &statement.lines {
+        let (value, indicator) = if line.credit > 0.0 {
+            (line.credit, "CRDT")
+        } else {
+            (line.debit + line.fee, "DBIT")
+        };
+        xml.push_str("      <Ntry>\n");
+        xml.push_str(&format!("        <NtryRef>{}</NtryRef>\n", escape(&max_text(&line.tx_id, 35))));
+        xml.push_str(&format!(
+            "        <Amt Ccy=\"{}\">{}</Amt>\n",
+            escape(ccy),
+            amount(value, ccy)
+        ));
+        xml.push_str(&format!("        <CdtDbtInd>{}</CdtDbtInd>\n", indicator));
+        xml.push_str("        <Sts>BOOK</Sts>\n");
+        xml.push_str(&format!("        <BookgDt><Dt>{}</Dt></BookgDt>\n", date(&line.booked_at)));
+        xml.push_str(&format!("        <ValDt><Dt>{}</Dt></ValDt>\n", date(&line.booked_at)));
+        if let Some(external_id) = &line.external_id {
+            xml.push_str(&format!(
+                "        <AcctSvcrRef>{}</AcctSvcrRef>\n",
+                escape(&max_text(external_id, 35))
+            ));
+        }
+        xml.push_str(&format!(
+            "        <BkTxCd><Prtry><Cd>{}</Cd></Prtry></BkTxCd>\n",
+            line.transaction_type.to_string().to_uppercase()
+        ));
+        if line.fee > 0.0 {
+            xml.push_str(&format!(
+                "        <Chrgs><Amt Ccy=\"{}\">{}</Amt></Chrgs>\n",
+                escape(ccy),
+                amount(line.fee, ccy)
+            ));
+        }
+        xml.push_str("        <NtryDtls>\n");
+        xml.push_str("          <TxDtls>\n");
+        xml.push_str("            <Refs>\n");
+        if let Some(external_id) = &line.external_id {
+            xml.push_str(&format!(
+                "              <AcctSvcrRef>{}</AcctSvcrRef>\n",
+                escape(&max_text(external_id, 35))
+            ));
+        }
+        xml.push_str(&format!(
+            "              <EndToEndId>{}</EndToEndId>\n",
+            escape(&max_text(&line.tx_id, 35))
+        ));
+        xml.push_str("            </Refs>\n");
+        if !line.memo.is_empty() {
+            xml.push_str(&format!(
+                "            <RmtInf><Ustrd>{}</Ustrd></RmtInf>\n",
+                escape(&max_text(&line.memo, 140))
+            ));
+        }
+        xml.push_str("          </TxDtls>\n");
+        xml.push_str("        </NtryDtls>\n");
+        xml.push_str("      </Ntry>\n");
+    }
+    xml.push_str(&format!("    </{}>\n", message.body()));
+    xml.push_str(&format!("  </{}>\n", message.root()));
+    xml.push_str("</Document>\n");
+    xml
+}
+
+fn balance(code: &str, value: f64, ccy: &str, at: &str) -> String {
+    let indicator = if value < 0.0 { "DBIT" } else { "CRDT" };
+    format!(
+        "      <Bal>\n        <Tp><CdOrPrtry><Cd>{}</Cd></CdOrPrtry></Tp>\n        <Amt Ccy=\"{}\">{}</Amt>\n        <CdtDbtInd>{}</CdtDbtInd>\n        <Dt><Dt>{}</Dt></Dt>\n      </Bal>\n",
+        code,
+        escape(ccy),
+        amount(value.abs(), ccy),
+        indicator,
+        date(at)
+    )
+}
+
+fn amount(value: f64, ccy: &str) -> String {
+    format!("{:.*}", currency_decimals(ccy), value)
+}
+
+fn date(timestamp: &str) -> String {
+    timestamp.chars().take(10).collect()
+}
+
+fn date_time(timestamp: &str) -> String {
+    match chrono::DateTime::parse_from_rfc3339(timestamp) {
+        Ok(parsed) => parsed.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        Err(_) => format!("{}T00:00:00", date(timestamp)),
+    }
+}
+
+fn max_text(text: &str, max: usize) -> String {
+    text.chars().take(max).collect()
+}
@@ -0,0 +1,118 @@
+use crate::domain::{audit::AuditEntry, statement::Statement};
+
+pub fn statement_csv(statement: &Statement) -> String {
+    let mut rows: Vec<Vec<String>> = vec![
+        vec!["account".to_string(), statement.account_number.clone()],
+        vec!["asset".to_string(), statement.asset.clone()],
+        vec!["from".to_string(), statement.from.clone()],
+        vec!["to".to_string(), statement.to.clone()],
+        vec!["opening_balance".to_string(), statement.opening_balance.to_string()],
+        vec!["closing_balance".to_string(), statement.closing_balance.to_string()],
+        vec!["total_fees".to_string(), statement.total_fees.to_string()],
+        vec!["total_hold".to_string(), statement.total_hold.to_string()],
+        vec![],
+        vec![
+            "booked_at".to_string(),
+            "tx_id".to_string(),
+            "external_id".to_string(),
+            "type".to_string(),
+            "memo".to_string(),
+            "credit".to_string(),
+            "debit".to_string(),
+            "fee".to_string(),
+            "balance".to_string(),
+        ],
+    ];
+    for line in &statement.lines {
+        rows.push(vec![
+            line.booked_at.clone(),
+            line.tx_id.clone(),
+            line.external_id.clone().unwrap_or_default(),
+            line.transaction_type.to_string(),
+            line.memo.clone(),
+            line.credit.to_string(),
+            line.debit.to_string(),
+            line.fee.to_string(),
+            line.balance.to_string(),
+        ]);
+    }
+    if !statement.holds.is_empty() {
+        rows.push(vec![]);
+        rows.push(vec![
+            "pending_since".to_string(),
+            "tx_id".to_string(),
+            "type".to_string(),
+            "hold".to_string(),
+        ]);
+        for hold in &statement.holds {
+            rows.push(vec![
+                hold.timestamp.clone(),
+                hold.tx_id.clone(),
+                hold.transaction_type.to_string(),
+                hold.amount.to_string(),
+            ]);
+        }
+    }
+    to_csv(&rows)
+}
+
+pub fn audit_csv(entries: &[AuditEntry]) -> String {
+    let mut rows: Vec<Vec<String>> = vec![vec![
+        "occurred_at".to_string(),
+        "id".to_string(),
+        "actor_id".to_string(),
+        "actor_role".to_string(),
+        "action".to_string(),
+        "resource".to_string(),
+        "outcome".to_string(),
+        "reason".to_string(),
+        "changes".to_string(),
+        "request_id".to_string(),
+        "ip".to_string(),
+    ]];
+    for entry in entries {
+        let changes = entry
+            .changes
+            .iter()
+            .map(|change| {
+                format!(
+                    "{}: {} -> {}",
+                    change.field,
+                    change.before.clone().unwrap_or_default(),
+                    change.after.clone().unwrap_or_default()
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("; ");
+        rows.push(vec![
+            entry.occurred_at.clone(),
+            entry.id.clone(),
+            entry.actor_id.clone().unwrap_or_default(),
+            entry.actor_role.clone().unwrap_or_default(),
+            entry.action.clone(),
+            entry.resource.clone(),
+            entry.outcome.to_string(),
+            entry.reason.clone().unwrap_or_default(),
+            changes,
+            entry.request_id.clone(),
+            entry.ip.clone().unwrap_or_default(),
+        ]);
+    }
+    to_csv(&rows)
+}
+
+fn to_csv(rows: &[Vec<String>]) -> String {
+    rows.iter()
+        .map(|row| row.iter().map(|field| escape(field)).collect::<Vec<String>>().join(","))
+        .collect::<Vec<String>>()
+        .join("\r\n")
+        + "\r\n"
+}
+
+fn escape(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,44 @@
+pub mod camt;
+pub mod csv;
+pub mod mt940;
+pub mod ofx;
+pub mod pdf;
+
+use rocket::serde::json::to_pretty_string;
+
+use crate::domain::statement::{Statement, StatementFormat};
+
+pub fn render_statement(statement: &Statement, format: &StatementFormat) -> Result<String, String> {
+    match format {
+        StatementFormat::Json => match to_pretty_string(statement) {
+            Ok(json) => Ok(json),
+            Err(e) => Err(format!("Error rendering statement: {}", e)),
+        },
+        StatementFormat::Csv => Ok(csv::statement_csv(statement)),
+        StatementFormat::Pdf => Ok(pdf::statement_pdf(statement)),
+        StatementFormat::Camt053 => Ok(camt::statement_camt(statement, camt::CamtMessage::Statement053)),
+        StatementFormat::Camt052 => Ok(camt::statement_camt(statement, camt::CamtMessage::Report052)),
+        StatementFormat::Mt940 => Ok(mt940::statement_mt940(statement)),
+        StatementFormat::Ofx => Ok(ofx::statement_ofx(statement)),
+    }
+}
+
+pub fn currency_decimals(ccy: &str) -> usize {
+    match ccy {
+        "JPY" => 0,
+        _ => 2,
+    }
+}
+
+pub fn escape_xml(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
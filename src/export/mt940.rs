@@ -0,0 +1,147 @@
+use chrono::Utc;
+
+use crate::domain::statement::{Statement, StatementLine};
+
+use super::currency_decimals;
+
+const NARRATIVE_LINES: usize = 6;
+const NARRATIVE_WIDTH: usize = 65;
+
+pub fn statement_mt940(statement: &Statement) -> String {
+    let ccy = &statement.asset;
+    let reference = swift_text(
+        &format!("STMT{}", Utc::now().format("%y%m%d%H%M%S")),
+        16,
+    );
+    let mut fields: Vec<String> = vec![
+        format!(":20:{}", reference),
+        format!(":25:{}", swift_text(&statement.account_number, 35)),
+        ":28C:1/1".to_string(),
+        balance("60F", statement.opening_balance, ccy, &statement.from),
+    ];
+    for line in &statement.lines {
+        fields.extend(entry(line, ccy));
+    }
+    fields.push(balance("62F", statement.closing_balance, ccy, &statement.to));
+    fields.push("-".to_string());
+    fields.join("\r\n") + "\r\n"
+}
+
+fn entry(line: &StatementLine, ccy: &str) -> Vec<String> {
+    let value_date = swift_date(&line.booked_at);
+    let entry_date = value_date.chars().skip(2).collect::<String>();
+    let customer_reference = swift_text(&line.tx_id, 16);
+    let bank_reference = match &line.external_id {
+        Some(external_id) => format!("//{}", swift_text(external_id, 16)),
+        None => String::new(),
+    };
+    let mut fields = Vec::new();
+    if line.credit > 0.0 {
+        fields.push(format!(
+            ":61:{}{}C{}NTRF{}{}",
+            value_date,
+            entry_date,
+            amount(line.credit, ccy),
+            customer_reference,
+            bank_reference
+        ));
+    } else {
+        fields.push(format!(
+            ":61:{}{}D{}NTRF{}{}",
+            value_date,
+            entry_date,
+            amount(line.debit, ccy),
+            customer_reference,
+            bank_reference
+        ));
+    }
+    fields.push(format!(":86:{}", narrative(&format!("{} {}", line.transaction_type, line.memo))));
+    if line.fee > 0.0 {
+        fields.push(format!(
+            ":61:{}{}D{}NCHG{}{}",
+            value_date,
+            entry_date,
+            amount(line.fee, ccy),
+            customer_reference,
+            bank_reference
+        ));
+        fields.push(format!(":86:{}", narrative(&format!("Fee {}", line.tx_id))));
+    }
+    fields
+}
+
+fn balance(tag: &str, value: f64, ccy: &str, at: &str) -> String {
+    let mark = if value < 0.0 { "D" } else { "C" };
+    format!(":{}:{}{}{}{}", tag, mark, swift_date(at), ccy, amount(value.abs(), ccy))
+}
+
+fn amount(value: f64, ccy: &str) -> String {
+    let decimals = currency_decimals(ccy);
+    let formatted = format!("{:.*}", decimals, value).replace('.', ",");
+    if decimals == 0 {
+        formatted + ","
+    } else {
+        formatted
+    }
+}
+
+fn swift_date(timestamp: &str) -> String {
+    timestamp
+        .chars()
+        .take(10)
+        .filter(|c| c.is_ascii_digit())
+        .skip(2)
+        .collect()
+}
+
+/// `:86:` content, at most 6 lines of 65 characters. A continuation line starting with `:` or
+/// `-` would read as a new field, so that character is blanked.
+fn narrative(text: &str) -> String {
+    let text: Vec<char> = swift_text(text, NARRATIVE_LINES * NARRATIVE_WIDTH).chars().collect();
+    text.chunks(NARRATIVE_WIDTH)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let line: String = chunk.iter().collect();
+            match line.strip_prefix(|c: char| c == ':' || c == '-') {
+                Some(rest) if index > 0 => format!(" {}", rest),
+                _ => line,
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\r\n")
+}
+
+fn swift_text(text: &str, max: usize) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "/-?:().,'+ ".contains(c) {
+                c
+            } else {
+                ' '
+            }
+        })
+        .take(max)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrative_wraps_to_six_lines_of_65() {
+        let wrapped = narrative(&"a".repeat(500));
+        let lines: Vec<&str> = wrapped.split("\r\n").collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines.iter().all(|line| line.chars().count() == 65));
+        assert_eq!(narrative("Deposit salary"), "Deposit salary");
+    }
+
+    #[test]
+    fn continuation_lines_do_not_start_a_field() {
+        let wrapped = narrative(&format!("{}:62F:C", "a".repeat(65)));
+        assert_eq!(wrapped, format!("{}\r\n 62F:C", "a".repeat(65)));
+        let wrapped = narrative(&format!("{}-", "a".repeat(65)));
+        assert_eq!(wrapped, format!("{}\r\n ", "a".repeat(65)));
+    }
+}
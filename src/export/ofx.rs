@@ -0,0 +1,127 @@
+use std::env;
+
+use chrono::{DateTime, Utc};
+
+use crate::domain::statement::{Statement, StatementLine};
+
+use super::{currency_decimals, escape_xml as escape};
+
+pub fn statement_ofx(statement: &Statement) -> String {
+    let ccy = &statement.asset;
+    let bank_id = env::var("OFX_BANK_ID").unwrap_or("MYBANK".to_string());
+    let mut transactions = String::new();
+    for line in &statement.lines {
+        transactions.push_str(&entry(line, ccy));
+    }
+    let mut ofx = String::new();
+    ofx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n");
+    ofx.push_str("<?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" OLDFILEUID=\"NONE\" NEWFILEUID=\"NONE\"?>\n");
+    ofx.push_str("<OFX>\n");
+    ofx.push_str("  <SIGNONMSGSRSV1>\n    <SONRS>\n");
+    ofx.push_str("      <STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>\n");
+    ofx.push_str(&format!("      <DTSERVER>{}</DTSERVER>\n", ofx_date(&Utc::now().to_rfc3339())));
+    ofx.push_str("      <LANGUAGE>ENG</LANGUAGE>\n");
+    ofx.push_str("    </SONRS>\n  </SIGNONMSGSRSV1>\n");
+    ofx.push_str("  <BANKMSGSRSV1>\n    <STMTTRNRS>\n");
+    ofx.push_str("      <TRNUID>0</TRNUID>\n");
+    ofx.push_str("      <STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>\n");
+    ofx.push_str("      <STMTRS>\n");
+    ofx.push_str(&format!("        <CURDEF>{}</CURDEF>\n", escape(ccy)));
+    ofx.push_str("        <BANKACCTFROM>\n");
+    ofx.push_str(&format!("          <BANKID>{}</BANKID>\n", escape(&bank_id)));
+    ofx.push_str(&format!("          <ACCTID>{}</ACCTID>\n", escape(&statement.account_number)));
+    ofx.push_str("          <ACCTTYPE>CHECKING</ACCTTYPE>\n");
+    ofx.push_str("        </BANKACCTFROM>\n");
+    ofx.push_str("        <BANKTRANLIST>\n");
+    ofx.push_str(&format!("          <DTSTART>{}</DTSTART>\n", ofx_date(&statement.from)));
+    ofx.push_str(&format!("          <DTEND>{}</DTEND>\n", ofx_date(&statement.to)));
+    ofx.push_str(&transactions);
+    ofx.push_str("        </BANKTRANLIST>\n");
+    ofx.push_str("        <LEDGERBAL>\n");
+    ofx.push_str(&format!("          <BALAMT>{}</BALAMT>\n", amount(statement.closing_balance, ccy)));
+    ofx.push_str(&format!("          <DTASOF>{}</DTASOF>\n", ofx_date(&statement.to)));
+    ofx.push_str("        </LEDGERBAL>\n");
+    ofx.push_str("      </STMTRS>\n");
+    ofx.push_str("    </STMTTRNRS>\n  </BANKMSGSRSV1>\n");
+    ofx.push_str("</OFX>\n");
+    ofx
+}
+
+fn entry(line: &StatementLine, ccy: &str) -> String {
+    let mut records = String::new();
+    let (trntype, value) = if line.credit > 0.0 {
+        ("CREDIT", line.credit)
+    } else {
+        ("DEBIT", -line.debit)
+    };
+    records.push_str(&record(
+        trntype,
+        &line.booked_at,
+        &amount(value, ccy),
+        &line.tx_id,
+        line.external_id.as_deref(),
+        &line.transaction_type.to_string(),
+        &line.memo,
+    ));
+    if line.fee > 0.0 {
+        records.push_str(&record(
+            "FEE",
+            &line.booked_at,
+            &amount(-line.fee, ccy),
+            &format!("{}-FEE", line.tx_id),
+            line.external_id.as_deref(),
+            "Fee",
+            &line.memo,
+        ));
+    }
+    records
+}
+
+fn record(
+    trntype: &str,
+    posted: &str,
+    amount: &str,
+    fitid: &str,
+    refnum: Option<&str>,
+    name: &str,
+    memo: &str,
+) -> String {
+    let mut record = String::new();
+    record.push_str("          <STMTTRN>\n");
+    record.push_str(&format!("            <TRNTYPE>{}</TRNTYPE>\n", trntype));
+    record.push_str(&format!("            <DTPOSTED>{}</DTPOSTED>\n", ofx_date(posted)));
+    record.push_str(&format!("            <TRNAMT>{}</TRNAMT>\n", amount));
+    record.push_str(&format!("            <FITID>{}</FITID>\n", escape(fitid)));
+    if let Some(refnum) = refnum {
+        record.push_str(&format!(
+            "            <REFNUM>{}</REFNUM>\n",
+            escape(&refnum.chars().take(32).collect::<String>())
+        ));
+    }
+    record.push_str(&format!(
+        "            <NAME>{}</NAME>\n",
+        escape(&name.chars().take(32).collect::<String>())
+    ));
+    if !memo.is_empty() {
+        record.push_str(&format!(
+            "            <MEMO>{}</MEMO>\n",
+            escape(&memo.chars().take(255).collect::<String>())
+        ));
+    }
+    record.push_str("          </STMTTRN>\n");
+    record
+}
+
+fn amount(value: f64, ccy: &str) -> String {
+    format!("{:.*}", currency_decimals(ccy), value)
+}
+
+fn ofx_date(timestamp: &str) -> String {
+    match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(parsed) => parsed
+            .with_timezone(&Utc)
+            .format("%Y%m%d%H%M%S.000[0:GMT]")
+            .to_string(),
+        Err(_) => timestamp.chars().take(10).filter(|c| c.is_ascii_digit()).collect(),
+    }
+}
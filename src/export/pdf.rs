@@ -0,0 +1,111 @@
+use crate::domain::statement::Statement;
+
+const LINES_PER_PAGE: usize = 60;
+
+pub fn statement_pdf(statement: &Statement) -> String {
+    let mut text = vec![
+        format!("Statement of account {} ({})", statement.account_number, statement.asset),
+        format!("Period: {} - {}", statement.from, statement.to),
+        format!("Generated: {}", statement.generated_at),
+        String::new(),
+        format!("Opening balance: {:>18.8}", statement.opening_balance),
+        format!("Closing balance: {:>18.8}", statement.closing_balance),
+        format!("Total fees:      {:>18.8}", statement.total_fees),
+        format!("Pending holds:   {:>18.8}", statement.total_hold),
+        String::new(),
+        format!(
+            "{:<25} {:<32} {:>14} {:>14} {:>10} {:>16}",
+            "Booked", "Transaction", "Credit", "Debit", "Fee", "Balance"
+        ),
+    ];
+    for line in &statement.lines {
+        text.push(format!(
+            "{:<25} {:<32} {:>14.8} {:>14.8} {:>10.4} {:>16.8}",
+            line.booked_at.chars().take(25).collect::<String>(),
+            line.tx_id,
+            line.credit,
+            line.debit,
+            line.fee,
+            line.balance
+        ));
+    }
+    if !statement.holds.is_empty() {
+        text.push(String::new());
+        text.push("Pending holds".to_string());
+        for hold in &statement.holds {
+            text.push(format!(
+                "{:<25} {:<32} {:>14.8}",
+                hold.timestamp.chars().take(25).collect::<String>(),
+                hold.tx_id,
+                hold.amount
+            ));
+        }
+    }
+    render_text(&text)
+}
+
+pub fn render_text(lines: &[String]) -> String {
+    let pages: Vec<&[String]> = if lines.is_empty() {
+        vec![lines]
+    } else {
+        lines.chunks(LINES_PER_PAGE).collect()
+    };
+    // objects: 1 catalog, 2 pages, 3 font, then a page and a content stream per page
+    let mut objects: Vec<String> = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len())
+                .map(|i| format!("{} 0 R", 4 + i * 2))
+                .collect::<Vec<String>>()
+                .join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+    ];
+    for (i, page) in pages.iter().enumerate() {
+        let mut stream = "BT /F1 7 Tf 9 TL 20 810 Td\n".to_string();
+        for line in page.iter() {
+            stream.push_str(&format!("({}) Tj T*\n", escape(line)));
+        }
+        stream.push_str("ET");
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 842 842] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            5 + i * 2
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}\nendstream",
+            stream.len(),
+            stream
+        ));
+    }
+    let mut pdf = "%PDF-1.4\n".to_string();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+    }
+    let xref = pdf.len();
+    pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    pdf.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    ));
+    pdf
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '(' => "\\(".to_string(),
+            ')' => "\\)".to_string(),
+            '\\' => "\\\\".to_string(),
+            c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
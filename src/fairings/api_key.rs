@@ -0,0 +1,181 @@
+use std::{collections::HashMap, ops::Deref, sync::Mutex};
+
+use chrono::Utc;
+use revolt_rocket_okapi::{
+    gen::OpenApiGenerator, okapi::openapi3::RequestBody, request::OpenApiFromData, JsonSchema,
+};
+use rocket::{
+    data::{self, FromData, Limits},
+    fairing::{Fairing, Info, Kind},
+    http::Status,
+    serde::{json::Json, DeserializeOwned},
+    Data, Request,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    domain::{api_key::ApiKey, user::User},
+    fairings::{idempotency, request_context::client_ip},
+    mongo::Db,
+};
+
+pub const KEY_HEADER: &str = "X-Api-Key";
+pub const TIMESTAMP_HEADER: &str = "X-Api-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Api-Signature";
+/// Hex SHA-256 of bodies larger than a fairing can peek, checked against the body by `SignedJson`.
+pub const CONTENT_SHA256_HEADER: &str = "X-Api-Content-Sha256";
+/// How far the signed timestamp may be from the server clock, in seconds.
+const MAX_CLOCK_SKEW: i64 = 300;
+/// What a fairing can peek of the body, larger ones are signed through `CONTENT_SHA256_HEADER`.
+const PEEK_LIMIT: usize = 512;
+
+/// Hex SHA-256 of the body the request is signed with, None when it could not be told.
+struct BodyDigest(Option<String>);
+
+/// Hashes the body of requests signed with an API key, so `AuthorizedUser` can check the
+/// signature without consuming the body the handler reads.
+pub struct ApiKeyBody;
+
+#[rocket::async_trait]
+impl Fairing for ApiKeyBody {
+    fn info(&self) -> Info {
+        Info {
+            name: "API key body digest",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, data: &mut Data<'_>) {
+        if req.headers().get_one(KEY_HEADER).is_none() {
+            return;
+        }
+        let body = data.peek(PEEK_LIMIT).await.to_vec();
+        let digest = match data.peek_complete() {
+            true => Some(sha256_hex(&body)),
+            false => req.headers().get_one(CONTENT_SHA256_HEADER).map(|digest| digest.to_lowercase()),
+        };
+        req.local_cache(|| BodyDigest(digest));
+    }
+}
+
+/// Signatures seen within the clock skew, a signed request is only accepted once.
+#[derive(Default)]
+pub struct ReplayCache {
+    seen: Mutex<HashMap<String, i64>>,
+}
+impl ReplayCache {
+    /// Records the signature, false when it was already used.
+    fn first_use(&self, key_id: &str, signature: &str, timestamp: i64) -> bool {
+        let now = Utc::now().timestamp();
+        let mut seen = self.seen.lock().expect("replay cache lock");
+        seen.retain(|_, at| now - *at <= MAX_CLOCK_SKEW);
+        seen.insert(format!("{}:{}", key_id, signature), timestamp).is_none()
+    }
+}
+
+/// Json body that, on requests signed with an API key, has to hash to the digest the signature
+/// covers. Handlers reachable with a key read their body through it instead of `Json`.
+#[derive(Debug)]
+pub struct SignedJson<T>(pub T);
+impl<T> Deref for SignedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned> FromData<'r> for SignedJson<T> {
+    type Error = String;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = req.limits().get("json").unwrap_or(Limits::JSON);
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return data::Outcome::Failure((Status::PayloadTooLarge, "Body is too large".to_string())),
+            Err(e) => return data::Outcome::Failure((Status::BadRequest, e.to_string())),
+        };
+        if req.headers().get_one(KEY_HEADER).is_some() {
+            let signed = &req.local_cache(|| BodyDigest(None)).0;
+            if signed.as_deref() != Some(sha256_hex(&body).as_str()) {
+                return data::Outcome::Failure((Status::Unauthorized, "Body does not match its signature".to_string()));
+            }
+        }
+        if let Err(status) = idempotency::claim_with_body(req, &body).await {
+            return data::Outcome::Failure((status, "Idempotency-Key was already used".to_string()));
+        }
+        match serde_json::from_slice(&body) {
+            Ok(value) => data::Outcome::Success(SignedJson(value)),
+            Err(e) => data::Outcome::Failure((Status::UnprocessableEntity, e.to_string())),
+        }
+    }
+}
+
+impl<'r, T: JsonSchema + DeserializeOwned> OpenApiFromData<'r> for SignedJson<T> {
+    fn request_body(gen: &mut OpenApiGenerator) -> revolt_rocket_okapi::Result<RequestBody> {
+        Json::<T>::request_body(gen)
+    }
+}
+
+/// The key a request is signed with and its user, when the key is active, allowed from the
+/// caller's address and the signature matches.
+pub async fn authenticate(request: &Request<'_>, key_id: &str) -> Option<(ApiKey, User)> {
+    let key_db = request.rocket().state::<Db<ApiKey>>()?;
+    let user_db = request.rocket().state::<Db<User>>()?;
+    let replays = request.rocket().state::<ReplayCache>()?;
+    let timestamp = request.headers().get_one(TIMESTAMP_HEADER)?;
+    let signature = request.headers().get_one(SIGNATURE_HEADER)?;
+    let signed_at = match timestamp.parse::<i64>() {
+        Ok(at) if (Utc::now().timestamp() - at).abs() <= MAX_CLOCK_SKEW => at,
+        _ => return None,
+    };
+    let body_sha256 = match &request.local_cache(|| BodyDigest(None)).0 {
+        Some(digest) => digest.clone(),
+        None => return None,
+    };
+    let key = key_db.get_by_id(key_id).await.ok()?;
+    if !key.is_active() || !key.allows_ip(client_ip(request)) {
+        return None;
+    }
+    let path = request.uri().to_string();
+    if !key.verify(request.method().as_str(), &path, timestamp, &body_sha256, signature) {
+        return None;
+    }
+    // a captured request sent again within the skew carries the same signature
+    if !replays.first_use(&key.id, signature, signed_at) {
+        return None;
+    }
+    let user = user_db.get_by_id(&key.user_id).await.ok()?;
+    Some((key, user))
+}
+
+fn sha256_hex(value: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(value);
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_are_accepted_once() {
+        let replays = ReplayCache::default();
+        let now = Utc::now().timestamp();
+        assert!(replays.first_use("key_1", "v1=aa", now));
+        assert!(!replays.first_use("key_1", "v1=aa", now));
+        assert!(replays.first_use("key_2", "v1=aa", now));
+        assert!(replays.first_use("key_1", "v1=bb", now));
+    }
+
+    #[test]
+    fn forgets_signatures_past_the_skew() {
+        let replays = ReplayCache::default();
+        let old = Utc::now().timestamp() - MAX_CLOCK_SKEW - 1;
+        assert!(replays.first_use("key_1", "v1=aa", old));
+        assert!(replays.first_use("key_1", "v1=bb", Utc::now().timestamp()));
+        assert_eq!(replays.seen.lock().unwrap().len(), 1);
+    }
+}
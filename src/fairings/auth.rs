@@ -1,14 +1,46 @@
-use revolt_rocket_okapi::{JsonSchema, OpenApiFromRequest};
+use std::marker::PhantomData;
+
+use mongodb::bson::{doc, Bson};
+use revolt_rocket_okapi::{
+    gen::OpenApiGenerator,
+    request::{OpenApiFromRequest, RequestHeaderInput},
+    JsonSchema, OpenApiFromRequest,
+};
 use rocket::{request::{FromRequest, Outcome}, Request, http::Status};
 use serde::{Serialize, Deserialize};
 
-use crate::{security::jwt::{DecodeJwtHelper, get_jwt_secret, decode_jwt, check_data_from_auth_header}, domain::user::Role};
+use crate::{security::{audit, ownership::OwnershipResolver, jwt::{DecodeJwtHelper, decode_jwt, check_data_from_auth_header, predates_sessions}, keys::KeyRing}, domain::{account::Account, audit::{AuditEntry, AuditOutcome}, grant::AccessGrant, session::UserSession, user::{Permission, Role}}, fairings::{api_key::{self, KEY_HEADER}, idempotency, request_context::RequestContext}, mongo::{Db, VersionedDb, VersionedStore}};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, OpenApiFromRequest)]
 pub struct AuthorizedUser {
     pub user_id: String,
     pub role: Role,
+    /// Accounts the user owns, resolved when the request comes in rather than read from the token.
     pub resource: Vec<String>,
+    /// Permissions carried in the token, see `Role::permissions`.
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    /// Grants other owners gave this user, read on every request so revocations apply at once.
+    #[serde(default)]
+    pub grants: Vec<AccessGrant>,
+    /// Session the token was issued for, None for API keys and older tokens.
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// API key the request was signed with, None for tokens.
+    #[serde(default)]
+    pub api_key_id: Option<String>,
+    /// Accounts the API key is limited to, no limit when empty.
+    #[serde(default)]
+    pub key_accounts: Vec<String>,
+}
+impl AuthorizedUser {
+    pub fn has(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+    /// False for accounts outside the API key's list, whatever the role or ownership says.
+    pub fn reaches(&self, account_number: &str) -> bool {
+        self.key_accounts.is_empty() || self.key_accounts.iter().any(|account| account == account_number)
+    }
 }
 
 #[rocket::async_trait]
@@ -16,18 +48,179 @@ impl<'r> FromRequest<'r> for AuthorizedUser {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let auth_header = request.headers().get_one("Authorization");
-        let secret = &get_jwt_secret().await;
-        match check_data_from_auth_header(auth_header) {
-            Ok(vec_header) => match decode_jwt(vec_header[1].to_string(), secret) {
-                DecodeJwtHelper::Ok(token_data) => Outcome::Success(AuthorizedUser {
-                    user_id: token_data.claims.user_id,
-                    role: Role::from_str(&token_data.claims.role),
-                    resource: token_data.claims.resource.split(',').map(|s| s.to_string()).collect(),
-                }),
-                DecodeJwtHelper::Err => Outcome::Failure((Status::Unauthorized, ())),
-            },
-            Err(_) => Outcome::Failure((Status::Unauthorized, ())),
+        // cached so `Permitted` next to `AuthorizedUser` decodes the token and reads the stores once
+        let user = request.local_cache_async(async { authorize(request).await }).await;
+        let user = match user {
+            Some(user) => user,
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        match idempotency::claim_for(request, user).await {
+            Ok(_) => Outcome::Success(user.clone()),
+            Err(status) => Outcome::Failure((status, ())),
+        }
+    }
+}
+
+async fn authorize(request: &Request<'_>) -> Option<AuthorizedUser> {
+    if let Some(key_id) = request.headers().get_one(KEY_HEADER) {
+        return authorize_key(request, key_id).await;
+    }
+    let auth_header = request.headers().get_one("Authorization");
+    let keys = request.rocket().state::<KeyRing>()?;
+    match check_data_from_auth_header(auth_header) {
+        Ok(vec_header) => match decode_jwt(vec_header[1].to_string(), keys) {
+            DecodeJwtHelper::Ok(token_data) => {
+                let role = Role::from_str(&token_data.claims.role);
+                // tokens issued before permissions were claimed get the ones of their role
+                let permissions = match token_data.claims.permissions.as_str() {
+                    "" => role.permissions(),
+                    permissions => permissions.split(',').filter_map(Permission::from_str).collect(),
+                };
+                // logging out revokes the session, its access tokens stop working with it. Tokens
+                // without one cannot be revoked, they are only taken until those issued before
+                // sessions were stored expired
+                let session_id = match token_data.claims.sid.as_str() {
+                    "" if predates_sessions(&token_data.claims) => None,
+                    "" => return None,
+                    sid => match session_is_active(request, sid, &token_data.claims.user_id).await {
+                        true => Some(sid.to_string()),
+                        false => return None,
+                    },
+                };
+                let mut authorized = principal(request, token_data.claims.user_id, role, permissions).await;
+                authorized.session_id = session_id;
+                Some(authorized)
+            }
+            DecodeJwtHelper::Err => None,
+        },
+        Err(_) => None,
+    }
+}
+
+/// The user of a validly signed API key, narrowed to the key's scopes and accounts.
+async fn authorize_key(request: &Request<'_>, key_id: &str) -> Option<AuthorizedUser> {
+    let (key, user) = api_key::authenticate(request, key_id).await?;
+    let role_permissions = user.role.permissions();
+    let permissions: Vec<Permission> = role_permissions.iter().filter(|permission| key.scopes.contains(permission)).copied().collect();
+    // a key scoped below its user's role loses the blanket access that comes with the role
+    let role = match permissions.len() == role_permissions.len() {
+        true => user.role,
+        false => Role::User,
+    };
+    let mut authorized = principal(request, user.id, role, permissions).await;
+    if !key.accounts.is_empty() {
+        authorized.resource.retain(|account| key.accounts.contains(account));
+        authorized.grants.retain(|grant| key.accounts.contains(&grant.account_number));
+    }
+    authorized.api_key_id = Some(key.id);
+    authorized.key_accounts = key.accounts;
+    Some(authorized)
+}
+
+async fn principal(request: &Request<'_>, user_id: String, role: Role, permissions: Vec<Permission>) -> AuthorizedUser {
+    let resource = match request.rocket().state::<OwnershipResolver>() {
+        Some(ownership) => ownership.accounts_of(&user_id).await,
+        None => Vec::new(),
+    };
+    let grants = match (request.rocket().state::<VersionedDb<AccessGrant>>(), request.rocket().state::<VersionedDb<Account>>()) {
+        (Some(grant_db), Some(account_db)) => active_grants(grant_db.as_ref(), account_db.as_ref(), &user_id).await,
+        _ => Vec::new(),
+    };
+    AuthorizedUser {
+        user_id,
+        role,
+        resource,
+        permissions,
+        grants,
+        session_id: None,
+        api_key_id: None,
+        key_accounts: Vec::new(),
+    }
+}
+
+async fn session_is_active(request: &Request<'_>, session_id: &str, user_id: &str) -> bool {
+    let session_db = match request.rocket().state::<VersionedDb<UserSession>>() {
+        Some(session_db) => session_db,
+        None => return false,
+    };
+    match session_db.get_by_id(session_id).await {
+        Ok(session) => session.user_id == user_id && session.is_active(),
+        Err(_) => false,
+    }
+}
+
+/// Unrevoked, unexpired grants of `user_id` whose grantor is still a Full owner of the account,
+/// none when they cannot be read.
+async fn active_grants(grant_db: &dyn VersionedStore<AccessGrant>, account_db: &dyn VersionedStore<Account>, user_id: &str) -> Vec<AccessGrant> {
+    let grants = match grant_db.find(doc! {"grantee": user_id, "revoked_at": Bson::Null}, doc! {}, 0).await {
+        Ok(grants) => grants,
+        Err(_) => return Vec::new(),
+    };
+    let mut active = Vec::new();
+    for grant in grants.into_iter().filter(|grant| grant.is_active()) {
+        match account_db.get_by_id(&grant.account_number).await {
+            Ok(account) if grant.granted_by_owner(&account) => active.push(grant),
+            _ => (),
+        };
+    }
+    active
+}
+
+/// Names the permission an endpoint needs, see `Permitted`.
+pub trait RequiredPermission: Send + Sync + 'static {
+    const PERMISSION: Permission;
+}
+
+macro_rules! required_permission {
+    ($($name:ident),*) => {
+        $(
+            pub struct $name;
+            impl RequiredPermission for $name {
+                const PERMISSION: Permission = Permission::$name;
+            }
+        )*
+    };
+}
+required_permission!(AccountsRead, AccountsFreeze, TransactionsApprove, AssetsManage, UsersManage, ReportsRead);
+
+/// The caller holds the permission `P`, e.g. `Permitted<AccountsFreeze>`. Answers 403 and audits
+/// the attempt otherwise.
+pub struct Permitted<P: RequiredPermission> {
+    pub user: AuthorizedUser,
+    permission: PhantomData<P>,
+}
+
+#[rocket::async_trait]
+impl<'r, P: RequiredPermission> FromRequest<'r> for Permitted<P> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<AuthorizedUser>().await {
+            Outcome::Success(user) => user,
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(forward) => return Outcome::Forward(forward),
+        };
+        if user.has(P::PERMISSION) {
+            return Outcome::Success(Permitted { user, permission: PhantomData });
         }
+        if let (Some(audit_db), Outcome::Success(context)) = (
+            request.rocket().state::<Db<AuditEntry>>(),
+            request.guard::<RequestContext>().await,
+        ) {
+            let entry = audit::entry(&context, Some(&user), "auth.forbidden", request.uri().path().as_str())
+                .outcome(AuditOutcome::Denied, Some(format!("Missing permission {}", P::PERMISSION)));
+            audit::log(audit_db.as_ref(), entry).await;
+        }
+        Outcome::Failure((Status::Forbidden, ()))
+    }
+}
+
+impl<'r, P: RequiredPermission> OpenApiFromRequest<'r> for Permitted<P> {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> revolt_rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
     }
 }
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::time::sleep,
+    Orbit, Rocket,
+};
+
+use crate::{
+    api::fiat::expire_deposits,
+    domain::{event::DomainEvent, ledger::Fiat, transaction::Transaction},
+    mongo::{Db, LedgerDb, VersionedDb},
+};
+
+/// Cancels expired deposits in the background once Rocket is up, so their holds are released
+/// without waiting for someone to call the expiry endpoint.
+pub struct DepositExpirer {
+    transactions: VersionedDb<Transaction>,
+    ledgers: LedgerDb<Fiat>,
+    outbox: Db<DomainEvent>,
+    interval: Duration,
+}
+impl DepositExpirer {
+    pub fn new(transactions: VersionedDb<Transaction>, ledgers: LedgerDb<Fiat>, outbox: Db<DomainEvent>, interval: Duration) -> DepositExpirer {
+        DepositExpirer {
+            transactions,
+            ledgers,
+            outbox,
+            interval,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for DepositExpirer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Deposit expirer",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, _: &Rocket<Orbit>) {
+        let transactions = self.transactions.clone();
+        let ledgers = self.ledgers.clone();
+        let outbox = self.outbox.clone();
+        let interval = self.interval;
+        rocket::tokio::spawn(async move {
+            loop {
+                if let Err(e) = expire_deposits(transactions.as_ref(), ledgers.as_ref(), outbox.as_ref()).await {
+                    println!("Error expiring deposits: {}", e);
+                }
+                sleep(interval).await;
+            }
+        });
+    }
+}
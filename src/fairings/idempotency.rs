@@ -0,0 +1,353 @@
+use std::{io::Cursor, sync::Mutex};
+
+use chrono::{Duration, Utc};
+use rocket::{
+    fairing::{self, Fairing, Info, Kind},
+    http::{ContentType, Method, Status},
+    serde::json::to_string,
+    Build, Data, Request, Response, Rocket,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    fairings::auth::AuthorizedUser,
+    mongo::Db,
+    response::error::ErrorResponse,
+};
+
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+/// What the fairing peeks of the body, larger ones are fingerprinted by `SignedJson`.
+const PEEK_LIMIT: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub id: String,
+    pub fingerprint: String,
+    pub status: Option<u16>,
+    pub content_type: Option<String>,
+    pub body: Option<String>,
+    pub created_at: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Clone)]
+enum Decision {
+    Skip,
+    /// Carries a key not claimed yet, waiting for the caller and, past what the fairing could
+    /// peek, for the body.
+    Pending {
+        key: String,
+        scope: Option<String>,
+        body: Option<Vec<u8>>,
+    },
+    Fresh(IdempotencyRecord),
+    Replay(IdempotencyRecord),
+    Conflict(String),
+}
+
+/// Where a request stands with its key, moved along by the guards that learn the caller and the body.
+struct Claim(Mutex<Decision>);
+
+/// Replays the stored response of a mutating request sent again with the same `Idempotency-Key`.
+/// Keys are scoped to the authenticated caller, the API key for signed requests and the user
+/// otherwise, and expire after `ttl` seconds. Requests nobody is authenticated for are not
+/// remembered.
+#[derive(Clone)]
+pub struct Idempotency {
+    db: Db<IdempotencyRecord>,
+    ttl: i64,
+}
+impl Idempotency {
+    pub fn new(db: Db<IdempotencyRecord>, ttl: i64) -> Idempotency {
+        Idempotency { db, ttl }
+    }
+    async fn decide(&self, id: String, fingerprint: String) -> Decision {
+        let now = Utc::now();
+        match self.db.get_by_id(&id).await {
+            Ok(record) if record.expires_at < now.to_rfc3339() => {
+                if let Err(e) = self.db.delete_by_id(&id).await {
+                    return Decision::Conflict(e);
+                }
+            }
+            Ok(record) if record.fingerprint != fingerprint => {
+                return Decision::Conflict(
+                    "Idempotency-Key was already used with a different request".to_string(),
+                )
+            }
+            Ok(record) if record.status.is_none() => {
+                return Decision::Conflict(
+                    "A request with this Idempotency-Key is still being processed".to_string(),
+                )
+            }
+            Ok(record) => return Decision::Replay(record),
+            Err(_) => {}
+        }
+        let record = IdempotencyRecord {
+            id,
+            fingerprint,
+            status: None,
+            content_type: None,
+            body: None,
+            created_at: now.to_rfc3339(),
+            expires_at: (now + Duration::seconds(self.ttl)).to_rfc3339(),
+        };
+        // the unique index on id makes concurrent first requests lose this race
+        match self.db.create(record.clone()).await {
+            Ok(_) => Decision::Fresh(record),
+            Err(_) => Decision::Conflict(
+                "A request with this Idempotency-Key is still being processed".to_string(),
+            ),
+        }
+    }
+    /// Stores the response of the request that claimed the key.
+    async fn remember(&self, mut record: IdempotencyRecord, res: &mut Response<'_>) {
+        // server errors are not remembered so the client can retry with the same key
+        if res.status().class().is_server_error() {
+            if let Err(e) = self.db.delete_by_id(&record.id).await {
+                println!("Error releasing idempotency key: {}", e);
+            }
+            return;
+        }
+        let body = res.body_mut().to_string().await.unwrap_or_default();
+        record.status = Some(res.status().code);
+        record.content_type = res.content_type().map(|content_type| content_type.to_string());
+        record.body = Some(body.clone());
+        res.set_sized_body(body.len(), Cursor::new(body));
+        if let Err(e) = self.db.update_by_id(&record.id.clone(), record).await {
+            println!("Error storing idempotent response: {}", e);
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Idempotency {
+    fn info(&self) -> Info {
+        Info {
+            name: "Idempotency-Key",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        Ok(rocket.manage(self.clone()))
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, data: &mut Data<'_>) {
+        if !matches!(
+            req.method(),
+            Method::Post | Method::Put | Method::Patch | Method::Delete
+        ) {
+            return;
+        }
+        let key = match req.headers().get_one(IDEMPOTENCY_HEADER) {
+            Some(key) if !key.trim().is_empty() => key.trim().to_string(),
+            _ => return,
+        };
+        let body = data.peek(PEEK_LIMIT).await.to_vec();
+        let body = match data.peek_complete() {
+            true => Some(body),
+            false => None,
+        };
+        req.local_cache(|| Claim(Mutex::new(Decision::Pending { key, scope: None, body })));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let decision = req.local_cache(|| Claim(Mutex::new(Decision::Skip))).0.lock().expect("idempotency lock").clone();
+        let (status, content_type, body) = match decision {
+            Decision::Fresh(record) => return self.remember(record, res).await,
+            Decision::Replay(record) => (
+                Status::from_code(record.status.unwrap_or(200)).unwrap_or(Status::Ok),
+                record
+                    .content_type
+                    .and_then(|content_type| ContentType::parse_flexible(&content_type))
+                    .unwrap_or(ContentType::JSON),
+                record.body.unwrap_or_default(),
+            ),
+            Decision::Conflict(message) => (
+                Status::Conflict,
+                ContentType::JSON,
+                to_string(&ErrorResponse::new("Idempotency-Key".to_string(), message))
+                    .unwrap_or_default(),
+            ),
+            _ => return,
+        };
+        res.set_status(status);
+        res.set_header(content_type);
+        res.set_raw_header("Idempotent-Replayed", (status != Status::Conflict).to_string());
+        res.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+/// Scopes the request's key to `user` and claims it when the fairing saw the whole body. Err
+/// with the status to fail the guard with when the request was already answered or is running,
+/// the fairing then sends what it should get instead.
+pub async fn claim_for(request: &Request<'_>, user: &AuthorizedUser) -> Result<(), Status> {
+    let scope = match &user.api_key_id {
+        Some(key_id) => format!("key:{}", key_id),
+        None => format!("user:{}", user.user_id),
+    };
+    claim(request, Some(scope), None).await
+}
+
+/// Claims the request's key with its full body, for data guards reading what the fairing could not peek.
+pub async fn claim_with_body(request: &Request<'_>, body: &[u8]) -> Result<(), Status> {
+    claim(request, None, Some(body)).await
+}
+
+async fn claim(request: &Request<'_>, scope: Option<String>, body: Option<&[u8]>) -> Result<(), Status> {
+    let claim = request.local_cache(|| Claim(Mutex::new(Decision::Skip)));
+    let (id, fingerprint) = {
+        let mut decision = claim.0.lock().expect("idempotency lock");
+        let (key, known_scope, peeked) = match &mut *decision {
+            Decision::Pending { key, scope, body } => (key, scope, body),
+            Decision::Replay(_) | Decision::Conflict(_) => return Err(Status::Conflict),
+            _ => return Ok(()),
+        };
+        if scope.is_some() {
+            *known_scope = scope;
+        }
+        let (scope, body) = match (known_scope.as_ref(), body.or(peeked.as_deref())) {
+            (Some(scope), Some(body)) => (scope, body),
+            _ => return Ok(()),
+        };
+        let mut fingerprint = format!("{}|{}|", request.method(), request.uri()).into_bytes();
+        fingerprint.extend(body);
+        (sha256_hex(format!("{}|{}", scope, key).as_bytes()), sha256_hex(&fingerprint))
+    };
+    let idempotency = match request.rocket().state::<Idempotency>() {
+        Some(idempotency) => idempotency,
+        None => return Ok(()),
+    };
+    let decision = idempotency.decide(id, fingerprint).await;
+    let outcome = match decision {
+        Decision::Replay(_) | Decision::Conflict(_) => Err(Status::Conflict),
+        _ => Ok(()),
+    };
+    *claim.0.lock().expect("idempotency lock") = decision;
+    outcome
+}
+
+fn sha256_hex(value: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(value);
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use rocket::{
+        http::Header,
+        local::asynchronous::Client,
+        post,
+        request::{FromRequest, Outcome},
+        routes, State,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{domain::user::Role, fairings::api_key::SignedJson, memory::MemoryRepository};
+
+    fn idempotency() -> Idempotency {
+        Idempotency::new(Arc::new(MemoryRepository::new("id".to_string())), 60)
+    }
+
+    #[rocket::async_test]
+    async fn first_request_is_fresh_and_blocks_the_next() {
+        let idempotency = idempotency();
+        assert!(matches!(idempotency.decide("k".to_string(), "f".to_string()).await, Decision::Fresh(_)));
+        assert!(matches!(idempotency.decide("k".to_string(), "f".to_string()).await, Decision::Conflict(_)));
+    }
+
+    #[rocket::async_test]
+    async fn replays_the_stored_response() {
+        let idempotency = idempotency();
+        let mut record = match idempotency.decide("k".to_string(), "f".to_string()).await {
+            Decision::Fresh(record) => record,
+            _ => panic!("expected a fresh request"),
+        };
+        record.status = Some(201);
+        record.body = Some("{}".to_string());
+        idempotency.db.update_by_id("k", record).await.unwrap();
+        match idempotency.decide("k".to_string(), "f".to_string()).await {
+            Decision::Replay(record) => assert_eq!(record.status, Some(201)),
+            _ => panic!("expected a replay"),
+        }
+        assert!(matches!(idempotency.decide("k".to_string(), "other".to_string()).await, Decision::Conflict(_)));
+    }
+
+    #[rocket::async_test]
+    async fn expired_keys_start_over() {
+        let idempotency = idempotency();
+        let mut record = match idempotency.decide("k".to_string(), "f".to_string()).await {
+            Decision::Fresh(record) => record,
+            _ => panic!("expected a fresh request"),
+        };
+        record.status = Some(201);
+        record.expires_at = (Utc::now() - Duration::seconds(1)).to_rfc3339();
+        idempotency.db.update_by_id("k", record).await.unwrap();
+        assert!(matches!(idempotency.decide("k".to_string(), "other".to_string()).await, Decision::Fresh(_)));
+    }
+
+    /// Stands in for `AuthorizedUser`, the caller is whoever `X-User` names.
+    struct Caller;
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for Caller {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Caller, ()> {
+            let user = AuthorizedUser {
+                user_id: request.headers().get_one("X-User").unwrap_or_default().to_string(),
+                role: Role::User,
+                resource: Vec::new(),
+                permissions: Vec::new(),
+                grants: Vec::new(),
+                session_id: None,
+                api_key_id: None,
+                key_accounts: Vec::new(),
+            };
+            match claim_for(request, &user).await {
+                Ok(_) => Outcome::Success(Caller),
+                Err(status) => Outcome::Failure((status, ())),
+            }
+        }
+    }
+
+    #[post("/count", data = "<_body>")]
+    fn count(_caller: Caller, _body: SignedJson<Value>, calls: &State<AtomicUsize>) -> String {
+        (calls.fetch_add(1, Ordering::SeqCst) + 1).to_string()
+    }
+
+    #[rocket::async_test]
+    async fn keys_are_scoped_to_the_caller_and_cover_the_whole_body() {
+        let rocket = rocket::build()
+            .manage(AtomicUsize::new(0))
+            .attach(idempotency())
+            .mount("/", routes![count]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let body = |filler: &str| serde_json::json!({ "memo": filler.repeat(PEEK_LIMIT) }).to_string();
+        let send = |user: &'static str, body: String| {
+            client
+                .post("/count")
+                .header(ContentType::JSON)
+                .header(Header::new(IDEMPOTENCY_HEADER, "k"))
+                .header(Header::new("X-User", user))
+                .body(body)
+                .dispatch()
+        };
+
+        assert_eq!(send("a", body("x")).await.into_string().await.unwrap(), "1");
+        let replayed = send("a", body("x")).await;
+        assert_eq!(replayed.headers().get_one("Idempotent-Replayed"), Some("true"));
+        assert_eq!(replayed.into_string().await.unwrap(), "1");
+        // another caller's key is their own, even under the same name
+        assert_eq!(send("b", body("x")).await.into_string().await.unwrap(), "2");
+        // past the peek, a different body is still told apart
+        assert_eq!(send("a", body("y")).await.status(), Status::Conflict);
+    }
+}
@@ -1 +1,7 @@
-pub mod auth;
\ No newline at end of file
+pub mod api_key;
+pub mod auth;
+pub mod expiry;
+pub mod idempotency;
+pub mod precondition;
+
+pub mod request_context;
\ No newline at end of file
@@ -0,0 +1,41 @@
+use revolt_rocket_okapi::OpenApiFromRequest;
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    Request,
+};
+
+/// Version the client expects, taken from the `If-Match` header (`"3"`, `W/"3"`).
+/// A missing header or `*` accepts any version.
+#[derive(Debug, Clone, PartialEq, OpenApiFromRequest)]
+pub struct IfMatch {
+    pub version: Option<u64>,
+}
+impl IfMatch {
+    pub fn matches(&self, version: u64) -> bool {
+        match self.version {
+            Some(expected) => expected == version,
+            None => true,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = match request.headers().get_one("If-Match") {
+            Some(header) => header.trim(),
+            None => return Outcome::Success(IfMatch { version: None }),
+        };
+        if header == "*" {
+            return Outcome::Success(IfMatch { version: None });
+        }
+        let tag = header.trim_start_matches("W/").trim_matches('"');
+        match tag.parse::<u64>() {
+            Ok(version) => Outcome::Success(IfMatch { version: Some(version) }),
+            Err(_) => Outcome::Failure((Status::BadRequest, ())),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::{env, net::IpAddr};
+
+use revolt_rocket_okapi::OpenApiFromRequest;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    request::{FromRequest, Outcome},
+    Request, Response,
+};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+struct RequestId(String);
+
+fn request_id(request: &Request<'_>) -> String {
+    request
+        .local_cache(|| {
+            RequestId(match request.headers().get_one(REQUEST_ID_HEADER) {
+                Some(id) if !id.is_empty() && id.len() <= 128 => id.to_string(),
+                _ => Uuid::new_v4().to_string(),
+            })
+        })
+        .0
+        .clone()
+}
+
+/// Reverse proxies trusted to name the client in `X-Real-IP`, from `TRUSTED_PROXIES` (comma
+/// separated addresses). Anyone else could send the header, so their own address is used.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TrustedProxies(Vec<IpAddr>);
+impl TrustedProxies {
+    pub fn from_env() -> Result<TrustedProxies, String> {
+        let proxies = match env::var("TRUSTED_PROXIES") {
+            Ok(proxies) => proxies,
+            Err(_) => return Ok(TrustedProxies::default()),
+        };
+        let mut trusted = Vec::new();
+        for proxy in proxies.split(',').map(|proxy| proxy.trim()).filter(|proxy| !proxy.is_empty()) {
+            match proxy.parse() {
+                Ok(ip) => trusted.push(ip),
+                Err(_) => return Err(format!("Error parsing trusted proxy: {}", proxy)),
+            }
+        }
+        Ok(TrustedProxies(trusted))
+    }
+    /// `real_ip` when the request came through a trusted proxy that sent one, `remote` otherwise.
+    pub fn client_of(&self, remote: Option<IpAddr>, real_ip: Option<IpAddr>) -> Option<IpAddr> {
+        match remote {
+            Some(remote) if self.0.contains(&remote) => real_ip.or(Some(remote)),
+            remote => remote,
+        }
+    }
+}
+
+/// Address of the client, see `TrustedProxies`.
+pub fn client_ip(request: &Request<'_>) -> Option<IpAddr> {
+    let remote = request.remote().map(|remote| remote.ip());
+    match request.rocket().state::<TrustedProxies>() {
+        Some(proxies) => proxies.client_of(remote, request.real_ip()),
+        None => remote,
+    }
+}
+
+/// Where a request came from, for the audit log. The request id is the caller's
+/// `X-Request-Id` when sent, a fresh uuid otherwise.
+#[derive(Debug, Clone, PartialEq, OpenApiFromRequest)]
+pub struct RequestContext {
+    pub request_id: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestContext {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestContext {
+            request_id: request_id(request),
+            ip: client_ip(request).map(|ip| ip.to_string()),
+            user_agent: request.headers().get_one("User-Agent").map(|agent| agent.to_string()),
+        })
+    }
+}
+
+/// Echoes the request id on every response so callers can quote it.
+pub struct RequestIdHeader;
+
+#[rocket::async_trait]
+impl Fairing for RequestIdHeader {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request id header",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_raw_header(REQUEST_ID_HEADER, request_id(request));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn real_ip_only_counts_from_a_trusted_proxy() {
+        let proxies = TrustedProxies(vec!["10.0.0.1".parse().unwrap()]);
+        assert_eq!(proxies.client_of(ip("10.0.0.1"), ip("203.0.113.7")), ip("203.0.113.7"));
+        assert_eq!(proxies.client_of(ip("10.0.0.1"), None), ip("10.0.0.1"));
+        assert_eq!(proxies.client_of(ip("198.51.100.2"), ip("203.0.113.7")), ip("198.51.100.2"));
+        assert_eq!(TrustedProxies::default().client_of(ip("10.0.0.1"), ip("203.0.113.7")), ip("10.0.0.1"));
+    }
+}
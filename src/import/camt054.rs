@@ -0,0 +1,100 @@
+use crate::domain::reconciliation::BankLine;
+
+use super::parse_amount;
+
+pub fn parse(content: &str) -> Result<Vec<BankLine>, String> {
+    if !content.contains("camt.054") {
+        return Err("Document is not a camt.054 notification".to_string());
+    }
+    let mut lines = Vec::new();
+    for entry in elements(content, "Ntry") {
+        let (amount_attributes, amount_value) = match element_with_attributes(entry, "Amt") {
+            Some(amount) => amount,
+            None => return Err("Entry without amount".to_string()),
+        };
+        let credit = match element(entry, "CdtDbtInd") {
+            Some(indicator) => indicator == "CRDT",
+            None => return Err("Entry without credit/debit indicator".to_string()),
+        };
+        let booked_at = element(entry, "BookgDt")
+            .and_then(|booking| element(booking, "Dt").or_else(|| element(booking, "DtTm")))
+            .unwrap_or_default();
+        let bank_reference = element(entry, "AcctSvcrRef")
+            .or_else(|| element(entry, "NtryRef"))
+            .unwrap_or_default();
+        let mut remittance: Vec<&str> = elements(entry, "Ustrd");
+        if let Some(reference) = element(entry, "CdtrRefInf").and_then(|info| element(info, "Ref")) {
+            remittance.push(reference);
+        }
+        if let Some(end_to_end) = element(entry, "EndToEndId") {
+            remittance.push(end_to_end);
+        }
+        lines.push(BankLine {
+            booked_at: booked_at.to_string(),
+            amount: parse_amount(amount_value)?,
+            currency: attribute(amount_attributes, "Ccy"),
+            credit,
+            bank_reference: unescape(bank_reference),
+            remittance: unescape(&remittance.join(" ")),
+        });
+    }
+    Ok(lines)
+}
+
+fn elements<'a>(content: &'a str, tag: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = content;
+    while let Some((_, inner, after)) = next_element(rest, tag) {
+        found.push(inner);
+        rest = after;
+    }
+    found
+}
+
+fn element<'a>(content: &'a str, tag: &str) -> Option<&'a str> {
+    next_element(content, tag).map(|(_, inner, _)| inner.trim())
+}
+
+fn element_with_attributes<'a>(content: &'a str, tag: &str) -> Option<(&'a str, &'a str)> {
+    next_element(content, tag).map(|(attributes, inner, _)| (attributes, inner.trim()))
+}
+
+// returns (attributes, inner content, remaining input) of the first <tag> element
+fn next_element<'a>(content: &'a str, tag: &str) -> Option<(&'a str, &'a str, &'a str)> {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut search = 0;
+    loop {
+        let start = search + content[search..].find(&open)?;
+        let after_name = start + open.len();
+        let next = content[after_name..].chars().next()?;
+        if next != '>' && next != ' ' {
+            search = after_name;
+            continue;
+        }
+        let tag_end = after_name + content[after_name..].find('>')?;
+        let attributes = &content[after_name..tag_end];
+        let inner_start = tag_end + 1;
+        let inner_end = inner_start + content[inner_start..].find(&close)?;
+        return Some((
+            attributes,
+            &content[inner_start..inner_end],
+            &content[inner_end + close.len()..],
+        ));
+    }
+}
+
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let key = format!("{}=\"", name);
+    let start = attributes.find(&key)? + key.len();
+    let end = start + attributes[start..].find('"')?;
+    Some(attributes[start..end].to_string())
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,90 @@
+use crate::{domain::reconciliation::BankLine, dto::reconciliation::CsvMapping};
+
+use super::parse_amount;
+
+pub fn parse(content: &str, mapping: &CsvMapping) -> Result<Vec<BankLine>, String> {
+    let delimiter = mapping.delimiter.unwrap_or(',');
+    let mut rows = content
+        .lines()
+        .filter(|row| !row.trim().is_empty())
+        .map(|row| split_row(row, delimiter));
+    let header = if mapping.has_header {
+        match rows.next() {
+            Some(header) => header,
+            None => return Ok(Vec::new()),
+        }
+    } else {
+        Vec::new()
+    };
+    let date = column(&header, &mapping.date)?;
+    let amount = column(&header, &mapping.amount)?;
+    let bank_reference = column(&header, &mapping.bank_reference)?;
+    let remittance = column(&header, &mapping.remittance)?;
+    let currency = match &mapping.currency {
+        Some(name) => Some(column(&header, name)?),
+        None => None,
+    };
+    let credit_debit = match &mapping.credit_debit {
+        Some(name) => Some(column(&header, name)?),
+        None => None,
+    };
+    let mut lines = Vec::new();
+    for (i, row) in rows.enumerate() {
+        let field = |index: usize| -> Result<String, String> {
+            match row.get(index) {
+                Some(value) => Ok(value.trim().to_string()),
+                None => Err(format!("Row {} is missing column {}", i + 1, index)),
+            }
+        };
+        let value = parse_amount(&field(amount)?)?;
+        let credit = match credit_debit {
+            Some(index) => matches!(
+                field(index)?.to_uppercase().as_str(),
+                "C" | "CR" | "CRDT" | "CREDIT"
+            ),
+            None => value > 0.0,
+        };
+        lines.push(BankLine {
+            booked_at: field(date)?,
+            amount: value.abs(),
+            currency: match currency {
+                Some(index) => Some(field(index)?.to_uppercase()),
+                None => None,
+            },
+            credit,
+            bank_reference: field(bank_reference)?,
+            remittance: field(remittance)?,
+        });
+    }
+    Ok(lines)
+}
+
+fn column(header: &[String], name: &str) -> Result<usize, String> {
+    if let Some(index) = header.iter().position(|column| column.trim() == name) {
+        return Ok(index);
+    }
+    match name.parse::<usize>() {
+        Ok(index) => Ok(index),
+        Err(_) => Err(format!("Column not found: {}", name)),
+    }
+}
+
+fn split_row(row: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
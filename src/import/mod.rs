@@ -0,0 +1,41 @@
+pub mod camt054;
+pub mod csv;
+pub mod mt940;
+
+use crate::{
+    domain::reconciliation::{BankLine, ImportFormat},
+    dto::reconciliation::CsvMapping,
+};
+
+pub fn parse_statement(
+    format: &ImportFormat,
+    content: &str,
+    mapping: Option<&CsvMapping>,
+) -> Result<Vec<BankLine>, String> {
+    match format {
+        ImportFormat::Csv => match mapping {
+            Some(mapping) => csv::parse(content, mapping),
+            None => Err("CSV imports require a column mapping".to_string()),
+        },
+        ImportFormat::Camt054 => camt054::parse(content),
+        ImportFormat::Mt940 => mt940::parse(content),
+    }
+}
+
+pub fn parse_amount(value: &str) -> Result<f64, String> {
+    let cleaned: String = value
+        .trim()
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '\'')
+        .collect();
+    let normalized = match (cleaned.rfind(','), cleaned.rfind('.')) {
+        (Some(comma), Some(dot)) if comma > dot => cleaned.replace('.', "").replace(',', "."),
+        (Some(_), Some(_)) => cleaned.replace(',', ""),
+        (Some(_), None) => cleaned.replace(',', "."),
+        _ => cleaned,
+    };
+    match normalized.parse::<f64>() {
+        Ok(amount) => Ok(amount),
+        Err(_) => Err(format!("Invalid amount: {}", value)),
+    }
+}
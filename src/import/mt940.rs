@@ -0,0 +1,124 @@
+use crate::domain::reconciliation::BankLine;
+
+use super::parse_amount;
+
+pub fn parse(content: &str) -> Result<Vec<BankLine>, String> {
+    let mut lines: Vec<BankLine> = Vec::new();
+    let mut currency: Option<String> = None;
+    let mut in_remittance = false;
+    for raw in content.lines() {
+        let row = raw.trim_end();
+        if let Some(value) = row.strip_prefix(":60F:").or_else(|| row.strip_prefix(":60M:")) {
+            in_remittance = false;
+            // mark(1) + date(6) + currency(3)
+            currency = value.get(7..10).map(|ccy| ccy.to_string());
+        } else if let Some(value) = row.strip_prefix(":61:") {
+            in_remittance = false;
+            lines.push(parse_entry(value, currency.clone())?);
+        } else if let Some(value) = row.strip_prefix(":86:") {
+            in_remittance = true;
+            if let Some(line) = lines.last_mut() {
+                line.remittance = format!("{} {}", line.remittance, value);
+            }
+        } else if row.starts_with(':') || row.starts_with('-') {
+            in_remittance = false;
+        } else if in_remittance {
+            if let Some(line) = lines.last_mut() {
+                line.remittance.push_str(row);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+fn parse_entry(value: &str, currency: Option<String>) -> Result<BankLine, String> {
+    let invalid = || format!("Invalid :61: line: {}", value);
+    let value_date = match value.get(0..6) {
+        Some(date) if date.chars().all(|c| c.is_ascii_digit()) => date,
+        _ => return Err(invalid()),
+    };
+    let mut rest = &value[6..];
+    // optional entry date MMDD
+    if rest.get(..4).map(|date| date.chars().all(|c| c.is_ascii_digit())).unwrap_or(false) {
+        rest = &rest[4..];
+    }
+    let credit = if let Some(after) = rest.strip_prefix("RC") {
+        rest = after;
+        false
+    } else if let Some(after) = rest.strip_prefix("RD") {
+        rest = after;
+        true
+    } else if let Some(after) = rest.strip_prefix('C') {
+        rest = after;
+        true
+    } else if let Some(after) = rest.strip_prefix('D') {
+        rest = after;
+        false
+    } else {
+        return Err(invalid());
+    };
+    // optional funds code
+    if rest.chars().next().map(|c| c.is_ascii_alphabetic()).unwrap_or(false) {
+        rest = &rest[1..];
+    }
+    let amount_end = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == ','))
+        .ok_or_else(invalid)?;
+    let amount = parse_amount(&rest[..amount_end])?;
+    // transaction type identification code (4 characters, e.g. NTRF)
+    let references = rest.get(amount_end + 4..).unwrap_or("");
+    let (customer_reference, bank_reference) = match references.split_once("//") {
+        Some((customer, bank)) => (customer, bank),
+        None => (references, ""),
+    };
+    // NONREF stands for no reference at all, it must not make unrelated lines look the same
+    let bank_reference = match (bank_reference.trim(), customer_reference.trim()) {
+        ("", "NONREF") => "",
+        ("", customer) => customer,
+        (bank, _) => bank,
+    };
+    Ok(BankLine {
+        booked_at: format!(
+            "20{}-{}-{}",
+            value_date.get(..2).ok_or_else(invalid)?,
+            value_date.get(2..4).ok_or_else(invalid)?,
+            value_date.get(4..6).ok_or_else(invalid)?
+        ),
+        amount,
+        currency,
+        credit,
+        bank_reference: bank_reference.to_string(),
+        remittance: customer_reference.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_credit() {
+        let lines = parse(":60F:C231001EUR100,00\n:61:2310021002C150,00NTRFREF123//BANK456\n:86:DEP-ABC\n-").unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].booked_at, "2023-10-02");
+        assert_eq!(lines[0].amount, 150.0);
+        assert!(lines[0].credit);
+        assert_eq!(lines[0].currency.as_deref(), Some("EUR"));
+        assert_eq!(lines[0].bank_reference, "BANK456");
+    }
+
+    #[test]
+    fn nonref_is_no_reference() {
+        let lines = parse(":61:231002C150,00NTRFNONREF\n:86:DEP-ABC").unwrap();
+        assert_eq!(lines[0].bank_reference, "");
+        let lines = parse(":61:231002C150,00NTRFNONREF//BANK456").unwrap();
+        assert_eq!(lines[0].bank_reference, "BANK456");
+    }
+
+    #[test]
+    fn rejects_multibyte_dates_without_panicking() {
+        assert!(parse(":61:2é10é2C150,00NTRFREF").is_err());
+        assert!(parse(":61:éééC150,00NTRFREF").is_err());
+        assert!(parse(":61:23").is_err());
+    }
+}
@@ -1,13 +1,19 @@
-use api::{account::*, crypto::*, fiat::*, transaction::*, auth::*};
+use api::{account::*, crypto::*, fiat::*, transaction::*, auth::*, statement::*, reconciliation::*, webhook::*, integrity::*, ledger::*, audit::*, closure::*, grant::*, user::*, api_key::*, password::*};
 use chrono::Local;
-use domain::{
-    account::Account,
-    asset::AssetManager,
-    ledger::{Crypto, Fiat},
-    transaction::Transaction, user::User,
-};
+use domain::{asset::AssetManager, transaction::HASH_CHAIN_SECRET, webhook::RetryPolicy};
 use dotenv::dotenv;
-use mongo::Data;
+use events::{
+    anchor::{LedgerAppender, RootSealer},
+    publisher::Chain,
+    relay::OutboxRelay,
+    webhook::{WebhookDispatcher, WebhookFanout},
+};
+use fairings::{
+    api_key::{ApiKeyBody, ReplayCache},
+    expiry::DepositExpirer,
+    idempotency::Idempotency,
+    request_context::{RequestIdHeader, TrustedProxies},
+};
 use response::error::ErrorResponse;
 use revolt_rocket_okapi::{
     openapi_get_routes,
@@ -15,58 +21,139 @@ use revolt_rocket_okapi::{
     settings::UrlObject,
     swagger_ui::{make_swagger_ui, SwaggerUIConfig},
 };
-use rocket::{catch, catchers, http::Method, launch, serde::json::Json, Request};
+use rocket::{catch, catchers, http::Method, launch, routes, serde::json::Json, Request};
 use rocket_cors::{AllowedOrigins, CorsOptions};
 use serde::Serialize;
-use std::env;
+use std::{env, sync::Arc, time::Duration};
+use security::{jwt::SESSIONLESS_UNTIL, keys::KeyRing, ownership::OwnershipResolver, password::PasswordPolicy, signing::RootSigner};
+use storage::Stores;
 mod api;
 mod domain;
 mod dto;
+mod events;
+mod export;
+mod import;
+mod memory;
 mod mongo;
+mod notifier;
 mod response;
 mod fairings;
 mod security;
+mod sql;
+mod storage;
 
 #[launch]
 async fn rocket() -> _ {
     dotenv().ok();
 
-    let db_uri = match env::var("DBURI") {
-        Ok(v) => {
-            println!("DBURI: {}", &v);
-            v
+    let storage = env::var("STORAGE").unwrap_or("mongo".to_string());
+    println!("STORAGE: {}", &storage);
+    let stores = match storage.as_str() {
+        "memory" => Stores::memory(),
+        "mongo" => {
+            let db_uri = match env::var("DBURI") {
+                Ok(v) => {
+                    println!("DBURI: {}", &v);
+                    v
+                }
+                Err(_) => panic!("Error loading env variable: DBURI"),
+            };
+            let db_name = match env::var("DBNAME") {
+                Ok(v) => {
+                    println!("DBNAME: {}", &v);
+                    v
+                }
+                Err(_) => panic!("Error loading env variable: DBNAME"),
+            };
+            match Stores::mongo(&db_uri, &db_name).await {
+                Ok(stores) => stores,
+                Err(e) => panic!("Error creating client: {}", e),
+            }
         }
-        Err(_) => panic!("Error loading env variable: DBURI"),
-    };
-    let db_name = match env::var("DBNAME") {
-        Ok(v) => {
-            println!("DBNAME: {}", &v);
-            v
+        "sql" => {
+            let database_url = match env::var("DATABASE_URL") {
+                Ok(v) => v,
+                Err(_) => panic!("Error loading env variable: DATABASE_URL"),
+            };
+            match Stores::sql(&database_url).await {
+                Ok(stores) => stores,
+                Err(e) => panic!("Error creating client: {}", e),
+            }
         }
-        Err(_) => panic!("Error loading env variable: DBNAME"),
+        other => panic!("Unknown STORAGE backend: {}", other),
     };
-
-    let client = match Data::new(&db_uri, "My Bank", &db_name).await {
-        Ok(client) => client,
-        Err(e) => {
-            panic!("Error creating client: {}", e);
-        }
+    let idempotency_ttl = match env::var("IDEMPOTENCY_TTL") {
+        Ok(v) => v.parse().expect("Error parsing env variable: IDEMPOTENCY_TTL"),
+        Err(_) => 86400,
+    };
+    let publisher = match events::publisher::from_env().await {
+        Ok(publisher) => publisher,
+        Err(e) => panic!("Error creating event publisher: {}", e),
+    };
+    let relay_interval = match env::var("OUTBOX_POLL_INTERVAL_MS") {
+        Ok(v) => v.parse().expect("Error parsing env variable: OUTBOX_POLL_INTERVAL_MS"),
+        Err(_) => 1000,
+    };
+    let relay_batch = match env::var("OUTBOX_BATCH_SIZE") {
+        Ok(v) => v.parse().expect("Error parsing env variable: OUTBOX_BATCH_SIZE"),
+        Err(_) => 100,
+    };
+    let publisher = Arc::new(Chain::new(vec![
+        publisher,
+        Arc::new(WebhookFanout::new(stores.webhook.clone(), stores.webhook_delivery.clone())),
+        Arc::new(LedgerAppender::new(stores.ledger_entry.clone())),
+    ]));
+    let webhook_interval = match env::var("WEBHOOK_POLL_INTERVAL_MS") {
+        Ok(v) => v.parse().expect("Error parsing env variable: WEBHOOK_POLL_INTERVAL_MS"),
+        Err(_) => 1000,
+    };
+    let webhook_policy = RetryPolicy {
+        max_attempts: match env::var("WEBHOOK_MAX_ATTEMPTS") {
+            Ok(v) => v.parse().expect("Error parsing env variable: WEBHOOK_MAX_ATTEMPTS"),
+            Err(_) => 8,
+        },
+        backoff_seconds: match env::var("WEBHOOK_BACKOFF_SECONDS") {
+            Ok(v) => v.parse().expect("Error parsing env variable: WEBHOOK_BACKOFF_SECONDS"),
+            Err(_) => 30,
+        },
+    };
+    // read now so a missing secret stops startup instead of the first transaction
+    lazy_static::initialize(&HASH_CHAIN_SECRET);
+    // likewise a cutoff for tokens without a session that has passed
+    lazy_static::initialize(&SESSIONLESS_UNTIL);
+    let root_signer = match RootSigner::from_env() {
+        Ok(signer) => Arc::new(signer),
+        Err(e) => panic!("Error loading ledger signing key: {}", e),
+    };
+    let token_keys = match KeyRing::from_env() {
+        Ok(keys) => keys,
+        Err(e) => panic!("Error loading token signing keys: {}", e),
+    };
+    let trusted_proxies = match TrustedProxies::from_env() {
+        Ok(proxies) => proxies,
+        Err(e) => panic!("Error loading trusted proxies: {}", e),
+    };
+    let password_policy = match PasswordPolicy::from_env() {
+        Ok(policy) => policy,
+        Err(e) => panic!("Error loading password policy: {}", e),
+    };
+    let notifier = match notifier::from_env() {
+        Ok(notifier) => notifier,
+        Err(e) => panic!("Error creating notifier: {}", e),
     };
-    let fiat_db = client
-        .get_repo::<Fiat>("fiat_vault", "id".to_string())
-        .unwrap();
-    let crypto_db = client
-        .get_repo::<Crypto>("crypto_vault", "id".to_string())
-        .unwrap();
-    let wallet_db = client
-        .get_repo::<Account>("wallet", "account_number".to_string())
-        .unwrap();
-    let transaction_db = client
-        .get_repo::<Transaction>("transaction", "tx_id".to_string())
-        .unwrap();
-    let user_db = client
-        .get_repo::<User>("user", "id".to_string())
-        .unwrap();
+    let seal_interval = match env::var("LEDGER_SEAL_INTERVAL_MS") {
+        Ok(v) => v.parse().expect("Error parsing env variable: LEDGER_SEAL_INTERVAL_MS"),
+        Err(_) => 60000,
+    };
+    let expiry_interval = match env::var("DEPOSIT_EXPIRY_INTERVAL_MS") {
+        Ok(v) => v.parse().expect("Error parsing env variable: DEPOSIT_EXPIRY_INTERVAL_MS"),
+        Err(_) => 60000,
+    };
+    let ownership_ttl = match env::var("OWNERSHIP_CACHE_TTL_MS") {
+        Ok(v) => v.parse().expect("Error parsing env variable: OWNERSHIP_CACHE_TTL_MS"),
+        Err(_) => 5000,
+    };
+    let ownership = OwnershipResolver::new(stores.account.clone(), Duration::from_millis(ownership_ttl));
     let asset_manager = AssetManager::new();
     let cors = CorsOptions::default()
         .allowed_origins(AllowedOrigins::all())
@@ -82,15 +169,39 @@ async fn rocket() -> _ {
         register,
         login,
         refresh_tokens,
+        logout,
+        logout_all,
+        get_sessions,
+        revoke_session,
+        change_password,
+        forgot_password,
+        reset_password,
+
+        set_user_role,
 
         create_account,
+        get_owned_accounts,
+        rename_account,
+        set_account_owner,
+        remove_account_owner,
+
+        create_grant,
+        get_account_grants,
+        get_my_grants,
+        revoke_grant,
+        create_api_key,
+        get_api_keys,
+        rotate_api_key,
+        revoke_api_key,
         get_accounts,
         get_account,
         disable_account,
         enable_account,
+        close_account,
         get_fiats,
         get_cryptos,
         balances,
+        get_account_transactions,
 
         create_crypto,
         get_crypto,
@@ -103,27 +214,109 @@ async fn rocket() -> _ {
         get_fiat,
         fiat_deposit,
         fiat_confirm_deposit,
+        fiat_expire_deposits,
         fiat_withdrawal,
         fiat_release_withdrawal,
 
+        get_statement,
+        request_statement,
+        get_statement_job,
+        download_statement,
+
+        import_bank_statement,
+        get_bank_statement_import,
+        get_review_queue,
+        resolve_review_item,
+        dismiss_review_item,
+
+        create_webhook,
+        get_webhooks,
+        get_webhook,
+        disable_webhook,
+        rotate_webhook_secret,
+        get_webhook_deliveries,
+        replay_webhook_delivery,
+
+        get_transaction,
+        search_transactions,
         submit_transaction,
         confirm_transaction,
         complete_transaction,
         fail_transaction,
-        cancel_transaction
+        cancel_transaction,
+
+        verify_transaction,
+        request_hash_chain_verification,
+        get_hash_chain_verification,
+
+        get_ledger_roots,
+        get_ledger_root,
+        get_inclusion_proof,
+
+        search_audit_log,
+        export_audit_log
     ];
     
     rocket::build()
         .manage(cors.to_cors())
-        .manage(fiat_db)
-        .manage(crypto_db)
+        .manage(stores.fiat.clone())
+        .manage(stores.crypto)
         .manage(asset_manager)
-        .manage(wallet_db)
-        .manage(transaction_db)
-        .manage(user_db)
+        .manage(stores.account)
+        .manage(ownership)
+        .manage(stores.transaction.clone())
+        .manage(stores.user)
+        .manage(stores.statement_job)
+        .manage(stores.statement_import)
+        .manage(stores.review)
+        .manage(stores.outbox.clone())
+        .manage(stores.webhook.clone())
+        .manage(stores.webhook_delivery.clone())
+        .manage(stores.integrity_report)
+        .manage(stores.ledger_entry.clone())
+        .manage(stores.daily_root.clone())
+        .manage(stores.audit)
+        .manage(stores.grant)
+        .manage(stores.api_key)
+        .manage(ReplayCache::default())
+        .manage(stores.session)
+        .manage(token_keys)
+        .manage(stores.password_reset)
+        .manage(password_policy)
+        .manage(trusted_proxies)
+        .manage(notifier)
+        .attach(RequestIdHeader)
+        .attach(ApiKeyBody)
+        .attach(Idempotency::new(stores.idempotency, idempotency_ttl))
+        .attach(OutboxRelay::new(
+            stores.outbox.clone(),
+            publisher,
+            Duration::from_millis(relay_interval),
+            relay_batch,
+        ))
+        .attach(WebhookDispatcher::new(
+            stores.webhook,
+            stores.webhook_delivery,
+            webhook_policy,
+            Duration::from_millis(webhook_interval),
+            relay_batch,
+        ))
+        .attach(DepositExpirer::new(
+            stores.transaction,
+            stores.fiat,
+            stores.outbox,
+            Duration::from_millis(expiry_interval),
+        ))
+        .attach(RootSealer::new(
+            stores.ledger_entry,
+            stores.daily_root,
+            root_signer,
+            Duration::from_millis(seal_interval),
+        ))
         .mount(
             "/v1", unique_v1_api
         )
+        .mount("/", routes![jwks])
         .mount(
             "/swagger-ui/",
             make_swagger_ui(&SwaggerUIConfig {
@@ -148,7 +341,7 @@ async fn rocket() -> _ {
         )
         .register(
             "/",
-            catchers![unauthorized, not_found, internal_sever_error, bad_format],
+            catchers![unauthorized, forbidden, not_found, internal_sever_error, bad_format],
         )
 }
 
@@ -161,6 +354,15 @@ pub fn unauthorized() -> Json<ErrorResponse> {
     })
 }
 
+#[catch(403)]
+pub fn forbidden() -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        cause: "FORBIDDEN".to_string(),
+        message: "Your role does not have the permission this endpoint needs".to_string(),
+        date: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    })
+}
+
 #[catch(404)]
 pub fn not_found(_req: &Request) -> Json<ErrorResponse> {
     println!("{}", _req);
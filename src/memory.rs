@@ -0,0 +1,574 @@
+use std::{
+    any::Any,
+    cmp::Ordering,
+    marker::PhantomData,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use async_trait::async_trait;
+use mongodb::bson::{self, Bson, Document};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::mongo::{
+    is_closed, Crud, LedgerOp, LedgerOperations, Session, StoreError, Transactional, Versioned, VersionedCrud,
+};
+
+/// Process-local stand-in for a MongoDB collection, for local development and tests.
+/// Entities are kept as bson documents in insertion order so filters, sorting and
+/// dotted field paths like `asset.symbol` behave as they do against MongoDB.
+#[derive(Clone)]
+pub struct MemoryRepository<T> {
+    key_field: String,
+    documents: Arc<RwLock<Vec<Document>>>,
+    entity: PhantomData<fn() -> T>,
+}
+impl<T> MemoryRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    pub fn new(key_field: String) -> MemoryRepository<T> {
+        MemoryRepository {
+            key_field,
+            documents: Arc::new(RwLock::new(Vec::new())),
+            entity: PhantomData,
+        }
+    }
+    fn read(&self) -> RwLockReadGuard<'_, Vec<Document>> {
+        self.documents.read().unwrap_or_else(|e| e.into_inner())
+    }
+    fn write(&self) -> RwLockWriteGuard<'_, Vec<Document>> {
+        self.documents.write().unwrap_or_else(|e| e.into_inner())
+    }
+    fn to_document(entity: &T) -> Result<Document, String> {
+        bson::to_document(entity).map_err(|e| format!("Error serializing entity: {}", e))
+    }
+    fn from_document(document: Document) -> Result<T, String> {
+        bson::from_document(document).map_err(|e| format!("Error deserializing entity: {}", e))
+    }
+    fn position(&self, documents: &[Document], id: &str) -> Option<usize> {
+        documents
+            .iter()
+            .position(|document| lookup(document, &self.key_field) == Some(&Bson::String(id.to_string())))
+    }
+    fn insert(&self, documents: &mut Vec<Document>, entity: &T) -> Result<String, String> {
+        let document = Self::to_document(entity)?;
+        let id = match lookup(&document, &self.key_field) {
+            Some(Bson::String(id)) => id.clone(),
+            _ => return Err(format!("Error creating entity: missing {}", self.key_field)),
+        };
+        // mirrors the unique index every collection has on its key field
+        if self.position(documents, &id).is_some() {
+            return Err(format!("Error creating entity: duplicate key {}", id));
+        }
+        documents.push(document);
+        Ok(id)
+    }
+    /// Lets `session` take `change` back if it aborts.
+    fn remember(&self, session: &mut MemorySession, id: &str, change: Change) {
+        session.undo.push(Undo {
+            documents: self.documents.clone(),
+            key_field: self.key_field.clone(),
+            id: id.to_string(),
+            change,
+        });
+    }
+}
+
+#[async_trait]
+impl<T> Crud<T> for MemoryRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    async fn create_many(&self, entities: Vec<T>) -> Result<Vec<String>, String> {
+        let mut documents = self.write();
+        let mut ids = Vec::new();
+        for entity in entities.iter() {
+            ids.push(self.insert(&mut documents, entity)?);
+        }
+        Ok(ids)
+    }
+    async fn create(&self, new_entity: T) -> Result<String, String> {
+        let mut documents = self.write();
+        self.insert(&mut documents, &new_entity)
+    }
+    async fn get_all(&self, skip: usize, limit: usize) -> Result<Vec<T>, String> {
+        let documents = self.read();
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        documents
+            .iter()
+            .skip(skip)
+            .take(limit)
+            .map(|document| Self::from_document(document.clone()))
+            .collect()
+    }
+    async fn get_by_id(&self, id: &str) -> Result<T, String> {
+        let documents = self.read();
+        match self.position(&documents, id) {
+            Some(index) => Self::from_document(documents[index].clone()),
+            None => Err("Entity not found".to_uppercase()),
+        }
+    }
+    async fn update_by_id(&self, id: &str, edit_entity: T) -> Result<T, String> {
+        let edit_doc = Self::to_document(&edit_entity)?;
+        let mut documents = self.write();
+        let index = match self.position(&documents, id) {
+            Some(index) => index,
+            None => return Err("Error updating entity".to_uppercase()),
+        };
+        for (field, value) in edit_doc {
+            documents[index].insert(field, value);
+        }
+        Self::from_document(documents[index].clone())
+    }
+    async fn delete_by_id(&self, id: &str) -> Result<bool, String> {
+        let mut documents = self.write();
+        match self.position(&documents, id) {
+            Some(index) => {
+                documents.remove(index);
+                Ok(true)
+            }
+            None => Err("Error deleting entity".to_uppercase()),
+        }
+    }
+    async fn get_by_fields(&self, field: Vec<String>, value: Vec<String>) -> Result<Vec<T>, String> {
+        let documents = self.read();
+        documents
+            .iter()
+            .filter(|document| {
+                field.iter().zip(value.iter()).all(|(field, value)| {
+                    lookup(document, field) == Some(&Bson::String(value.clone()))
+                })
+            })
+            .map(|document| Self::from_document(document.clone()))
+            .collect()
+    }
+    async fn find(&self, filter: Document, sort: Document, limit: usize) -> Result<Vec<T>, String> {
+        let mut found: Vec<Document> = self
+            .read()
+            .iter()
+            .filter(|document| matches(document, &filter))
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| compare_sorted(a, b, &sort));
+        if limit > 0 {
+            found.truncate(limit);
+        }
+        found.into_iter().map(Self::from_document).collect()
+    }
+    async fn count(&self) -> u64 {
+        self.read().len() as u64
+    }
+    async fn create_in(&self, session: &mut dyn Session, new_entity: T) -> Result<String, String> {
+        let session = memory_session(session)?;
+        let mut documents = self.write();
+        let id = self.insert(&mut documents, &new_entity)?;
+        self.remember(session, &id, Change::Created);
+        Ok(id)
+    }
+    async fn get_by_id_in(&self, session: &mut dyn Session, id: &str) -> Result<T, String> {
+        memory_session(session)?;
+        self.get_by_id(id).await
+    }
+}
+
+/// Writes are applied at once and taken back one by one on `abort`: ledger movements are
+/// reversed and other writes only restore the fields still holding what the session wrote, so
+/// what other requests wrote meanwhile stays, on the same documents too. There is no isolation
+/// between sessions.
+#[derive(Default)]
+pub struct MemorySession {
+    undo: Vec<Undo>,
+}
+
+struct Undo {
+    documents: Arc<RwLock<Vec<Document>>>,
+    key_field: String,
+    id: String,
+    change: Change,
+}
+
+/// One write of a session, as much as it takes to reverse it.
+enum Change {
+    Created,
+    Replaced { before: Document, after: Document },
+    /// Amounts a ledger operation added, leaving the ledger at `version`.
+    Added { balance: f64, hold: f64, version: i64 },
+}
+
+#[async_trait]
+impl Session for MemorySession {
+    async fn commit(&mut self) -> Result<(), String> {
+        self.undo.clear();
+        Ok(())
+    }
+    async fn abort(&mut self) -> Result<(), String> {
+        while let Some(undo) = self.undo.pop() {
+            let mut documents = undo.documents.write().unwrap_or_else(|e| e.into_inner());
+            let index = documents
+                .iter()
+                .position(|document| lookup(document, &undo.key_field) == Some(&Bson::String(undo.id.clone())));
+            let index = match index {
+                Some(index) => index,
+                None => continue,
+            };
+            match undo.change {
+                Change::Created => {
+                    documents.remove(index);
+                }
+                Change::Replaced { before, after } => {
+                    let document = &mut documents[index];
+                    for (field, written) in after {
+                        if document.get(&field) != Some(&written) {
+                            continue;
+                        }
+                        match before.get(&field) {
+                            Some(value) => document.insert(field, value.clone()),
+                            None => document.remove(&field),
+                        };
+                    }
+                }
+                Change::Added { balance, hold, version } => {
+                    let document = &mut documents[index];
+                    document.insert("balance", number_field(document, "balance") - balance);
+                    document.insert("hold", number_field(document, "hold") - hold);
+                    // back to the version before unless others wrote since, they may have read this one
+                    let current = number_field(document, "version") as i64;
+                    document.insert("version", if current == version { version - 1 } else { current + 1 });
+                }
+            }
+        }
+        Ok(())
+    }
+    fn as_any(&mut self) -> &mut (dyn Any + Send) {
+        self
+    }
+}
+
+fn memory_session(session: &mut dyn Session) -> Result<&mut MemorySession, String> {
+    match session.as_any().downcast_mut::<MemorySession>() {
+        Some(session) => Ok(session),
+        None => Err("Session belongs to another storage backend".to_string()),
+    }
+}
+
+#[async_trait]
+impl<T> Transactional<T> for MemoryRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    async fn get_session(&self) -> Result<Box<dyn Session>, String> {
+        Ok(Box::new(MemorySession::default()))
+    }
+}
+
+#[async_trait]
+impl<T> VersionedCrud<T> for MemoryRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + Versioned + 'static,
+{
+    async fn update_versioned(&self, id: &str, entity: T) -> Result<T, StoreError> {
+        let mut documents = self.write();
+        self.update_versioned_locked(&mut documents, id, entity)
+    }
+    async fn update_versioned_in(&self, session: &mut dyn Session, id: &str, entity: T) -> Result<T, StoreError> {
+        let session = memory_session(session).map_err(StoreError::Database)?;
+        let mut documents = self.write();
+        let before = match self.position(&documents, id) {
+            Some(index) => documents[index].clone(),
+            None => return Err(StoreError::NotFound(id.to_string())),
+        };
+        let updated = self.update_versioned_locked(&mut documents, id, entity)?;
+        let after = Self::to_document(&updated).map_err(StoreError::Database)?;
+        self.remember(session, id, Change::Replaced { before, after });
+        Ok(updated)
+    }
+}
+impl<T> MemoryRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + Versioned + 'static,
+{
+    fn update_versioned_locked(&self, documents: &mut [Document], id: &str, mut entity: T) -> Result<T, StoreError> {
+        let expected = entity.version();
+        let index = match self.position(documents, id) {
+            Some(index) => index,
+            None => return Err(StoreError::NotFound(id.to_string())),
+        };
+        let stored = number_field(&documents[index], "version");
+        if stored != expected as f64 {
+            return Err(StoreError::Conflict { id: id.to_string(), expected });
+        }
+        entity.set_version(expected + 1);
+        let edit_doc = Self::to_document(&entity).map_err(StoreError::Database)?;
+        for (field, value) in edit_doc {
+            documents[index].insert(field, value);
+        }
+        Ok(entity)
+    }
+}
+
+#[async_trait]
+impl<T> LedgerOperations<T> for MemoryRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    async fn apply(&self, id: &str, op: LedgerOp) -> Result<T, StoreError> {
+        let mut documents = self.write();
+        self.apply_locked(&mut documents, id, op)
+    }
+    async fn apply_in(&self, session: &mut dyn Session, id: &str, op: LedgerOp) -> Result<T, StoreError> {
+        let session = memory_session(session).map_err(StoreError::Database)?;
+        let mut documents = self.write();
+        let (balance, hold) = op.deltas();
+        let ledger = self.apply_locked(&mut documents, id, op)?;
+        let version = self.position(&documents, id).map_or(0, |index| number_field(&documents[index], "version") as i64);
+        self.remember(session, id, Change::Added { balance, hold, version });
+        Ok(ledger)
+    }
+}
+impl<T> MemoryRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    fn apply_locked(&self, documents: &mut [Document], id: &str, op: LedgerOp) -> Result<T, StoreError> {
+        let amount = op.amount();
+        if amount < 0.0 {
+            return Err(StoreError::Rejected("Amount must be positive".to_string()));
+        }
+        let index = match self.position(documents, id) {
+            Some(index) => index,
+            None => return Err(StoreError::NotFound(id.to_string())),
+        };
+        let document = &mut documents[index];
+        if is_closed(document) {
+            return Err(op.missed(id, Some(true)));
+        }
+        if let Some(guard) = op.guard() {
+            if number_field(document, guard) < amount {
+                return Err(op.missed(id, Some(false)));
+            }
+        }
+        let (balance, hold) = op.deltas();
+        let balance = number_field(document, "balance") + balance;
+        let hold = number_field(document, "hold") + hold;
+        let version = number_field(document, "version") as i64 + 1;
+        document.insert("balance", balance);
+        document.insert("hold", hold);
+        document.insert("version", version);
+        Self::from_document(document.clone()).map_err(StoreError::Database)
+    }
+}
+
+/// Resolves a dotted path such as `asset.symbol` inside a document.
+pub(crate) fn lookup<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut parts = path.split('.');
+    let mut value = document.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_document()?.get(part)?;
+    }
+    Some(value)
+}
+
+/// Evaluates the subset of the MongoDB query language the repositories are called with.
+pub(crate) fn matches(document: &Document, filter: &Document) -> bool {
+    filter.iter().all(|(key, condition)| match key.as_str() {
+        "$and" => sub_filters(condition).iter().all(|filter| matches(document, filter)),
+        "$or" => sub_filters(condition).iter().any(|filter| matches(document, filter)),
+        path => matches_condition(lookup(document, path), condition),
+    })
+}
+
+fn sub_filters(condition: &Bson) -> Vec<&Document> {
+    match condition.as_array() {
+        Some(filters) => filters.iter().filter_map(|filter| filter.as_document()).collect(),
+        None => Vec::new(),
+    }
+}
+
+fn matches_condition(value: Option<&Bson>, condition: &Bson) -> bool {
+    let operators = match condition {
+        Bson::Document(operators) if operators.keys().all(|key| key.starts_with('$')) => operators,
+        expected => return equals(value, expected),
+    };
+    operators.iter().all(|(operator, operand)| {
+        let ordering = value.and_then(|value| compare(value, operand));
+        match operator.as_str() {
+            "$eq" => equals(value, operand),
+            "$ne" => !equals(value, operand),
+            "$gt" => ordering == Some(Ordering::Greater),
+            "$gte" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            "$lt" => ordering == Some(Ordering::Less),
+            "$lte" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            "$in" => match operand.as_array() {
+                Some(candidates) => candidates.iter().any(|candidate| equals(value, candidate)),
+                None => false,
+            },
+            "$exists" => operand.as_bool().unwrap_or(true) == value.is_some(),
+            "$regex" => match (value, operand) {
+                (Some(Bson::String(text)), Bson::String(pattern)) => {
+                    let ignore_case = operators.get_str("$options").unwrap_or("").contains('i');
+                    contains_literal(text, pattern, ignore_case)
+                }
+                _ => false,
+            },
+            "$options" => true,
+            _ => false,
+        }
+    })
+}
+
+/// Missing fields equal null, like in MongoDB.
+fn equals(value: Option<&Bson>, expected: &Bson) -> bool {
+    match value {
+        Some(value) => compare(value, expected) == Some(Ordering::Equal),
+        None => *expected == Bson::Null,
+    }
+}
+
+fn number_field(document: &Document, path: &str) -> f64 {
+    lookup(document, path).and_then(number).unwrap_or(0.0)
+}
+
+pub(crate) fn number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(value) => Some(*value as f64),
+        Bson::Int64(value) => Some(*value as f64),
+        Bson::Double(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (number(a), number(b)) {
+        return a.partial_cmp(&b);
+    }
+    match (a, b) {
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+        (a, b) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+pub(crate) fn compare_sorted(a: &Document, b: &Document, sort: &Document) -> Ordering {
+    for (field, direction) in sort {
+        let ordering = match (lookup(a, field), lookup(b, field)) {
+            (Some(a), Some(b)) => compare(a, b).unwrap_or(Ordering::Equal),
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        let ordering = if number(direction).unwrap_or(1.0) < 0.0 {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Only literal patterns are supported, which is what `escape_regex` produces for memo searches.
+fn contains_literal(text: &str, pattern: &str, ignore_case: bool) -> bool {
+    let mut literal = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => literal.extend(chars.next()),
+            _ => literal.push(c),
+        }
+    }
+    if ignore_case {
+        text.to_lowercase().contains(&literal.to_lowercase())
+    } else {
+        text.contains(&literal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::*;
+    use crate::{
+        domain::{audit::AuditEntry, ledger::Fiat},
+        mongo::{finish, ledger_suite, AppendOnly},
+    };
+
+    /// Two funded USD ledgers, `acc1_USD` and `acc2_USD`.
+    async fn ledgers() -> MemoryRepository<Fiat> {
+        let repo = MemoryRepository::new("id".to_string());
+        ledger_suite::fund(&repo).await;
+        repo
+    }
+
+    #[rocket::async_test]
+    async fn aborted_session_keeps_what_others_wrote_meanwhile() {
+        let ledgers = ledgers().await;
+        let mut session = ledgers.get_session().await.unwrap();
+        ledgers.apply_in(&mut *session, "acc1_USD", LedgerOp::Withdraw(40.0)).await.unwrap();
+        // another request, outside the session, on another ledger
+        ledgers.apply("acc2_USD", LedgerOp::Deposit(25.0)).await.unwrap();
+        let result: Result<(), StoreError> = Err(StoreError::Rejected("Rolled back".to_string()));
+        assert!(finish(session, result).await.is_err());
+        assert_eq!(ledgers.get_by_id("acc1_USD").await.unwrap().hold, 0.0);
+        let other = ledgers.get_by_id("acc2_USD").await.unwrap();
+        assert_eq!((other.balance, other.hold), (100.0, 25.0));
+        let found = ledgers.find(doc! {"hold": {"$gt": 0.0}}, doc! {}, 0).await.unwrap();
+        assert_eq!(found.len(), 1);
+    }
+
+    #[rocket::async_test]
+    async fn aborted_session_keeps_what_others_wrote_to_the_same_documents() {
+        let ledgers = ledgers().await;
+        let mut session = ledgers.get_session().await.unwrap();
+        ledgers.apply_in(&mut *session, "acc1_USD", LedgerOp::Withdraw(40.0)).await.unwrap();
+        let mut renamed = ledgers.get_by_id("acc2_USD").await.unwrap();
+        renamed.account_number = "renamed".to_string();
+        ledgers.update_versioned_in(&mut *session, "acc2_USD", renamed).await.unwrap();
+        // another transfer, outside the session, into both ledgers
+        ledgers.apply("acc1_USD", LedgerOp::Deposit(25.0)).await.unwrap();
+        ledgers.apply("acc2_USD", LedgerOp::Deposit(25.0)).await.unwrap();
+        let result: Result<(), StoreError> = Err(StoreError::Rejected("Rolled back".to_string()));
+        assert!(finish(session, result).await.is_err());
+        let ledger = ledgers.get_by_id("acc1_USD").await.unwrap();
+        assert_eq!((ledger.balance, ledger.hold), (100.0, 25.0));
+        // the version moves on, someone may have read the one the session wrote
+        assert_eq!(ledger.version, 3);
+        let other = ledgers.get_by_id("acc2_USD").await.unwrap();
+        assert_eq!((other.account_number.as_str(), other.hold, other.version), ("acc2", 25.0, 2));
+    }
+
+    #[rocket::async_test]
+    async fn aborted_session_undoes_every_write() {
+        ledger_suite::aborted_session_undoes_every_write(&ledgers().await).await;
+    }
+
+    #[rocket::async_test]
+    async fn committed_session_keeps_every_write() {
+        ledger_suite::committed_session_keeps_every_write(&ledgers().await).await;
+    }
+
+    #[rocket::async_test]
+    async fn closed_ledgers_take_no_operation() {
+        ledger_suite::closed_ledgers_take_no_operation(&ledgers().await).await;
+    }
+
+    #[rocket::async_test]
+    async fn stale_versions_conflict() {
+        ledger_suite::stale_versions_conflict(&ledgers().await).await;
+    }
+
+    #[rocket::async_test]
+    async fn append_only_stores_refuse_rewrites() {
+        let log = AppendOnly(Arc::new(MemoryRepository::<AuditEntry>::new("id".to_string())));
+        let entry = AuditEntry::new("auth.login", "user", "request".to_string(), None);
+        log.create(entry.clone()).await.unwrap();
+        let mut rewritten = entry.clone();
+        rewritten.action = "auth.logout".to_string();
+        assert!(log.update_by_id(&entry.id, rewritten).await.is_err());
+        assert!(log.delete_by_id(&entry.id).await.is_err());
+        assert_eq!(log.get_by_id(&entry.id).await.unwrap(), entry);
+    }
+}
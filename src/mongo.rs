@@ -1,12 +1,15 @@
 use async_std::stream::StreamExt;
 use async_trait::async_trait;
 use mongodb::{
-    bson::{self, doc},
-    options::{ClientOptions, FindOptions},
-    Client, Collection, Database, ClientSession,
+    bson::{self, doc, Document},
+    options::{ClientOptions, FindOneAndUpdateOptions, FindOptions, IndexOptions, ReturnDocument},
+    Client, Collection, Database, ClientSession, IndexModel,
 };
 use serde::{de::DeserializeOwned, Serialize};
-use std::borrow::Borrow;
+use std::{any::Any, borrow::Borrow, fmt::Display, sync::Arc};
+
+/// Number of times `modify` reloads an entity after losing a version race.
+pub const MAX_UPDATE_RETRIES: usize = 5;
 pub struct Data {
     pub client: Client,
     pub db: Database,
@@ -39,28 +42,274 @@ impl Data {
         })
     }
 }
+#[derive(Clone)]
 pub struct Repository<T> 
 where T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static{
     key_field: String,
     collection: Collection<T>,
     client: Client,
 }
+impl<T> Repository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    pub async fn create_index(&self, keys: Document, unique: bool) -> Result<String, String> {
+        let options = IndexOptions::builder().unique(unique).build();
+        let index = IndexModel::builder().keys(keys).options(options).build();
+        match self.collection.create_index(index, None).await {
+            Ok(result) => Ok(result.index_name),
+            Err(e) => Err(format!("Error creating index: {}", e)),
+        }
+    }
+    /// Unique index named `name` over the documents `filter` matches only, such as those where
+    /// an optional field is set, so the many without it do not collide.
+    pub async fn create_partial_unique_index(&self, name: &str, keys: Document, filter: Document) -> Result<String, String> {
+        let options = IndexOptions::builder()
+            .name(name.to_string())
+            .unique(true)
+            .partial_filter_expression(filter)
+            .build();
+        let index = IndexModel::builder().keys(keys).options(options).build();
+        match self.collection.create_index(index, None).await {
+            Ok(result) => Ok(result.index_name),
+            Err(e) => Err(format!("Error creating index: {}", e)),
+        }
+    }
+}
+/// Unit of work of a request. Writes made with the `*_in` methods of any store of the same
+/// backend become visible together on `commit` and are undone together on `abort`.
+#[async_trait]
+pub trait Session: Send {
+    async fn commit(&mut self) -> Result<(), String>;
+    async fn abort(&mut self) -> Result<(), String>;
+    /// The backend's own handle, for its repositories to write through.
+    fn as_any(&mut self) -> &mut (dyn Any + Send);
+}
+#[async_trait]
+impl Session for ClientSession {
+    async fn commit(&mut self) -> Result<(), String> {
+        self.commit_transaction().await.map_err(|e| e.to_string())
+    }
+    async fn abort(&mut self) -> Result<(), String> {
+        self.abort_transaction().await.map_err(|e| e.to_string())
+    }
+    fn as_any(&mut self) -> &mut (dyn Any + Send) {
+        self
+    }
+}
 #[async_trait]
 pub trait Transactional<T>: Send + Sync {
-    async fn get_session(&self)->Result<ClientSession,String>;
+    /// Returns a session with a transaction already started.
+    async fn get_session(&self)->Result<Box<dyn Session>,String>;
+}
+
+/// Commits `session` when `result` is Ok and aborts it otherwise, handing `result` back.
+pub async fn finish<R>(mut session: Box<dyn Session>, result: Result<R, StoreError>) -> Result<R, StoreError> {
+    match result {
+        Ok(value) => match session.commit().await {
+            Ok(_) => Ok(value),
+            Err(e) => Err(StoreError::Database(format!("Error committing: {}", e))),
+        },
+        Err(e) => {
+            if let Err(abort) = session.abort().await {
+                println!("Error aborting session: {}", abort);
+            }
+            Err(e)
+        }
+    }
+}
+
+fn client_session(session: &mut dyn Session) -> Result<&mut ClientSession, String> {
+    match session.as_any().downcast_mut::<ClientSession>() {
+        Some(session) => Ok(session),
+        None => Err("Session belongs to another storage backend".to_string()),
+    }
+}
+
+/// Whether a stored ledger has been closed; ledgers written before closing existed have no flag.
+pub(crate) fn is_closed(ledger: &Document) -> bool {
+    ledger.get_bool("closed").unwrap_or(false)
 }
 #[async_trait]
 impl<T> Transactional<T> for Repository<T>
 where
     T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
 {
-    async fn get_session(&self)->Result<ClientSession,String>{
-       match self.client.start_session(None).await{
-              Ok(session) => Ok(session),
+    async fn get_session(&self)->Result<Box<dyn Session>,String>{
+       let mut session = match self.client.start_session(None).await{
+              Ok(session) => session,
               Err(e) => return Err(format!("Error creating session: {}", e))
+       };
+       match session.start_transaction(None).await {
+              Ok(_) => Ok(Box::new(session)),
+              Err(e) => Err(e.to_string())
        }
     }
 }
+pub trait Versioned {
+    fn version(&self) -> u64;
+    fn set_version(&mut self, version: u64);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreError {
+    NotFound(String),
+    Conflict { id: String, expected: u64 },
+    Rejected(String),
+    InsufficientFunds(String),
+    Database(String),
+}
+impl Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::NotFound(id) => write!(f, "Entity {} not found", id),
+            StoreError::Conflict { id, expected } => write!(
+                f,
+                "Entity {} was modified concurrently, version {} is stale",
+                id, expected
+            ),
+            StoreError::Rejected(e) => write!(f, "{}", e),
+            StoreError::InsufficientFunds(e) => write!(f, "{}", e),
+            StoreError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl From<String> for StoreError {
+    fn from(e: String) -> Self {
+        StoreError::Rejected(e)
+    }
+}
+impl From<StoreError> for String {
+    fn from(e: StoreError) -> Self {
+        e.to_string()
+    }
+}
+
+#[async_trait]
+pub trait VersionedCrud<T: Versioned>: Crud<T> {
+    /// Writes `entity` only if the stored copy still has `entity.version()`, and bumps the version.
+    async fn update_versioned(&self, id: &str, entity: T) -> Result<T, StoreError>;
+    /// `update_versioned` as part of `session`.
+    async fn update_versioned_in(&self, session: &mut dyn Session, id: &str, entity: T) -> Result<T, StoreError>;
+}
+
+/// Loads the entity, applies `change` and writes it back, starting over from a fresh copy
+/// when another writer got there first. `change` must be safe to run more than once.
+pub async fn modify<T, R, F>(repo: &R, id: &str, change: F) -> Result<T, StoreError>
+where
+    T: Versioned + Send,
+    R: VersionedCrud<T> + ?Sized,
+    F: Fn(&mut T) -> Result<(), String>,
+{
+    let mut attempt = 0;
+    loop {
+        let mut entity = repo.get_by_id(id).await.map_err(StoreError::Database)?;
+        change(&mut entity)?;
+        match repo.update_versioned(id, entity).await {
+            Err(StoreError::Conflict { .. }) if attempt < MAX_UPDATE_RETRIES => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+/// `modify` as part of `session`, reading what the session already wrote.
+pub async fn modify_in<T, R, F>(repo: &R, session: &mut dyn Session, id: &str, change: F) -> Result<T, StoreError>
+where
+    T: Versioned + Send,
+    R: VersionedCrud<T> + ?Sized,
+    F: Fn(&mut T) -> Result<(), String>,
+{
+    let mut attempt = 0;
+    loop {
+        let mut entity = repo.get_by_id_in(session, id).await.map_err(StoreError::Database)?;
+        change(&mut entity)?;
+        match repo.update_versioned_in(session, id, entity).await {
+            Err(StoreError::Conflict { .. }) if attempt < MAX_UPDATE_RETRIES => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+/// Balance movements applied by the database itself, mirroring `FungibleTradeable`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LedgerOp {
+    Deposit(f64),
+    Withdraw(f64),
+    ConfirmDeposit(f64),
+    ConfirmWithdraw(f64),
+    CancelDeposit(f64),
+    CancelWithdraw(f64),
+}
+impl LedgerOp {
+    pub fn amount(&self) -> f64 {
+        match self {
+            LedgerOp::Deposit(amount)
+            | LedgerOp::Withdraw(amount)
+            | LedgerOp::ConfirmDeposit(amount)
+            | LedgerOp::ConfirmWithdraw(amount)
+            | LedgerOp::CancelDeposit(amount)
+            | LedgerOp::CancelWithdraw(amount) => *amount,
+        }
+    }
+    /// Field that must hold at least `amount` for the operation to apply.
+    pub fn guard(&self) -> Option<&'static str> {
+        match self {
+            LedgerOp::Deposit(_) => None,
+            LedgerOp::Withdraw(_) => Some("balance"),
+            _ => Some("hold"),
+        }
+    }
+    /// Changes to (balance, hold).
+    pub fn deltas(&self) -> (f64, f64) {
+        let amount = self.amount();
+        match self {
+            LedgerOp::Deposit(_) => (0.0, amount),
+            LedgerOp::Withdraw(_) => (-amount, amount),
+            LedgerOp::ConfirmDeposit(_) => (amount, -amount),
+            LedgerOp::ConfirmWithdraw(_) => (0.0, -amount),
+            LedgerOp::CancelDeposit(_) => (0.0, -amount),
+            LedgerOp::CancelWithdraw(_) => (amount, -amount),
+        }
+    }
+    pub fn insufficient(&self) -> String {
+        match self {
+            LedgerOp::Withdraw(_) | LedgerOp::CancelWithdraw(_) => "Insufficient balance".to_string(),
+            _ => "Insufficient funds in hold".to_string(),
+        }
+    }
+    /// Why the operation matched no ledger at `id`, given whether it is closed, None when
+    /// there is no such ledger.
+    pub fn missed(&self, id: &str, closed: Option<bool>) -> StoreError {
+        match closed {
+            None => StoreError::NotFound(id.to_string()),
+            Some(true) => StoreError::Rejected(format!("Ledger {} is closed", id)),
+            Some(false) => StoreError::InsufficientFunds(self.insufficient()),
+        }
+    }
+}
+
+#[async_trait]
+pub trait LedgerOperations<T>: Send + Sync {
+    /// Applies `op` in a single conditional update and returns the ledger as it is afterwards.
+    /// Closed ledgers are rejected by the same update, so nothing moves once one is closed.
+    async fn apply(&self, id: &str, op: LedgerOp) -> Result<T, StoreError>;
+    /// `apply` as part of `session`.
+    async fn apply_in(&self, session: &mut dyn Session, id: &str, op: LedgerOp) -> Result<T, StoreError>;
+}
+
+/// Storage needed by versioned entities: optimistic updates and sessions.
+pub trait VersionedStore<T: Versioned>: VersionedCrud<T> + Transactional<T> {}
+impl<T: Versioned, S: VersionedCrud<T> + Transactional<T>> VersionedStore<T> for S {}
+
+/// Storage needed by ledgers: versioned entities that also take atomic balance movements.
+pub trait LedgerStore<T: Versioned>: VersionedStore<T> + LedgerOperations<T> {}
+impl<T: Versioned, S: VersionedStore<T> + LedgerOperations<T>> LedgerStore<T> for S {}
+
+/// Handles managed by Rocket, backed by MongoDB or by memory depending on `STORAGE`.
+pub type Db<T> = Arc<dyn Crud<T>>;
+pub type VersionedDb<T> = Arc<dyn VersionedStore<T>>;
+pub type LedgerDb<T> = Arc<dyn LedgerStore<T>>;
+
 #[async_trait]
 pub trait Crud<T>: Send + Sync {
     async fn create_many(&self, new_entities: Vec<T>) -> Result<Vec<String>, String>;
@@ -70,7 +319,53 @@ pub trait Crud<T>: Send + Sync {
     async fn update_by_id(&self, id: &str, edit_entity: T) -> Result<T, String>;
     async fn delete_by_id(&self, id: &str) -> Result<bool, String>;
     async fn get_by_fields(&self, field: Vec<String>, value: Vec<String>) -> Result<Vec<T>, String>;
+    async fn find(&self, filter: Document, sort: Document, limit: usize) -> Result<Vec<T>, String>;
     async fn count(&self)->u64;
+    /// `create` as part of `session`.
+    async fn create_in(&self, session: &mut dyn Session, new_entity: T) -> Result<String, String>;
+    /// `get_by_id` as part of `session`, seeing what it wrote.
+    async fn get_by_id_in(&self, session: &mut dyn Session, id: &str) -> Result<T, String>;
+}
+
+/// Store that only takes inserts, whatever backs it: updates and deletes are refused, so a
+/// record such as the audit log cannot be rewritten through the service.
+pub struct AppendOnly<T>(pub Db<T>);
+
+#[async_trait]
+impl<T: Send + Sync + 'static> Crud<T> for AppendOnly<T> {
+    async fn create_many(&self, new_entities: Vec<T>) -> Result<Vec<String>, String> {
+        self.0.create_many(new_entities).await
+    }
+    async fn create(&self, new_entity: T) -> Result<String, String> {
+        self.0.create(new_entity).await
+    }
+    async fn get_all(&self, skip: usize, limit: usize) -> Result<Vec<T>, String> {
+        self.0.get_all(skip, limit).await
+    }
+    async fn get_by_id(&self, id: &str) -> Result<T, String> {
+        self.0.get_by_id(id).await
+    }
+    async fn update_by_id(&self, id: &str, _edit_entity: T) -> Result<T, String> {
+        Err(format!("Entity {} is append only and cannot be updated", id))
+    }
+    async fn delete_by_id(&self, id: &str) -> Result<bool, String> {
+        Err(format!("Entity {} is append only and cannot be deleted", id))
+    }
+    async fn get_by_fields(&self, field: Vec<String>, value: Vec<String>) -> Result<Vec<T>, String> {
+        self.0.get_by_fields(field, value).await
+    }
+    async fn find(&self, filter: Document, sort: Document, limit: usize) -> Result<Vec<T>, String> {
+        self.0.find(filter, sort, limit).await
+    }
+    async fn count(&self) -> u64 {
+        self.0.count().await
+    }
+    async fn create_in(&self, session: &mut dyn Session, new_entity: T) -> Result<String, String> {
+        self.0.create_in(session, new_entity).await
+    }
+    async fn get_by_id_in(&self, session: &mut dyn Session, id: &str) -> Result<T, String> {
+        self.0.get_by_id_in(session, id).await
+    }
 }
 
 #[async_trait]
@@ -195,10 +490,243 @@ where
         };
         Ok(entities)
     }
+    async fn find(&self, filter: Document, sort: Document, limit: usize) -> Result<Vec<T>, String> {
+        let find_options = FindOptions::builder()
+        .sort(sort)
+        .limit(limit as i64)
+        .build();
+
+        let mut cursors = match self.collection.find(filter, find_options).await {
+            Ok(cursors) => cursors,
+            Err(e) => return Err(format!("Error finding entities: {}", e)),
+        };
+        let mut entities: Vec<T> = Vec::new();
+        while let Some(entity) = cursors.next().await {
+            let entity = match entity {
+                Ok(entity) => entity,
+                Err(e) => return Err(format!("Error finding entities: {}", e)),
+            };
+            entities.push(entity);
+        };
+        Ok(entities)
+    }
     async fn count(&self)->u64{
         match self.collection.count_documents(None, None).await {
             Ok(count) => count,
             _ => return 0,
         }
     }
+    async fn create_in(&self, session: &mut dyn Session, new_entity: T) -> Result<String, String> {
+        let session = client_session(session)?;
+        match self.collection.insert_one_with_session(new_entity.borrow(), None, session).await {
+            Ok(entity) => Ok(entity.inserted_id.to_string()),
+            Err(e) => Err(format!("Error creating entity: {}", e)),
+        }
+    }
+    async fn get_by_id_in(&self, session: &mut dyn Session, id: &str) -> Result<T, String> {
+        let session = client_session(session)?;
+        match self.collection.find_one_with_session(doc! {&self.key_field: id}, None, session).await {
+            Ok(Some(entity)) => Ok(entity),
+            Ok(None) => Err("Entity not found".to_uppercase()),
+            Err(e) => Err(format!("Error getting entity: {}", e)),
+        }
+    }
+}
+#[async_trait]
+impl<T> VersionedCrud<T> for Repository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + Versioned + 'static,
+{
+    async fn update_versioned(&self, id: &str, mut entity: T) -> Result<T, StoreError> {
+        let expected = entity.version();
+        let (filter, update) = self.versioned_update(id, &mut entity)?;
+        let updated = self
+            .collection
+            .update_one(filter, update, None)
+            .await
+            .map_err(|e| StoreError::Database(format!("Error updating entity: {}", e)))?;
+        if updated.matched_count > 0 {
+            return Ok(entity);
+        }
+        match self.collection.count_documents(doc! {&self.key_field: id}, None).await {
+            Ok(0) => Err(StoreError::NotFound(id.to_string())),
+            Ok(_) => Err(StoreError::Conflict { id: id.to_string(), expected }),
+            Err(e) => Err(StoreError::Database(format!("Error updating entity: {}", e))),
+        }
+    }
+    async fn update_versioned_in(&self, session: &mut dyn Session, id: &str, mut entity: T) -> Result<T, StoreError> {
+        let expected = entity.version();
+        let (filter, update) = self.versioned_update(id, &mut entity)?;
+        let session = client_session(session).map_err(StoreError::Database)?;
+        let updated = self
+            .collection
+            .update_one_with_session(filter, update, None, session)
+            .await
+            .map_err(|e| StoreError::Database(format!("Error updating entity: {}", e)))?;
+        if updated.matched_count > 0 {
+            return Ok(entity);
+        }
+        match self.collection.count_documents_with_session(doc! {&self.key_field: id}, None, session).await {
+            Ok(0) => Err(StoreError::NotFound(id.to_string())),
+            Ok(_) => Err(StoreError::Conflict { id: id.to_string(), expected }),
+            Err(e) => Err(StoreError::Database(format!("Error updating entity: {}", e))),
+        }
+    }
+}
+impl<T> Repository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + Versioned + 'static,
+{
+    /// Filter on the expected version and the update writing `entity` with the next one.
+    fn versioned_update(&self, id: &str, entity: &mut T) -> Result<(Document, Document), StoreError> {
+        let expected = entity.version();
+        // documents written before versioning have no version field and count as version 0
+        let filter = if expected == 0 {
+            doc! {&self.key_field: id, "$or": [{"version": 0_i64}, {"version": {"$exists": false}}]}
+        } else {
+            doc! {&self.key_field: id, "version": expected as i64}
+        };
+        entity.set_version(expected + 1);
+        let edit_doc = bson::to_document(&*entity)
+            .map_err(|e| StoreError::Database(format!("Error serializing entity: {}", e)))?;
+        Ok((filter, doc! {"$set": edit_doc}))
+    }
+}
+#[async_trait]
+impl<T> LedgerOperations<T> for Repository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    async fn apply(&self, id: &str, op: LedgerOp) -> Result<T, StoreError> {
+        let (filter, update) = self.ledger_update(id, &op)?;
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        match self.collection.find_one_and_update(filter, update, options).await {
+            Ok(Some(entity)) => Ok(entity),
+            Ok(None) => match self.raw().find_one(doc! {&self.key_field: id}, None).await {
+                Ok(ledger) => Err(op.missed(id, ledger.map(|ledger| is_closed(&ledger)))),
+                Err(e) => Err(StoreError::Database(format!("Error updating ledger: {}", e))),
+            },
+            Err(e) => Err(StoreError::Database(format!("Error updating ledger: {}", e))),
+        }
+    }
+    async fn apply_in(&self, session: &mut dyn Session, id: &str, op: LedgerOp) -> Result<T, StoreError> {
+        let (filter, update) = self.ledger_update(id, &op)?;
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let session = client_session(session).map_err(StoreError::Database)?;
+        match self.collection.find_one_and_update_with_session(filter, update, options, session).await {
+            Ok(Some(entity)) => Ok(entity),
+            Ok(None) => match self.raw().find_one_with_session(doc! {&self.key_field: id}, None, session).await {
+                Ok(ledger) => Err(op.missed(id, ledger.map(|ledger| is_closed(&ledger)))),
+                Err(e) => Err(StoreError::Database(format!("Error updating ledger: {}", e))),
+            },
+            Err(e) => Err(StoreError::Database(format!("Error updating ledger: {}", e))),
+        }
+    }
+}
+impl<T> Repository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    /// The collection read as plain documents, for fields `T` does not expose.
+    fn raw(&self) -> Collection<Document> {
+        self.collection.clone_with_type()
+    }
+    /// Filter matching the ledger only while it is open and `op` is covered, and the update moving it.
+    fn ledger_update(&self, id: &str, op: &LedgerOp) -> Result<(Document, Document), StoreError> {
+        let amount = op.amount();
+        if amount < 0.0 {
+            return Err(StoreError::Rejected("Amount must be positive".to_string()));
+        }
+        let mut filter = doc! {&self.key_field: id, "closed": {"$ne": true}};
+        if let Some(field) = op.guard() {
+            filter.insert(field, doc! {"$gte": amount});
+        }
+        let (balance, hold) = op.deltas();
+        // the version moves too so versioned writers holding an older copy conflict
+        Ok((filter, doc! {"$inc": {"balance": balance, "hold": hold, "version": 1_i64}}))
+    }
+}
+
+/// Behaviour every `LedgerStore` backend owes the API, run by the tests of each backend.
+#[cfg(test)]
+pub mod ledger_suite {
+    use super::*;
+    use crate::domain::{
+        asset::{Asset, AssetType},
+        ledger::{Accounting, Fiat},
+    };
+
+    /// Funds `acc1_USD` and `acc2_USD` with 100 each.
+    pub async fn fund(ledgers: &dyn LedgerStore<Fiat>) {
+        for account_number in ["acc1", "acc2"] {
+            let asset = Asset {
+                name: "United State Dollar".to_string(),
+                symbol: "USD".to_string(),
+                asset_type: AssetType::Fiat,
+            };
+            let mut ledger = Fiat::new(account_number.to_string(), asset).unwrap();
+            ledger.balance = 100.0;
+            ledgers.create(ledger).await.unwrap();
+        }
+    }
+
+    /// Expects `acc3` to be an account that has no ledger yet.
+    pub async fn aborted_session_undoes_every_write(ledgers: &dyn LedgerStore<Fiat>) {
+        let mut session = ledgers.get_session().await.unwrap();
+        ledgers.apply_in(&mut *session, "acc1_USD", LedgerOp::Withdraw(40.0)).await.unwrap();
+        ledgers.apply_in(&mut *session, "acc2_USD", LedgerOp::Deposit(40.0)).await.unwrap();
+        let mut opened = ledgers.get_by_id("acc1_USD").await.unwrap();
+        opened.id = "acc3_USD".to_string();
+        opened.account_number = "acc3".to_string();
+        ledgers.create_in(&mut *session, opened).await.unwrap();
+        let failed = ledgers.apply_in(&mut *session, "acc1_USD", LedgerOp::Withdraw(100.0)).await;
+        assert!(matches!(failed, Err(StoreError::InsufficientFunds(_))));
+        assert!(finish(session, failed).await.is_err());
+        let ledger = ledgers.get_by_id("acc1_USD").await.unwrap();
+        assert_eq!((ledger.balance, ledger.hold, ledger.version), (100.0, 0.0, 0));
+        assert_eq!(ledgers.get_by_id("acc2_USD").await.unwrap().hold, 0.0);
+        assert!(ledgers.get_by_id("acc3_USD").await.is_err());
+    }
+
+    pub async fn committed_session_keeps_every_write(ledgers: &dyn LedgerStore<Fiat>) {
+        let mut session = ledgers.get_session().await.unwrap();
+        ledgers.apply_in(&mut *session, "acc1_USD", LedgerOp::Withdraw(40.0)).await.unwrap();
+        let ledger = ledgers.get_by_id_in(&mut *session, "acc1_USD").await.unwrap();
+        let result = ledgers.update_versioned_in(&mut *session, "acc1_USD", ledger).await;
+        assert!(finish(session, result).await.is_ok());
+        let ledger = ledgers.get_by_id("acc1_USD").await.unwrap();
+        assert_eq!((ledger.balance, ledger.hold, ledger.version), (60.0, 40.0, 2));
+    }
+
+    pub async fn closed_ledgers_take_no_operation(ledgers: &dyn LedgerStore<Fiat>) {
+        let mut ledger = ledgers.get_by_id("acc1_USD").await.unwrap();
+        ledger.balance = 0.0;
+        ledger.close().unwrap();
+        ledgers.update_versioned("acc1_USD", ledger).await.unwrap();
+        let moved = ledgers.apply("acc1_USD", LedgerOp::Deposit(10.0)).await;
+        assert!(matches!(moved, Err(StoreError::Rejected(_))));
+        let ledger = ledgers.get_by_id("acc1_USD").await.unwrap();
+        assert!(ledger.closed);
+        assert_eq!(ledger.hold, 0.0);
+        assert!(matches!(ledgers.apply("acc9_USD", LedgerOp::Deposit(10.0)).await, Err(StoreError::NotFound(_))));
+    }
+
+    pub async fn stale_versions_conflict(ledgers: &dyn LedgerStore<Fiat>) {
+        let ledger = ledgers.get_by_id("acc1_USD").await.unwrap();
+        ledgers.update_versioned("acc1_USD", ledger.clone()).await.unwrap();
+        let stale = ledgers.update_versioned("acc1_USD", ledger).await;
+        assert!(matches!(stale, Err(StoreError::Conflict { expected: 0, .. })));
+        let mut session = ledgers.get_session().await.unwrap();
+        let stale = ledgers.get_by_id("acc2_USD").await.unwrap();
+        ledgers.update_versioned("acc2_USD", stale.clone()).await.unwrap();
+        let result = ledgers.update_versioned_in(&mut *session, "acc2_USD", stale.clone()).await;
+        assert!(matches!(result, Err(StoreError::Conflict { .. })));
+        assert!(finish(session, result).await.is_err());
+        assert_eq!(ledgers.get_by_id("acc2_USD").await.unwrap().version, 1);
+        assert!(matches!(ledgers.update_versioned("acc9_USD", stale).await, Err(StoreError::NotFound(_))));
+    }
 }
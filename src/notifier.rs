@@ -0,0 +1,72 @@
+use std::{env, sync::Arc};
+
+use async_trait::async_trait;
+use rocket::tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex};
+use serde::Serialize;
+
+/// A message for a user, e.g. a password reset token.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, notification: &Notification) -> Result<(), String>;
+}
+
+/// Picks the notifier named by `NOTIFIER`: file (default) or log. Both are for local runs,
+/// delivering by email or SMS is another implementation of `Notifier`.
+pub fn from_env() -> Result<Arc<dyn Notifier>, String> {
+    let notifier = env::var("NOTIFIER").unwrap_or("file".to_string());
+    match notifier.as_str() {
+        "file" => {
+            let path = env::var("NOTIFICATION_FILE").unwrap_or("notifications.jsonl".to_string());
+            Ok(Arc::new(FileNotifier::new(path)))
+        }
+        "log" => Ok(Arc::new(LogNotifier)),
+        other => Err(format!("Unknown NOTIFIER {}", other)),
+    }
+}
+
+/// Appends notifications to a json lines file.
+pub struct FileNotifier {
+    path: String,
+    lock: Mutex<()>,
+}
+impl FileNotifier {
+    pub fn new(path: String) -> FileNotifier {
+        FileNotifier {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn send(&self, notification: &Notification) -> Result<(), String> {
+        let mut line = match serde_json::to_string(notification) {
+            Ok(line) => line,
+            Err(e) => return Err(format!("Error serializing notification: {}", e)),
+        };
+        line.push('\n');
+        let _guard = self.lock.lock().await;
+        let mut file = match OpenOptions::new().create(true).append(true).open(&self.path).await {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Error opening {}: {}", self.path, e)),
+        };
+        file.write_all(line.as_bytes()).await.map_err(|e| format!("Error writing {}: {}", self.path, e))
+    }
+}
+
+/// Prints notifications to stdout.
+pub struct LogNotifier;
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn send(&self, notification: &Notification) -> Result<(), String> {
+        println!("Notification to {}: {}\n{}", notification.to, notification.subject, notification.body);
+        Ok(())
+    }
+}
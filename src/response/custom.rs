@@ -1,10 +1,88 @@
-use revolt_rocket_okapi::JsonSchema;
+use std::io::Cursor;
+
+use revolt_rocket_okapi::{
+    gen::OpenApiGenerator,
+    okapi::openapi3::{RefOr, Response as OpenApiResponse, Responses},
+    response::OpenApiResponderInner,
+    JsonSchema,
+};
+use rocket::{
+    http::ContentType,
+    response::{self, Responder},
+    serde::json::Json,
+    Request, Response,
+};
 use serde::Serialize;
 
+use crate::mongo::Versioned;
+
 #[derive(Debug, Serialize, JsonSchema)]
 pub struct Pagination<T>{
     pub skip: u64,
     pub limit: u64,
     pub count: u64,
     pub result: Vec<T>
-}
\ No newline at end of file
+}
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CursorPagination<T>{
+    pub limit: u64,
+    pub next_cursor: Option<String>,
+    pub result: Vec<T>
+}
+
+pub struct Download {
+    pub content_type: ContentType,
+    pub filename: String,
+    pub body: String,
+}
+impl<'r> Responder<'r, 'static> for Download {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .header(self.content_type)
+            .raw_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", self.filename),
+            )
+            .sized_body(self.body.len(), Cursor::new(self.body))
+            .ok()
+    }
+}
+impl OpenApiResponderInner for Download {
+    fn responses(_gen: &mut OpenApiGenerator) -> revolt_rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        responses.responses.insert(
+            "200".to_string(),
+            RefOr::Object(OpenApiResponse {
+                description: "File download".to_string(),
+                ..Default::default()
+            }),
+        );
+        Ok(responses)
+    }
+}
+
+/// Json body sent with an `ETag` carrying the entity version, to be echoed back in `If-Match`.
+pub struct ETagged<T> {
+    pub version: u64,
+    pub body: Json<T>,
+}
+impl<T: Versioned> ETagged<T> {
+    pub fn new(entity: T) -> ETagged<T> {
+        ETagged {
+            version: entity.version(),
+            body: Json(entity),
+        }
+    }
+}
+impl<'r, T: Serialize> Responder<'r, 'static> for ETagged<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(self.body.respond_to(request)?)
+            .raw_header("ETag", format!("\"{}\"", self.version))
+            .ok()
+    }
+}
+impl<T: Serialize + JsonSchema> OpenApiResponderInner for ETagged<T> {
+    fn responses(gen: &mut OpenApiGenerator) -> revolt_rocket_okapi::Result<Responses> {
+        Json::<T>::responses(gen)
+    }
+}
@@ -1,7 +1,10 @@
 use chrono::{Utc};
 use revolt_rocket_okapi::JsonSchema;
+use rocket::{http::Status, serde::json::Json};
 use serde::Serialize;
 
+use crate::mongo::StoreError;
+
 #[derive(Debug, Serialize, JsonSchema)]
 pub struct ErrorResponse {
     pub(crate) cause: String,
@@ -16,4 +19,12 @@ impl ErrorResponse {
             date: Utc::now().to_rfc3339(),
         }
     }
-}
\ No newline at end of file
+    /// Maps a storage error to its response, version conflicts become 409 so clients know to re-read.
+    pub fn from_store(cause: &str, e: StoreError) -> (Status, Json<ErrorResponse>) {
+        let status = match e {
+            StoreError::Conflict { .. } => Status::Conflict,
+            _ => Status::BadRequest,
+        };
+        (status, Json(ErrorResponse::new(cause.to_string(), e.to_string())))
+    }
+}
@@ -0,0 +1,58 @@
+use rocket::{http::Status, serde::json::Json};
+use serde::Serialize;
+
+use crate::{
+    domain::audit::{AuditEntry, AuditOutcome},
+    fairings::{auth::AuthorizedUser, request_context::RequestContext},
+    mongo::Crud,
+    response::{custom::ETagged, error::ErrorResponse},
+};
+
+/// Entry for `action` on `resource` in the current request, by `actor` when authenticated.
+pub fn entry(context: &RequestContext, actor: Option<&AuthorizedUser>, action: &str, resource: &str) -> AuditEntry {
+    let entry = AuditEntry::new(action, resource, context.request_id.clone(), context.ip.clone());
+    match actor {
+        Some(actor) => entry.by(actor.user_id.clone(), actor.role.to_string()),
+        None => entry,
+    }
+}
+
+/// Appends to the audit log. A failed write is reported but never fails the request,
+/// the operation it describes has already happened.
+pub async fn log(audit_db: &dyn Crud<AuditEntry>, entry: AuditEntry) {
+    if let Err(e) = audit_db.create(entry).await {
+        println!("Error writing audit log: {}", e);
+    }
+}
+
+pub async fn denied(audit_db: &dyn Crud<AuditEntry>, entry: AuditEntry) {
+    log(audit_db, entry.outcome(AuditOutcome::Denied, None)).await
+}
+
+/// Logs how a handler ended and hands its result back unchanged.
+pub async fn outcome<T>(
+    audit_db: &dyn Crud<AuditEntry>,
+    entry: AuditEntry,
+    result: Result<T, (Status, Json<ErrorResponse>)>,
+) -> Result<T, (Status, Json<ErrorResponse>)> {
+    let entry = match &result {
+        Ok(_) => entry,
+        Err((_, error)) => entry.outcome(AuditOutcome::Failure, Some(error.message.clone())),
+    };
+    log(audit_db, entry).await;
+    result
+}
+
+/// Like `outcome`, recording what changed between `before` and the returned entity.
+pub async fn changed<T: Serialize>(
+    audit_db: &dyn Crud<AuditEntry>,
+    entry: AuditEntry,
+    before: &T,
+    result: Result<ETagged<T>, (Status, Json<ErrorResponse>)>,
+) -> Result<ETagged<T>, (Status, Json<ErrorResponse>)> {
+    let entry = match &result {
+        Ok(tagged) => entry.changes(before, &tagged.body.0),
+        Err(_) => entry,
+    };
+    outcome(audit_db, entry, result).await
+}
@@ -1,46 +1,28 @@
 use bcrypt::{hash, verify};
-use chrono::Utc;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::TokenData;
 use lazy_static::lazy_static;
 use rocket::{http::Status, serde::json::Json};
 use serde::{Deserialize, Serialize};
 use std::env;
+use uuid::Uuid;
 
 use crate::{
     domain::{
+        session::UserSession,
         user::{Role, User},
     },
     dto::user::{RefreshToken, Token},
     response::error::ErrorResponse,
+    security::keys::{deadline_from_env, KeyRing},
 };
 
-//encode prepare data
-pub async fn encode_token(
+pub fn check_password(
     user: &User,
-    resources: Vec<String>,
     password: String,
-) -> Result<Json<Token>, (Status, Json<ErrorResponse>)> {
+) -> Result<(), (Status, Json<ErrorResponse>)> {
     match verify(password, &user.password) {
-        Ok(true) => {
-            match encode_token_and_refresh(
-                user.id.clone(),
-                user.role.clone(),
-                resources.join(","),
-                get_jwt_secret().await,
-                get_jwt_refresh().await,
-                get_jwt_refresh_expiration().await,
-                get_jwt_expiration().await,
-            ) {
-                Ok(tokens) => Ok(Json(tokens)),
-                Err(_) => Err((
-                    Status::InternalServerError,
-                    Json(ErrorResponse::new(
-                        "Error encoding token".to_string(),
-                        "Error encoding token".to_string(),
-                    )),
-                )),
-            }
-        }
+        Ok(true) => Ok(()),
         Ok(false) => Err((
             Status::BadRequest,
             Json(ErrorResponse::new(
@@ -74,15 +56,16 @@ pub fn check_data_from_auth_header(auth_header: Option<&str>) -> Result<Vec<&str
     };
 }
 //encode prepare data
-pub async fn encode_token_by_refresh(
+pub async fn encode_session_tokens(
     user: &User,
-    resources: Vec<String>,
+    session: &UserSession,
+    access_keys: &KeyRing,
 ) -> Result<Json<Token>, (Status, Json<ErrorResponse>)> {
     match encode_token_and_refresh(
         user.id.clone(),
         user.role.clone(),
-        resources.join(","),
-        get_jwt_secret().await,
+        session,
+        access_keys,
         get_jwt_refresh().await,
         get_jwt_refresh_expiration().await,
         get_jwt_expiration().await,
@@ -98,16 +81,13 @@ pub async fn encode_token_by_refresh(
     }
 }
 
-//decode jwt from body and return id
-pub async fn decode_jwt_return_id(refresh_token: Json<RefreshToken>) -> Result<String, ()> {
+//decode refresh token from body and return its claims
+pub async fn decode_refresh_token(refresh_token: Json<RefreshToken>) -> Result<Claims, ()> {
     match decode_jwt(
         refresh_token.refresh_token.to_string(),
         get_jwt_refresh().await,
     ) {
-        DecodeJwtHelper::Ok(token_data) => {
-            let id_str = token_data.claims.user_id;
-            Ok(id_str)
-        }
+        DecodeJwtHelper::Ok(token_data) => Ok(token_data.claims),
         DecodeJwtHelper::Err => Err(()),
     }
 }
@@ -115,25 +95,27 @@ pub async fn decode_jwt_return_id(refresh_token: Json<RefreshToken>) -> Result<S
 pub fn encode_token_and_refresh(
     id: String,
     role: Role,
-    resource: String,
-    jwt_secret: &str,
-    refresh_token_secret: &str,
+    session: &UserSession,
+    access_keys: &KeyRing,
+    refresh_keys: &KeyRing,
     expiration_refresh_token: &i64,
     expiration_token: &i64,
 ) -> Result<Token, ()> {
     match encode_jwt(
         id.clone(),
         role.clone(),
-        resource.clone(),
-        jwt_secret,
+        &session.id,
+        &Uuid::new_v4().to_string(),
+        access_keys,
         expiration_token,
     ) {
         EncodeJwtHelper::Ok(token) => {
             match encode_jwt(
                 id,
                 role,
-                resource,
-                refresh_token_secret,
+                &session.id,
+                &session.refresh_token_id,
+                refresh_keys,
                 expiration_refresh_token,
             ) {
                 EncodeJwtHelper::Ok(refresh_token) => Ok(Token {
@@ -146,14 +128,10 @@ pub fn encode_token_and_refresh(
         EncodeJwtHelper::Err => Err(()),
     }
 }
-pub fn decode_jwt(token: String, secret: &str) -> DecodeJwtHelper {
-    let token = decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(secret.as_ref()),
-        &Validation::default(),
-    );
-    match token {
-        Ok(token_string) => DecodeJwtHelper::Ok(Box::new(token_string)),
+/// Verifies `token` with the key of `keys` its `kid` header names.
+pub fn decode_jwt(token: String, keys: &KeyRing) -> DecodeJwtHelper {
+    match keys.decode::<Claims>(&token) {
+        Ok(token_data) => DecodeJwtHelper::Ok(Box::new(token_data)),
         Err(_) => DecodeJwtHelper::Err,
     }
 }
@@ -177,15 +155,23 @@ pub enum DecodeJwtHelper {
 pub struct Claims {
     pub user_id: String,
     pub role: String,
-    pub resource: String,
+    /// Permissions of the role when the token was issued, comma separated.
+    #[serde(default)]
+    pub permissions: String,
+    /// Session the token belongs to, empty for tokens issued before sessions were stored.
+    #[serde(default)]
+    pub sid: String,
+    #[serde(default)]
+    pub jti: String,
     pub exp: usize,
 }
 
 pub fn encode_jwt(
     id: String,
     role: Role,
-    resource: String,
-    secret: &str,
+    session_id: &str,
+    token_id: &str,
+    keys: &KeyRing,
     expiration: &i64,
 ) -> EncodeJwtHelper {
     let expiration = Utc::now()
@@ -193,26 +179,24 @@ pub fn encode_jwt(
         .expect("valid timestamp")
         .timestamp();
 
+    let permissions: Vec<String> = role.permissions().iter().map(|permission| permission.to_string()).collect();
     let my_claims = Claims {
         user_id: id,
         role: role.to_string(),
-        resource,
+        permissions: permissions.join(","),
+        sid: session_id.to_string(),
+        jti: token_id.to_string(),
         exp: expiration as usize,
     };
-    match encode(
-        &Header::default(),
-        &my_claims,
-        &EncodingKey::from_secret(secret.as_ref()),
-    ) {
+    match keys.encode(&my_claims) {
         Ok(token) => EncodeJwtHelper::Ok(token),
         Err(_) => EncodeJwtHelper::Err,
     }
 }
 lazy_static! {
-    static ref JWT_SECRET: String =
-        env::var("JWT_SECRET").expect("Error loading env variable: JWT_SECRET");
-    static ref JWT_REFRESH: String =
-        env::var("JWT_REFRESH").expect("Error loading env variable: JWT_REFRESH");
+    /// Refresh tokens only come back to this service, they stay HS256 with a secret of its own.
+    static ref JWT_REFRESH: KeyRing =
+        KeyRing::shared(&env::var("JWT_REFRESH").expect("Error loading env variable: JWT_REFRESH"));
     static ref JWT_EXPIRES_IN: i64 =
         env::var("JWT_EXPIRES_IN")
             .expect("Error loading env variable: JWT_REFRESH")
@@ -223,12 +207,20 @@ lazy_static! {
             .expect("Error loading env variable: JWT_REFRESH_EXPIRES_IN")
             .parse()
             .expect("Error parsing env variable: JWT_REFRESH_EXPIRES_IN");
+    /// Refreshing extends a session up to this long after its login, 90 days by default.
+    static ref SESSION_MAX_LIFETIME: i64 = match env::var("SESSION_MAX_LIFETIME") {
+        Ok(v) => v.parse().expect("Error parsing env variable: SESSION_MAX_LIFETIME"),
+        Err(_) => 7776000,
+    };
+    /// Access tokens without a session were issued by a release that stored none, so they all
+    /// expire by the date set when deploying this one. None once they did: they are refused.
+    pub static ref SESSIONLESS_UNTIL: Option<DateTime<Utc>> = match deadline_from_env("SESSIONLESS_TOKENS_UNTIL") {
+        Ok(until) => until,
+        Err(e) => panic!("Error loading env variable: {}", e),
+    };
 }
 
-pub async fn get_jwt_secret() -> &'static str {
-    &JWT_SECRET
-}
-pub async fn get_jwt_refresh() -> &'static str {
+pub async fn get_jwt_refresh() -> &'static KeyRing {
     &JWT_REFRESH
 }
 pub async fn get_jwt_expiration() -> &'static i64 {
@@ -238,3 +230,12 @@ pub async fn get_jwt_expiration() -> &'static i64 {
 pub async fn get_jwt_refresh_expiration() -> &'static i64 {
     &JWT_REFRESH_EXPIRES_IN
 }
+
+pub async fn get_session_max_lifetime() -> &'static i64 {
+    &SESSION_MAX_LIFETIME
+}
+
+/// Whether a token without a session still falls within the tokens issued before sessions.
+pub fn predates_sessions(claims: &Claims) -> bool {
+    SESSIONLESS_UNTIL.map_or(false, |until| claims.exp as i64 <= until.timestamp())
+}
@@ -0,0 +1,372 @@
+use std::{env, fs, path::Path};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+        OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+};
+use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::DecodePrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// One entry of the `JWT_KEYS_FILE` manifest. `private_key` is a PEM file, relative to the manifest.
+#[derive(Debug, Deserialize)]
+struct KeyConfig {
+    kid: String,
+    alg: Algorithm,
+    private_key: String,
+    /// When the key starts signing, right away when missing.
+    sign_from: Option<String>,
+    /// When tokens signed with it stop being accepted, never when missing.
+    verify_until: Option<String>,
+}
+
+struct TokenKey {
+    /// None only for the shared secret, which tokens name with no `kid`.
+    kid: Option<String>,
+    algorithm: Algorithm,
+    /// None for keys kept to verify tokens signed before a switch.
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    /// Public part published in the JWKS, None for shared secrets.
+    jwk: Option<Jwk>,
+    sign_from: DateTime<Utc>,
+    verify_until: Option<DateTime<Utc>>,
+}
+impl TokenKey {
+    fn verifies(&self, now: DateTime<Utc>) -> bool {
+        self.verify_until.map_or(true, |verify_until| verify_until > now)
+    }
+    fn signs(&self, now: DateTime<Utc>) -> bool {
+        self.sign_from <= now && self.verifies(now)
+    }
+}
+
+/// Keys access tokens are signed and verified with. Rotation is scheduled in the manifest: the
+/// newest key past its `sign_from` signs, every key before its `verify_until` verifies, so a
+/// new key is published ahead of use and the old one stays valid until its tokens expire.
+pub struct KeyRing {
+    keys: Vec<TokenKey>,
+}
+impl KeyRing {
+    /// Asymmetric keys from the `JWT_KEYS_FILE` manifest, or HS256 with `JWT_SECRET` without one.
+    /// With both, the secret only verifies tokens issued before the switch, until
+    /// `JWT_SECRET_VERIFY_UNTIL`.
+    pub fn from_env() -> Result<KeyRing, String> {
+        let secret = env::var("JWT_SECRET").ok();
+        match env::var("JWT_KEYS_FILE") {
+            Ok(path) => {
+                let mut ring = KeyRing::from_file(Path::new(&path))?;
+                if let Some(secret) = secret {
+                    match deadline_from_env("JWT_SECRET_VERIFY_UNTIL")? {
+                        Some(verify_until) => ring.retire_shared(&secret, verify_until),
+                        None => return Err("JWT_SECRET_VERIFY_UNTIL is needed to retire JWT_SECRET".to_string()),
+                    }
+                }
+                Ok(ring)
+            }
+            Err(_) => match secret {
+                Some(secret) => Ok(KeyRing::shared(&secret)),
+                None => Err("Set JWT_KEYS_FILE or JWT_SECRET".to_string()),
+            },
+        }
+    }
+    pub fn shared(secret: &str) -> KeyRing {
+        KeyRing {
+            keys: vec![shared_key(secret, true, None)],
+        }
+    }
+    /// Keeps verifying tokens signed with the shared secret until `verify_until`, never signing.
+    fn retire_shared(&mut self, secret: &str, verify_until: DateTime<Utc>) {
+        self.keys.push(shared_key(secret, false, Some(verify_until)));
+    }
+    pub fn from_file(path: &Path) -> Result<KeyRing, String> {
+        let manifest = match fs::read_to_string(path) {
+            Ok(manifest) => manifest,
+            Err(e) => return Err(format!("Error reading {}: {}", path.display(), e)),
+        };
+        let configs: Vec<KeyConfig> = match serde_json::from_str(&manifest) {
+            Ok(configs) => configs,
+            Err(e) => return Err(format!("Error parsing {}: {}", path.display(), e)),
+        };
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut keys: Vec<TokenKey> = Vec::new();
+        for config in configs {
+            if keys.iter().any(|key| key.kid.as_deref() == Some(config.kid.as_str())) {
+                return Err(format!("Key id {} is used twice", config.kid));
+            }
+            keys.push(load_key(dir, config)?);
+        }
+        if keys.is_empty() {
+            return Err(format!("{} has no keys", path.display()));
+        }
+        Ok(KeyRing { keys })
+    }
+    /// Signs with the current key, naming it in the `kid` header.
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, String> {
+        let now = Utc::now();
+        let (key, encoding) = match self
+            .keys
+            .iter()
+            .filter(|key| key.signs(now))
+            .filter_map(|key| key.encoding.as_ref().map(|encoding| (key, encoding)))
+            .max_by_key(|(key, _)| key.sign_from)
+        {
+            Some(current) => current,
+            None => return Err("No key signs tokens at this time".to_string()),
+        };
+        let mut header = Header::new(key.algorithm);
+        header.kid = key.kid.clone();
+        encode(&header, claims, encoding).map_err(|e| e.to_string())
+    }
+    /// Verifies with the key the token's `kid` names, as long as that key is still accepted.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<TokenData<T>, String> {
+        let header = decode_header(token).map_err(|e| e.to_string())?;
+        let now = Utc::now();
+        let key = match self
+            .keys
+            .iter()
+            .find(|key| key.kid == header.kid && key.algorithm == header.alg && key.verifies(now))
+        {
+            Some(key) => key,
+            None => return Err(format!("Unknown signing key {:?}", header.kid)),
+        };
+        decode::<T>(token, &key.decoding, &Validation::new(key.algorithm)).map_err(|e| e.to_string())
+    }
+    /// Public keys still accepted, including the ones scheduled to sign next.
+    pub fn jwks(&self) -> JwkSet {
+        let now = Utc::now();
+        JwkSet {
+            keys: self.keys.iter().filter(|key| key.verifies(now)).filter_map(|key| key.jwk.clone()).collect(),
+        }
+    }
+}
+
+fn shared_key(secret: &str, signs: bool, verify_until: Option<DateTime<Utc>>) -> TokenKey {
+    TokenKey {
+        kid: None,
+        algorithm: Algorithm::HS256,
+        encoding: match signs {
+            true => Some(EncodingKey::from_secret(secret.as_ref())),
+            false => None,
+        },
+        decoding: DecodingKey::from_secret(secret.as_ref()),
+        jwk: None,
+        sign_from: DateTime::<Utc>::MIN_UTC,
+        verify_until,
+    }
+}
+
+fn load_key(dir: &Path, config: KeyConfig) -> Result<TokenKey, String> {
+    let pem = match fs::read_to_string(dir.join(&config.private_key)) {
+        Ok(pem) => pem,
+        Err(e) => return Err(format!("Error reading key {}: {}", config.kid, e)),
+    };
+    let invalid = |e: String| format!("Invalid key {}: {}", config.kid, e);
+    let (encoding, decoding, parameters) = match config.alg {
+        Algorithm::RS256 => {
+            let private = RsaPrivateKey::from_pkcs8_pem(&pem)
+                .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+                .map_err(|e| invalid(e.to_string()))?;
+            let n = URL_SAFE_NO_PAD.encode(private.n().to_bytes_be());
+            let e = URL_SAFE_NO_PAD.encode(private.e().to_bytes_be());
+            (
+                EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(|e| invalid(e.to_string()))?,
+                DecodingKey::from_rsa_components(&n, &e).map_err(|e| invalid(e.to_string()))?,
+                AlgorithmParameters::RSA(RSAKeyParameters { key_type: RSAKeyType::RSA, n, e }),
+            )
+        }
+        Algorithm::EdDSA => {
+            let private = <ed25519_dalek::SigningKey as ed25519_dalek::pkcs8::DecodePrivateKey>::from_pkcs8_pem(&pem)
+                .map_err(|e| invalid(e.to_string()))?;
+            let x = URL_SAFE_NO_PAD.encode(private.verifying_key().to_bytes());
+            (
+                EncodingKey::from_ed_pem(pem.as_bytes()).map_err(|e| invalid(e.to_string()))?,
+                DecodingKey::from_ed_components(&x).map_err(|e| invalid(e.to_string()))?,
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x,
+                }),
+            )
+        }
+        alg => return Err(format!("Key {} uses {:?}, only RS256 and EdDSA are supported", config.kid, alg)),
+    };
+    let sign_from = match parse_date(&config.kid, config.sign_from)? {
+        Some(sign_from) => sign_from,
+        None => DateTime::<Utc>::MIN_UTC,
+    };
+    let verify_until = parse_date(&config.kid, config.verify_until)?;
+    if verify_until.map_or(false, |verify_until| verify_until <= sign_from) {
+        return Err(format!("Key {} stops verifying before it signs", config.kid));
+    }
+    Ok(TokenKey {
+        kid: Some(config.kid.clone()),
+        algorithm: config.alg,
+        encoding: Some(encoding),
+        decoding,
+        jwk: Some(Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                algorithm: Some(config.alg),
+                key_id: Some(config.kid),
+                ..Default::default()
+            },
+            algorithm: parameters,
+        }),
+        sign_from,
+        verify_until,
+    })
+}
+
+fn parse_date(kid: &str, date: Option<String>) -> Result<Option<DateTime<Utc>>, String> {
+    match date {
+        Some(date) => match DateTime::parse_from_rfc3339(&date) {
+            Ok(date) => Ok(Some(date.with_timezone(&Utc))),
+            Err(_) => Err(format!("Key {} dates must be RFC 3339", kid)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// An RFC 3339 date from the env variable `name`. Fixed in config rather than counted from
+/// startup, so restarting never extends it; refused once passed so stale config stops startup.
+pub fn deadline_from_env(name: &str) -> Result<Option<DateTime<Utc>>, String> {
+    deadline(name, env::var(name).ok(), Utc::now())
+}
+
+fn deadline(name: &str, value: Option<String>, now: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, String> {
+    let deadline = match value {
+        Some(value) => match DateTime::parse_from_rfc3339(&value) {
+            Ok(deadline) => deadline.with_timezone(&Utc),
+            Err(_) => return Err(format!("{} must be an RFC 3339 date", name)),
+        },
+        None => return Ok(None),
+    };
+    if deadline <= now {
+        return Err(format!("{} has passed, remove it", name));
+    }
+    Ok(Some(deadline))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey, KeypairBytes};
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Claims {
+        sub: String,
+        exp: i64,
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            sub: "user".to_string(),
+            exp: (Utc::now() + Duration::minutes(5)).timestamp(),
+        }
+    }
+
+    /// Writes a manifest of EdDSA keys `(kid, sign_from, verify_until)` to a fresh directory.
+    fn ring(keys: Vec<(&str, Option<DateTime<Utc>>, Option<DateTime<Utc>>)>) -> Result<KeyRing, String> {
+        let dir = env::temp_dir().join(format!("keys-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut manifest = Vec::new();
+        for (index, (kid, sign_from, verify_until)) in keys.into_iter().enumerate() {
+            // PKCS#8 v1 without the public key, as `openssl genpkey` writes it
+            let bytes = KeypairBytes { secret_key: [index as u8 + 1; 32], public_key: None };
+            let pem = bytes.to_pkcs8_pem(LineEnding::LF).unwrap();
+            fs::write(dir.join(format!("{}.pem", kid)), pem.as_bytes()).unwrap();
+            manifest.push(serde_json::json!({
+                "kid": kid,
+                "alg": "EdDSA",
+                "private_key": format!("{}.pem", kid),
+                "sign_from": sign_from.map(|date| date.to_rfc3339()),
+                "verify_until": verify_until.map(|date| date.to_rfc3339()),
+            }));
+        }
+        let path = dir.join("jwt.json");
+        fs::write(&path, serde_json::to_string(&manifest).unwrap()).unwrap();
+        KeyRing::from_file(&path)
+    }
+
+    fn kid(token: &str) -> Option<String> {
+        decode_header(token).unwrap().kid
+    }
+
+    #[test]
+    fn shared_secret_signs_and_verifies() {
+        let ring = KeyRing::shared("secret");
+        let token = ring.encode(&claims()).unwrap();
+        assert_eq!(kid(&token), None);
+        assert_eq!(ring.decode::<Claims>(&token).unwrap().claims.sub, "user");
+        assert!(KeyRing::shared("other").decode::<Claims>(&token).is_err());
+        assert!(ring.jwks().keys.is_empty());
+    }
+
+    #[test]
+    fn retired_secret_only_verifies_until_its_deadline() {
+        let token = KeyRing::shared("secret").encode(&claims()).unwrap();
+        let mut ring = ring(vec![("a", None, None)]).unwrap();
+        ring.retire_shared("secret", Utc::now() + Duration::minutes(5));
+        assert!(ring.decode::<Claims>(&token).is_ok());
+        assert_eq!(kid(&ring.encode(&claims()).unwrap()), Some("a".to_string()));
+
+        let mut ring = self::ring(vec![("a", None, None)]).unwrap();
+        ring.retire_shared("secret", Utc::now() - Duration::seconds(1));
+        assert!(ring.decode::<Claims>(&token).is_err());
+    }
+
+    #[test]
+    fn deadlines_are_absolute_and_refused_once_passed() {
+        let now = Utc::now();
+        let until = (now + Duration::hours(1)).to_rfc3339();
+        assert_eq!(deadline("UNTIL", Some(until.clone()), now).unwrap().map(|until| until.timestamp()), Some((now + Duration::hours(1)).timestamp()));
+        assert_eq!(deadline("UNTIL", None, now), Ok(None));
+        assert!(deadline("UNTIL", Some(until), now + Duration::hours(2)).is_err());
+        assert!(deadline("UNTIL", Some("tomorrow".to_string()), now).is_err());
+    }
+
+    #[test]
+    fn newest_key_past_sign_from_signs() {
+        let now = Utc::now();
+        let ring = ring(vec![
+            ("a", None, Some(now + Duration::hours(1))),
+            ("b", Some(now - Duration::minutes(1)), None),
+            ("c", Some(now + Duration::hours(1)), None),
+        ])
+        .unwrap();
+        let token = ring.encode(&claims()).unwrap();
+        assert_eq!(kid(&token), Some("b".to_string()));
+        assert!(ring.decode::<Claims>(&token).is_ok());
+        // the scheduled key is published ahead of use
+        assert_eq!(ring.jwks().keys.len(), 3);
+    }
+
+    #[test]
+    fn rotated_out_keys_stop_verifying() {
+        let now = Utc::now();
+        let old = ring(vec![("a", None, None)]).unwrap();
+        let token = old.encode(&claims()).unwrap();
+        let ring = ring(vec![("a", Some(now - Duration::hours(2)), Some(now - Duration::hours(1))), ("b", None, None)]).unwrap();
+        assert!(ring.decode::<Claims>(&token).is_err());
+        assert_eq!(ring.jwks().keys.len(), 1);
+        let ring = self::ring(vec![("a", None, Some(now + Duration::hours(1))), ("b", Some(now - Duration::minutes(1)), None)]).unwrap();
+        assert!(ring.decode::<Claims>(&token).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_manifests() {
+        let now = Utc::now();
+        assert!(ring(vec![]).is_err());
+        assert!(ring(vec![("a", None, None), ("a", None, None)]).is_err());
+        assert!(ring(vec![("a", Some(now), Some(now - Duration::hours(1)))]).is_err());
+    }
+}
@@ -1,2 +1,7 @@
 pub mod jwt;
-pub mod permissions;
\ No newline at end of file
+pub mod permissions;
+pub mod signing;
+pub mod audit;
+pub mod ownership;
+pub mod keys;
+pub mod password;
\ No newline at end of file
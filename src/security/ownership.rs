@@ -0,0 +1,62 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use mongodb::bson::doc;
+
+use crate::{
+    domain::account::Account,
+    mongo::{VersionedDb, VersionedStore},
+};
+
+/// Resolves the accounts a user owns from the account store on every request, so accounts
+/// created or left after login count at once. Answers are kept for `ttl` to spare the store.
+pub struct OwnershipResolver {
+    account_db: VersionedDb<Account>,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Instant, Vec<String>)>>,
+}
+impl OwnershipResolver {
+    pub fn new(account_db: VersionedDb<Account>, ttl: Duration) -> OwnershipResolver {
+        OwnershipResolver {
+            account_db,
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Account numbers `user_id` owns or co-owns, none when they cannot be read.
+    pub async fn accounts_of(&self, user_id: &str) -> Vec<String> {
+        if let Some((at, accounts)) = self.cache.lock().unwrap().get(user_id) {
+            if at.elapsed() < self.ttl {
+                return accounts.clone();
+            }
+        }
+        let accounts: Vec<String> = match owned_accounts(self.account_db.as_ref(), user_id).await {
+            Ok(accounts) => accounts.into_iter().map(|account| account.account_number).collect(),
+            Err(e) => {
+                println!("Error resolving accounts of {}: {}", user_id, e);
+                return Vec::new();
+            }
+        };
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < self.ttl);
+        cache.insert(user_id.to_string(), (Instant::now(), accounts.clone()));
+        accounts
+    }
+    /// Drops what is known of the owners of `account`, call it after ownership changes.
+    pub fn forget(&self, account: &Account) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.remove(&account.user_owner_id);
+        for owner in account.owners.keys() {
+            cache.remove(owner);
+        }
+    }
+}
+
+pub async fn owned_accounts(account_db: &dyn VersionedStore<Account>, user_id: &str) -> Result<Vec<Account>, String> {
+    let owner = format!("owners.{}", user_id);
+    let filter = doc! {"$or": [{"user_owner_id": user_id}, {&owner: {"$exists": true}}]};
+    account_db.find(filter, doc! {"account_number": 1}, 0).await
+}
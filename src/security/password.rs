@@ -0,0 +1,100 @@
+use std::{collections::HashSet, env, fs};
+
+/// bcrypt only reads the first 72 bytes, longer passwords would silently match their prefix.
+const MAX_PASSWORD_BYTES: usize = 72;
+
+/// Rules new passwords must meet, and how they are hashed and reset.
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub hash_cost: u32,
+    /// How long a reset token stays usable, in seconds.
+    pub reset_ttl: i64,
+    /// Known breached passwords, lowercased.
+    breached: HashSet<String>,
+}
+impl PasswordPolicy {
+    /// Reads `PASSWORD_MIN_LENGTH` (12), `PASSWORD_HASH_COST` (12), `PASSWORD_RESET_TTL_SECONDS`
+    /// (1800) and `PASSWORD_BREACHED_FILE`, one password per line, checked when set.
+    pub fn from_env() -> Result<PasswordPolicy, String> {
+        let breached = match env::var("PASSWORD_BREACHED_FILE") {
+            Ok(path) => match fs::read_to_string(&path) {
+                Ok(list) => list
+                    .lines()
+                    .map(|line| line.trim().to_lowercase())
+                    .filter(|line| !line.is_empty())
+                    .collect(),
+                Err(e) => return Err(format!("Error reading {}: {}", path, e)),
+            },
+            Err(_) => HashSet::new(),
+        };
+        Ok(PasswordPolicy {
+            min_length: number("PASSWORD_MIN_LENGTH", 12)?,
+            hash_cost: number("PASSWORD_HASH_COST", bcrypt::DEFAULT_COST)?,
+            reset_ttl: number("PASSWORD_RESET_TTL_SECONDS", 1800)?,
+            breached,
+        })
+    }
+    pub fn check(&self, password: &str, email: &str) -> Result<(), String> {
+        if password.chars().count() < self.min_length {
+            return Err(format!("Password must have at least {} characters", self.min_length));
+        }
+        if password.len() > MAX_PASSWORD_BYTES {
+            return Err(format!("Password must fit in {} bytes", MAX_PASSWORD_BYTES));
+        }
+        if password.eq_ignore_ascii_case(email.trim()) {
+            return Err("Password must not be your email".to_string());
+        }
+        if self.breached.contains(&password.to_lowercase()) {
+            return Err("Password appears in a list of breached passwords".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn number<T: std::str::FromStr>(name: &str, default: T) -> Result<T, String> {
+    match env::var(name) {
+        Ok(value) => value.parse().map_err(|_| format!("Error parsing env variable: {}", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(breached: &[&str]) -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 12,
+            hash_cost: 4,
+            reset_ttl: 60,
+            breached: breached.iter().map(|password| password.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_a_long_enough_password() {
+        assert!(policy(&[]).check("correct horse battery", "ana@example.com").is_ok());
+    }
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        assert!(policy(&[]).check("ééééééééééé", "ana@example.com").is_err());
+        assert!(policy(&[]).check("éééééééééééé", "ana@example.com").is_ok());
+    }
+
+    #[test]
+    fn rejects_what_bcrypt_would_truncate() {
+        assert!(policy(&[]).check(&"a".repeat(MAX_PASSWORD_BYTES), "ana@example.com").is_ok());
+        assert!(policy(&[]).check(&"a".repeat(MAX_PASSWORD_BYTES + 1), "ana@example.com").is_err());
+    }
+
+    #[test]
+    fn rejects_the_email() {
+        assert!(policy(&[]).check("Ana@Example.com", " ana@example.com ").is_err());
+    }
+
+    #[test]
+    fn rejects_breached_passwords_in_any_case() {
+        assert!(policy(&["correct horse battery"]).check("Correct Horse Battery", "ana@example.com").is_err());
+    }
+}
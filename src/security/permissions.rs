@@ -1,7 +1,10 @@
-use crate::{fairings::auth::AuthorizedUser, domain::user::Role};
+use crate::{fairings::auth::AuthorizedUser, domain::{account::{Account, OwnerPermission}, grant::{AccessGrant, GrantScope}, user::Permission}};
 
 pub fn can_continue(auth:AuthorizedUser, resource: &str) -> bool {
-    if auth.role == Role::Admin {
+    if !auth.reaches(resource) {
+        return false;
+    }
+    if auth.has(Permission::AccountsOperate) {
         return true;
     }
     if auth.resource.contains(&resource.to_string()) {
@@ -23,9 +26,212 @@ pub fn can_continue(auth:AuthorizedUser, resource: &str) -> bool {
 //     false
 // }
 
-pub fn only_admin(auth:AuthorizedUser) -> bool {
-    if auth.role == Role::Admin {
+/// Owners and admins, staff allowed to read every account when `scope` only views, or a user
+/// holding an active grant with `scope` on the account. The grants on `auth` are only those
+/// whose grantor was still a Full owner when the request came in.
+pub fn can_access(auth:AuthorizedUser, resource: &str, scope: GrantScope) -> bool {
+    if !auth.reaches(resource) {
+        return false;
+    }
+    let views = matches!(scope, GrantScope::ViewBalances | GrantScope::ViewTransactions);
+    if views && auth.has(Permission::AccountsRead) {
         return true;
     }
-    false
-}
\ No newline at end of file
+    if auth.grants.iter().any(|grant| grant.covers(resource, &scope)) {
+        return true;
+    }
+    can_continue(auth, resource)
+}
+
+/// Staff approving transactions, owners of either side, or a grantee allowed to approve
+/// transfers out of `from`.
+pub fn can_confirm(auth: AuthorizedUser, from: &str, to: &str) -> bool {
+    if auth.has(Permission::TransactionsApprove) && (auth.reaches(from) || auth.reaches(to)) {
+        return true;
+    }
+    can_access(auth.clone(), from, GrantScope::ApproveTransfers) || can_continue(auth, to)
+}
+
+/// Checks the caller's permission on the stored account, the token only says it is an owner.
+pub fn can_operate(auth:AuthorizedUser, account: &Account, needed: OwnerPermission) -> bool {
+    if !auth.reaches(&account.account_number) {
+        return false;
+    }
+    if auth.has(Permission::AccountsOperate) {
+        return true;
+    }
+    account.allows(&auth.user_id, &needed)
+}
+
+
+/// The grant a transfer debiting `total` out of `account`, fees included, can be made under by
+/// callers that cannot transact on it as owners.
+pub fn transfer_grant(auth: &AuthorizedUser, account: &Account, total: f64) -> Option<AccessGrant> {
+    if !auth.reaches(&account.account_number) {
+        return None;
+    }
+    auth.grants
+        .iter()
+        .find(|grant| grant.allows(account, &GrantScope::InitiateTransfers) && grant.fits(total))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::domain::{account::AccountStatus, user::Role};
+
+    fn user(user_id: &str, role: Role, resource: Vec<&str>) -> AuthorizedUser {
+        AuthorizedUser {
+            user_id: user_id.to_string(),
+            permissions: role.permissions(),
+            role,
+            resource: resource.iter().map(|account| account.to_string()).collect(),
+            grants: Vec::new(),
+            session_id: None,
+            api_key_id: None,
+            key_accounts: Vec::new(),
+        }
+    }
+
+    fn account(account_number: &str, owners: Vec<(&str, OwnerPermission)>) -> Account {
+        Account {
+            user_owner_id: owners[0].0.to_string(),
+            account_number: account_number.to_string(),
+            name: String::new(),
+            owners: owners.into_iter().map(|(id, permission)| (id.to_string(), permission)).collect(),
+            accounts_fiat: HashMap::new(),
+            accounts_crypto: HashMap::new(),
+            constraints: HashMap::new(),
+            active: true,
+            status: AccountStatus::Open,
+            closure: None,
+            version: 0,
+        }
+    }
+
+    #[test]
+    fn view_owners_cannot_transact() {
+        let account = account("A", vec![("owner", OwnerPermission::Full), ("viewer", OwnerPermission::View)]);
+        let viewer = user("viewer", Role::User, vec!["A"]);
+        assert!(can_continue(viewer.clone(), "A"));
+        assert!(can_operate(viewer.clone(), &account, OwnerPermission::View));
+        assert!(!can_operate(viewer.clone(), &account, OwnerPermission::Transact));
+        assert!(!can_transfer(viewer, &account, 1.0));
+    }
+
+    #[test]
+    fn key_accounts_limit_every_check() {
+        let a = account("A", vec![("owner", OwnerPermission::Full)]);
+        let b = account("B", vec![("owner", OwnerPermission::Full)]);
+        let mut owner = user("owner", Role::User, vec!["A", "B"]);
+        owner.api_key_id = Some("key_1".to_string());
+        owner.key_accounts = vec!["A".to_string()];
+        assert!(can_operate(owner.clone(), &a, OwnerPermission::Full));
+        assert!(!can_operate(owner.clone(), &b, OwnerPermission::View));
+        assert!(!can_transfer(owner.clone(), &b, 1.0));
+        assert!(!can_continue(owner.clone(), "B"));
+        assert!(!can_access(owner, "B", GrantScope::ViewBalances));
+    }
+
+    #[test]
+    fn staff_act_on_other_accounts_only_with_accounts_operate() {
+        let b = account("B", vec![("owner", OwnerPermission::Full)]);
+        let operator = user("operator", Role::Operator, vec![]);
+        assert!(!can_continue(operator.clone(), "B"));
+        assert!(!can_operate(operator, &b, OwnerPermission::View));
+        let mut support = user("support", Role::Support, vec![]);
+        support.permissions.push(Permission::AccountsOperate);
+        assert!(can_continue(support.clone(), "B"));
+        assert!(can_operate(support, &b, OwnerPermission::Transact));
+        let mut admin = user("admin", Role::Admin, vec![]);
+        admin.permissions.retain(|permission| *permission != Permission::AccountsOperate);
+        assert!(!can_continue(admin, "B"));
+    }
+
+    #[test]
+    fn confirming_needs_approval_rights_not_read_access() {
+        let support = user("support", Role::Support, vec![]);
+        assert!(can_access(support.clone(), "A", GrantScope::ViewTransactions));
+        assert!(!can_access(support.clone(), "A", GrantScope::ApproveTransfers));
+        assert!(!can_confirm(support, "A", "B"));
+        assert!(!can_confirm(user("auditor", Role::Auditor, vec![]), "A", "B"));
+        assert!(can_confirm(user("operator", Role::Operator, vec![]), "A", "B"));
+        assert!(can_confirm(user("owner", Role::User, vec!["B"]), "A", "B"));
+        let mut grantee = user("grantee", Role::User, vec![]);
+        assert!(!can_confirm(grantee.clone(), "A", "B"));
+        grantee.grants.push(
+            AccessGrant::new("A".to_string(), "owner".to_string(), "grantee".to_string(), vec![GrantScope::ApproveTransfers], None, None, None)
+                .unwrap(),
+        );
+        assert!(can_confirm(grantee, "A", "B"));
+    }
+
+    #[test]
+    fn key_accounts_limit_admins() {
+        let b = account("B", vec![("owner", OwnerPermission::Full)]);
+        let mut admin = user("admin", Role::Admin, vec![]);
+        assert!(can_operate(admin.clone(), &b, OwnerPermission::Full));
+        admin.key_accounts = vec!["A".to_string()];
+        assert!(!can_operate(admin.clone(), &b, OwnerPermission::Full));
+        assert!(!can_continue(admin, "B"));
+    }
+
+    /// What submitting a transfer checks, see `authorize_transfer`.
+    fn can_transfer(auth: AuthorizedUser, account: &Account, total: f64) -> bool {
+        can_operate(auth.clone(), account, OwnerPermission::Transact) || transfer_grant(&auth, account, total).is_some()
+    }
+
+    fn initiate_grant(transfer_limit: f64, total_limit: Option<f64>) -> AccessGrant {
+        AccessGrant::new(
+            "A".to_string(),
+            "owner".to_string(),
+            "grantee".to_string(),
+            vec![GrantScope::InitiateTransfers],
+            Some(transfer_limit),
+            total_limit,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn grants_allow_transfers_up_to_the_limit() {
+        let account = account("A", vec![("owner", OwnerPermission::Full)]);
+        let mut grantee = user("grantee", Role::User, vec![]);
+        grantee.grants.push(initiate_grant(100.0, None));
+        assert!(can_transfer(grantee.clone(), &account, 100.0));
+        assert!(!can_transfer(grantee.clone(), &account, 100.5));
+        assert!(!can_operate(grantee, &account, OwnerPermission::Transact));
+    }
+
+    #[test]
+    fn grants_stop_when_the_grantor_loses_full() {
+        let mut account = account("A", vec![("owner", OwnerPermission::Full), ("other", OwnerPermission::Full)]);
+        let mut grantee = user("grantee", Role::User, vec![]);
+        grantee.grants.push(initiate_grant(100.0, None));
+        assert!(can_transfer(grantee.clone(), &account, 10.0));
+        account.set_owner("owner".to_string(), Some(OwnerPermission::Transact)).unwrap();
+        assert!(!can_transfer(grantee.clone(), &account, 10.0));
+        account.set_owner("owner".to_string(), None).unwrap();
+        assert!(!can_transfer(grantee, &account, 10.0));
+    }
+
+    #[test]
+    fn grants_cap_what_is_transferred_in_total() {
+        let account = account("A", vec![("owner", OwnerPermission::Full)]);
+        let mut grant = initiate_grant(100.0, Some(150.0));
+        grant.charge(100.0).unwrap();
+        assert!(grant.fits(50.0));
+        assert!(!grant.fits(50.5));
+        assert!(grant.charge(60.0).is_err());
+        assert_eq!(grant.transferred, 100.0);
+        let mut grantee = user("grantee", Role::User, vec![]);
+        grantee.grants.push(grant);
+        assert!(can_transfer(grantee.clone(), &account, 50.0));
+        assert!(!can_transfer(grantee, &account, 60.0));
+        assert!(AccessGrant::new("A".to_string(), "owner".to_string(), "grantee".to_string(), vec![GrantScope::InitiateTransfers], Some(100.0), Some(50.0), None).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::env;
+
+use ed25519_dalek::{Signer, SigningKey};
+
+/// Ed25519 key signing the published ledger roots. Auditors check signatures against the
+/// hex public key recorded in every root.
+pub struct RootSigner {
+    key: SigningKey,
+}
+impl RootSigner {
+    /// Reads the hex 32 byte seed in `LEDGER_SIGNING_KEY`. There is no fallback: roots signed
+    /// with a key that dies with the process could not be checked against later ones.
+    pub fn from_env() -> Result<RootSigner, String> {
+        match env::var("LEDGER_SIGNING_KEY") {
+            Ok(seed) => RootSigner::from_hex(&seed),
+            Err(_) => Err("LEDGER_SIGNING_KEY is not set".to_string()),
+        }
+    }
+    pub fn from_hex(seed: &str) -> Result<RootSigner, String> {
+        let seed: [u8; 32] = match hex::decode(seed.trim()) {
+            Ok(bytes) => match bytes.try_into() {
+                Ok(seed) => seed,
+                Err(_) => return Err("LEDGER_SIGNING_KEY must be 32 bytes of hex".to_string()),
+            },
+            Err(e) => return Err(format!("Error parsing LEDGER_SIGNING_KEY: {}", e)),
+        };
+        Ok(RootSigner {
+            key: SigningKey::from_bytes(&seed),
+        })
+    }
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key.verifying_key().to_bytes())
+    }
+    pub fn sign(&self, message: &str) -> String {
+        hex::encode(self.key.sign(message.as_bytes()).to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    use super::*;
+
+    const SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+    #[test]
+    fn signatures_verify_with_the_published_key() {
+        let signer = RootSigner::from_hex(SEED).unwrap();
+        assert_eq!(signer.public_key(), "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let public_key: [u8; 32] = hex::decode(signer.public_key()).unwrap().try_into().unwrap();
+        let signature: [u8; 64] = hex::decode(signer.sign("2024-01-01|1|2|2|root|None")).unwrap().try_into().unwrap();
+        let key = VerifyingKey::from_bytes(&public_key).unwrap();
+        let signature = Signature::from_bytes(&signature);
+        assert!(key.verify(b"2024-01-01|1|2|2|root|None", &signature).is_ok());
+        assert!(key.verify(b"2024-01-01|1|2|2|forged|None", &signature).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_seeds() {
+        assert!(RootSigner::from_hex("abcd").is_err());
+        assert!(RootSigner::from_hex("not hex").is_err());
+    }
+}
@@ -0,0 +1,1141 @@
+use std::{any::Any as AnyType, marker::PhantomData};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use mongodb::bson::{self, Bson, Document};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use sqlx::{
+    any::{AnyArguments, AnyKind, AnyPool, AnyPoolOptions, AnyRow},
+    query::Query,
+    Any, Row, Transaction,
+};
+
+use crate::{
+    memory::{compare_sorted, matches, number},
+    mongo::{
+        Crud, LedgerOp, LedgerOperations, Session, StoreError, Transactional, Versioned,
+        VersionedCrud, MAX_UPDATE_RETRIES,
+    },
+};
+
+/// SQLSTATE PostgreSQL returns when a serializable transaction loses a race, safe to retry.
+const SERIALIZATION_FAILURE: &str = "40001";
+const MAX_CONNECTIONS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Text,
+    Real,
+    Boolean,
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: Kind,
+}
+const fn text(name: &'static str) -> Column {
+    Column { name, kind: Kind::Text }
+}
+const fn real(name: &'static str) -> Column {
+    Column { name, kind: Kind::Real }
+}
+const fn boolean(name: &'static str) -> Column {
+    Column { name, kind: Kind::Boolean }
+}
+
+/// Where an entity lives: its key, the fields copied into their own columns so the schema
+/// can constrain and index them, and the whole entity as json in `document`.
+/// The columns win over `document` when reading, ledger balances only ever move there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Table {
+    pub name: &'static str,
+    pub key: &'static str,
+    pub columns: &'static [Column],
+}
+pub const USERS: Table = Table { name: "users", key: "id", columns: &[text("email")] };
+pub const ACCOUNTS: Table = Table {
+    name: "accounts",
+    key: "account_number",
+    columns: &[text("user_owner_id"), boolean("active")],
+};
+pub const FIAT_LEDGERS: Table = Table {
+    name: "fiat_ledgers",
+    key: "id",
+    columns: &[text("account_number"), real("balance"), real("hold"), boolean("closed")],
+};
+pub const CRYPTO_LEDGERS: Table = Table {
+    name: "crypto_ledgers",
+    key: "id",
+    columns: &[text("account_number"), real("balance"), real("hold"), boolean("closed")],
+};
+pub const TRANSACTIONS: Table = Table {
+    name: "transactions",
+    key: "tx_id",
+    columns: &[
+        text("asset"),
+        text("from_wallet"),
+        text("to_wallet"),
+        text("timestamp"),
+        text("external_id"),
+        text("payment_reference"),
+    ],
+};
+pub const STATEMENT_JOBS: Table = Table { name: "statement_jobs", key: "id", columns: &[] };
+pub const STATEMENT_IMPORTS: Table = Table { name: "statement_imports", key: "id", columns: &[] };
+pub const REVIEW_ITEMS: Table = Table { name: "review_items", key: "id", columns: &[] };
+pub const IDEMPOTENCY_KEYS: Table = Table { name: "idempotency_keys", key: "id", columns: &[] };
+pub const OUTBOX_EVENTS: Table = Table {
+    name: "outbox_events",
+    key: "id",
+    columns: &[text("aggregate_id"), text("occurred_at"), boolean("published")],
+};
+pub const INTEGRITY_REPORTS: Table = Table { name: "integrity_reports", key: "id", columns: &[] };
+pub const LEDGER_ENTRIES: Table = Table {
+    name: "ledger_entries",
+    key: "id",
+    columns: &[text("tx_id"), text("day")],
+};
+pub const DAILY_ROOTS: Table = Table { name: "daily_roots", key: "id", columns: &[] };
+pub const AUDIT_LOG: Table = Table {
+    name: "audit_log",
+    key: "id",
+    columns: &[text("actor_id"), text("action"), text("occurred_at")],
+};
+pub const ACCESS_GRANTS: Table = Table {
+    name: "access_grants",
+    key: "id",
+    columns: &[text("account_number"), text("grantee")],
+};
+pub const API_KEYS: Table = Table { name: "api_keys", key: "id", columns: &[text("user_id")] };
+pub const SESSIONS: Table = Table { name: "user_sessions", key: "id", columns: &[text("user_id")] };
+pub const PASSWORD_RESETS: Table = Table { name: "password_resets", key: "id", columns: &[text("user_id")] };
+pub const WEBHOOK_SUBSCRIPTIONS: Table = Table {
+    name: "webhook_subscriptions",
+    key: "id",
+    columns: &[text("account_number"), boolean("active"), text("created_at")],
+};
+pub const WEBHOOK_DELIVERIES: Table = Table {
+    name: "webhook_deliveries",
+    key: "id",
+    columns: &[text("subscription_id"), text("status"), text("next_attempt_at"), text("created_at")],
+};
+
+/// Schema changes in the order they are applied, written in the dialect PostgreSQL and SQLite share.
+/// Applied versions are recorded in `schema_migrations`; never edit one that has shipped.
+const MIGRATIONS: &[(i64, &[&str])] = &[(
+    1,
+    &[
+        "CREATE TABLE users (
+            id TEXT PRIMARY KEY,
+            email TEXT NOT NULL UNIQUE,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE TABLE accounts (
+            account_number TEXT PRIMARY KEY,
+            user_owner_id TEXT NOT NULL REFERENCES users (id),
+            active BOOLEAN NOT NULL,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE TABLE fiat_ledgers (
+            id TEXT PRIMARY KEY,
+            account_number TEXT NOT NULL REFERENCES accounts (account_number),
+            balance DOUBLE PRECISION NOT NULL CHECK (balance >= 0),
+            hold DOUBLE PRECISION NOT NULL CHECK (hold >= 0),
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE TABLE crypto_ledgers (
+            id TEXT PRIMARY KEY,
+            account_number TEXT NOT NULL REFERENCES accounts (account_number),
+            balance DOUBLE PRECISION NOT NULL CHECK (balance >= 0),
+            hold DOUBLE PRECISION NOT NULL CHECK (hold >= 0),
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE TABLE transactions (
+            tx_id TEXT PRIMARY KEY,
+            asset TEXT NOT NULL,
+            from_wallet TEXT,
+            to_wallet TEXT,
+            timestamp TEXT NOT NULL,
+            external_id TEXT,
+            payment_reference TEXT,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX transactions_from_wallet ON transactions (from_wallet, timestamp, tx_id)",
+        "CREATE INDEX transactions_to_wallet ON transactions (to_wallet, timestamp, tx_id)",
+        "CREATE INDEX transactions_timestamp ON transactions (timestamp, tx_id)",
+        "CREATE INDEX transactions_external_id ON transactions (external_id)",
+        "CREATE INDEX transactions_payment_reference ON transactions (payment_reference)",
+        "CREATE TABLE statement_jobs (
+            id TEXT PRIMARY KEY,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE TABLE statement_imports (
+            id TEXT PRIMARY KEY,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE TABLE review_items (
+            id TEXT PRIMARY KEY,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE TABLE idempotency_keys (
+            id TEXT PRIMARY KEY,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+    ],
+), (
+    2,
+    &[
+        "CREATE TABLE outbox_events (
+            id TEXT PRIMARY KEY,
+            aggregate_id TEXT NOT NULL,
+            occurred_at TEXT NOT NULL,
+            published BOOLEAN NOT NULL,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX outbox_events_pending ON outbox_events (published, occurred_at)",
+    ],
+), (
+    3,
+    &[
+        "CREATE TABLE webhook_subscriptions (
+            id TEXT PRIMARY KEY,
+            account_number TEXT REFERENCES accounts (account_number),
+            active BOOLEAN NOT NULL,
+            created_at TEXT NOT NULL,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX webhook_subscriptions_account ON webhook_subscriptions (account_number)",
+        "CREATE TABLE webhook_deliveries (
+            id TEXT PRIMARY KEY,
+            subscription_id TEXT NOT NULL REFERENCES webhook_subscriptions (id),
+            status TEXT NOT NULL,
+            next_attempt_at TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX webhook_deliveries_due ON webhook_deliveries (status, next_attempt_at)",
+        "CREATE INDEX webhook_deliveries_log ON webhook_deliveries (subscription_id, created_at)",
+    ],
+), (
+    4,
+    &["CREATE TABLE integrity_reports (
+        id TEXT PRIMARY KEY,
+        version BIGINT NOT NULL DEFAULT 0,
+        document TEXT NOT NULL
+    )"],
+), (
+    5,
+    &[
+        "CREATE TABLE ledger_entries (
+            id TEXT PRIMARY KEY,
+            tx_id TEXT NOT NULL UNIQUE REFERENCES transactions (tx_id),
+            day TEXT NOT NULL,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX ledger_entries_day ON ledger_entries (day, id)",
+        "CREATE TABLE daily_roots (
+            id TEXT PRIMARY KEY,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+    ],
+), (
+    6,
+    &[
+        "CREATE TABLE audit_log (
+            id TEXT PRIMARY KEY,
+            actor_id TEXT,
+            action TEXT NOT NULL,
+            occurred_at TEXT NOT NULL,
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX audit_log_occurred_at ON audit_log (occurred_at)",
+        "CREATE INDEX audit_log_actor ON audit_log (actor_id, occurred_at)",
+    ],
+), (
+    7,
+    &[
+        "CREATE TABLE access_grants (
+            id TEXT PRIMARY KEY,
+            account_number TEXT NOT NULL REFERENCES accounts (account_number),
+            grantee TEXT NOT NULL REFERENCES users (id),
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX access_grants_grantee ON access_grants (grantee)",
+        "CREATE INDEX access_grants_account ON access_grants (account_number)",
+    ],
+), (
+    8,
+    &[
+        "CREATE TABLE api_keys (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL REFERENCES users (id),
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX api_keys_user ON api_keys (user_id)",
+    ],
+), (
+    9,
+    &[
+        "CREATE TABLE user_sessions (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL REFERENCES users (id),
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX user_sessions_user ON user_sessions (user_id)",
+    ],
+), (
+    10,
+    &[
+        "CREATE TABLE password_resets (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL REFERENCES users (id),
+            version BIGINT NOT NULL DEFAULT 0,
+            document TEXT NOT NULL
+        )",
+        "CREATE INDEX password_resets_user ON password_resets (user_id)",
+    ],
+), (
+    11,
+    &[
+        "ALTER TABLE fiat_ledgers ADD COLUMN closed BOOLEAN NOT NULL DEFAULT FALSE",
+        "ALTER TABLE crypto_ledgers ADD COLUMN closed BOOLEAN NOT NULL DEFAULT FALSE",
+        "UPDATE fiat_ledgers SET closed = TRUE WHERE document LIKE '%\"closed\":true%'",
+        "UPDATE crypto_ledgers SET closed = TRUE WHERE document LIKE '%\"closed\":true%'",
+    ],
+), (
+    12,
+    &[
+        "DROP INDEX transactions_payment_reference",
+        "CREATE UNIQUE INDEX transactions_payment_reference ON transactions (payment_reference)",
+    ],
+)];
+
+/// Opens the pool for a `postgres://` or `sqlite://` url and brings the schema up to date.
+pub async fn connect(url: &str) -> Result<AnyPool, String> {
+    let pool = match AnyPoolOptions::new().max_connections(MAX_CONNECTIONS).connect(url).await {
+        Ok(pool) => pool,
+        Err(e) => return Err(format!("Error connecting to database: {}", e)),
+    };
+    migrate(&pool).await?;
+    Ok(pool)
+}
+
+pub async fn migrate(pool: &AnyPool) -> Result<(), String> {
+    let created = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (version BIGINT PRIMARY KEY, applied_at TEXT NOT NULL)",
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = created {
+        return Err(format!("Error creating schema_migrations: {}", e));
+    }
+    let applied: i64 = match sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_migrations")
+        .fetch_one(pool)
+        .await
+        .and_then(|row| row.try_get("version"))
+    {
+        Ok(applied) => applied,
+        Err(e) => return Err(format!("Error reading schema_migrations: {}", e)),
+    };
+    for (version, statements) in MIGRATIONS.iter().filter(|(version, _)| *version > applied) {
+        let failed = |e: sqlx::Error| format!("Error applying migration {}: {}", version, e);
+        let mut transaction = pool.begin().await.map_err(failed)?;
+        for statement in statements.iter() {
+            sqlx::query(statement).execute(&mut *transaction).await.map_err(failed)?;
+        }
+        sqlx::query("INSERT INTO schema_migrations (version, applied_at) VALUES ($1, $2)")
+            .bind(*version)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&mut *transaction)
+            .await
+            .map_err(failed)?;
+        transaction.commit().await.map_err(failed)?;
+    }
+    Ok(())
+}
+
+/// Relational store for one entity type. Writes that check something first run in a
+/// serializable transaction; `find` runs the conditions on the key and columns in SQL and
+/// checks the whole filter again in process like the memory store.
+#[derive(Clone)]
+pub struct SqlRepository<T> {
+    pool: AnyPool,
+    table: Table,
+    entity: PhantomData<fn() -> T>,
+}
+impl<T> SqlRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    pub fn new(pool: AnyPool, table: Table) -> SqlRepository<T> {
+        SqlRepository {
+            pool,
+            table,
+            entity: PhantomData,
+        }
+    }
+    async fn begin(&self) -> Result<Transaction<'static, Any>, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        // SQLite only ever runs serializable transactions
+        if matches!(self.pool.any_kind(), AnyKind::Postgres) {
+            sqlx::query("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE")
+                .execute(&mut *transaction)
+                .await?;
+        }
+        Ok(transaction)
+    }
+    async fn exists(&self, transaction: &mut Transaction<'static, Any>, id: &str) -> Result<bool, sqlx::Error> {
+        let sql = format!("SELECT COUNT(*) AS count FROM {} WHERE {} = $1", self.table.name, self.table.key);
+        let row = sqlx::query(&sql).bind(id.to_string()).fetch_one(&mut **transaction).await?;
+        Ok(row.try_get::<i64, _>("count")? > 0)
+    }
+    fn select_sql(&self) -> String {
+        let mut names = vec!["version", "document"];
+        names.extend(self.table.columns.iter().map(|column| column.name));
+        format!("SELECT {} FROM {}", names.join(", "), self.table.name)
+    }
+    fn insert_sql(&self) -> String {
+        let mut names = vec![self.table.key];
+        names.extend(self.table.columns.iter().map(|column| column.name));
+        names.extend(["version", "document"]);
+        let params: Vec<String> = (1..=names.len()).map(|i| format!("${}", i)).collect();
+        format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            self.table.name,
+            names.join(", "),
+            params.join(", ")
+        )
+    }
+    /// Parameters are the columns, version and document, then the key and, if `versioned`, the expected version.
+    fn update_sql(&self, versioned: bool) -> String {
+        let mut names: Vec<&str> = self.table.columns.iter().map(|column| column.name).collect();
+        names.extend(["version", "document"]);
+        let assignments: Vec<String> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("{} = ${}", name, i + 1))
+            .collect();
+        let mut sql = format!(
+            "UPDATE {} SET {} WHERE {} = ${}",
+            self.table.name,
+            assignments.join(", "),
+            self.table.key,
+            names.len() + 1
+        );
+        if versioned {
+            sql.push_str(&format!(" AND version = ${}", names.len() + 2));
+        }
+        sql
+    }
+    fn to_value(entity: &T) -> Result<Value, String> {
+        serde_json::to_value(entity).map_err(|e| format!("Error serializing entity: {}", e))
+    }
+    fn key_of(&self, value: &Value) -> Result<String, String> {
+        match value.get(self.table.key).and_then(Value::as_str) {
+            Some(key) => Ok(key.to_string()),
+            None => Err(format!("Error creating entity: missing {}", self.table.key)),
+        }
+    }
+    fn bind_entity<'q>(
+        &self,
+        mut query: Query<'q, Any, AnyArguments<'q>>,
+        value: &Value,
+    ) -> Query<'q, Any, AnyArguments<'q>> {
+        for column in self.table.columns {
+            let field = value.get(column.name);
+            query = match column.kind {
+                Kind::Text => query.bind(field.and_then(Value::as_str).map(str::to_string)),
+                Kind::Real => query.bind(field.and_then(Value::as_f64).unwrap_or(0.0)),
+                Kind::Boolean => query.bind(field.and_then(Value::as_bool).unwrap_or(false)),
+            };
+        }
+        let version = value.get("version").and_then(Value::as_i64).unwrap_or(0);
+        query.bind(version).bind(value.to_string())
+    }
+    fn from_row(&self, row: &AnyRow) -> Result<T, String> {
+        let failed = |e: sqlx::Error| format!("Error reading entity: {}", e);
+        let document: String = row.try_get("document").map_err(failed)?;
+        let mut value: Value = match serde_json::from_str(&document) {
+            Ok(value) => value,
+            Err(e) => return Err(format!("Error reading entity: {}", e)),
+        };
+        let fields = match value.as_object_mut() {
+            Some(fields) => fields,
+            None => return Err("Error reading entity: document is not an object".to_string()),
+        };
+        for column in self.table.columns {
+            let column_value = match column.kind {
+                Kind::Text => row
+                    .try_get::<Option<String>, _>(column.name)
+                    .map(|text| text.map(Value::from).unwrap_or(Value::Null)),
+                Kind::Real => row.try_get::<f64, _>(column.name).map(Value::from),
+                Kind::Boolean => row.try_get::<bool, _>(column.name).map(Value::from),
+            }
+            .map_err(failed)?;
+            fields.insert(column.name.to_string(), column_value);
+        }
+        if fields.contains_key("version") {
+            let version: i64 = row.try_get("version").map_err(failed)?;
+            fields.insert("version".to_string(), Value::from(version));
+        }
+        serde_json::from_value(value).map_err(|e| format!("Error reading entity: {}", e))
+    }
+    /// Parameters are the balance and hold deltas, the key and, if the op has a guard, the amount.
+    fn ledger_sql(&self, op: &LedgerOp) -> String {
+        // the version moves too so versioned writers holding an older copy conflict
+        let mut sql = format!(
+            "UPDATE {} SET balance = balance + $1, hold = hold + $2, version = version + 1 WHERE {} = $3 AND NOT closed",
+            self.table.name, self.table.key
+        );
+        if let Some(field) = op.guard() {
+            sql.push_str(&format!(" AND {} >= $4", field));
+        }
+        sql
+    }
+    async fn apply_once(&self, sql: &str, id: &str, op: &LedgerOp) -> Result<T, StoreError> {
+        let failed = |e: sqlx::Error| store_error(e, "Error updating ledger", id, 0);
+        let mut transaction = self.begin().await.map_err(failed)?;
+        let entity = self.apply_on(&mut transaction, sql, id, op).await?;
+        transaction.commit().await.map_err(failed)?;
+        Ok(entity)
+    }
+    async fn apply_on(
+        &self,
+        transaction: &mut Transaction<'static, Any>,
+        sql: &str,
+        id: &str,
+        op: &LedgerOp,
+    ) -> Result<T, StoreError> {
+        let failed = |e: sqlx::Error| store_error(e, "Error updating ledger", id, 0);
+        let (balance, hold) = op.deltas();
+        let mut query = sqlx::query(sql).bind(balance).bind(hold).bind(id.to_string());
+        if op.guard().is_some() {
+            query = query.bind(op.amount());
+        }
+        let updated = query.execute(&mut **transaction).await.map_err(failed)?;
+        if updated.rows_affected() == 0 {
+            let sql = format!("SELECT closed FROM {} WHERE {} = $1", self.table.name, self.table.key);
+            let row = sqlx::query(&sql)
+                .bind(id.to_string())
+                .fetch_optional(&mut **transaction)
+                .await
+                .map_err(failed)?;
+            let closed = match row {
+                Some(row) => Some(row.try_get::<bool, _>("closed").map_err(failed)?),
+                None => None,
+            };
+            return Err(op.missed(id, closed));
+        }
+        let sql = format!("{} WHERE {} = $1", self.select_sql(), self.table.key);
+        let row = sqlx::query(&sql)
+            .bind(id.to_string())
+            .fetch_one(&mut **transaction)
+            .await
+            .map_err(failed)?;
+        self.from_row(&row).map_err(StoreError::Database)
+    }
+    fn kind_of(&self, field: &str) -> Option<Kind> {
+        if field == self.table.key {
+            return Some(Kind::Text);
+        }
+        self.table.columns.iter().find(|column| column.name == field).map(|column| column.kind)
+    }
+    /// Text compares byte by byte like Rust strings, PostgreSQL would use the database locale.
+    fn ordered(&self, field: &str, kind: Kind) -> String {
+        match (kind, self.pool.any_kind()) {
+            (Kind::Text, AnyKind::Postgres) => format!("{} COLLATE \"C\"", field),
+            _ => field.to_string(),
+        }
+    }
+    /// Adds the SQL for the parts of `filter` on the key and columns to `clauses`,
+    /// false when something was left for `matches` to check.
+    fn push_filter(&self, filter: &Document, clauses: &mut Vec<String>, params: &mut Vec<Param>) -> bool {
+        let mut complete = true;
+        for (field, condition) in filter {
+            complete &= match field.as_str() {
+                "$and" => match condition.as_array() {
+                    Some(filters) => filters.iter().fold(true, |complete, filter| match filter.as_document() {
+                        Some(filter) => self.push_filter(filter, clauses, params) && complete,
+                        None => false,
+                    }),
+                    None => false,
+                },
+                "$or" => self.push_any(condition, clauses, params),
+                path => match self.kind_of(path) {
+                    Some(kind) => self.push_condition(path, kind, condition, clauses, params),
+                    None => false,
+                },
+            };
+        }
+        complete
+    }
+    /// An `$or` is only pushed when every branch is, a partial branch would drop rows it matches.
+    fn push_any(&self, condition: &Bson, clauses: &mut Vec<String>, params: &mut Vec<Param>) -> bool {
+        let filters = match condition.as_array() {
+            Some(filters) if !filters.is_empty() => filters,
+            _ => return false,
+        };
+        let bound = params.len();
+        let mut branches = Vec::new();
+        for filter in filters {
+            let mut branch = Vec::new();
+            let pushed = match filter.as_document() {
+                Some(filter) => self.push_filter(filter, &mut branch, params),
+                None => false,
+            };
+            if !pushed {
+                params.truncate(bound);
+                return false;
+            }
+            branches.push(match branch.is_empty() {
+                true => "1 = 1".to_string(),
+                false => format!("({})", branch.join(" AND ")),
+            });
+        }
+        clauses.push(format!("({})", branches.join(" OR ")));
+        true
+    }
+    fn push_condition(
+        &self,
+        field: &str,
+        kind: Kind,
+        condition: &Bson,
+        clauses: &mut Vec<String>,
+        params: &mut Vec<Param>,
+    ) -> bool {
+        let operators = match condition {
+            Bson::Document(operators) if operators.keys().all(|key| key.starts_with('$')) => operators,
+            expected => return self.push_comparison(field, kind, "$eq", expected, clauses, params),
+        };
+        operators.iter().fold(true, |complete, (operator, operand)| {
+            self.push_comparison(field, kind, operator, operand, clauses, params) && complete
+        })
+    }
+    fn push_comparison(
+        &self,
+        field: &str,
+        kind: Kind,
+        operator: &str,
+        operand: &Bson,
+        clauses: &mut Vec<String>,
+        params: &mut Vec<Param>,
+    ) -> bool {
+        let clause = match (operator, operand) {
+            ("$eq", Bson::Null) => format!("{} IS NULL", field),
+            ("$ne", Bson::Null) => format!("{} IS NOT NULL", field),
+            ("$in", Bson::Array(candidates)) => {
+                let candidates: Option<Vec<Param>> =
+                    candidates.iter().map(|candidate| Param::of(kind, candidate)).collect();
+                let candidates = match candidates {
+                    Some(candidates) => candidates,
+                    None => return false,
+                };
+                if candidates.is_empty() {
+                    "1 = 0".to_string()
+                } else {
+                    let mut placeholders = Vec::new();
+                    for candidate in candidates {
+                        params.push(candidate);
+                        placeholders.push(format!("${}", params.len()));
+                    }
+                    format!("{} IN ({})", field, placeholders.join(", "))
+                }
+            }
+            ("$eq" | "$ne" | "$gt" | "$gte" | "$lt" | "$lte", operand) => {
+                match Param::of(kind, operand) {
+                    Some(param) => params.push(param),
+                    None => return false,
+                }
+                let n = params.len();
+                let ordered = self.ordered(field, kind);
+                match operator {
+                    "$eq" => format!("{} = ${}", field, n),
+                    // missing and null fields are not equal to a value, like in MongoDB
+                    "$ne" => format!("({} IS NULL OR {} <> ${})", field, field, n),
+                    "$gt" => format!("{} > ${}", ordered, n),
+                    "$gte" => format!("{} >= ${}", ordered, n),
+                    "$lt" => format!("{} < ${}", ordered, n),
+                    _ => format!("{} <= ${}", ordered, n),
+                }
+            }
+            _ => return false,
+        };
+        clauses.push(clause);
+        true
+    }
+    async fn update_versioned_on(
+        &self,
+        transaction: &mut Transaction<'static, Any>,
+        id: &str,
+        mut entity: T,
+    ) -> Result<T, StoreError>
+    where
+        T: Versioned,
+    {
+        let expected = entity.version();
+        entity.set_version(expected + 1);
+        let value = Self::to_value(&entity).map_err(StoreError::Database)?;
+        let failed = |e: sqlx::Error| store_error(e, "Error updating entity", id, expected);
+        let sql = self.update_sql(true);
+        let updated = self
+            .bind_entity(sqlx::query(&sql), &value)
+            .bind(id.to_string())
+            .bind(expected as i64)
+            .execute(&mut **transaction)
+            .await
+            .map_err(failed)?;
+        if updated.rows_affected() == 0 {
+            return match self.exists(transaction, id).await.map_err(failed)? {
+                true => Err(StoreError::Conflict { id: id.to_string(), expected }),
+                false => Err(StoreError::NotFound(id.to_string())),
+            };
+        }
+        Ok(entity)
+    }
+}
+
+/// A value bound to a `find` condition, typed after the column it is compared with.
+enum Param {
+    Text(String),
+    Real(f64),
+    Boolean(bool),
+}
+impl Param {
+    fn of(kind: Kind, value: &Bson) -> Option<Param> {
+        match (kind, value) {
+            (Kind::Text, Bson::String(text)) => Some(Param::Text(text.clone())),
+            (Kind::Real, value) => number(value).map(Param::Real),
+            (Kind::Boolean, Bson::Boolean(value)) => Some(Param::Boolean(*value)),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl<T> Crud<T> for SqlRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    async fn create_many(&self, entities: Vec<T>) -> Result<Vec<String>, String> {
+        let failed = |e: sqlx::Error| format!("Error creating entities: {}", e);
+        let sql = self.insert_sql();
+        let mut transaction = self.begin().await.map_err(failed)?;
+        let mut ids = Vec::new();
+        for entity in entities.iter() {
+            let value = Self::to_value(entity)?;
+            let id = self.key_of(&value)?;
+            self.bind_entity(sqlx::query(&sql).bind(id.clone()), &value)
+                .execute(&mut *transaction)
+                .await
+                .map_err(failed)?;
+            ids.push(id);
+        }
+        transaction.commit().await.map_err(failed)?;
+        Ok(ids)
+    }
+    async fn create(&self, new_entity: T) -> Result<String, String> {
+        let value = Self::to_value(&new_entity)?;
+        let id = self.key_of(&value)?;
+        let sql = self.insert_sql();
+        match self.bind_entity(sqlx::query(&sql).bind(id.clone()), &value).execute(&self.pool).await {
+            Ok(_) => Ok(id),
+            Err(e) => Err(format!("Error creating entity: {}", e)),
+        }
+    }
+    async fn get_all(&self, skip: usize, limit: usize) -> Result<Vec<T>, String> {
+        let mut sql = format!("{} ORDER BY {}", self.select_sql(), self.table.key);
+        // SQLite has no OFFSET without LIMIT, an unlimited page skips in process instead
+        let skip_rows = if limit > 0 {
+            sql.push_str(&format!(" LIMIT {} OFFSET {}", limit, skip));
+            0
+        } else {
+            skip
+        };
+        let rows = match sqlx::query(&sql).fetch_all(&self.pool).await {
+            Ok(rows) => rows,
+            Err(e) => return Err(format!("Error getting entities: {}", e)),
+        };
+        rows.iter().skip(skip_rows).map(|row| self.from_row(row)).collect()
+    }
+    async fn get_by_id(&self, id: &str) -> Result<T, String> {
+        let sql = format!("{} WHERE {} = $1", self.select_sql(), self.table.key);
+        match sqlx::query(&sql).bind(id.to_string()).fetch_optional(&self.pool).await {
+            Ok(Some(row)) => self.from_row(&row),
+            Ok(None) => Err("Entity not found".to_uppercase()),
+            Err(e) => Err(format!("Error getting entity: {}", e)),
+        }
+    }
+    async fn update_by_id(&self, id: &str, edit_entity: T) -> Result<T, String> {
+        let value = Self::to_value(&edit_entity)?;
+        let sql = self.update_sql(false);
+        let updated = match self
+            .bind_entity(sqlx::query(&sql), &value)
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await
+        {
+            Ok(updated) => updated,
+            Err(e) => return Err(format!("Error updating entity: {}", e)),
+        };
+        if updated.rows_affected() > 0 {
+            self.get_by_id(id).await
+        } else {
+            Err("Error updating entity".to_uppercase())
+        }
+    }
+    async fn delete_by_id(&self, id: &str) -> Result<bool, String> {
+        let sql = format!("DELETE FROM {} WHERE {} = $1", self.table.name, self.table.key);
+        match sqlx::query(&sql).bind(id.to_string()).execute(&self.pool).await {
+            Ok(deleted) if deleted.rows_affected() > 0 => Ok(true),
+            Ok(_) => Err("Error deleting entity".to_uppercase()),
+            Err(e) => Err(format!("Error deleting entity: {}", e)),
+        }
+    }
+    async fn get_by_fields(&self, field: Vec<String>, value: Vec<String>) -> Result<Vec<T>, String> {
+        let mut filter = Document::new();
+        for (field, value) in field.into_iter().zip(value.into_iter()) {
+            filter.insert(field, value);
+        }
+        self.find(filter, Document::new(), 0).await
+    }
+    async fn find(&self, filter: Document, sort: Document, limit: usize) -> Result<Vec<T>, String> {
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+        let complete = self.push_filter(&filter, &mut clauses, &mut params);
+        let mut sql = self.select_sql();
+        if !clauses.is_empty() {
+            sql.push_str(&format!(" WHERE {}", clauses.join(" AND ")));
+        }
+        let mut order = Vec::new();
+        for (field, direction) in sort.iter() {
+            let kind = match self.kind_of(field) {
+                Some(kind) => kind,
+                None => {
+                    order.clear();
+                    break;
+                }
+            };
+            let descending = number(direction).unwrap_or(1.0) < 0.0;
+            // nulls come first in ascending order, like in MongoDB
+            order.push(format!("({} IS NULL) {}", field, if descending { "ASC" } else { "DESC" }));
+            order.push(format!("{} {}", self.ordered(field, kind), if descending { "DESC" } else { "ASC" }));
+        }
+        let sorted = order.len() == sort.len() * 2;
+        order.push(self.table.key.to_string());
+        sql.push_str(&format!(" ORDER BY {}", order.join(", ")));
+        // the limit can only go to SQL when it sees the whole filter and the whole sort
+        if complete && sorted && limit > 0 {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+        let mut query = sqlx::query(&sql);
+        for param in params {
+            query = match param {
+                Param::Text(text) => query.bind(text),
+                Param::Real(number) => query.bind(number),
+                Param::Boolean(value) => query.bind(value),
+            };
+        }
+        let rows = match query.fetch_all(&self.pool).await {
+            Ok(rows) => rows,
+            Err(e) => return Err(format!("Error finding entities: {}", e)),
+        };
+        let mut found = Vec::new();
+        for row in rows.iter() {
+            let entity = self.from_row(row)?;
+            let document = bson::to_document(&entity).map_err(|e| format!("Error finding entities: {}", e))?;
+            if matches(&document, &filter) {
+                found.push((document, entity));
+            }
+        }
+        found.sort_by(|(a, _), (b, _)| compare_sorted(a, b, &sort));
+        if limit > 0 {
+            found.truncate(limit);
+        }
+        Ok(found.into_iter().map(|(_, entity)| entity).collect())
+    }
+    async fn count(&self) -> u64 {
+        let sql = format!("SELECT COUNT(*) AS count FROM {}", self.table.name);
+        match sqlx::query(&sql)
+            .fetch_one(&self.pool)
+            .await
+            .and_then(|row| row.try_get::<i64, _>("count"))
+        {
+            Ok(count) => count as u64,
+            _ => 0,
+        }
+    }
+    async fn create_in(&self, session: &mut dyn Session, new_entity: T) -> Result<String, String> {
+        let transaction = sql_transaction(session)?;
+        let value = Self::to_value(&new_entity)?;
+        let id = self.key_of(&value)?;
+        let sql = self.insert_sql();
+        match self
+            .bind_entity(sqlx::query(&sql).bind(id.clone()), &value)
+            .execute(&mut **transaction)
+            .await
+        {
+            Ok(_) => Ok(id),
+            Err(e) => Err(format!("Error creating entity: {}", e)),
+        }
+    }
+    async fn get_by_id_in(&self, session: &mut dyn Session, id: &str) -> Result<T, String> {
+        let transaction = sql_transaction(session)?;
+        let sql = format!("{} WHERE {} = $1", self.select_sql(), self.table.key);
+        match sqlx::query(&sql).bind(id.to_string()).fetch_optional(&mut **transaction).await {
+            Ok(Some(row)) => self.from_row(&row),
+            Ok(None) => Err("Entity not found".to_uppercase()),
+            Err(e) => Err(format!("Error getting entity: {}", e)),
+        }
+    }
+}
+
+/// Serializable transaction held open for the length of a request.
+pub struct SqlSession {
+    transaction: Option<Transaction<'static, Any>>,
+}
+
+#[async_trait]
+impl Session for SqlSession {
+    async fn commit(&mut self) -> Result<(), String> {
+        match self.transaction.take() {
+            Some(transaction) => transaction.commit().await.map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+    async fn abort(&mut self) -> Result<(), String> {
+        match self.transaction.take() {
+            Some(transaction) => transaction.rollback().await.map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+    fn as_any(&mut self) -> &mut (dyn AnyType + Send) {
+        self
+    }
+}
+
+fn sql_transaction(session: &mut dyn Session) -> Result<&mut Transaction<'static, Any>, String> {
+    match session.as_any().downcast_mut::<SqlSession>() {
+        Some(SqlSession {
+            transaction: Some(transaction),
+        }) => Ok(transaction),
+        Some(_) => Err("Session is already finished".to_string()),
+        None => Err("Session belongs to another storage backend".to_string()),
+    }
+}
+
+#[async_trait]
+impl<T> Transactional<T> for SqlRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    async fn get_session(&self) -> Result<Box<dyn Session>, String> {
+        match self.begin().await {
+            Ok(transaction) => Ok(Box::new(SqlSession {
+                transaction: Some(transaction),
+            })),
+            Err(e) => Err(format!("Error creating session: {}", e)),
+        }
+    }
+}
+
+#[async_trait]
+impl<T> VersionedCrud<T> for SqlRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + Versioned + 'static,
+{
+    async fn update_versioned(&self, id: &str, entity: T) -> Result<T, StoreError> {
+        let expected = entity.version();
+        let failed = |e: sqlx::Error| store_error(e, "Error updating entity", id, expected);
+        let mut transaction = self.begin().await.map_err(failed)?;
+        let entity = self.update_versioned_on(&mut transaction, id, entity).await?;
+        transaction.commit().await.map_err(failed)?;
+        Ok(entity)
+    }
+    async fn update_versioned_in(&self, session: &mut dyn Session, id: &str, entity: T) -> Result<T, StoreError> {
+        let transaction = sql_transaction(session).map_err(StoreError::Database)?;
+        self.update_versioned_on(transaction, id, entity).await
+    }
+}
+
+#[async_trait]
+impl<T> LedgerOperations<T> for SqlRepository<T>
+where
+    T: Send + Sync + Clone + Serialize + DeserializeOwned + Unpin + 'static,
+{
+    async fn apply(&self, id: &str, op: LedgerOp) -> Result<T, StoreError> {
+        if op.amount() < 0.0 {
+            return Err(StoreError::Rejected("Amount must be positive".to_string()));
+        }
+        let sql = self.ledger_sql(&op);
+        let mut attempt = 0;
+        loop {
+            match self.apply_once(&sql, id, &op).await {
+                Err(StoreError::Conflict { .. }) if attempt < MAX_UPDATE_RETRIES => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+    async fn apply_in(&self, session: &mut dyn Session, id: &str, op: LedgerOp) -> Result<T, StoreError> {
+        if op.amount() < 0.0 {
+            return Err(StoreError::Rejected("Amount must be positive".to_string()));
+        }
+        let transaction = sql_transaction(session).map_err(StoreError::Database)?;
+        self.apply_on(transaction, &self.ledger_sql(&op), id, &op).await
+    }
+}
+
+fn store_error(e: sqlx::Error, context: &str, id: &str, expected: u64) -> StoreError {
+    match &e {
+        sqlx::Error::Database(error) if error.code().as_deref() == Some(SERIALIZATION_FAILURE) => {
+            StoreError::Conflict { id: id.to_string(), expected }
+        }
+        _ => StoreError::Database(format!("{}: {}", context, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{domain::ledger::Fiat, mongo::ledger_suite};
+
+    async fn pool() -> AnyPool {
+        let path = std::env::temp_dir().join(format!("account-module-{}.db", Uuid::new_v4()));
+        connect(&format!("sqlite://{}?mode=rwc", path.display())).await.unwrap()
+    }
+
+    fn transaction(tx_id: &str, from_wallet: Option<&str>, timestamp: &str) -> Value {
+        json!({
+            "tx_id": tx_id,
+            "asset": "USD",
+            "from_wallet": from_wallet,
+            "to_wallet": "b",
+            "timestamp": timestamp,
+            "external_id": null,
+            "payment_reference": null,
+            "version": 0,
+        })
+    }
+
+    async fn transactions(pool: &AnyPool) -> SqlRepository<Value> {
+        let repo = SqlRepository::new(pool.clone(), TRANSACTIONS);
+        repo.create(transaction("t1", Some("a"), "2024-01-01T00:00:00Z")).await.unwrap();
+        repo.create(transaction("t2", Some("a"), "2024-01-03T00:00:00Z")).await.unwrap();
+        repo.create(transaction("t3", Some("c"), "2024-01-02T00:00:00Z")).await.unwrap();
+        repo.create(transaction("t4", None, "2024-01-04T00:00:00Z")).await.unwrap();
+        repo
+    }
+
+    fn ids(found: Vec<Value>) -> Vec<String> {
+        found.iter().map(|tx| tx["tx_id"].as_str().unwrap().to_string()).collect()
+    }
+
+    /// Two funded USD ledgers, `acc1_USD` and `acc2_USD`, with what they reference and an
+    /// `acc3` account without ledgers.
+    async fn ledgers() -> SqlRepository<Fiat> {
+        let pool = pool().await;
+        let users: SqlRepository<Value> = SqlRepository::new(pool.clone(), USERS);
+        let accounts: SqlRepository<Value> = SqlRepository::new(pool.clone(), ACCOUNTS);
+        users.create(json!({"id": "u1", "email": "u1@example.com"})).await.unwrap();
+        for account_number in ["acc1", "acc2", "acc3"] {
+            accounts
+                .create(json!({"account_number": account_number, "user_owner_id": "u1", "active": true}))
+                .await
+                .unwrap();
+        }
+        let repo = SqlRepository::new(pool, FIAT_LEDGERS);
+        ledger_suite::fund(&repo).await;
+        repo
+    }
+
+    #[rocket::async_test]
+    async fn find_filters_sorts_and_limits_in_sql() {
+        let pool = pool().await;
+        let repo = transactions(&pool).await;
+        let found = repo.find(doc! {"from_wallet": "a"}, doc! {"timestamp": -1}, 0).await.unwrap();
+        assert_eq!(ids(found), ["t2", "t1"]);
+        let found = repo.find(doc! {"timestamp": {"$gte": "2024-01-02"}}, doc! {"timestamp": 1}, 2).await.unwrap();
+        assert_eq!(ids(found), ["t3", "t2"]);
+        let found = repo.find(doc! {"tx_id": {"$in": ["t1", "t3", "t9"]}}, doc! {}, 0).await.unwrap();
+        assert_eq!(ids(found), ["t1", "t3"]);
+        let found = repo.find(doc! {"tx_id": {"$in": []}}, doc! {}, 0).await.unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn payment_references_name_one_transaction() {
+        let pool = pool().await;
+        let repo = transactions(&pool).await;
+        let mut deposit = transaction("t5", None, "2024-01-05T00:00:00Z");
+        deposit["payment_reference"] = json!("DEP-ABCD-1234");
+        repo.create(deposit.clone()).await.unwrap();
+        deposit["tx_id"] = json!("t6");
+        assert!(repo.create(deposit).await.is_err());
+        assert_eq!(repo.count().await, 5);
+    }
+
+    #[rocket::async_test]
+    async fn find_treats_null_columns_like_mongodb() {
+        let pool = pool().await;
+        let repo = transactions(&pool).await;
+        let found = repo.find(doc! {"from_wallet": null}, doc! {}, 0).await.unwrap();
+        assert_eq!(ids(found), ["t4"]);
+        let found = repo.find(doc! {"from_wallet": {"$ne": "a"}}, doc! {}, 0).await.unwrap();
+        assert_eq!(ids(found), ["t3", "t4"]);
+        let found = repo.find(doc! {}, doc! {"from_wallet": 1}, 1).await.unwrap();
+        assert_eq!(ids(found), ["t4"]);
+    }
+
+    #[rocket::async_test]
+    async fn find_checks_what_sql_cannot_in_process() {
+        let pool = pool().await;
+        let repo = transactions(&pool).await;
+        let filter = doc! {"$or": [{"from_wallet": "c"}, {"document_only": {"$exists": false}, "tx_id": "t1"}]};
+        let found = repo.find(filter, doc! {}, 0).await.unwrap();
+        assert_eq!(ids(found), ["t1", "t3"]);
+        let found = repo.find(doc! {"to_wallet": "b", "version": 0}, doc! {"version": 1}, 1).await.unwrap();
+        assert_eq!(ids(found), ["t1"]);
+    }
+
+    #[rocket::async_test]
+    async fn aborted_session_undoes_every_write() {
+        ledger_suite::aborted_session_undoes_every_write(&ledgers().await).await;
+    }
+
+    #[rocket::async_test]
+    async fn committed_session_keeps_every_write() {
+        ledger_suite::committed_session_keeps_every_write(&ledgers().await).await;
+    }
+
+    #[rocket::async_test]
+    async fn closed_ledgers_take_no_operation() {
+        ledger_suite::closed_ledgers_take_no_operation(&ledgers().await).await;
+    }
+
+    #[rocket::async_test]
+    async fn stale_versions_conflict() {
+        ledger_suite::stale_versions_conflict(&ledgers().await).await;
+    }
+}
@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use mongodb::bson::doc;
+
+use crate::{
+    domain::{
+        account::Account,
+        api_key::ApiKey,
+        audit::AuditEntry,
+        event::DomainEvent,
+        grant::AccessGrant,
+        integrity::IntegrityReport,
+        ledger::{Crypto, Fiat},
+        merkle::{DailyRoot, LedgerEntry},
+        password_reset::PasswordReset,
+        reconciliation::{ReviewItem, StatementImport},
+        session::UserSession,
+        statement::StatementJob,
+        transaction::Transaction,
+        user::User,
+        webhook::{WebhookDelivery, WebhookSubscription},
+    },
+    fairings::idempotency::IdempotencyRecord,
+    memory::MemoryRepository,
+    mongo::{AppendOnly, Data, Db, LedgerDb, VersionedDb},
+    sql::{
+        self, SqlRepository, ACCESS_GRANTS, ACCOUNTS, API_KEYS, AUDIT_LOG, CRYPTO_LEDGERS, DAILY_ROOTS, FIAT_LEDGERS, IDEMPOTENCY_KEYS, INTEGRITY_REPORTS,
+        LEDGER_ENTRIES, PASSWORD_RESETS, REVIEW_ITEMS, SESSIONS,
+        OUTBOX_EVENTS, STATEMENT_IMPORTS, STATEMENT_JOBS, TRANSACTIONS, USERS, WEBHOOK_DELIVERIES,
+        WEBHOOK_SUBSCRIPTIONS,
+    },
+};
+
+/// Every store the api manages, built for the backend chosen with `STORAGE`.
+pub struct Stores {
+    pub fiat: LedgerDb<Fiat>,
+    pub crypto: LedgerDb<Crypto>,
+    pub account: VersionedDb<Account>,
+    pub transaction: VersionedDb<Transaction>,
+    pub user: Db<User>,
+    pub statement_job: Db<StatementJob>,
+    pub statement_import: Db<StatementImport>,
+    pub review: VersionedDb<ReviewItem>,
+    pub idempotency: Db<IdempotencyRecord>,
+    pub outbox: Db<DomainEvent>,
+    pub webhook: Db<WebhookSubscription>,
+    pub webhook_delivery: Db<WebhookDelivery>,
+    pub integrity_report: Db<IntegrityReport>,
+    pub ledger_entry: Db<LedgerEntry>,
+    pub daily_root: Db<DailyRoot>,
+    pub audit: Db<AuditEntry>,
+    pub grant: VersionedDb<AccessGrant>,
+    pub api_key: Db<ApiKey>,
+    pub session: VersionedDb<UserSession>,
+    pub password_reset: VersionedDb<PasswordReset>,
+}
+impl Stores {
+    pub async fn mongo(uri: &str, database: &str) -> Result<Stores, String> {
+        let client = Data::new(uri, "My Bank", database).await?;
+        let transaction = client.get_repo::<Transaction>("transaction", "tx_id".to_string())?;
+        for (keys, unique) in [
+            (doc! {"tx_id": 1}, true),
+            (doc! {"from_wallet": 1, "timestamp": -1, "tx_id": -1}, false),
+            (doc! {"to_wallet": 1, "timestamp": -1, "tx_id": -1}, false),
+            (doc! {"timestamp": -1, "tx_id": -1}, false),
+            (doc! {"external_id": 1}, false),
+        ] {
+            if let Err(e) = transaction.create_index(keys, unique).await {
+                return Err(format!("Error creating transaction index: {}", e));
+            }
+        }
+        // a reference names one deposit, transactions without one are left out of the index
+        if let Err(e) = transaction
+            .create_partial_unique_index(
+                "payment_reference_unique",
+                doc! {"payment_reference": 1},
+                doc! {"payment_reference": {"$type": "string"}},
+            )
+            .await
+        {
+            return Err(format!("Error creating transaction index: {}", e));
+        }
+        let idempotency = client.get_repo::<IdempotencyRecord>("idempotency_key", "id".to_string())?;
+        if let Err(e) = idempotency.create_index(doc! {"id": 1}, true).await {
+            return Err(format!("Error creating idempotency index: {}", e));
+        }
+        let outbox = client.get_repo::<DomainEvent>("outbox", "id".to_string())?;
+        for (keys, unique) in [(doc! {"id": 1}, true), (doc! {"published": 1, "occurred_at": 1}, false)] {
+            if let Err(e) = outbox.create_index(keys, unique).await {
+                return Err(format!("Error creating outbox index: {}", e));
+            }
+        }
+        let webhook = client.get_repo::<WebhookSubscription>("webhook_subscription", "id".to_string())?;
+        for (keys, unique) in [(doc! {"id": 1}, true), (doc! {"account_number": 1}, false)] {
+            if let Err(e) = webhook.create_index(keys, unique).await {
+                return Err(format!("Error creating webhook index: {}", e));
+            }
+        }
+        let webhook_delivery = client.get_repo::<WebhookDelivery>("webhook_delivery", "id".to_string())?;
+        for (keys, unique) in [
+            (doc! {"id": 1}, true),
+            (doc! {"status": 1, "next_attempt_at": 1}, false),
+            (doc! {"subscription_id": 1, "created_at": -1}, false),
+        ] {
+            if let Err(e) = webhook_delivery.create_index(keys, unique).await {
+                return Err(format!("Error creating webhook delivery index: {}", e));
+            }
+        }
+        let ledger_entry = client.get_repo::<LedgerEntry>("ledger_entry", "id".to_string())?;
+        for (keys, unique) in [
+            (doc! {"id": 1}, true),
+            (doc! {"tx_id": 1}, true),
+            (doc! {"day": 1, "sequence": 1}, false),
+            (doc! {"sequence": -1}, false),
+        ] {
+            if let Err(e) = ledger_entry.create_index(keys, unique).await {
+                return Err(format!("Error creating ledger entry index: {}", e));
+            }
+        }
+        let daily_root = client.get_repo::<DailyRoot>("daily_root", "id".to_string())?;
+        if let Err(e) = daily_root.create_index(doc! {"id": 1}, true).await {
+            return Err(format!("Error creating daily root index: {}", e));
+        }
+        let audit = client.get_repo::<AuditEntry>("audit_log", "id".to_string())?;
+        for (keys, unique) in [
+            (doc! {"id": 1}, true),
+            (doc! {"occurred_at": -1}, false),
+            (doc! {"actor_id": 1, "occurred_at": -1}, false),
+            (doc! {"action": 1, "occurred_at": -1}, false),
+        ] {
+            if let Err(e) = audit.create_index(keys, unique).await {
+                return Err(format!("Error creating audit log index: {}", e));
+            }
+        }
+        let grant = client.get_repo::<AccessGrant>("access_grant", "id".to_string())?;
+        for (keys, unique) in [(doc! {"id": 1}, true), (doc! {"grantee": 1}, false), (doc! {"account_number": 1}, false)] {
+            if let Err(e) = grant.create_index(keys, unique).await {
+                return Err(format!("Error creating access grant index: {}", e));
+            }
+        }
+        let api_key = client.get_repo::<ApiKey>("api_key", "id".to_string())?;
+        for (keys, unique) in [(doc! {"id": 1}, true), (doc! {"user_id": 1}, false)] {
+            if let Err(e) = api_key.create_index(keys, unique).await {
+                return Err(format!("Error creating api key index: {}", e));
+            }
+        }
+        let session = client.get_repo::<UserSession>("user_session", "id".to_string())?;
+        for (keys, unique) in [(doc! {"id": 1}, true), (doc! {"user_id": 1}, false)] {
+            if let Err(e) = session.create_index(keys, unique).await {
+                return Err(format!("Error creating session index: {}", e));
+            }
+        }
+        let password_reset = client.get_repo::<PasswordReset>("password_reset", "id".to_string())?;
+        for (keys, unique) in [(doc! {"id": 1}, true), (doc! {"user_id": 1}, false)] {
+            if let Err(e) = password_reset.create_index(keys, unique).await {
+                return Err(format!("Error creating password reset index: {}", e));
+            }
+        }
+        Ok(Stores {
+            fiat: Arc::new(client.get_repo::<Fiat>("fiat_vault", "id".to_string())?),
+            crypto: Arc::new(client.get_repo::<Crypto>("crypto_vault", "id".to_string())?),
+            account: Arc::new(client.get_repo::<Account>("wallet", "account_number".to_string())?),
+            transaction: Arc::new(transaction),
+            user: Arc::new(client.get_repo::<User>("user", "id".to_string())?),
+            statement_job: Arc::new(client.get_repo::<StatementJob>("statement_job", "id".to_string())?),
+            statement_import: Arc::new(
+                client.get_repo::<StatementImport>("statement_import", "id".to_string())?,
+            ),
+            review: Arc::new(client.get_repo::<ReviewItem>("review_item", "id".to_string())?),
+            idempotency: Arc::new(idempotency),
+            outbox: Arc::new(outbox),
+            webhook: Arc::new(webhook),
+            webhook_delivery: Arc::new(webhook_delivery),
+            integrity_report: Arc::new(client.get_repo::<IntegrityReport>("integrity_report", "id".to_string())?),
+            ledger_entry: Arc::new(ledger_entry),
+            daily_root: Arc::new(daily_root),
+            audit: Arc::new(AppendOnly(Arc::new(audit))),
+            grant: Arc::new(grant),
+            api_key: Arc::new(api_key),
+            session: Arc::new(session),
+            password_reset: Arc::new(password_reset),
+        })
+    }
+    /// Relational stores on PostgreSQL or SQLite, migrated to the latest schema on startup.
+    pub async fn sql(url: &str) -> Result<Stores, String> {
+        let pool = sql::connect(url).await?;
+        Ok(Stores {
+            fiat: Arc::new(SqlRepository::<Fiat>::new(pool.clone(), FIAT_LEDGERS)),
+            crypto: Arc::new(SqlRepository::<Crypto>::new(pool.clone(), CRYPTO_LEDGERS)),
+            account: Arc::new(SqlRepository::<Account>::new(pool.clone(), ACCOUNTS)),
+            transaction: Arc::new(SqlRepository::<Transaction>::new(pool.clone(), TRANSACTIONS)),
+            user: Arc::new(SqlRepository::<User>::new(pool.clone(), USERS)),
+            statement_job: Arc::new(SqlRepository::<StatementJob>::new(pool.clone(), STATEMENT_JOBS)),
+            statement_import: Arc::new(SqlRepository::<StatementImport>::new(pool.clone(), STATEMENT_IMPORTS)),
+            review: Arc::new(SqlRepository::<ReviewItem>::new(pool.clone(), REVIEW_ITEMS)),
+            idempotency: Arc::new(SqlRepository::<IdempotencyRecord>::new(pool.clone(), IDEMPOTENCY_KEYS)),
+            outbox: Arc::new(SqlRepository::<DomainEvent>::new(pool.clone(), OUTBOX_EVENTS)),
+            webhook: Arc::new(SqlRepository::<WebhookSubscription>::new(pool.clone(), WEBHOOK_SUBSCRIPTIONS)),
+            webhook_delivery: Arc::new(SqlRepository::<WebhookDelivery>::new(pool.clone(), WEBHOOK_DELIVERIES)),
+            integrity_report: Arc::new(SqlRepository::<IntegrityReport>::new(pool.clone(), INTEGRITY_REPORTS)),
+            ledger_entry: Arc::new(SqlRepository::<LedgerEntry>::new(pool.clone(), LEDGER_ENTRIES)),
+            daily_root: Arc::new(SqlRepository::<DailyRoot>::new(pool.clone(), DAILY_ROOTS)),
+            audit: Arc::new(AppendOnly(Arc::new(SqlRepository::<AuditEntry>::new(pool.clone(), AUDIT_LOG)))),
+            grant: Arc::new(SqlRepository::<AccessGrant>::new(pool.clone(), ACCESS_GRANTS)),
+            api_key: Arc::new(SqlRepository::<ApiKey>::new(pool.clone(), API_KEYS)),
+            session: Arc::new(SqlRepository::<UserSession>::new(pool.clone(), SESSIONS)),
+            password_reset: Arc::new(SqlRepository::<PasswordReset>::new(pool, PASSWORD_RESETS)),
+        })
+    }
+    /// Empty stores living in the process, lost on restart.
+    pub fn memory() -> Stores {
+        Stores {
+            fiat: Arc::new(MemoryRepository::<Fiat>::new("id".to_string())),
+            crypto: Arc::new(MemoryRepository::<Crypto>::new("id".to_string())),
+            account: Arc::new(MemoryRepository::<Account>::new("account_number".to_string())),
+            transaction: Arc::new(MemoryRepository::<Transaction>::new("tx_id".to_string())),
+            user: Arc::new(MemoryRepository::<User>::new("id".to_string())),
+            statement_job: Arc::new(MemoryRepository::<StatementJob>::new("id".to_string())),
+            statement_import: Arc::new(MemoryRepository::<StatementImport>::new("id".to_string())),
+            review: Arc::new(MemoryRepository::<ReviewItem>::new("id".to_string())),
+            idempotency: Arc::new(MemoryRepository::<IdempotencyRecord>::new("id".to_string())),
+            outbox: Arc::new(MemoryRepository::<DomainEvent>::new("id".to_string())),
+            webhook: Arc::new(MemoryRepository::<WebhookSubscription>::new("id".to_string())),
+            webhook_delivery: Arc::new(MemoryRepository::<WebhookDelivery>::new("id".to_string())),
+            integrity_report: Arc::new(MemoryRepository::<IntegrityReport>::new("id".to_string())),
+            ledger_entry: Arc::new(MemoryRepository::<LedgerEntry>::new("id".to_string())),
+            daily_root: Arc::new(MemoryRepository::<DailyRoot>::new("id".to_string())),
+            audit: Arc::new(AppendOnly(Arc::new(MemoryRepository::<AuditEntry>::new("id".to_string())))),
+            grant: Arc::new(MemoryRepository::<AccessGrant>::new("id".to_string())),
+            api_key: Arc::new(MemoryRepository::<ApiKey>::new("id".to_string())),
+            session: Arc::new(MemoryRepository::<UserSession>::new("id".to_string())),
+            password_reset: Arc::new(MemoryRepository::<PasswordReset>::new("id".to_string())),
+        }
+    }
+}